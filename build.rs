@@ -1,10 +1,18 @@
 use std::env;
 use std::path::PathBuf;
-use bootloader::DiskImageBuilder;
+use bootloader::{BootConfig, DiskImageBuilder};
 
 fn main() {
     let kernel_path = env::var("CARGO_BIN_FILE_KERNEL").unwrap();
-    let image_builder = DiskImageBuilder::new(PathBuf::from(kernel_path));
+    let mut image_builder = DiskImageBuilder::new(PathBuf::from(kernel_path));
+
+    // the render benchmarks are meant to run on a 1280x720 frame buffer
+    if env::var_os("CARGO_FEATURE_BENCH").is_some() {
+        let mut boot_config = BootConfig::default();
+        boot_config.frame_buffer.minimum_framebuffer_width = Some(1280);
+        boot_config.frame_buffer.minimum_framebuffer_height = Some(720);
+        image_builder.set_boot_config(&boot_config);
+    }
 
     let out_dir = PathBuf::from("target").join(env::var("PROFILE").unwrap());
     let uefi_path = out_dir.join("tokyo-uefi.img");
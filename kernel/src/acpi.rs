@@ -0,0 +1,284 @@
+//! Discovery of the ACPI tables provided by the firmware.
+//!
+//! Only the static tables are parsed, starting from the RSDP the bootloader found. Each subsystem
+//! reads the fields it needs from its own table, e.g. the HPET or the MCFG.
+//!
+//! AML isn't interpreted. Devices declared in the DSDT and SSDTs are instead found by their
+//! `_HID` objects, which firmware almost always declares as a constant right inside the device.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::slice;
+use spin::Once;
+use x86_64::PhysAddr;
+use crate::mem;
+
+/// Size of the header every system description table starts with.
+pub const HEADER_SIZE: usize = 36;
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+
+/// Offsets of the 32-bit and 64-bit DSDT addresses in the FADT.
+const FADT_DSDT: usize = 40;
+const FADT_X_DSDT: usize = 140;
+
+const NAME_OP: u8 = 0x08;
+const DWORD_PREFIX: u8 = 0x0C;
+const STRING_PREFIX: u8 = 0x0D;
+const DUAL_NAME_PREFIX: u8 = 0x2E;
+const MULTI_NAME_PREFIX: u8 = 0x2F;
+const DEVICE_OP: [u8; 2] = [0x5B, 0x82];
+
+static TABLES: Once<Vec<Table>> = Once::new();
+static DEVICES: Once<Vec<Device>> = Once::new();
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum AcpiError {
+    /// The RSDP is missing its signature or has a bad checksum.
+    InvalidRsdp,
+    /// The RSDT or XSDT is missing its signature or has a bad checksum.
+    InvalidRoot
+}
+
+/// A system description table, including its header.
+#[derive(Debug, Copy, Clone)]
+pub struct Table {
+    address: PhysAddr,
+    bytes: &'static [u8]
+}
+
+impl Table {
+    /// Reads the table at a physical address, returning `None` if its checksum is bad.
+    ///
+    /// ## Safety
+    /// The address has to point to a table that stays mapped and unchanged.
+    unsafe fn read(address: PhysAddr) -> Option<Self> {
+        let start = mem::phys_to_virt(address).as_ptr::<u8>();
+        let header = unsafe { slice::from_raw_parts(start, HEADER_SIZE) };
+        let length = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        if length < HEADER_SIZE {
+            return None;
+        }
+
+        let bytes = unsafe { slice::from_raw_parts(start, length) };
+        checksum(bytes).then_some(Self { address, bytes })
+    }
+
+    pub fn address(&self) -> PhysAddr {
+        self.address
+    }
+
+    pub fn signature(&self) -> [u8; 4] {
+        [self.bytes[0], self.bytes[1], self.bytes[2], self.bytes[3]]
+    }
+
+    pub fn revision(&self) -> u8 {
+        self.bytes[8]
+    }
+
+    /// Provides the whole table, starting with its header.
+    pub fn bytes(&self) -> &'static [u8] {
+        self.bytes
+    }
+
+    /// Provides the contents following the header.
+    pub fn data(&self) -> &'static [u8] {
+        &self.bytes[HEADER_SIZE..]
+    }
+
+    /// Reads a byte at an offset from the start of the table.
+    pub fn u8(&self, offset: usize) -> Option<u8> {
+        self.bytes.get(offset).copied()
+    }
+
+    /// Reads a little-endian `u16` at an offset from the start of the table.
+    pub fn u16(&self, offset: usize) -> Option<u16> {
+        Some(u16::from_le_bytes(self.bytes.get(offset..offset + 2)?.try_into().ok()?))
+    }
+
+    /// Reads a little-endian `u32` at an offset from the start of the table.
+    pub fn u32(&self, offset: usize) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes.get(offset..offset + 4)?.try_into().ok()?))
+    }
+
+    /// Reads a little-endian `u64` at an offset from the start of the table.
+    pub fn u64(&self, offset: usize) -> Option<u64> {
+        Some(u64::from_le_bytes(self.bytes.get(offset..offset + 8)?.try_into().ok()?))
+    }
+}
+
+/// A device declared in the DSDT or an SSDT.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Device {
+    /// The last segment of the device's path in the namespace, e.g. "COM1".
+    pub name: String,
+    /// The hardware ID, e.g. "PNP0501".
+    pub hid: String,
+    /// Counts the devices with the same hardware ID declared before this one.
+    pub instance: usize
+}
+
+/// Finds the tables listed by the RSDT or XSDT, along with the DSDT and the devices it declares.
+///
+/// Tables with a bad checksum are skipped. Must be called after the physical offset is set.
+///
+/// ## Safety
+/// `rsdp` has to be the physical address of the RSDP, as provided by the bootloader.
+pub unsafe fn init(rsdp: PhysAddr) -> Result<usize, AcpiError> {
+    let start = mem::phys_to_virt(rsdp).as_ptr::<u8>();
+    let header = unsafe { slice::from_raw_parts(start, 20) };
+    if &header[..8] != RSDP_SIGNATURE || !checksum(header) {
+        return Err(AcpiError::InvalidRsdp);
+    }
+
+    // revision 2 and up add the 64-bit XSDT, which is preferred over the RSDT
+    let revision = header[15];
+    let (root, entry_size) = if revision >= 2 {
+        let extended = unsafe { slice::from_raw_parts(start, 36) };
+        if !checksum(extended) {
+            return Err(AcpiError::InvalidRsdp);
+        }
+        (u64::from_le_bytes(extended[24..32].try_into().unwrap()), 8)
+    } else {
+        (u32::from_le_bytes(header[16..20].try_into().unwrap()) as u64, 4)
+    };
+
+    let root = unsafe { Table::read(PhysAddr::new(root)) }.ok_or(AcpiError::InvalidRoot)?;
+    let expected = if entry_size == 8 { b"XSDT" } else { b"RSDT" };
+    if &root.signature() != expected {
+        return Err(AcpiError::InvalidRoot);
+    }
+
+    let mut tables: Vec<Table> = root.data()
+        .chunks_exact(entry_size)
+        .map(|entry| entry.iter().rev().fold(0, |address, &byte| (address << 8) | byte as u64))
+        .filter_map(|address| unsafe { Table::read(PhysAddr::new(address)) })
+        .collect();
+
+    // the DSDT is only referenced by the FADT, preferring its 64-bit address
+    let fadt = tables.iter().find(|table| &table.signature() == b"FACP").copied();
+    if let Some(fadt) = fadt {
+        let address = fadt.u64(FADT_X_DSDT)
+            .filter(|&address| address != 0)
+            .or(fadt.u32(FADT_DSDT).map(u64::from))
+            .filter(|&address| address != 0);
+        if let Some(dsdt) = address.and_then(|address| unsafe { Table::read(PhysAddr::new(address)) }) {
+            tables.push(dsdt);
+        }
+    }
+
+    let count = tables.len();
+    let tables = TABLES.call_once(|| tables);
+    DEVICES.call_once(|| {
+        let mut devices = Vec::new();
+        for table in tables.iter().filter(|table| matches!(&table.signature(), b"DSDT" | b"SSDT")) {
+            scan_devices(table.bytes(), &mut devices);
+        }
+        devices
+    });
+    Ok(count)
+}
+
+/// Provides the first table with a signature, such as `b"HPET"`.
+pub fn find(signature: &[u8; 4]) -> Option<Table> {
+    tables().iter().find(|table| &table.signature() == signature).copied()
+}
+
+/// Provides every table found, or none if ACPI isn't available.
+pub fn tables() -> &'static [Table] {
+    TABLES.get().map_or(&[], |tables| tables.as_slice())
+}
+
+/// Provides every device declared with a hardware ID, in the order of the tables.
+pub fn devices() -> &'static [Device] {
+    DEVICES.get().map_or(&[], |devices| devices.as_slice())
+}
+
+/// Finds the `_HID` objects in a definition block, along with the devices declaring them.
+fn scan_devices(aml: &[u8], devices: &mut Vec<Device>) {
+    for offset in HEADER_SIZE..aml.len().saturating_sub(5) {
+        if aml[offset] != NAME_OP || &aml[offset + 1..offset + 5] != b"_HID" {
+            continue;
+        }
+        let hid = match aml.get(offset + 5) {
+            Some(&DWORD_PREFIX) => match aml.get(offset + 6..offset + 10) {
+                Some(id) => eisa_id([id[0], id[1], id[2], id[3]]),
+                None => continue
+            },
+            Some(&STRING_PREFIX) => aml[offset + 6..]
+                .iter()
+                .take_while(|&&byte| byte != 0)
+                .map(|&byte| byte as char)
+                .collect(),
+            _ => continue // computed by a method, or not a device ID
+        };
+        let Some(name) = enclosing_device(aml, offset) else {
+            continue;
+        };
+
+        let instance = devices.iter().filter(|device| device.hid == hid).count();
+        devices.push(Device { name, hid, instance });
+    }
+}
+
+/// Finds the name of the innermost device whose package contains an offset.
+fn enclosing_device(aml: &[u8], offset: usize) -> Option<String> {
+    (HEADER_SIZE..offset).rev().find_map(|start| {
+        if aml[start..start + 2] != DEVICE_OP {
+            return None;
+        }
+
+        // the package length counts from its own first byte
+        let lead = *aml.get(start + 2)?;
+        let extra = (lead >> 6) as usize;
+        let length = if extra == 0 {
+            (lead & 0x3F) as usize
+        } else {
+            aml.get(start + 3..start + 3 + extra)?
+                .iter()
+                .enumerate()
+                .fold((lead & 0x0F) as usize, |length, (i, &byte)| length | (byte as usize) << (4 + 8 * i))
+        };
+        if offset >= start + 2 + length {
+            return None;
+        }
+
+        last_name_segment(aml.get(start + 3 + extra..)?)
+    })
+}
+
+/// Reads the last segment of a name string, without its trailing padding.
+fn last_name_segment(name: &[u8]) -> Option<String> {
+    let prefixes = name.iter().take_while(|&&byte| byte == b'\\' || byte == b'^').count();
+    let name = &name[prefixes..];
+    let (start, segments) = match *name.first()? {
+        DUAL_NAME_PREFIX => (1, 2),
+        MULTI_NAME_PREFIX => (2, *name.get(1)? as usize),
+        0 => return None,
+        _ => (0, 1)
+    };
+    let last = start + 4 * segments.checked_sub(1)?;
+    let segment = name.get(last..last + 4)?;
+    Some(segment.iter().map(|&byte| byte as char).collect::<String>().trim_end_matches('_').into())
+}
+
+/// Decodes a compressed EISA ID, e.g. to "PNP0501".
+fn eisa_id(id: [u8; 4]) -> String {
+    let letter = |bits: u8| (b'@' + (bits & 0x1F)) as char;
+    let digit = |bits: u8| char::from_digit((bits & 0x0F) as u32, 16).unwrap().to_ascii_uppercase();
+
+    let mut hid = String::new();
+    hid.push(letter(id[0] >> 2));
+    hid.push(letter((id[0] & 0x03) << 3 | id[1] >> 5));
+    hid.push(letter(id[1]));
+    for byte in [id[2], id[3]] {
+        hid.push(digit(byte >> 4));
+        hid.push(digit(byte));
+    }
+    hid
+}
+
+/// Checks that the bytes sum up to zero, as every ACPI structure does.
+fn checksum(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
@@ -0,0 +1,171 @@
+//! The local APIC of the boot CPU.
+//!
+//! Legacy interrupts still arrive through the PICs, which the local APIC passes on in virtual
+//! wire mode. Its own timer is used to wake the CPU from [tickless idle](crate::time::idle).
+
+use core::arch::x86_64::__cpuid;
+use core::hint;
+use core::ptr;
+use core::time::Duration;
+use spin::Once;
+use x86_64::registers::model_specific::Msr;
+use x86_64::{PhysAddr, VirtAddr};
+use crate::idt::InterruptIndex;
+use crate::{mem, time};
+
+const APIC_BASE_MSR: u32 = 0x1B;
+const TSC_DEADLINE_MSR: u32 = 0x6E0;
+
+const ID: usize = 0x020;
+const EOI: usize = 0x0B0;
+const SPURIOUS: usize = 0x0F0;
+const ICR_LOW: usize = 0x300;
+const LVT_TIMER: usize = 0x320;
+const LVT_LINT0: usize = 0x350;
+const LVT_LINT1: usize = 0x360;
+const TIMER_INITIAL: usize = 0x380;
+const TIMER_CURRENT: usize = 0x390;
+const TIMER_DIVIDE: usize = 0x3E0;
+
+/// Physical address bits of the base in the APIC base MSR.
+const BASE_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+/// Set in the spurious interrupt vector register to enable the local APIC.
+const SOFTWARE_ENABLE: u32 = 1 << 8;
+/// Set in a local vector table entry to mask it.
+const MASKED: u32 = 1 << 16;
+/// Set in the interrupt command register to send an interrupt to the sending CPU itself.
+const DESTINATION_SELF: u32 = 0b01 << 18;
+const DELIVERY_NMI: u32 = 0b100 << 8;
+const DELIVERY_EXTINT: u32 = 0b111 << 8;
+const TIMER_TSC_DEADLINE: u32 = 0b10 << 17;
+const DIVIDE_BY_16: u32 = 0b0011;
+
+/// Time spent counting timer ticks against the clock source.
+const CALIBRATION: Duration = Duration::from_millis(10);
+
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+
+static BASE: Once<VirtAddr> = Once::new();
+static TIMER: Once<TimerMode> = Once::new();
+
+/// How the timer is programmed to fire once.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum TimerMode {
+    /// Counts down from an initial count, decrementing at `frequency` hertz.
+    OneShot { frequency: u64 },
+    /// Fires once the TSC, running at `frequency` hertz, reaches a deadline.
+    TscDeadline { frequency: u64 }
+}
+
+/// Enables the local APIC in virtual wire mode and sets up its timer.
+///
+/// The timer is calibrated against the current clock source, so this must be called after
+/// [`init_clocks`](time::init_clocks) and with interrupts enabled.
+pub fn init() {
+    let base = unsafe { Msr::new(APIC_BASE_MSR).read() } & BASE_MASK;
+    BASE.call_once(|| mem::map_mmio(PhysAddr::new(base), 4096).expect("local APIC registers should be mappable"));
+
+    unsafe {
+        // the PICs are wired to LINT0, and NMIs to LINT1
+        write(LVT_LINT0, DELIVERY_EXTINT);
+        write(LVT_LINT1, DELIVERY_NMI);
+        write(LVT_TIMER, MASKED | InterruptIndex::ApicTimer as u32);
+        write(SPURIOUS, SOFTWARE_ENABLE | InterruptIndex::ApicSpurious as u32);
+    }
+
+    // the TSC deadline is only usable if the TSC frequency is known
+    let source = time::clock_source();
+    let mode = if supports_tsc_deadline() && source.name == "tsc" {
+        TimerMode::TscDeadline { frequency: source.frequency }
+    } else {
+        TimerMode::OneShot { frequency: calibrate() }
+    };
+    TIMER.call_once(|| mode);
+}
+
+/// Provides the ID of the local APIC, which identifies the CPU to interrupt controllers.
+pub fn id() -> u8 {
+    (unsafe { read(ID) } >> 24) as u8
+}
+
+/// Signals the end of an interrupt delivered by the local APIC.
+pub fn eoi() {
+    unsafe { write(EOI, 0); }
+}
+
+/// Raises a vector on the current CPU, as if a device had, e.g. to test its handler.
+pub fn raise(vector: u8) {
+    unsafe { write(ICR_LOW, DESTINATION_SELF | vector as u32); }
+}
+
+/// Provides how the timer is programmed, or `None` before [`init`](init).
+pub fn timer_mode() -> Option<TimerMode> {
+    TIMER.get().copied()
+}
+
+/// Raises the timer interrupt once, after `delay`. Replaces a previously armed timer.
+pub fn arm_timer(delay: Duration) {
+    let mode = *TIMER.get().expect("local APIC should be initialized");
+    let vector = InterruptIndex::ApicTimer as u32;
+    unsafe {
+        match mode {
+            TimerMode::OneShot { frequency } => {
+                let count = delay.as_nanos() * frequency as u128 / NANOS_PER_SECOND;
+                write(LVT_TIMER, vector);
+                write(TIMER_DIVIDE, DIVIDE_BY_16);
+                write(TIMER_INITIAL, count.clamp(1, u32::MAX as u128) as u32);
+            }
+            TimerMode::TscDeadline { frequency } => {
+                let cycles = (delay.as_nanos() * frequency as u128 / NANOS_PER_SECOND) as u64;
+                write(LVT_TIMER, TIMER_TSC_DEADLINE | vector);
+                Msr::new(TSC_DEADLINE_MSR).write(time::tsc::read().saturating_add(cycles.max(1)));
+            }
+        }
+    }
+}
+
+/// Stops an armed timer from firing.
+pub fn disarm_timer() {
+    unsafe {
+        match TIMER.get() {
+            Some(TimerMode::OneShot { .. }) => write(TIMER_INITIAL, 0),
+            Some(TimerMode::TscDeadline { .. }) => Msr::new(TSC_DEADLINE_MSR).write(0),
+            None => {}
+        }
+        write(LVT_TIMER, MASKED | InterruptIndex::ApicTimer as u32);
+    }
+}
+
+fn supports_tsc_deadline() -> bool {
+    __cpuid(1).ecx & (1 << 24) != 0
+}
+
+/// Measures how fast the timer counts down in one-shot mode.
+fn calibrate() -> u64 {
+    unsafe {
+        write(LVT_TIMER, MASKED | InterruptIndex::ApicTimer as u32);
+        write(TIMER_DIVIDE, DIVIDE_BY_16);
+        write(TIMER_INITIAL, u32::MAX);
+    }
+
+    let start = time::now();
+    while time::now() - start < CALIBRATION {
+        hint::spin_loop();
+    }
+    let counted = u32::MAX - unsafe { read(TIMER_CURRENT) };
+    let elapsed = time::now() - start;
+    unsafe { write(TIMER_INITIAL, 0); }
+
+    (counted as u128 * NANOS_PER_SECOND / elapsed.as_nanos()) as u64
+}
+
+unsafe fn read(register: usize) -> u32 {
+    let base = *BASE.get().expect("local APIC should be initialized");
+    unsafe { ptr::read_volatile((base + register as u64).as_ptr::<u32>()) }
+}
+
+unsafe fn write(register: usize, value: u32) {
+    let base = *BASE.get().expect("local APIC should be initialized");
+    unsafe { ptr::write_volatile((base + register as u64).as_mut_ptr::<u32>(), value) }
+}
@@ -0,0 +1,359 @@
+//! SATA disks on an AHCI controller, transferring data by DMA.
+//!
+//! Each port with a disk gets a command list, a received FIS area and a command table, and sends
+//! commands one at a time through the first slot. Data passes through a bounce buffer, described
+//! by a single PRD entry, and completion is polled for. A command that fails or times out has
+//! its port stopped and started again, which the port needs to carry on after an error.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::{hint, mem, ptr};
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::{PhysAddr, VirtAddr};
+use crate::ata::{self, Identify, SECTOR_SIZE};
+use crate::block::{self, BlockDevice, BlockError};
+use crate::driver::{Device, Driver, Match, ProbeError};
+use crate::mem::dma::DmaBuffer;
+use crate::pci;
+use crate::serial_println;
+use crate::time;
+
+pub static DRIVER: Driver = Driver {
+    name: "ahci",
+    matches: &[Match::PciClass { class: 0x01, subclass: 0x06, prog_if: Some(0x01) }],
+    probe,
+    remove
+};
+
+/// The BAR holding the controller's registers.
+const ABAR: usize = 5;
+
+// registers of the controller
+const CAPABILITIES: u64 = 0x00;
+const GLOBAL_CONTROL: u64 = 0x04;
+const PORTS_IMPLEMENTED: u64 = 0x0C;
+
+/// Set in the capabilities if the controller can address memory above 4 GiB.
+const CAPABILITY_64BIT: u32 = 1 << 31;
+
+/// Set in the global control register to use the controller through AHCI rather than IDE.
+const AHCI_ENABLE: u32 = 1 << 31;
+
+const PORTS: u64 = 0x100;
+const PORT_SIZE: u64 = 0x80;
+
+// registers of each port
+const COMMAND_LIST_BASE: u64 = 0x00;
+const FIS_BASE: u64 = 0x08;
+const INTERRUPT_STATUS: u64 = 0x10;
+const INTERRUPT_ENABLE: u64 = 0x14;
+const COMMAND: u64 = 0x18;
+const TASK_FILE: u64 = 0x20;
+const SIGNATURE: u64 = 0x24;
+const SATA_STATUS: u64 = 0x28;
+const SATA_ERROR: u64 = 0x30;
+const COMMAND_ISSUE: u64 = 0x38;
+
+const COMMAND_START: u32 = 1 << 0;
+const COMMAND_FIS_RECEIVE: u32 = 1 << 4;
+const COMMAND_FIS_RUNNING: u32 = 1 << 14;
+const COMMAND_LIST_RUNNING: u32 = 1 << 15;
+
+/// Set in the interrupt status if the task file reported an error.
+const TASK_FILE_ERROR: u32 = 1 << 30;
+
+/// The device detection field of the SATA status, when a device is present and communicating.
+const DETECTION_ESTABLISHED: u32 = 3;
+const SIGNATURE_ATA: u32 = 0x0000_0101;
+
+/// Type of the FIS sending a command from the host to the device.
+const FIS_HOST_TO_DEVICE: u8 = 0x27;
+/// Set in the second byte of a host to device FIS if it carries a command.
+const FIS_COMMAND: u8 = 1 << 7;
+const FIS_LENGTH: u32 = 5; // in dwords
+
+/// Set in a command header if data flows to the device.
+const HEADER_WRITE: u32 = 1 << 6;
+
+// layout of the memory of each port, with the data starting on its own page
+const COMMAND_LIST: usize = 0;
+const RECEIVED_FIS: usize = 1024;
+const COMMAND_TABLE: usize = 2048;
+const PRDT: usize = COMMAND_TABLE + 0x80;
+const DATA: usize = 4096;
+
+/// Bytes transferred by a single command, with longer requests split up.
+const MAX_TRANSFER: usize = 64 * 1024;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Disks the driver found, by the name of the controller's device.
+static DISKS: Mutex<Vec<(String, Arc<AhciDisk>)>> = Mutex::new(Vec::new());
+
+/// The registers and memory of a port with a disk.
+struct Port {
+    registers: VirtAddr,
+    memory: DmaBuffer
+}
+
+impl Port {
+    fn read(&self, register: u64) -> u32 {
+        unsafe { ptr::read_volatile((self.registers + register).as_ptr::<u32>()) }
+    }
+
+    fn write(&self, register: u64, value: u32) {
+        unsafe { ptr::write_volatile((self.registers + register).as_mut_ptr::<u32>(), value) }
+    }
+
+    /// Waits for bits of a register to clear.
+    fn wait_clear(&self, register: u64, bits: u32) -> Result<(), BlockError> {
+        let deadline = time::now() + TIMEOUT;
+        while self.read(register) & bits != 0 {
+            if time::now() > deadline {
+                return Err(BlockError::Timeout);
+            }
+            hint::spin_loop();
+        }
+        Ok(())
+    }
+
+    /// Stops processing commands, so the memory can be changed.
+    fn stop(&self) -> Result<(), BlockError> {
+        self.write(COMMAND, self.read(COMMAND) & !COMMAND_START);
+        self.wait_clear(COMMAND, COMMAND_LIST_RUNNING)?;
+        self.write(COMMAND, self.read(COMMAND) & !COMMAND_FIS_RECEIVE);
+        self.wait_clear(COMMAND, COMMAND_FIS_RUNNING)
+    }
+
+    /// Points the port at its memory and starts processing commands.
+    fn start(&self) {
+        let base = self.memory.phys().as_u64();
+        for (register, address) in [(COMMAND_LIST_BASE, base + COMMAND_LIST as u64), (FIS_BASE, base + RECEIVED_FIS as u64)] {
+            self.write(register, address as u32);
+            self.write(register + 4, (address >> 32) as u32);
+        }
+        self.write(SATA_ERROR, u32::MAX);
+        self.write(INTERRUPT_STATUS, u32::MAX);
+        self.write(INTERRUPT_ENABLE, 0);
+        self.write(COMMAND, self.read(COMMAND) | COMMAND_FIS_RECEIVE);
+        self.write(COMMAND, self.read(COMMAND) | COMMAND_START);
+    }
+
+    /// Stops the port for good, leaking its memory if it keeps running, as it may still write to it.
+    fn release(self) {
+        if self.stop().is_err() {
+            mem::forget(self);
+        }
+    }
+
+    /// Runs a command in the first slot, transferring `length` bytes of the bounce buffer.
+    fn run(&mut self, command: u8, sector: u64, count: u16, length: usize, write: bool) -> Result<(), BlockError> {
+        let base = self.memory.phys().as_u64();
+        let memory = self.memory.as_mut_slice();
+
+        let table = base + COMMAND_TABLE as u64;
+        let mut flags = FIS_LENGTH | if length > 0 { 1 << 16 } else { 0 }; // one PRD entry
+        if write {
+            flags |= HEADER_WRITE;
+        }
+        memory[COMMAND_LIST..COMMAND_LIST + 32].fill(0);
+        memory[COMMAND_LIST..COMMAND_LIST + 4].copy_from_slice(&flags.to_le_bytes());
+        memory[COMMAND_LIST + 8..COMMAND_LIST + 16].copy_from_slice(&table.to_le_bytes());
+
+        let sector = sector.to_le_bytes();
+        let count = count.to_le_bytes();
+        memory[COMMAND_TABLE..PRDT].fill(0);
+        memory[COMMAND_TABLE..COMMAND_TABLE + 16].copy_from_slice(&[
+            FIS_HOST_TO_DEVICE, FIS_COMMAND, command, 0,
+            sector[0], sector[1], sector[2], ata::DEVICE_LBA,
+            sector[3], sector[4], sector[5], 0,
+            count[0], count[1], 0, 0
+        ]);
+
+        memory[PRDT..PRDT + 16].fill(0);
+        if length > 0 {
+            memory[PRDT..PRDT + 8].copy_from_slice(&(base + DATA as u64).to_le_bytes());
+            memory[PRDT + 12..PRDT + 16].copy_from_slice(&(length as u32 - 1).to_le_bytes());
+        }
+
+        let result = self.issue();
+        if result.is_err() {
+            // the port halts on errors and keeps the command issued, until it's stopped and
+            // started again, which also clears the errors
+            if self.stop().is_ok() {
+                self.start();
+            }
+        }
+        result
+    }
+
+    /// Issues the command in the first slot and waits for it to complete.
+    fn issue(&self) -> Result<(), BlockError> {
+        self.wait_clear(TASK_FILE, (ata::STATUS_BUSY | ata::STATUS_DRQ) as u32)?;
+        self.write(INTERRUPT_STATUS, u32::MAX);
+        self.write(COMMAND_ISSUE, 1);
+
+        let deadline = time::now() + TIMEOUT;
+        while self.read(COMMAND_ISSUE) & 1 != 0 {
+            if self.read(INTERRUPT_STATUS) & TASK_FILE_ERROR != 0 {
+                return Err(BlockError::Io);
+            }
+            if time::now() > deadline {
+                return Err(BlockError::Timeout);
+            }
+            hint::spin_loop();
+        }
+        match self.read(TASK_FILE) as u8 & (ata::STATUS_ERROR | ata::STATUS_FAULT) {
+            0 => Ok(()),
+            _ => Err(BlockError::Io)
+        }
+    }
+
+    fn identify(&mut self) -> Result<Identify, BlockError> {
+        self.run(ata::COMMAND_IDENTIFY, 0, 0, SECTOR_SIZE, false)?;
+        let mut words = [0; 256];
+        for (word, bytes) in words.iter_mut().zip(self.memory.as_slice()[DATA..DATA + SECTOR_SIZE].as_chunks::<2>().0) {
+            *word = u16::from_le_bytes(*bytes);
+        }
+        Ok(Identify::parse(&words))
+    }
+}
+
+/// A disk on one of the controller's ports.
+pub struct AhciDisk {
+    name: String,
+    port: Mutex<Port>,
+    identify: Identify
+}
+
+impl AhciDisk {
+    pub fn identify(&self) -> &Identify {
+        &self.identify
+    }
+}
+
+impl BlockDevice for AhciDisk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn blocks(&self) -> u64 {
+        self.identify.sectors
+    }
+
+    fn read(&self, block: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+        block::check_request(self, block, buffer.len())?;
+        let mut port = self.port.lock();
+        for (i, chunk) in buffer.chunks_mut(MAX_TRANSFER).enumerate() {
+            let sector = block + (i * MAX_TRANSFER / SECTOR_SIZE) as u64;
+            let count = (chunk.len() / SECTOR_SIZE) as u16;
+            port.run(ata::COMMAND_READ_DMA_EXT, sector, count, chunk.len(), false)?;
+            chunk.copy_from_slice(&port.memory.as_slice()[DATA..DATA + chunk.len()]);
+        }
+        Ok(())
+    }
+
+    fn write(&self, block: u64, buffer: &[u8]) -> Result<(), BlockError> {
+        block::check_request(self, block, buffer.len())?;
+        let mut port = self.port.lock();
+        for (i, chunk) in buffer.chunks(MAX_TRANSFER).enumerate() {
+            let sector = block + (i * MAX_TRANSFER / SECTOR_SIZE) as u64;
+            let count = (chunk.len() / SECTOR_SIZE) as u16;
+            port.memory.as_mut_slice()[DATA..DATA + chunk.len()].copy_from_slice(chunk);
+            port.run(ata::COMMAND_WRITE_DMA_EXT, sector, count, chunk.len(), true)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        self.port.lock().run(ata::COMMAND_FLUSH_CACHE_EXT, 0, 0, 0, false)
+    }
+}
+
+/// Checks whether a controller can reach `size` bytes at `phys`, given whether it supports 64-bit addressing.
+///
+/// Without it, the upper halves of the addresses are ignored.
+pub fn addressable(wide: bool, phys: PhysAddr, size: usize) -> bool {
+    wide || phys.as_u64() + size as u64 <= 1 << 32
+}
+
+fn probe(device: &Arc<Device>) -> Result<(), ProbeError> {
+    let function = device.pci().ok_or(ProbeError::Unsupported)?;
+    let registers = function.mapped_bar(ABAR).ok_or(ProbeError::MissingResource)?;
+    let read = |register: u64| unsafe { ptr::read_volatile((registers + register).as_ptr::<u32>()) };
+    function.enable(pci::COMMAND_MEMORY | pci::COMMAND_BUS_MASTER);
+    unsafe {
+        ptr::write_volatile((registers + GLOBAL_CONTROL).as_mut_ptr::<u32>(), read(GLOBAL_CONTROL) | AHCI_ENABLE);
+    }
+
+    let wide = read(CAPABILITIES) & CAPABILITY_64BIT != 0;
+    let mut disks: Vec<(String, Arc<AhciDisk>)> = Vec::new();
+    let implemented = read(PORTS_IMPLEMENTED);
+    for index in (0..32).filter(|index| implemented & (1 << index) != 0) {
+        let registers = registers + PORTS + index as u64 * PORT_SIZE;
+        let read = |register: u64| unsafe { ptr::read_volatile((registers + register).as_ptr::<u32>()) };
+        if read(SATA_STATUS) & 0x0F != DETECTION_ESTABLISHED || read(SIGNATURE) != SIGNATURE_ATA {
+            continue; // empty, or an ATAPI device
+        }
+
+        let memory = DmaBuffer::new(DATA + MAX_TRANSFER)
+            .filter(|memory| addressable(wide, memory.phys(), memory.size()));
+        let Some(memory) = memory else {
+            // the disks found so far go away along with the controller
+            for (_, disk) in disks {
+                detach(disk);
+            }
+            return Err(ProbeError::Failed);
+        };
+        let mut port = Port { registers, memory };
+
+        if port.stop().is_err() {
+            continue;
+        }
+        port.start();
+        // only disks supporting 48-bit LBAs are driven, since only those have DMA EXT commands
+        let identify = match port.identify() {
+            Ok(identify) if identify.lba48 => identify,
+            _ => {
+                port.release();
+                continue;
+            }
+        };
+
+        let disk = Arc::new(AhciDisk { name: block::next_name("sd"), port: Mutex::new(port), identify });
+        serial_println!("[tokyo] {}: {} sectors, {}", disk.name, disk.identify.sectors, disk.identify.model);
+        block::register(disk.clone());
+        disks.push((String::from(device.name()), disk));
+    }
+
+    DISKS.lock().extend(disks);
+    Ok(())
+}
+
+fn remove(device: &Arc<Device>) {
+    let removed: Vec<_> = {
+        let mut disks = DISKS.lock();
+        let (removed, kept) = mem::take(&mut *disks).into_iter().partition(|(name, _)| name == device.name());
+        *disks = kept;
+        removed
+    };
+    for (_, disk) in removed {
+        detach(disk);
+    }
+}
+
+/// Unregisters a disk and stops its port, leaking the disk if the port keeps running, as it may
+/// still write to the port's memory.
+fn detach(disk: Arc<AhciDisk>) {
+    block::unregister(&disk.name);
+    let stopped = disk.port.lock().stop().is_ok();
+    if !stopped {
+        mem::forget(disk);
+    }
+}
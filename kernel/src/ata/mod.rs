@@ -0,0 +1,70 @@
+//! ATA disks, reached through the legacy IDE ports or an AHCI controller.
+//!
+//! Both drivers describe their disks with the data returned by IDENTIFY DEVICE, and address
+//! them with 48-bit LBAs where the disk supports them.
+
+pub mod ahci;
+pub mod pio;
+
+use alloc::string::String;
+
+pub const SECTOR_SIZE: usize = 512;
+
+pub const COMMAND_READ_SECTORS: u8 = 0x20;
+pub const COMMAND_READ_SECTORS_EXT: u8 = 0x24;
+pub const COMMAND_READ_DMA_EXT: u8 = 0x25;
+pub const COMMAND_WRITE_SECTORS: u8 = 0x30;
+pub const COMMAND_WRITE_SECTORS_EXT: u8 = 0x34;
+pub const COMMAND_WRITE_DMA_EXT: u8 = 0x35;
+pub const COMMAND_FLUSH_CACHE: u8 = 0xE7;
+pub const COMMAND_FLUSH_CACHE_EXT: u8 = 0xEA;
+pub const COMMAND_IDENTIFY: u8 = 0xEC;
+
+pub const STATUS_ERROR: u8 = 1 << 0;
+pub const STATUS_DRQ: u8 = 1 << 3;
+pub const STATUS_FAULT: u8 = 1 << 5;
+pub const STATUS_BUSY: u8 = 1 << 7;
+
+/// Set in the device register to address sectors by LBA.
+pub const DEVICE_LBA: u8 = 1 << 6;
+
+/// Set in word 83 of the identify data if 48-bit LBAs are supported.
+const IDENTIFY_LBA48: u16 = 1 << 10;
+
+/// What a disk reported about itself in response to IDENTIFY DEVICE.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Identify {
+    pub model: String,
+    pub serial: String,
+    /// Capacity in sectors.
+    pub sectors: u64,
+    /// Whether 48-bit LBAs and the extended commands are supported.
+    pub lba48: bool
+}
+
+impl Identify {
+    /// Parses the 256 words returned by IDENTIFY DEVICE.
+    pub fn parse(words: &[u16; 256]) -> Self {
+        let lba48 = words[83] & IDENTIFY_LBA48 != 0;
+        let sectors = if lba48 {
+            (0..4).fold(0, |sectors, i| sectors | (words[100 + i] as u64) << (16 * i))
+        } else {
+            words[60] as u64 | (words[61] as u64) << 16
+        };
+        Self {
+            model: string(&words[27..47]),
+            serial: string(&words[10..20]),
+            sectors,
+            lba48
+        }
+    }
+}
+
+/// Reads a string stored with the two bytes of each word swapped, without its padding.
+fn string(words: &[u16]) -> String {
+    let string: String = words.iter()
+        .flat_map(|word| word.to_be_bytes())
+        .map(|byte| byte as char)
+        .collect();
+    String::from(string.trim())
+}
@@ -0,0 +1,308 @@
+//! ATA disks on the IDE controller, transferring data through its I/O ports.
+//!
+//! The controller has two channels with up to two disks each, at the legacy ports unless it's
+//! in native mode. Interrupts are disabled on both, with the status polled instead, since every
+//! sector passes through the CPU anyway. ATAPI devices such as CD drives are skipped.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::hint;
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+use crate::ata::{self, Identify, SECTOR_SIZE};
+use crate::block::{self, BlockDevice, BlockError};
+use crate::driver::{Device, Driver, Match, ProbeError};
+use crate::pci::Bar;
+use crate::serial_println;
+use crate::time;
+
+pub static DRIVER: Driver = Driver {
+    name: "ata-pio",
+    matches: &[Match::PciClass { class: 0x01, subclass: 0x01, prog_if: None }],
+    probe,
+    remove
+};
+
+/// Ports of each channel in compatibility mode, as command block and control port.
+const LEGACY_PORTS: [(u16, u16); 2] = [(0x1F0, 0x3F6), (0x170, 0x376)];
+/// Set in the programming interface if a channel is in native mode, for each channel.
+const NATIVE_MODE: [u8; 2] = [1 << 0, 1 << 2];
+
+// registers of the command block
+const DATA: u16 = 0;
+const SECTOR_COUNT: u16 = 2;
+const LBA_LOW: u16 = 3;
+const LBA_MID: u16 = 4;
+const LBA_HIGH: u16 = 5;
+const DEVICE: u16 = 6;
+const COMMAND: u16 = 7;
+const STATUS: u16 = 7;
+
+/// Set in the device control register to stop the disks from interrupting.
+const CONTROL_NO_INTERRUPTS: u8 = 1 << 1;
+/// Selects the second disk of a channel in the device register.
+const DEVICE_SLAVE: u8 = 1 << 4;
+
+/// Sectors transferred by a single command, with longer requests split up.
+const MAX_SECTORS: usize = 256;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// Time a missing disk is given to answer IDENTIFY DEVICE.
+const IDENTIFY_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Disks the driver found, by the name of the controller's device.
+static DISKS: Mutex<Vec<(String, Arc<AtaDisk>)>> = Mutex::new(Vec::new());
+
+/// The ports of one channel.
+#[derive(Debug)]
+struct Channel {
+    command: u16,
+    control: u16
+}
+
+impl Channel {
+    fn status(&self) -> u8 {
+        unsafe { Port::<u8>::new(self.command + STATUS).read() }
+    }
+
+    fn write(&self, register: u16, value: u8) {
+        unsafe { Port::<u8>::new(self.command + register).write(value) }
+    }
+
+    fn read(&self, register: u16) -> u8 {
+        unsafe { Port::<u8>::new(self.command + register).read() }
+    }
+
+    /// Selects a disk, waiting for it to take over the channel.
+    fn select(&self, slave: bool, device: u8) {
+        self.write(DEVICE, device | if slave { DEVICE_SLAVE } else { 0 });
+        // each read of the alternate status takes 100ns, and the switch takes 400ns
+        for _ in 0..4 {
+            unsafe { Port::<u8>::new(self.control).read() };
+        }
+    }
+
+    /// Waits until the disk isn't busy anymore, returning its status.
+    fn wait(&self, timeout: Duration) -> Result<u8, BlockError> {
+        let deadline = time::now() + timeout;
+        loop {
+            let status = self.status();
+            if status & ata::STATUS_BUSY == 0 {
+                return Ok(status);
+            }
+            if time::now() > deadline {
+                return Err(BlockError::Timeout);
+            }
+            hint::spin_loop();
+        }
+    }
+
+    /// Waits until the disk is ready to transfer a sector.
+    fn wait_data(&self) -> Result<(), BlockError> {
+        let deadline = time::now() + TIMEOUT;
+        loop {
+            let status = self.wait(TIMEOUT)?;
+            if status & (ata::STATUS_ERROR | ata::STATUS_FAULT) != 0 {
+                return Err(BlockError::Io);
+            }
+            if status & ata::STATUS_DRQ != 0 {
+                return Ok(());
+            }
+            if time::now() > deadline {
+                return Err(BlockError::Timeout);
+            }
+            hint::spin_loop();
+        }
+    }
+
+    /// Waits for a command without data to complete.
+    fn wait_done(&self) -> Result<(), BlockError> {
+        match self.wait(TIMEOUT)? & (ata::STATUS_ERROR | ata::STATUS_FAULT) {
+            0 => Ok(()),
+            _ => Err(BlockError::Io)
+        }
+    }
+
+    /// Identifies a disk, returning `None` if there isn't one or it isn't an ATA disk.
+    fn identify(&self, slave: bool) -> Option<Identify> {
+        self.select(slave, 0);
+        for register in [SECTOR_COUNT, LBA_LOW, LBA_MID, LBA_HIGH] {
+            self.write(register, 0);
+        }
+        self.write(COMMAND, ata::COMMAND_IDENTIFY);
+
+        // a missing disk reads as zero, and an empty channel floats high
+        if matches!(self.status(), 0 | 0xFF) {
+            return None;
+        }
+        self.wait(IDENTIFY_TIMEOUT).ok()?;
+        // ATAPI and SATA devices abort, leaving their signature in the LBA registers
+        if self.read(LBA_MID) != 0 || self.read(LBA_HIGH) != 0 {
+            return None;
+        }
+        self.wait_data().ok()?;
+
+        let mut words = [0; 256];
+        for word in &mut words {
+            *word = unsafe { Port::<u16>::new(self.command + DATA).read() };
+        }
+        Some(Identify::parse(&words))
+    }
+
+    /// Issues a command addressing `count` sectors, from 1 up to [`MAX_SECTORS`](MAX_SECTORS).
+    fn issue(&self, slave: bool, lba48: bool, sector: u64, count: usize, command: u8) -> Result<(), BlockError> {
+        self.wait(TIMEOUT)?;
+        let task_file = TaskFile::new(lba48, sector, count);
+        self.select(slave, task_file.device);
+        // each register holds two bytes in turn, so the high ones go first
+        for bytes in task_file.high.iter().chain([&task_file.low]) {
+            for (register, byte) in [SECTOR_COUNT, LBA_LOW, LBA_MID, LBA_HIGH].into_iter().zip(bytes) {
+                self.write(register, *byte);
+            }
+        }
+        self.write(COMMAND, command);
+        Ok(())
+    }
+}
+
+/// The registers addressing a run of sectors, each as the sector count, then the LBA bytes.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct TaskFile {
+    /// The device register, without the disk selected.
+    pub device: u8,
+    /// The high bytes, only written for 48-bit LBAs.
+    pub high: Option<[u8; 4]>,
+    pub low: [u8; 4]
+}
+
+impl TaskFile {
+    /// Addresses `count` sectors from `sector`, with the top 4 bits of a 28-bit LBA in the device register.
+    pub fn new(lba48: bool, sector: u64, count: usize) -> Self {
+        let low = [count as u8, sector as u8, (sector >> 8) as u8, (sector >> 16) as u8]; // 256 is written as 0
+        if lba48 {
+            let high = [(count >> 8) as u8, (sector >> 24) as u8, (sector >> 32) as u8, (sector >> 40) as u8];
+            Self { device: ata::DEVICE_LBA, high: Some(high), low }
+        } else {
+            Self { device: ata::DEVICE_LBA | (sector >> 24) as u8 & 0x0F, high: None, low }
+        }
+    }
+}
+
+/// A disk on either channel.
+pub struct AtaDisk {
+    name: String,
+    channel: Arc<Mutex<Channel>>,
+    slave: bool,
+    identify: Identify
+}
+
+impl AtaDisk {
+    pub fn identify(&self) -> &Identify {
+        &self.identify
+    }
+}
+
+impl BlockDevice for AtaDisk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn blocks(&self) -> u64 {
+        self.identify.sectors
+    }
+
+    fn read(&self, block: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+        block::check_request(self, block, buffer.len())?;
+        let command = if self.identify.lba48 { ata::COMMAND_READ_SECTORS_EXT } else { ata::COMMAND_READ_SECTORS };
+        let channel = self.channel.lock();
+        for (i, chunk) in buffer.chunks_mut(MAX_SECTORS * SECTOR_SIZE).enumerate() {
+            let sector = block + (i * MAX_SECTORS) as u64;
+            channel.issue(self.slave, self.identify.lba48, sector, chunk.len() / SECTOR_SIZE, command)?;
+            for data in chunk.as_chunks_mut::<SECTOR_SIZE>().0 {
+                channel.wait_data()?;
+                for word in data.as_chunks_mut::<2>().0 {
+                    *word = unsafe { Port::<u16>::new(channel.command + DATA).read() }.to_le_bytes();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&self, block: u64, buffer: &[u8]) -> Result<(), BlockError> {
+        block::check_request(self, block, buffer.len())?;
+        let command = if self.identify.lba48 { ata::COMMAND_WRITE_SECTORS_EXT } else { ata::COMMAND_WRITE_SECTORS };
+        let channel = self.channel.lock();
+        for (i, chunk) in buffer.chunks(MAX_SECTORS * SECTOR_SIZE).enumerate() {
+            let sector = block + (i * MAX_SECTORS) as u64;
+            channel.issue(self.slave, self.identify.lba48, sector, chunk.len() / SECTOR_SIZE, command)?;
+            for data in chunk.as_chunks::<SECTOR_SIZE>().0 {
+                channel.wait_data()?;
+                for word in data.as_chunks::<2>().0 {
+                    unsafe { Port::<u16>::new(channel.command + DATA).write(u16::from_le_bytes(*word)) };
+                }
+            }
+            channel.wait_done()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        let command = if self.identify.lba48 { ata::COMMAND_FLUSH_CACHE_EXT } else { ata::COMMAND_FLUSH_CACHE };
+        let channel = self.channel.lock();
+        channel.wait(TIMEOUT)?;
+        channel.select(self.slave, 0);
+        channel.write(COMMAND, command);
+        channel.wait_done()
+    }
+}
+
+fn probe(device: &Arc<Device>) -> Result<(), ProbeError> {
+    let function = device.pci().ok_or(ProbeError::Unsupported)?;
+
+    let mut disks = Vec::new();
+    for (index, (legacy_command, legacy_control)) in LEGACY_PORTS.into_iter().enumerate() {
+        let (command, control) = if function.prog_if & NATIVE_MODE[index] != 0 {
+            // the control port is the third of the second BAR of each channel
+            match (function.bars[index * 2], function.bars[index * 2 + 1]) {
+                (Some(Bar::Io { port: command, .. }), Some(Bar::Io { port: control, .. })) => (command, control + 2),
+                _ => continue
+            }
+        } else {
+            (legacy_command, legacy_control)
+        };
+
+        let channel = Channel { command, control };
+        unsafe { Port::<u8>::new(control).write(CONTROL_NO_INTERRUPTS) };
+        let found: Vec<(bool, Identify)> = [false, true]
+            .into_iter()
+            .filter_map(|slave| Some((slave, channel.identify(slave)?)))
+            .collect();
+
+        let channel = Arc::new(Mutex::new(channel));
+        for (slave, identify) in found {
+            let disk = Arc::new(AtaDisk { name: block::next_name("hd"), channel: channel.clone(), slave, identify });
+            serial_println!("[tokyo] {}: {} sectors, {}", disk.name, disk.identify.sectors, disk.identify.model);
+            block::register(disk.clone());
+            disks.push((String::from(device.name()), disk));
+        }
+    }
+
+    DISKS.lock().extend(disks);
+    Ok(())
+}
+
+fn remove(device: &Arc<Device>) {
+    DISKS.lock().retain(|(name, disk)| {
+        let removed = name == device.name();
+        if removed {
+            block::unregister(&disk.name);
+        }
+        !removed
+    });
+}
@@ -0,0 +1,326 @@
+//! The buffer cache, holding recently used blocks of every disk in memory.
+//!
+//! Once it's full, the least recently used blocks make way for the next. Writes only reach the
+//! cache, leaving blocks dirty until they're synced or evicted, and runs of adjacent blocks are
+//! read or written back with a single request to the device.
+//!
+//! The cache is never locked while a device is busy: requests are planned with the lock held,
+//! run without it, and their results stored once it's taken again.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+use crate::block::{BlockDevice, BlockError, Disk};
+
+/// Bytes of blocks the cache holds, across every disk.
+///
+/// Dirty blocks that fail to be written back stay cached, so the cache may hold more until
+/// the device recovers.
+pub const CAPACITY: usize = 512 * 1024;
+
+/// Bytes a single request merged from adjacent blocks covers at most.
+pub const MAX_REQUEST: usize = 128 * 1024;
+
+static CACHE: Mutex<Cache> = Mutex::new(Cache {
+    entries: BTreeMap::new(),
+    order: BTreeMap::new(),
+    disks: BTreeMap::new(),
+    size: 0,
+    clock: 0
+});
+
+/// Identifies a block by the disk's identifier and its number, so a disk's blocks are adjacent.
+type Key = (u64, u64);
+
+struct Entry {
+    data: Box<[u8]>,
+    dirty: bool,
+    /// When the block was last used, by the cache's clock.
+    used: u64,
+    /// When the block was last written, telling whether a write-back still has the latest data.
+    written: u64
+}
+
+/// Adjacent dirty blocks copied out of the cache, to be written back.
+struct Run {
+    start: u64,
+    data: Vec<u8>,
+    /// When each block was last written, at the time it was copied.
+    written: Vec<u64>
+}
+
+impl Run {
+    /// Provides the block after the last one.
+    fn end(&self) -> u64 {
+        self.start + self.written.len() as u64
+    }
+}
+
+struct Cache {
+    entries: BTreeMap<Key, Entry>,
+    /// The cached blocks by when they were last used, least recently first.
+    order: BTreeMap<u64, Key>,
+    /// Disks with blocks in the cache, by identifier, to write them back to.
+    disks: BTreeMap<u64, Arc<Disk>>,
+    /// Bytes of blocks held.
+    size: usize,
+    clock: u64
+}
+
+impl Cache {
+    fn is_dirty(&self, key: Key) -> bool {
+        self.entries.get(&key).is_some_and(|entry| entry.dirty)
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    /// Marks a block as the most recently used.
+    fn touch(&mut self, key: Key) {
+        let now = self.tick();
+        let Some(entry) = self.entries.get_mut(&key) else {
+            return;
+        };
+        self.order.remove(&entry.used);
+        entry.used = now;
+        self.order.insert(now, key);
+    }
+
+    /// Stores a block, leaving it to [`evict`](Self::evict) to make room for it.
+    fn insert(&mut self, key: Key, data: &[u8], dirty: bool) {
+        let now = self.tick();
+        if let Some(entry) = self.entries.get_mut(&key) {
+            entry.data.copy_from_slice(data);
+            if dirty {
+                entry.dirty = true;
+                entry.written = now;
+            }
+            return self.touch(key);
+        }
+
+        self.entries.insert(key, Entry { data: data.into(), dirty, used: now, written: now });
+        self.order.insert(now, key);
+        self.size += data.len();
+    }
+
+    fn remove(&mut self, key: Key) {
+        if let Some(entry) = self.entries.remove(&key) {
+            self.order.remove(&entry.used);
+            self.size -= entry.data.len();
+        }
+    }
+
+    /// Drops the least recently used clean blocks until the cache is within its capacity.
+    ///
+    /// Dirty blocks are passed over, with the first of them returned if that's not enough,
+    /// to be written back before trying again.
+    fn evict(&mut self) -> Option<Key> {
+        let mut excess = self.size.saturating_sub(CAPACITY);
+        let mut clean = Vec::new();
+        let mut dirty = None;
+        for &key in self.order.values() {
+            if excess == 0 {
+                break;
+            }
+            let entry = &self.entries[&key];
+            if entry.dirty {
+                dirty = dirty.or(Some(key));
+            } else {
+                excess = excess.saturating_sub(entry.data.len());
+                clean.push(key);
+            }
+        }
+        for key in clean {
+            self.remove(key);
+        }
+        if excess > 0 { dirty } else { None }
+    }
+
+    /// Copies out the dirty blocks of a disk in runs of adjacent ones, either just the run
+    /// around a block or all of them.
+    fn dirty_runs(&self, disk: &Disk, around: Option<u64>) -> Vec<Run> {
+        let limit = (MAX_REQUEST / disk.block_size()).max(1);
+        let range = match around {
+            Some(block) if self.is_dirty((disk.id, block)) => {
+                let mut start = block;
+                while start > 0 && block - start + 1 < limit as u64 && self.is_dirty((disk.id, start - 1)) {
+                    start -= 1;
+                }
+                (disk.id, start)..=(disk.id, start.saturating_add(limit as u64 - 1))
+            }
+            Some(_) => return Vec::new(),
+            None => (disk.id, 0)..=(disk.id, u64::MAX)
+        };
+
+        let mut runs: Vec<Run> = Vec::new();
+        for (&(_, block), entry) in self.entries.range(range).filter(|(_, entry)| entry.dirty) {
+            match runs.last_mut() {
+                Some(run) if run.end() == block && run.written.len() < limit => {
+                    run.data.extend_from_slice(&entry.data);
+                    run.written.push(entry.written);
+                }
+                _ => runs.push(Run { start: block, data: entry.data.to_vec(), written: vec![entry.written] })
+            }
+        }
+        if around.is_some() {
+            runs.truncate(1);
+        }
+        runs
+    }
+
+    /// Marks the blocks of a run that was written back as clean, unless they were written since.
+    fn written_back(&mut self, disk: &Disk, run: &Run) {
+        for (i, &written) in run.written.iter().enumerate() {
+            if let Some(entry) = self.entries.get_mut(&(disk.id, run.start + i as u64)) {
+                entry.dirty &= entry.written != written;
+            }
+        }
+    }
+}
+
+/// Writes back the dirty blocks of a disk, either the run around a block or all of them,
+/// returning the first error while still trying every run.
+fn write_back(disk: &Disk, around: Option<u64>) -> Result<(), BlockError> {
+    // write-backs of the same disk can't overtake each other, leaving older data on the device
+    let _writing = disk.writing.lock();
+    let runs = CACHE.lock().dirty_runs(disk, around);
+
+    let mut result = Ok(());
+    for run in runs {
+        disk.stats.lock().merged += run.written.len() as u64 - 1;
+        match disk.write_device(run.start, &run.data) {
+            Ok(()) => CACHE.lock().written_back(disk, &run),
+            Err(error) => result = result.and(Err(error))
+        }
+    }
+    result
+}
+
+/// Evicts blocks until the cache is within its capacity, writing back dirty ones first.
+///
+/// A failing write-back leaves its blocks dirty, to be tried again by a later eviction or sync,
+/// rather than failing the request that happened to make room.
+fn shrink() {
+    loop {
+        let victim = {
+            let mut cache = CACHE.lock();
+            cache.evict().and_then(|(id, block)| Some((cache.disks.get(&id)?.clone(), block)))
+        };
+        let Some((disk, block)) = victim else {
+            return;
+        };
+        if write_back(&disk, Some(block)).is_err() {
+            return;
+        }
+    }
+}
+
+/// Makes a disk's blocks cacheable.
+pub(super) fn attach(disk: Arc<Disk>) {
+    CACHE.lock().disks.insert(disk.id, disk);
+}
+
+/// Writes back a disk's dirty blocks and drops all of them, even if writing back fails.
+pub(super) fn detach(disk: &Disk) -> Result<(), BlockError> {
+    let result = sync(disk);
+    let mut cache = CACHE.lock();
+    let keys: Vec<Key> = cache.entries.range((disk.id, 0)..=(disk.id, u64::MAX)).map(|(&key, _)| key).collect();
+    for key in keys {
+        cache.remove(key);
+    }
+    cache.disks.remove(&disk.id);
+    result
+}
+
+/// Reads whole blocks, from the cache where they're held and from the disk otherwise.
+pub(super) fn read(disk: &Disk, block: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+    let size = disk.block_size();
+    let limit = (MAX_REQUEST / size).max(1);
+    let count = buffer.len() / size;
+
+    let mut i = 0;
+    while i < count {
+        // copy the cached blocks up to the next one that's missing, then read the missing ones in a row
+        let end = {
+            let mut cache = CACHE.lock();
+            while i < count {
+                let key = (disk.id, block + i as u64);
+                let Some(entry) = cache.entries.get(&key) else {
+                    break;
+                };
+                buffer[i * size..(i + 1) * size].copy_from_slice(&entry.data);
+                cache.touch(key);
+                disk.stats.lock().hits += 1;
+                i += 1;
+            }
+            if i == count {
+                break;
+            }
+
+            let mut end = i + 1;
+            while end < count && end - i < limit && !cache.entries.contains_key(&(disk.id, block + end as u64)) {
+                end += 1;
+            }
+            end
+        };
+
+        {
+            let mut stats = disk.stats.lock();
+            stats.misses += (end - i) as u64;
+            stats.merged += (end - i - 1) as u64;
+        }
+        disk.read_device(block + i as u64, &mut buffer[i * size..end * size])?;
+
+        let mut cache = CACHE.lock();
+        for j in i..end {
+            let key = (disk.id, block + j as u64);
+            let data = &mut buffer[j * size..(j + 1) * size];
+            match cache.entries.get(&key) {
+                // written while the device was being read, so the cache has the newer data
+                Some(entry) => data.copy_from_slice(&entry.data),
+                None => cache.insert(key, data, false)
+            }
+        }
+        i = end;
+    }
+
+    shrink();
+    Ok(())
+}
+
+/// Writes whole blocks to the cache, leaving them to be written back later.
+///
+/// Only memory is touched, so the write either reaches the cache in full or not at all.
+pub(super) fn write(disk: &Disk, block: u64, buffer: &[u8]) -> Result<(), BlockError> {
+    {
+        let mut cache = CACHE.lock();
+        for (i, data) in buffer.chunks(disk.block_size()).enumerate() {
+            cache.insert((disk.id, block + i as u64), data, true);
+        }
+    }
+    shrink();
+    Ok(())
+}
+
+/// Writes back a disk's dirty blocks.
+pub(super) fn sync(disk: &Disk) -> Result<(), BlockError> {
+    write_back(disk, None)
+}
+
+/// Counts the blocks of a disk that are cached, and how many of them are dirty.
+pub(super) fn blocks(disk: &Disk) -> (usize, usize) {
+    CACHE.lock().entries
+        .range((disk.id, 0)..=(disk.id, u64::MAX))
+        .fold((0, 0), |(cached, dirty), (_, entry)| (cached + 1, dirty + entry.dirty as usize))
+}
+
+/// Provides the bytes of blocks held, which stays within [`CAPACITY`](CAPACITY) while dirty
+/// blocks can be written back.
+pub fn size() -> usize {
+    CACHE.lock().size
+}
@@ -0,0 +1,264 @@
+//! Block devices, which store data in fixed-size blocks addressed by number.
+//!
+//! Storage drivers [register](register) each disk they find under a name, such as `vda` for the
+//! first virtio disk, which stays the same for as long as the disk does. Registered disks are
+//! handed out as a [`Disk`](Disk), which goes through the shared [buffer cache](cache) and keeps
+//! statistics, so filesystems use the same interface and caching whatever the driver.
+
+pub mod cache;
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+static DEVICES: Mutex<Vec<Arc<Disk>>> = Mutex::new(Vec::new());
+
+/// The identifier of the next disk registered, telling its blocks apart in the cache.
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum BlockError {
+    /// The blocks are past the end of the device.
+    OutOfRange,
+    /// The buffer isn't a whole number of blocks.
+    UnalignedBuffer,
+    /// The device can't be written to.
+    ReadOnly,
+    /// The device doesn't support the operation.
+    Unsupported,
+    /// The device didn't complete the request in time.
+    Timeout,
+    /// The device reported an error.
+    Io
+}
+
+/// A disk or similar device, read and written a block at a time.
+pub trait BlockDevice: Send + Sync {
+    /// Provides the name the device was registered under, e.g. "vda".
+    fn name(&self) -> &str;
+
+    /// Provides the size of a block in bytes.
+    fn block_size(&self) -> usize;
+
+    /// Provides the number of blocks.
+    fn blocks(&self) -> u64;
+
+    /// Whether writes are refused.
+    fn read_only(&self) -> bool {
+        false
+    }
+
+    /// Reads whole blocks into `buffer`, starting at `block`.
+    fn read(&self, block: u64, buffer: &mut [u8]) -> Result<(), BlockError>;
+
+    /// Writes whole blocks from `buffer`, starting at `block`.
+    fn write(&self, block: u64, buffer: &[u8]) -> Result<(), BlockError>;
+
+    /// Makes previous writes persistent, if the device caches them.
+    fn flush(&self) -> Result<(), BlockError>;
+}
+
+/// Requests a disk sent to its device and how its blocks fared in the cache.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
+pub struct Stats {
+    pub reads: u64,
+    pub blocks_read: u64,
+    pub writes: u64,
+    pub blocks_written: u64,
+    pub flushes: u64,
+    /// Requests saved by the cache joining adjacent blocks into one.
+    pub merged: u64,
+    /// Blocks read from the cache.
+    pub hits: u64,
+    /// Blocks read from the device, as they weren't cached.
+    pub misses: u64,
+    /// Requests the device failed.
+    pub errors: u64
+}
+
+/// A registered device, read and written through the buffer cache.
+///
+/// Writes stay in the cache until they're [synced](Disk::sync), evicted or flushed.
+pub struct Disk {
+    id: u64,
+    device: Arc<dyn BlockDevice>,
+    stats: Mutex<Stats>,
+    /// Held while blocks are written back, so one write-back can't overtake another.
+    writing: Mutex<()>
+}
+
+impl Disk {
+    /// Provides the driver's device, which bypasses the cache.
+    pub fn device(&self) -> &Arc<dyn BlockDevice> {
+        &self.device
+    }
+
+    pub fn stats(&self) -> Stats {
+        *self.stats.lock()
+    }
+
+    /// Counts the blocks in the cache, and how many of them are dirty.
+    pub fn cached(&self) -> (usize, usize) {
+        cache::blocks(self)
+    }
+
+    /// Writes back the dirty blocks in the cache, without flushing the device's own cache.
+    pub fn sync(&self) -> Result<(), BlockError> {
+        cache::sync(self)
+    }
+
+    /// Reads from the device, counting the request.
+    fn read_device(&self, block: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+        let result = self.device.read(block, buffer);
+        let blocks = (buffer.len() / self.block_size()) as u64;
+        let mut stats = self.stats.lock();
+        stats.reads += 1;
+        stats.blocks_read += blocks;
+        stats.errors += result.is_err() as u64;
+        result
+    }
+
+    /// Writes to the device, counting the request.
+    fn write_device(&self, block: u64, buffer: &[u8]) -> Result<(), BlockError> {
+        let result = self.device.write(block, buffer);
+        let blocks = (buffer.len() / self.block_size()) as u64;
+        let mut stats = self.stats.lock();
+        stats.writes += 1;
+        stats.blocks_written += blocks;
+        stats.errors += result.is_err() as u64;
+        result
+    }
+}
+
+impl BlockDevice for Disk {
+    fn name(&self) -> &str {
+        self.device.name()
+    }
+
+    fn block_size(&self) -> usize {
+        self.device.block_size()
+    }
+
+    fn blocks(&self) -> u64 {
+        self.device.blocks()
+    }
+
+    fn read_only(&self) -> bool {
+        self.device.read_only()
+    }
+
+    fn read(&self, block: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+        check_request(self, block, buffer.len())?;
+        cache::read(self, block, buffer)
+    }
+
+    fn write(&self, block: u64, buffer: &[u8]) -> Result<(), BlockError> {
+        check_request(self, block, buffer.len())?;
+        if self.read_only() {
+            return Err(BlockError::ReadOnly);
+        }
+        cache::write(self, block, buffer)
+    }
+
+    /// Writes back the dirty blocks and has the device make them persistent.
+    fn flush(&self) -> Result<(), BlockError> {
+        self.sync()?;
+        let result = self.device.flush();
+        let mut stats = self.stats.lock();
+        stats.flushes += 1;
+        stats.errors += result.is_err() as u64;
+        result
+    }
+}
+
+/// Checks that a request of `length` bytes at `block` lies within a device and covers whole blocks.
+pub fn check_request(device: &dyn BlockDevice, block: u64, length: usize) -> Result<(), BlockError> {
+    if !length.is_multiple_of(device.block_size()) {
+        return Err(BlockError::UnalignedBuffer);
+    }
+    let count = (length / device.block_size()) as u64;
+    match block.checked_add(count) {
+        Some(end) if end <= device.blocks() => Ok(()),
+        _ => Err(BlockError::OutOfRange)
+    }
+}
+
+/// Finds the first free name with a prefix, e.g. "vdb" if "vda" is taken.
+pub fn next_name(prefix: &str) -> String {
+    let devices = DEVICES.lock();
+    let letter = (b'a'..=b'z')
+        .map(|letter| letter as char)
+        .find(|&letter| {
+            !devices.iter().any(|device| {
+                device.name().strip_prefix(prefix).is_some_and(|rest| rest.len() == 1 && rest.starts_with(letter))
+            })
+        })
+        .expect("there should be a free device name");
+    let mut name = String::from(prefix);
+    name.push(letter);
+    name
+}
+
+/// Adds a device, replacing any with the same name.
+pub fn register(device: Arc<dyn BlockDevice>) {
+    let disk = Arc::new(Disk {
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        device,
+        stats: Mutex::new(Stats::default()),
+        writing: Mutex::new(())
+    });
+    cache::attach(disk.clone());
+    let replaced = {
+        let mut devices = DEVICES.lock();
+        let replaced = devices.iter().position(|registered| registered.name() == disk.name()).map(|i| devices.remove(i));
+        devices.push(disk);
+        replaced
+    };
+    if let Some(replaced) = replaced {
+        let _ = cache::detach(&replaced);
+    }
+}
+
+/// Removes a device, returning `false` if there isn't one with the name.
+///
+/// Its dirty blocks are written back first, as drivers unregister disks before letting go of them.
+pub fn unregister(name: &str) -> bool {
+    let removed = {
+        let mut devices = DEVICES.lock();
+        devices.iter().position(|disk| disk.name() == name).map(|i| devices.remove(i))
+    };
+    match removed {
+        Some(disk) => {
+            let _ = cache::detach(&disk);
+            true
+        }
+        None => false
+    }
+}
+
+/// Provides every device, in the order they were registered.
+pub fn devices() -> Vec<Arc<dyn BlockDevice>> {
+    DEVICES.lock().iter().map(|disk| disk.clone() as Arc<dyn BlockDevice>).collect()
+}
+
+/// Finds a device by name.
+pub fn find(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    disk(name).map(|disk| disk as Arc<dyn BlockDevice>)
+}
+
+/// Provides every disk, in the order they were registered.
+pub fn disks() -> Vec<Arc<Disk>> {
+    DEVICES.lock().clone()
+}
+
+/// Finds a disk by name.
+pub fn disk(name: &str) -> Option<Arc<Disk>> {
+    DEVICES.lock().iter().find(|disk| disk.name() == name).cloned()
+}
+
+/// Writes back the dirty blocks of every disk, returning the first error.
+pub fn sync() -> Result<(), BlockError> {
+    disks().iter().map(|disk| disk.sync()).fold(Ok(()), Result::and)
+}
@@ -0,0 +1,279 @@
+//! The driver model, binding the devices found on each bus to the drivers that handle them.
+//!
+//! Drivers describe the devices they handle with a table of [matches](Match), and are handed
+//! each matching device in turn by [`probe`](Driver::probe) until one accepts it. Devices and
+//! drivers can be added in any order, with whichever comes second triggering the probe. The
+//! built-in drivers are listed in [`BUILTIN`](BUILTIN) and registered by [`init`](init).
+//!
+//! Every device is named after where it was found, e.g. `pci/0000:00:03.0`, `acpi/PNP0501:00`
+//! or `isa/3f8`, so names stay the same across boots on the same machine.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+use crate::{acpi, ata, isa, nvme, pci, virtio};
+use crate::serial_println;
+
+/// The drivers registered by [`init`](init).
+const BUILTIN: &[&Driver] = &[
+    &ata::ahci::DRIVER,
+    &ata::pio::DRIVER,
+    &nvme::DRIVER,
+    &virtio::blk::DRIVER
+];
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry { devices: Vec::new(), drivers: Vec::new() });
+
+/// Why a driver declined a device.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum ProbeError {
+    /// The device matched, but turned out to be a model or mode the driver can't handle.
+    Unsupported,
+    /// A BAR, port or interrupt the driver needs is missing.
+    MissingResource,
+    /// The device didn't respond in time.
+    Timeout,
+    /// The device reported an error while being set up.
+    Failed
+}
+
+/// Where a device was found.
+#[derive(Debug, Clone)]
+pub enum Bus {
+    Pci(&'static pci::Device),
+    Acpi(&'static acpi::Device),
+    Isa(&'static isa::Device)
+}
+
+/// A device that can be bound to a driver.
+#[derive(Debug)]
+pub struct Device {
+    name: String,
+    bus: Bus
+}
+
+impl Device {
+    /// Names a device after where it was found.
+    fn new(bus: Bus) -> Self {
+        let name = match &bus {
+            Bus::Pci(device) => format!("pci/{}", device.address),
+            Bus::Acpi(device) => format!("acpi/{}:{:02}", device.hid, device.instance),
+            Bus::Isa(device) => format!("isa/{:x}", device.port)
+        };
+        Self { name, bus }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn bus(&self) -> &Bus {
+        &self.bus
+    }
+
+    /// Provides the PCI function, if the device is one.
+    pub fn pci(&self) -> Option<&'static pci::Device> {
+        match self.bus {
+            Bus::Pci(device) => Some(device),
+            _ => None
+        }
+    }
+}
+
+/// Describes devices a driver handles.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Match {
+    /// A PCI function with a vendor and device ID.
+    Pci { vendor_id: u16, device_id: u16 },
+    /// A PCI function with a class and subclass, and a programming interface unless it's `None`.
+    PciClass { class: u8, subclass: u8, prog_if: Option<u8> },
+    /// An ACPI device with a hardware ID, e.g. "PNP0501".
+    Acpi(&'static str),
+    /// A legacy ISA device at its first I/O port.
+    Isa(u16)
+}
+
+impl Match {
+    pub fn matches(&self, device: &Device) -> bool {
+        match (*self, &device.bus) {
+            (Match::Pci { vendor_id, device_id }, Bus::Pci(device)) => {
+                device.vendor_id == vendor_id && device.device_id == device_id
+            }
+            (Match::PciClass { class, subclass, prog_if }, Bus::Pci(device)) => {
+                device.class == class && device.subclass == subclass
+                    && prog_if.is_none_or(|prog_if| device.prog_if == prog_if)
+            }
+            (Match::Acpi(hid), Bus::Acpi(device)) => device.hid == hid,
+            (Match::Isa(port), Bus::Isa(device)) => device.port == port,
+            _ => false
+        }
+    }
+}
+
+/// A driver, with callbacks for taking over and releasing devices.
+#[derive(Debug)]
+pub struct Driver {
+    pub name: &'static str,
+    pub matches: &'static [Match],
+    /// Takes over a matching device, or declines it so other drivers can try.
+    pub probe: fn(&Arc<Device>) -> Result<(), ProbeError>,
+    /// Releases a device the driver took over, when either of them goes away.
+    pub remove: fn(&Arc<Device>)
+}
+
+impl Driver {
+    fn matches(&self, device: &Device) -> bool {
+        self.matches.iter().any(|entry| entry.matches(device))
+    }
+}
+
+struct Entry {
+    device: Arc<Device>,
+    driver: Option<&'static Driver>
+}
+
+struct Registry {
+    /// Every device, in the order they were added.
+    devices: Vec<Entry>,
+    drivers: Vec<&'static Driver>
+}
+
+/// Adds the devices found on every bus and registers the built-in drivers, returning how many
+/// devices are bound.
+///
+/// Must be called after the buses are scanned. Drivers may rely on interrupts and clocks.
+pub fn init() -> usize {
+    for device in pci::devices() {
+        add_device(Bus::Pci(device));
+    }
+    for device in acpi::devices() {
+        add_device(Bus::Acpi(device));
+    }
+    for device in isa::devices() {
+        add_device(Bus::Isa(device));
+    }
+    for driver in BUILTIN {
+        register(driver);
+    }
+    REGISTRY.lock().devices.iter().filter(|entry| entry.driver.is_some()).count()
+}
+
+/// Adds a device and probes the drivers matching it, returning the device.
+///
+/// A device that was already added is returned as is.
+pub fn add_device(bus: Bus) -> Arc<Device> {
+    let device = Device::new(bus);
+    let (device, drivers) = {
+        let mut registry = REGISTRY.lock();
+        if let Some(entry) = registry.devices.iter().find(|entry| entry.device.name == device.name) {
+            return entry.device.clone();
+        }
+
+        let device = Arc::new(device);
+        registry.devices.push(Entry { device: device.clone(), driver: None });
+        let drivers: Vec<&'static Driver> = registry.drivers.iter().copied().filter(|driver| driver.matches(&device)).collect();
+        (device, drivers)
+    };
+
+    for driver in drivers {
+        if bind(&device, driver) {
+            break;
+        }
+    }
+    device
+}
+
+/// Unbinds and removes a device, returning `false` if there isn't one with the name.
+pub fn remove_device(name: &str) -> bool {
+    let entry = {
+        let mut registry = REGISTRY.lock();
+        let Some(index) = registry.devices.iter().position(|entry| entry.device.name == name) else {
+            return false;
+        };
+        registry.devices.remove(index)
+    };
+    if let Some(driver) = entry.driver {
+        (driver.remove)(&entry.device);
+    }
+    true
+}
+
+/// Registers a driver and probes it with the unbound devices it matches, returning how many it took.
+///
+/// Registering a driver again, or one with the same name as another, does nothing.
+pub fn register(driver: &'static Driver) -> usize {
+    let devices: Vec<Arc<Device>> = {
+        let mut registry = REGISTRY.lock();
+        if registry.drivers.iter().any(|registered| registered.name == driver.name) {
+            return 0;
+        }
+        registry.drivers.push(driver);
+        registry.devices.iter()
+            .filter(|entry| entry.driver.is_none() && driver.matches(&entry.device))
+            .map(|entry| entry.device.clone())
+            .collect()
+    };
+
+    devices.iter().filter(|device| bind(device, driver)).count()
+}
+
+/// Unregisters a driver, releasing every device it took, and returns how many those were.
+pub fn unregister(driver: &'static Driver) -> usize {
+    let devices: Vec<Arc<Device>> = {
+        let mut registry = REGISTRY.lock();
+        registry.drivers.retain(|registered| registered.name != driver.name);
+        registry.devices.iter_mut()
+            .filter(|entry| entry.driver.is_some_and(|bound| bound.name == driver.name))
+            .map(|entry| {
+                entry.driver = None;
+                entry.device.clone()
+            })
+            .collect()
+    };
+
+    for device in &devices {
+        (driver.remove)(device);
+    }
+    devices.len()
+}
+
+/// Provides every device, in the order they were added, along with the name of its driver.
+pub fn devices() -> Vec<(Arc<Device>, Option<&'static str>)> {
+    REGISTRY.lock().devices.iter()
+        .map(|entry| (entry.device.clone(), entry.driver.map(|driver| driver.name)))
+        .collect()
+}
+
+/// Finds a device by name.
+pub fn find(name: &str) -> Option<Arc<Device>> {
+    REGISTRY.lock().devices.iter().find(|entry| entry.device.name == name).map(|entry| entry.device.clone())
+}
+
+/// Provides the name of the driver a device is bound to.
+pub fn driver_of(name: &str) -> Option<&'static str> {
+    REGISTRY.lock().devices.iter().find(|entry| entry.device.name == name)?.driver.map(|driver| driver.name)
+}
+
+/// Probes a driver with a device, binding them if it accepts and the device is still unbound.
+fn bind(device: &Arc<Device>, driver: &'static Driver) -> bool {
+    // probed without the lock, since drivers may add devices of their own
+    if let Err(error) = (driver.probe)(device) {
+        serial_println!("[tokyo] {} declined {}: {:?}", driver.name, device.name, error);
+        return false;
+    }
+
+    let mut registry = REGISTRY.lock();
+    match registry.devices.iter_mut().find(|entry| Arc::ptr_eq(&entry.device, device)) {
+        Some(entry) if entry.driver.is_none() => {
+            entry.driver = Some(driver);
+            true
+        }
+        _ => {
+            drop(registry);
+            (driver.remove)(device); // removed or taken by another driver while probing
+            false
+        }
+    }
+}
@@ -0,0 +1,551 @@
+//! A GDB Remote Serial Protocol stub listening on the second serial port (COM2).
+//!
+//! The stub takes control of the CPU whenever a breakpoint (`int3`) is hit, a single step
+//! finishes, or GDB sends data on COM2 while the kernel is running. It supports register and
+//! memory access, software breakpoints and single-stepping.
+//!
+//! The QEMU runners expose COM2 on TCP port 1235, so a debugger can attach with:
+//!
+//! ```text
+//! (gdb) target remote localhost:1235
+//! ```
+
+mod packet;
+
+use core::arch::global_asm;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::{Mutex, Once};
+use x86_64::registers::control::{Cr0, Cr0Flags};
+use x86_64::structures::paging::Translate;
+use x86_64::VirtAddr;
+use crate::gdb::packet::{Buffer, PACKET_SIZE};
+use crate::idt::{self, InterruptIndex, PICS};
+use crate::mem;
+use crate::serial::SERIAL2;
+
+const MAX_BREAKPOINTS: usize = 32;
+
+/// Trap flag in RFLAGS, raising a debug exception after every instruction.
+const TRAP_FLAG: u64 = 1 << 8;
+
+const VECTOR_DEBUG: u64 = 1;
+const VECTOR_BREAKPOINT: u64 = 3;
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+// Interrupt entry points that save every general purpose register before calling into Rust,
+// since the `x86-interrupt` ABI only exposes the interrupt stack frame.
+//
+// The CPU aligns the stack to 16 bytes before pushing its 5-word frame, and the vector,
+// error code and 15 registers pushed here keep that alignment for the call.
+global_asm!(
+    ".global gdb_debug_entry",
+    "gdb_debug_entry:",
+    "    push 0",
+    "    push {debug}",
+    "    jmp 2f",
+    ".global gdb_breakpoint_entry",
+    "gdb_breakpoint_entry:",
+    "    push 0",
+    "    push {breakpoint}",
+    "    jmp 2f",
+    ".global gdb_serial_entry",
+    "gdb_serial_entry:",
+    "    push 0",
+    "    push {serial}",
+    "2:",
+    "    push rax",
+    "    push rbx",
+    "    push rcx",
+    "    push rdx",
+    "    push rsi",
+    "    push rdi",
+    "    push rbp",
+    "    push r8",
+    "    push r9",
+    "    push r10",
+    "    push r11",
+    "    push r12",
+    "    push r13",
+    "    push r14",
+    "    push r15",
+    "    mov rdi, rsp",
+    "    cld",
+    "    call {trap}",
+    "    pop r15",
+    "    pop r14",
+    "    pop r13",
+    "    pop r12",
+    "    pop r11",
+    "    pop r10",
+    "    pop r9",
+    "    pop r8",
+    "    pop rbp",
+    "    pop rdi",
+    "    pop rsi",
+    "    pop rdx",
+    "    pop rcx",
+    "    pop rbx",
+    "    pop rax",
+    "    add rsp, 16",
+    "    iretq",
+    debug = const VECTOR_DEBUG,
+    breakpoint = const VECTOR_BREAKPOINT,
+    serial = const InterruptIndex::Com2 as u8,
+    trap = sym trap
+);
+
+extern "C" {
+    fn gdb_debug_entry();
+    fn gdb_breakpoint_entry();
+    fn gdb_serial_entry();
+}
+
+/// Handler address for the debug exception (single-stepping).
+pub(crate) fn debug_entry() -> VirtAddr {
+    VirtAddr::from_ptr(gdb_debug_entry as *const ())
+}
+
+/// Handler address for the breakpoint exception.
+pub(crate) fn breakpoint_entry() -> VirtAddr {
+    VirtAddr::from_ptr(gdb_breakpoint_entry as *const ())
+}
+
+/// Handler address for the COM2 interrupt, used to break into a running kernel.
+pub(crate) fn serial_entry() -> VirtAddr {
+    VirtAddr::from_ptr(gdb_serial_entry as *const ())
+}
+
+/// The register state saved by the entry points, in stack order.
+#[repr(C)]
+#[derive(Debug)]
+struct TrapFrame {
+    r15: u64,
+    r14: u64,
+    r13: u64,
+    r12: u64,
+    r11: u64,
+    r10: u64,
+    r9: u64,
+    r8: u64,
+    rbp: u64,
+    rdi: u64,
+    rsi: u64,
+    rdx: u64,
+    rcx: u64,
+    rbx: u64,
+    rax: u64,
+    vector: u64,
+    error_code: u64,
+    rip: u64,
+    cs: u64,
+    rflags: u64,
+    rsp: u64,
+    ss: u64
+}
+
+impl TrapFrame {
+    /// Number of registers in GDB's amd64 core register set.
+    const REGISTER_COUNT: usize = 24;
+
+    /// Provides a register by its GDB number, along with its size in bytes.
+    ///
+    /// The data segment registers are flat in long mode and are reported as zero.
+    fn register(&mut self, n: usize) -> Option<(&mut u64, usize)> {
+        let reg = match n {
+            0 => &mut self.rax,
+            1 => &mut self.rbx,
+            2 => &mut self.rcx,
+            3 => &mut self.rdx,
+            4 => &mut self.rsi,
+            5 => &mut self.rdi,
+            6 => &mut self.rbp,
+            7 => &mut self.rsp,
+            8 => &mut self.r8,
+            9 => &mut self.r9,
+            10 => &mut self.r10,
+            11 => &mut self.r11,
+            12 => &mut self.r12,
+            13 => &mut self.r13,
+            14 => &mut self.r14,
+            15 => &mut self.r15,
+            16 => &mut self.rip,
+            17 => return Some((&mut self.rflags, 4)),
+            18 => return Some((&mut self.cs, 4)),
+            19 => return Some((&mut self.ss, 4)),
+            _ => return None
+        };
+        Some((reg, 8))
+    }
+}
+
+static PHYSICAL_OFFSET: Once<VirtAddr> = Once::new();
+
+static STUB: Mutex<Stub> = Mutex::new(Stub::new());
+
+/// Set once GDB has sent data to the stub, until it detaches or kills the session.
+static ATTACHED: AtomicBool = AtomicBool::new(false);
+
+/// Enables the stub, allowing GDB to break into the running kernel over COM2.
+///
+/// Must be called after the PICs have been initialized.
+pub fn init(physical_offset: VirtAddr) {
+    PHYSICAL_OFFSET.call_once(|| physical_offset);
+
+    SERIAL2.lock(); // initialize the port, enabling its receive interrupt
+
+    idt::unmask(InterruptIndex::Com2);
+}
+
+/// Returns whether the stub has been enabled.
+pub fn is_enabled() -> bool {
+    PHYSICAL_OFFSET.is_completed()
+}
+
+/// Returns whether a debugger is attached to the stub.
+pub fn is_attached() -> bool {
+    ATTACHED.load(Ordering::Relaxed)
+}
+
+extern "C" fn trap(frame: &mut TrapFrame) {
+    let mut stub = STUB.lock();
+
+    let (signal, started) = match frame.vector {
+        VECTOR_BREAKPOINT => {
+            // rewind to the breakpoint address so GDB sees where it stopped
+            let addr = frame.rip.wrapping_sub(1);
+            if stub.breakpoints.contains(addr) {
+                frame.rip = addr;
+                stub.swbreak = true;
+            }
+            (SIGTRAP, false)
+        }
+        VECTOR_DEBUG => (SIGTRAP, false),
+        _ => {
+            let byte = SERIAL2.lock().receive();
+            unsafe { PICS.lock().notify_end_of_interrupt(InterruptIndex::Com2 as u8); }
+
+            let stop = match byte {
+                0x03 => (SIGINT, false), // break request (ctrl-c)
+                b'$' => (SIGINT, true),  // GDB attaching, or talking to an already stopped target
+                _ => return
+            };
+            ATTACHED.store(true, Ordering::Relaxed);
+            stop
+        }
+    };
+
+    frame.rflags &= !TRAP_FLAG;
+    stub.signal = signal;
+    stub.run(frame, started);
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Breakpoint {
+    addr: u64,
+    original: u8
+}
+
+/// Software breakpoints, patched into memory as `int3` instructions.
+struct Breakpoints([Option<Breakpoint>; MAX_BREAKPOINTS]);
+
+struct Stub {
+    input: Buffer,
+    output: Buffer,
+    breakpoints: Breakpoints,
+    signal: u8,
+    swbreak: bool
+}
+
+impl Stub {
+    const fn new() -> Self {
+        Self {
+            input: Buffer::new(),
+            output: Buffer::new(),
+            breakpoints: Breakpoints([None; MAX_BREAKPOINTS]),
+            signal: SIGTRAP,
+            swbreak: false
+        }
+    }
+
+    /// Serves GDB requests until it resumes execution.
+    fn run(&mut self, frame: &mut TrapFrame, mut started: bool) {
+        let mut port = SERIAL2.lock();
+
+        // a packet in flight means GDB isn't waiting on a stop reply
+        if !started {
+            self.output.clear();
+            stop_reply(&mut self.output, self.signal, self.swbreak);
+            started = packet::send(&mut port, self.output.as_bytes());
+        }
+
+        loop {
+            packet::receive(&mut port, &mut self.input, started);
+
+            self.output.clear();
+            let resume = self.handle(frame);
+            started = packet::send(&mut port, self.output.as_bytes());
+
+            if resume {
+                self.swbreak = false;
+                return;
+            }
+        }
+    }
+
+    /// Handles the packet in the input buffer, returning `true` if execution should resume.
+    ///
+    /// The output buffer is left empty for unsupported packets, as the protocol expects.
+    fn handle(&mut self, frame: &mut TrapFrame) -> bool {
+        let input = self.input.as_bytes();
+        let Some((&command, args)) = input.split_first() else {
+            return false;
+        };
+
+        // resuming doesn't send a reply until the next stop
+        match command {
+            b'c' | b's' => {
+                if let Some(addr) = packet::parse_hex(args) {
+                    frame.rip = addr;
+                }
+                if command == b's' {
+                    frame.rflags |= TRAP_FLAG;
+                }
+                return true;
+            }
+            b'D' | b'k' => {
+                self.breakpoints.clear();
+                ATTACHED.store(false, Ordering::Relaxed);
+                if command == b'D' {
+                    self.output.push_str("OK");
+                }
+                return true;
+            }
+            _ => {}
+        }
+
+        match command {
+            b'?' => stop_reply(&mut self.output, self.signal, self.swbreak),
+            b'g' => {
+                for n in 0..TrapFrame::REGISTER_COUNT {
+                    let (value, size) = frame.register(n).map(|(v, s)| (*v, s)).unwrap_or((0, 4));
+                    self.output.push_hex(&value.to_le_bytes()[..size]);
+                }
+            }
+            b'G' => {
+                let mut bytes = [0u8; PACKET_SIZE / 2];
+                match packet::decode_hex(args, &mut bytes) {
+                    Some(len) => {
+                        let mut offset = 0;
+                        for n in 0..TrapFrame::REGISTER_COUNT {
+                            let size = frame.register(n).map_or(4, |(_, s)| s);
+                            if offset + size > len {
+                                break;
+                            }
+                            // segment selectors can't be changed from here
+                            if n <= 17 {
+                                write_register(frame, n, &bytes[offset..offset + size]);
+                            }
+                            offset += size;
+                        }
+                        self.output.push_str("OK");
+                    }
+                    None => self.output.push_str("E01")
+                }
+            }
+            b'p' => match packet::parse_hex(args) {
+                Some(n) if (n as usize) < TrapFrame::REGISTER_COUNT => {
+                    let (value, size) = frame.register(n as usize).map(|(v, s)| (*v, s)).unwrap_or((0, 4));
+                    self.output.push_hex(&value.to_le_bytes()[..size]);
+                }
+                _ => self.output.push_str("E01")
+            },
+            b'P' => {
+                let mut bytes = [0u8; 8];
+                let parsed = split(args, b'=').and_then(|(n, value)| {
+                    Some((packet::parse_hex(n)? as usize, packet::decode_hex(value, &mut bytes)?))
+                });
+                match parsed {
+                    Some((n, len)) if n <= 17 && frame.register(n).is_some_and(|(_, s)| s == len) => {
+                        write_register(frame, n, &bytes[..len]);
+                        self.output.push_str("OK");
+                    }
+                    _ => self.output.push_str("E01")
+                }
+            }
+            b'm' => match parse_range(args) {
+                Some((addr, len)) if len <= (PACKET_SIZE / 2) as u64 && accessible(addr, len) => {
+                    for i in 0..len {
+                        let byte = self.breakpoints.read_byte(addr + i);
+                        self.output.push_hex(&[byte]);
+                    }
+                }
+                _ => self.output.push_str("E14")
+            },
+            b'M' => {
+                let mut bytes = [0u8; PACKET_SIZE / 2];
+                let parsed = split(args, b':').and_then(|(range, data)| {
+                    Some((parse_range(range)?, packet::decode_hex(data, &mut bytes)?))
+                });
+                match parsed {
+                    Some(((addr, len), decoded)) if len == decoded as u64 && accessible(addr, len) => {
+                        for (i, &byte) in bytes[..decoded].iter().enumerate() {
+                            self.breakpoints.write_byte(addr + i as u64, byte);
+                        }
+                        self.output.push_str("OK");
+                    }
+                    _ => self.output.push_str("E14")
+                }
+            }
+            b'Z' | b'z' => {
+                // only software breakpoints (type 0) are supported
+                let addr = args.strip_prefix(b"0,")
+                    .and_then(|rest| split(rest, b','))
+                    .and_then(|(addr, _kind)| packet::parse_hex(addr));
+                match addr {
+                    Some(addr) if command == b'Z' => match self.breakpoints.insert(addr) {
+                        true => self.output.push_str("OK"),
+                        false => self.output.push_str("E0E")
+                    },
+                    Some(addr) => {
+                        self.breakpoints.remove(addr);
+                        self.output.push_str("OK");
+                    }
+                    None => {}
+                }
+            }
+            b'H' => self.output.push_str("OK"), // there is only one thread
+            b'q' => {
+                if input.starts_with(b"qSupported") {
+                    self.output.push_str("PacketSize=");
+                    self.output.push_hex(&(PACKET_SIZE as u16).to_be_bytes());
+                    self.output.push_str(";swbreak+");
+                } else if input == b"qAttached" {
+                    self.output.push_str("1");
+                }
+            }
+            _ => {}
+        }
+        false
+    }
+}
+
+impl Breakpoints {
+    fn contains(&self, addr: u64) -> bool {
+        self.0.iter().flatten().any(|bp| bp.addr == addr)
+    }
+
+    /// Reads a byte of memory, hiding the `int3` instructions of inserted breakpoints.
+    fn read_byte(&self, addr: u64) -> u8 {
+        match self.0.iter().flatten().find(|bp| bp.addr == addr) {
+            Some(bp) => bp.original,
+            None => unsafe { ptr::read_volatile(addr as *const u8) }
+        }
+    }
+
+    /// Writes a byte of memory, keeping inserted breakpoints in place.
+    fn write_byte(&mut self, addr: u64, byte: u8) {
+        match self.0.iter_mut().flatten().find(|bp| bp.addr == addr) {
+            Some(bp) => bp.original = byte,
+            None => unsafe { poke(addr, byte) }
+        }
+    }
+
+    fn insert(&mut self, addr: u64) -> bool {
+        if self.contains(addr) {
+            return true;
+        }
+        if !accessible(addr, 1) {
+            return false;
+        }
+        match self.0.iter_mut().find(|bp| bp.is_none()) {
+            Some(slot) => {
+                let original = unsafe { ptr::read_volatile(addr as *const u8) };
+                unsafe { poke(addr, 0xCC); } // int3
+                *slot = Some(Breakpoint { addr, original });
+                true
+            }
+            None => false
+        }
+    }
+
+    fn remove(&mut self, addr: u64) {
+        for slot in self.0.iter_mut() {
+            if let Some(bp) = slot.filter(|bp| bp.addr == addr) {
+                unsafe { poke(bp.addr, bp.original); }
+                *slot = None;
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        for slot in self.0.iter_mut() {
+            if let Some(bp) = slot.take() {
+                unsafe { poke(bp.addr, bp.original); }
+            }
+        }
+    }
+}
+
+fn stop_reply(output: &mut Buffer, signal: u8, swbreak: bool) {
+    output.push(b'T');
+    output.push_hex(&[signal]);
+    if swbreak {
+        output.push_str("swbreak:;");
+    }
+}
+
+fn write_register(frame: &mut TrapFrame, n: usize, bytes: &[u8]) {
+    if let Some((reg, size)) = frame.register(n) {
+        let mut value = reg.to_le_bytes();
+        value[..size].copy_from_slice(&bytes[..size]);
+        *reg = u64::from_le_bytes(value);
+    }
+}
+
+/// Splits `bytes` at the first occurrence of `delimiter`.
+fn split(bytes: &[u8], delimiter: u8) -> Option<(&[u8], &[u8])> {
+    let i = bytes.iter().position(|&b| b == delimiter)?;
+    Some((&bytes[..i], &bytes[i + 1..]))
+}
+
+/// Parses an `addr,length` pair.
+fn parse_range(bytes: &[u8]) -> Option<(u64, u64)> {
+    let (addr, len) = split(bytes, b',')?;
+    Some((packet::parse_hex(addr)?, packet::parse_hex(len)?))
+}
+
+/// Checks that every page in the range is mapped, so GDB can't fault the kernel.
+fn accessible(addr: u64, len: u64) -> bool {
+    let Some(&offset) = PHYSICAL_OFFSET.get() else {
+        return false;
+    };
+    if len == 0 {
+        return true;
+    }
+    let Some(end) = addr.checked_add(len - 1) else {
+        return false;
+    };
+    if VirtAddr::try_new(addr).is_err() || VirtAddr::try_new(end).is_err() {
+        return false;
+    }
+
+    let mapper = unsafe { mem::mapper(offset) };
+    mem::page_range(addr, len).all(|page| mapper.translate_addr(page.start_address()).is_some())
+}
+
+/// Writes a byte regardless of page protection, allowing breakpoints in kernel code.
+///
+/// ## Safety
+///
+/// The address must be mapped, and the write must not break anything the kernel relies on.
+unsafe fn poke(addr: u64, byte: u8) {
+    let flags = Cr0::read();
+    unsafe {
+        Cr0::write(flags - Cr0Flags::WRITE_PROTECT);
+        ptr::write_volatile(addr as *mut u8, byte);
+        Cr0::write(flags);
+    }
+}
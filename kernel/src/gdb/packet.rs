@@ -0,0 +1,147 @@
+//! Packet framing and hex encoding for the GDB Remote Serial Protocol.
+
+use uart_16550::SerialPort;
+
+/// The largest packet the stub accepts, advertised to GDB through `qSupported`.
+pub(super) const PACKET_SIZE: usize = 4096;
+
+/// A fixed-size packet buffer, since the stub may run while the heap is unusable.
+pub(super) struct Buffer {
+    data: [u8; PACKET_SIZE],
+    len: usize
+}
+
+impl Buffer {
+    pub(super) const fn new() -> Self {
+        Self { data: [0; PACKET_SIZE], len: 0 }
+    }
+
+    pub(super) fn as_bytes(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    pub(super) fn push(&mut self, byte: u8) {
+        if self.len < PACKET_SIZE {
+            self.data[self.len] = byte;
+            self.len += 1;
+        }
+    }
+
+    pub(super) fn push_str(&mut self, s: &str) {
+        s.bytes().for_each(|b| self.push(b));
+    }
+
+    pub(super) fn push_hex(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.push(HEX[(byte >> 4) as usize]);
+            self.push(HEX[(byte & 0xF) as usize]);
+        }
+    }
+}
+
+const HEX: &[u8; 16] = b"0123456789abcdef";
+
+/// Reads a packet into `buffer`, acknowledging it once the checksum matches.
+///
+/// If `started` is set, the leading `$` has already been consumed by the caller.
+pub(super) fn receive(port: &mut SerialPort, buffer: &mut Buffer, mut started: bool) {
+    loop {
+        // skip acknowledgements and interrupt requests until a packet starts
+        while !started {
+            started = port.receive() == b'$';
+        }
+        started = false;
+
+        buffer.clear();
+        let mut checksum = 0u8;
+        loop {
+            match port.receive() {
+                b'#' => break,
+                b'$' => { // the previous packet was cut short, start over
+                    buffer.clear();
+                    checksum = 0;
+                }
+                byte => {
+                    checksum = checksum.wrapping_add(byte);
+                    buffer.push(byte);
+                }
+            }
+        }
+
+        let expected = hex_digit(port.receive())
+            .zip(hex_digit(port.receive()))
+            .map(|(high, low)| (high << 4) | low);
+
+        if expected == Some(checksum) {
+            port.send_raw(b'+');
+            return;
+        }
+        port.send_raw(b'-');
+    }
+}
+
+/// Sends a packet, retransmitting until GDB acknowledges it.
+///
+/// Returns `true` if GDB started a new packet instead of acknowledging,
+/// in which case the leading `$` has been consumed.
+pub(super) fn send(port: &mut SerialPort, data: &[u8]) -> bool {
+    loop {
+        port.send_raw(b'$');
+        let mut checksum = 0u8;
+        for &byte in data {
+            // escape bytes that carry meaning in the framing
+            let bytes: &[u8] = match byte {
+                b'$' | b'#' | b'}' | b'*' => &[b'}', byte ^ 0x20],
+                _ => core::slice::from_ref(&byte)
+            };
+            for &b in bytes {
+                checksum = checksum.wrapping_add(b);
+                port.send_raw(b);
+            }
+        }
+        port.send_raw(b'#');
+        port.send_raw(HEX[(checksum >> 4) as usize]);
+        port.send_raw(HEX[(checksum & 0xF) as usize]);
+
+        loop {
+            match port.receive() {
+                b'+' => return false,
+                b'$' => return true,
+                b'-' => break, // retransmit
+                _ => {}
+            }
+        }
+    }
+}
+
+pub(super) fn hex_digit(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        _ => None
+    }
+}
+
+/// Parses a big-endian hex number, as used for addresses and lengths.
+pub(super) fn parse_hex(bytes: &[u8]) -> Option<u64> {
+    if bytes.is_empty() || bytes.len() > 16 {
+        return None;
+    }
+    bytes.iter().try_fold(0u64, |acc, &b| Some((acc << 4) | hex_digit(b)? as u64))
+}
+
+/// Decodes a hex string into `out`, returning the number of bytes written.
+pub(super) fn decode_hex(bytes: &[u8], out: &mut [u8]) -> Option<usize> {
+    if bytes.len() % 2 != 0 || bytes.len() / 2 > out.len() {
+        return None;
+    }
+    for (i, pair) in bytes.chunks_exact(2).enumerate() {
+        out[i] = (hex_digit(pair[0])? << 4) | hex_digit(pair[1])?;
+    }
+    Some(bytes.len() / 2)
+}
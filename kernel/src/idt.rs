@@ -1,22 +1,43 @@
 use pic8259::ChainedPics;
 use spin::{Lazy, Mutex};
+use x86_64::instructions::interrupts;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
-use crate::{block_indefinitely, gdt, serial_println};
+use crate::{apic, block_indefinitely, gdb, gdt, irq, keyboard, serial_println, time};
 
 pub(crate) const PIC_OFFSET: u8 = 32;
 
 pub(crate) static PICS: Mutex<ChainedPics> =
     Mutex::new(unsafe { ChainedPics::new_contiguous(PIC_OFFSET) });
 
+/// Points each allocatable vector, given as rows of 16, at a stub dispatching it.
+macro_rules! set_dynamic_handlers {
+    ($idt:ident, $($row:literal)*) => {
+        $( set_dynamic_handlers!(@row $idt, $row, 0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15); )*
+    };
+    (@row $idt:ident, $row:literal, $($column:literal)*) => {
+        $( $idt[$row * 16 + $column].set_handler_fn(dynamic::<{ $row * 16 + $column }>); )*
+    };
+}
+
 pub(crate) static mut IDT: Lazy<InterruptDescriptorTable> = Lazy::new(||  {
     let mut idt = InterruptDescriptorTable::new();
 
+    // vectors allocated at runtime, from irq::FIRST_VECTOR to irq::LAST_VECTOR
+    set_dynamic_handlers!(idt, 0x3 0x4 0x5 0x6 0x7 0x8 0x9 0xA 0xB 0xC 0xD 0xE);
+
     // hardware timer handler
     idt[InterruptIndex::Timer as usize].set_handler_fn(timer);
 
     // keyboard handler
     idt[InterruptIndex::Keyboard as usize].set_handler_fn(keyboard);
 
+    // real-time clock handler
+    idt[InterruptIndex::Rtc as usize].set_handler_fn(rtc);
+
+    // local APIC handlers
+    idt[InterruptIndex::ApicTimer as usize].set_handler_fn(apic_timer);
+    idt[InterruptIndex::ApicSpurious as usize].set_handler_fn(apic_spurious);
+
     unsafe {
         // double fault handler
         idt.double_fault
@@ -26,6 +47,13 @@ pub(crate) static mut IDT: Lazy<InterruptDescriptorTable> = Lazy::new(||  {
 
     idt.page_fault.set_handler_fn(page_fault);
 
+    unsafe {
+        // debugger entry points, which need the full register state
+        idt.debug.set_handler_addr(gdb::debug_entry());
+        idt.breakpoint.set_handler_addr(gdb::breakpoint_entry());
+        idt[InterruptIndex::Com2 as usize].set_handler_addr(gdb::serial_entry());
+    }
+
     idt
 });
 
@@ -35,11 +63,44 @@ pub(crate) fn init() {
     }
 }
 
+/// Enables a hardware interrupt on the PICs, which mask most of them by default.
+pub(crate) fn unmask(index: InterruptIndex) {
+    let irq = index as u8 - PIC_OFFSET;
+    // the handlers take the same lock to acknowledge an interrupt
+    interrupts::without_interrupts(|| unsafe {
+        let mut pics = PICS.lock();
+        let [primary, secondary] = pics.read_masks();
+        if irq < 8 {
+            pics.write_masks(primary & !(1 << irq), secondary);
+        } else {
+            pics.write_masks(primary & !(1 << 2), secondary & !(1 << (irq - 8))); // through the cascade
+        }
+    });
+}
+
+/// Disables a hardware interrupt on the PICs again.
+pub(crate) fn mask(index: InterruptIndex) {
+    let irq = index as u8 - PIC_OFFSET;
+    interrupts::without_interrupts(|| unsafe {
+        let mut pics = PICS.lock();
+        let [primary, secondary] = pics.read_masks();
+        if irq < 8 {
+            pics.write_masks(primary | (1 << irq), secondary);
+        } else {
+            pics.write_masks(primary, secondary | (1 << (irq - 8)));
+        }
+    });
+}
+
 #[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
 #[repr(u8)]
 pub(crate) enum InterruptIndex {
     Timer = PIC_OFFSET,
-    Keyboard
+    Keyboard,
+    Com2 = PIC_OFFSET + 3,
+    Rtc = PIC_OFFSET + 8,
+    ApicTimer = 0xF0,
+    ApicSpurious = 0xFF
 }
 
 macro_rules! eoi {
@@ -61,10 +122,27 @@ extern "x86-interrupt" fn page_fault(frame: InterruptStackFrame, code: PageFault
 }
 
 extern "x86-interrupt" fn timer(_frame: InterruptStackFrame) {
+    time::tick();
     eoi!(Timer);
 }
 
 extern "x86-interrupt" fn keyboard(_frame: InterruptStackFrame) {
+    keyboard::interrupt();
     eoi!(Keyboard);
 }
 
+extern "x86-interrupt" fn rtc(_frame: InterruptStackFrame) {
+    time::rtc::interrupt();
+    eoi!(Rtc);
+}
+
+extern "x86-interrupt" fn apic_timer(_frame: InterruptStackFrame) {
+    // only wakes the CPU from tickless idle
+    apic::eoi();
+}
+
+extern "x86-interrupt" fn dynamic<const VECTOR: u8>(_frame: InterruptStackFrame) {
+    irq::dispatch(VECTOR);
+}
+
+extern "x86-interrupt" fn apic_spurious(_frame: InterruptStackFrame) {}
@@ -0,0 +1,69 @@
+//! Interrupt vectors allocated at runtime, for devices that can be told which one to raise.
+//!
+//! The vectors between the PICs and the local APIC's own are handed out one at a time, each with
+//! its own handler, so a device using [MSI or MSI-X](crate::pci::msi) never shares a vector. The
+//! fixed vectors of the PICs and the local APIC are listed by `InterruptIndex` instead.
+
+use alloc::boxed::Box;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use crate::apic;
+
+/// The first vector that can be allocated, right after those of the PICs.
+pub const FIRST_VECTOR: u8 = 0x30;
+/// The last vector that can be allocated, right before the local APIC timer.
+pub const LAST_VECTOR: u8 = 0xEF;
+
+const COUNT: usize = (LAST_VECTOR - FIRST_VECTOR) as usize + 1;
+
+type Handler = Box<dyn Fn() + Send + Sync>;
+
+static HANDLERS: Mutex<[Option<Handler>; COUNT]> = Mutex::new([const { None }; COUNT]);
+
+/// An allocated interrupt vector.
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct Vector(u8);
+
+impl Vector {
+    pub fn number(self) -> u8 {
+        self.0
+    }
+}
+
+/// Allocates the lowest free vector to run `handler`, returning `None` if every one is in use.
+///
+/// The handler runs in interrupt context, so it must not block, allocate or change handlers.
+/// The local APIC is signalled the end of the interrupt once it returns.
+pub fn allocate<F: Fn() + Send + Sync + 'static>(handler: F) -> Option<Vector> {
+    let handler: Handler = Box::new(handler);
+    interrupts::without_interrupts(|| {
+        let mut handlers = HANDLERS.lock();
+        let index = handlers.iter().position(Option::is_none)?;
+        handlers[index] = Some(handler);
+        Some(Vector(FIRST_VECTOR + index as u8))
+    })
+}
+
+/// Frees a vector, returning `false` if it wasn't allocated.
+///
+/// Whatever raises the vector has to be stopped first, since it's ignored afterwards.
+pub fn free(vector: Vector) -> bool {
+    let handler = interrupts::without_interrupts(|| {
+        HANDLERS.lock()[(vector.0 - FIRST_VECTOR) as usize].take()
+    });
+    handler.is_some() // dropped with interrupts enabled
+}
+
+/// Provides how many vectors are allocated.
+pub fn allocated() -> usize {
+    interrupts::without_interrupts(|| HANDLERS.lock().iter().filter(|handler| handler.is_some()).count())
+}
+
+/// Runs the handler of an allocated vector, called by the interrupt stubs.
+pub(crate) fn dispatch(vector: u8) {
+    // interrupts are disabled here and whenever the handlers are changed, so the lock is free
+    if let Some(handler) = &HANDLERS.lock()[(vector - FIRST_VECTOR) as usize] {
+        handler();
+    }
+    apic::eoi();
+}
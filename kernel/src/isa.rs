@@ -0,0 +1,77 @@
+//! Legacy ISA devices, which can't be enumerated and are looked for at their standard ports.
+//!
+//! Only devices without a PCI function of their own are listed, so the IDE controller of the
+//! chipset, whose legacy ports belong to its PCI function, isn't. Devices the ACPI tables declare
+//! are left to ACPI, so each shows up once.
+
+use alloc::vec::Vec;
+use spin::Once;
+use x86_64::instructions::port::Port;
+use crate::acpi;
+
+/// Offset of the scratch register of a serial port, which holds whatever is written to it.
+const SERIAL_SCRATCH: u16 = 7;
+
+/// Devices every PC has, ordered by port, with those that may be missing checked for by `present`.
+const LEGACY: &[Device] = &[
+    Device { name: "pit", port: 0x40, irq: 0, acpi: ("PNP0100", 0) },
+    Device { name: "keyboard", port: 0x60, irq: 1, acpi: ("PNP0303", 0) },
+    Device { name: "rtc", port: 0x70, irq: 8, acpi: ("PNP0B00", 0) },
+    Device { name: "com4", port: 0x2E8, irq: 3, acpi: ("PNP0501", 3) },
+    Device { name: "com2", port: 0x2F8, irq: 3, acpi: ("PNP0501", 1) },
+    Device { name: "com3", port: 0x3E8, irq: 4, acpi: ("PNP0501", 2) },
+    Device { name: "com1", port: 0x3F8, irq: 4, acpi: ("PNP0501", 0) }
+];
+
+static DEVICES: Once<Vec<Device>> = Once::new();
+
+/// A device at fixed I/O ports.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Device {
+    pub name: &'static str,
+    /// The first I/O port used.
+    pub port: u16,
+    /// The PIC line the device interrupts on.
+    pub irq: u8,
+    /// The hardware ID ACPI would declare the device with, and its instance, assuming the
+    /// serial ports are declared in order.
+    pub acpi: (&'static str, usize)
+}
+
+impl Device {
+    /// Whether the ACPI tables declare the device, which makes it an ACPI device instead.
+    fn declared(&self) -> bool {
+        let (hid, instance) = self.acpi;
+        acpi::devices().iter().any(|device| device.hid == hid && device.instance == instance)
+    }
+
+    fn present(&self) -> bool {
+        if !self.name.starts_with("com") {
+            return true;
+        }
+
+        // the scratch register keeps a value only if a UART is there
+        let mut scratch = Port::<u8>::new(self.port + SERIAL_SCRATCH);
+        unsafe {
+            let saved = scratch.read();
+            scratch.write(0x5A);
+            let present = scratch.read() == 0x5A;
+            scratch.write(saved);
+            present
+        }
+    }
+}
+
+/// Looks for the legacy devices ACPI doesn't declare, returning how many are present.
+///
+/// Must be called after the ACPI tables are parsed.
+pub fn init() -> usize {
+    DEVICES.call_once(|| {
+        LEGACY.iter().copied().filter(|device| !device.declared() && device.present()).collect()
+    }).len()
+}
+
+/// Provides every device present, ordered by port.
+pub fn devices() -> &'static [Device] {
+    DEVICES.get().map_or(&[], |devices| devices.as_slice())
+}
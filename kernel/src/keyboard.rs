@@ -0,0 +1,123 @@
+//! PS/2 keyboard input.
+//!
+//! The interrupt handler only queues raw scancodes, which are decoded into key presses
+//! when read through [`next_key`](next_key) outside of interrupt context.
+
+use pc_keyboard::{layouts, DecodedKey, HandleControl, KeyCode, KeyState, Keyboard, ScancodeSet1};
+use spin::{Lazy, Mutex};
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+use crate::idt::{self, InterruptIndex};
+
+const DATA_PORT: u16 = 0x60;
+
+/// Scancodes held before further ones are dropped.
+const QUEUE_SIZE: usize = 128;
+
+static SCANCODES: Mutex<Queue> = Mutex::new(Queue::new());
+
+static DECODER: Lazy<Mutex<Decoder>> = Lazy::new(|| Mutex::new(Decoder {
+    keyboard: Keyboard::new(ScancodeSet1::new(), layouts::Us104Key, HandleControl::Ignore),
+    alt: false,
+    ctrl: false
+}));
+
+/// A key being pressed.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct KeyPress {
+    pub code: KeyCode,
+    /// The character or key produced according to the layout, if any.
+    pub key: Option<DecodedKey>,
+    /// Whether either alt key is held down.
+    pub alt: bool,
+    /// Whether either control key is held down.
+    pub ctrl: bool
+}
+
+/// Enables the keyboard interrupt.
+///
+/// Must be called after the PICs have been initialized.
+pub fn init() {
+    idt::unmask(InterruptIndex::Keyboard);
+}
+
+/// Queues the scancode that raised the keyboard interrupt.
+pub(crate) fn interrupt() {
+    let scancode = unsafe { Port::<u8>::new(DATA_PORT).read() };
+    SCANCODES.lock().push(scancode);
+}
+
+/// Decodes queued scancodes until a key is pressed, returning `None` once the queue is empty.
+pub fn next_key() -> Option<KeyPress> {
+    let mut decoder = DECODER.lock();
+    loop {
+        let scancode = interrupts::without_interrupts(|| SCANCODES.lock().pop())?;
+        if let Some(press) = decoder.decode(scancode) {
+            return Some(press);
+        }
+    }
+}
+
+/// Returns whether scancodes are waiting to be decoded.
+///
+/// Call with interrupts disabled before halting, to avoid missing one that arrives right after.
+pub fn has_input() -> bool {
+    interrupts::without_interrupts(|| !SCANCODES.lock().is_empty())
+}
+
+struct Decoder {
+    keyboard: Keyboard<layouts::Us104Key, ScancodeSet1>,
+    alt: bool,
+    ctrl: bool
+}
+
+impl Decoder {
+    fn decode(&mut self, scancode: u8) -> Option<KeyPress> {
+        let event = self.keyboard.add_byte(scancode).ok()??;
+
+        let down = event.state != KeyState::Up;
+        match event.code {
+            KeyCode::LAlt | KeyCode::RAltGr => self.alt = down,
+            KeyCode::LControl | KeyCode::RControl => self.ctrl = down,
+            _ => {}
+        }
+
+        let code = event.code;
+        let key = self.keyboard.process_keyevent(event);
+        down.then_some(KeyPress { code, key, alt: self.alt, ctrl: self.ctrl })
+    }
+}
+
+/// A fixed-size queue of scancodes.
+struct Queue {
+    scancodes: [u8; QUEUE_SIZE],
+    start: usize,
+    len: usize
+}
+
+impl Queue {
+    const fn new() -> Self {
+        Self { scancodes: [0; QUEUE_SIZE], start: 0, len: 0 }
+    }
+
+    fn push(&mut self, scancode: u8) {
+        if self.len < QUEUE_SIZE {
+            self.scancodes[(self.start + self.len) % QUEUE_SIZE] = scancode;
+            self.len += 1;
+        }
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let scancode = self.scancodes[self.start];
+        self.start = (self.start + 1) % QUEUE_SIZE;
+        self.len -= 1;
+        Some(scancode)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
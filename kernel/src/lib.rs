@@ -0,0 +1,108 @@
+#![no_std]
+#![feature(abi_x86_interrupt)]
+#![deny(unsafe_op_in_unsafe_fn)]
+
+pub mod acpi;
+pub mod apic;
+pub mod ata;
+pub mod block;
+pub mod driver;
+pub mod idt;
+pub mod gdt;
+pub mod gdb;
+pub mod irq;
+pub mod isa;
+pub mod keyboard;
+pub mod log;
+pub mod mem;
+pub mod nvme;
+pub mod pci;
+pub mod task;
+pub mod render;
+pub mod serial;
+pub mod shell;
+pub mod terminal;
+pub mod testing;
+pub mod time;
+pub mod virtio;
+
+extern crate alloc; // enable allocation
+
+use bootloader_api::{BootInfo, BootloaderConfig};
+use bootloader_api::config::{Mapping, Mappings};
+use x86_64::{instructions, PhysAddr, VirtAddr};
+use x86_64::instructions::interrupts;
+use crate::idt::PICS;
+use crate::mem::heap::KernelFrameAllocator;
+
+/// The bootloader configuration shared by the kernel and its test binaries.
+pub const CONFIG: BootloaderConfig = {
+    let mut config = BootloaderConfig::new_default();
+    config.mappings = {
+        let mut mappings = Mappings::new_default();
+        mappings.physical_memory = Some(Mapping::Dynamic);
+        mappings
+    };
+    config.kernel_stack_size = 5_000 * 1024; // 5,000 KiB
+    config
+};
+
+/// Brings up memory, firmware tables, buses, rendering, interrupts, clocks and drivers, in that order.
+pub fn init(boot_info: &'static mut BootInfo) {
+    serial_println!("[tokyo] system booted");
+
+    // memory allocation
+    let physical_offset = boot_info.physical_memory_offset.into_option().unwrap();
+    let mut offset_table = unsafe { mem::mapper(VirtAddr::new(physical_offset)) };
+    let mut frame_allocator = unsafe { KernelFrameAllocator::new(&boot_info.memory_regions).unwrap() };
+    mem::heap::init(&mut offset_table, &mut frame_allocator).expect("heap initialization should not fail");
+    mem::set_physical_offset(VirtAddr::new(physical_offset));
+    mem::init_mapping(offset_table, frame_allocator);
+
+    // firmware tables
+    match boot_info.rsdp_addr.into_option().map(|rsdp| unsafe { acpi::init(PhysAddr::new(rsdp)) }) {
+        Some(Ok(count)) => serial_println!("[tokyo] found {} ACPI tables and {} devices", count, acpi::devices().len()),
+        Some(Err(error)) => serial_println!("[tokyo] ACPI tables unavailable: {:?}", error),
+        None => serial_println!("[tokyo] ACPI tables unavailable: no RSDP")
+    }
+
+    // devices
+    let devices = pci::init();
+    serial_println!("[tokyo] found {} PCI devices", devices);
+    let devices = isa::init();
+    serial_println!("[tokyo] found {} ISA devices", devices);
+
+    // framebuffer
+    let frame_buffer = boot_info.framebuffer.as_mut().unwrap();
+    render::init_global_view(frame_buffer);
+    terminal::init(&*render::font::TOKYO);
+
+    gdt::init(); // global descriptor table
+    idt::init(); // interrupt descriptor table
+
+    unsafe { PICS.lock().initialize(); } // programmable interrupt controller
+    time::init(time::DEFAULT_FREQUENCY); // programmable interval timer
+    keyboard::init();
+
+    gdb::init(VirtAddr::new(physical_offset)); // remote debugging over COM2
+
+    interrupts::enable(); // set interrupts
+    time::init_clocks(); // calibrated against the timer interrupt
+    time::init_wall_clock(); // real-time clock
+    time::idle::init(); // local APIC timer
+
+    let bound = driver::init();
+    serial_println!("[tokyo] bound {} devices to drivers", bound);
+}
+
+/// Halts forever, without the tick if interrupts are enabled.
+pub fn block_indefinitely() -> ! {
+    loop {
+        if interrupts::are_enabled() {
+            interrupts::disable();
+            time::idle::halt();
+        } else {
+            instructions::hlt();
+        }
+    }
+}
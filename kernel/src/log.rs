@@ -0,0 +1,127 @@
+//! The kernel log, a ring buffer holding the most recent output of the kernel.
+//!
+//! Everything printed over serial is recorded here too, so the log can be shown on a
+//! terminal without a host attached. Every line starts with the [time](time::now)
+//! at which it was recorded.
+
+use core::fmt::{self, Arguments, Write};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use crate::time;
+
+/// Bytes kept before the oldest output is overwritten.
+pub const LOG_SIZE: usize = 64 * 1024;
+
+static LOG: Mutex<Log> = Mutex::new(Log::new());
+
+/// A fixed-size ring buffer of text.
+struct Log {
+    bytes: [u8; LOG_SIZE],
+    /// Total bytes ever written, which doubles as the position of the next byte.
+    written: u64,
+    /// Whether the next byte starts a line, and so needs a timestamp first.
+    line_start: bool
+}
+
+impl Log {
+    const fn new() -> Self {
+        Self { bytes: [0; LOG_SIZE], written: 0, line_start: true }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.bytes[(self.written % LOG_SIZE as u64) as usize] = byte;
+            self.written += 1;
+        }
+    }
+
+    fn oldest(&self) -> u64 {
+        self.written.saturating_sub(LOG_SIZE as u64)
+    }
+}
+
+impl Write for Log {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for line in s.split_inclusive('\n') {
+            if self.line_start {
+                let now = time::now();
+                write!(Raw(self), "[{:>5}.{:06}] ", now.as_secs(), now.subsec_micros())?;
+            }
+            self.push(line.as_bytes());
+            self.line_start = line.ends_with('\n');
+        }
+        Ok(())
+    }
+}
+
+/// Writes to the log as is, without timestamps.
+struct Raw<'a>(&'a mut Log);
+
+impl Write for Raw<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.push(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Records formatted text in the log.
+pub fn record(args: Arguments) {
+    interrupts::without_interrupts(|| {
+        LOG.lock().write_fmt(args).expect("log should be writable");
+    });
+}
+
+/// Provides the position after the most recent output, for use with [`read`](read).
+pub fn position() -> u64 {
+    interrupts::without_interrupts(|| LOG.lock().written)
+}
+
+/// Passes the output since `position` to `func`, returning the position to continue from.
+///
+/// If output was overwritten since, reading resumes at the oldest output still held.
+/// Characters split by the ring buffer's wrap-around or overwriting are replaced with
+/// [`REPLACEMENT_CHARACTER`](char::REPLACEMENT_CHARACTER).
+pub fn read<F: FnMut(&str)>(position: u64, mut func: F) -> u64 {
+    interrupts::without_interrupts(|| {
+        let log = LOG.lock();
+        let start = position.max(log.oldest());
+        if start >= log.written {
+            return log.written;
+        }
+
+        // the held output is at most two contiguous parts of the buffer
+        let from = (start % LOG_SIZE as u64) as usize;
+        let to = (log.written % LOG_SIZE as u64) as usize;
+        let parts: [&[u8]; 2] = if from < to {
+            [&log.bytes[from..to], &[]]
+        } else {
+            [&log.bytes[from..], &log.bytes[..to]]
+        };
+
+        for part in parts {
+            for chunk in part.utf8_chunks() {
+                func(chunk.valid());
+                if !chunk.invalid().is_empty() {
+                    func("\u{FFFD}");
+                }
+            }
+        }
+        log.written
+    })
+}
+
+/// Prints to the kernel log, without sending it over serial.
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => {
+        $crate::log::record(format_args!($($arg)*))
+    };
+}
+
+/// Prints to the kernel log, appending a newline.
+#[macro_export]
+macro_rules! println {
+    ()                       => { $crate::print!("\n") };
+    ($fmt:expr)              => { $crate::print!(concat!($fmt, "\n")) };
+    ($fmt:expr, $($arg:tt)*) => { $crate::print!(concat!($fmt, "\n"), $($arg)*) };
+}
@@ -1,70 +1,31 @@
 #![no_std]
 #![no_main]
-#![feature(abi_x86_interrupt)]
-#![deny(unsafe_op_in_unsafe_fn)]
-
-pub mod idt;
-pub mod gdt;
-pub mod mem;
-pub mod task;
-pub mod render;
-pub mod serial;
-
-extern crate alloc; // enable allocation
 
 use core::panic::PanicInfo;
-use bootloader_api::{BootInfo, BootloaderConfig};
-use bootloader_api::config::{Mapping, Mappings};
-use x86_64::{instructions, VirtAddr};
-use x86_64::instructions::interrupts;
-use crate::idt::PICS;
-use crate::mem::heap::KernelFrameAllocator;
-
-const CONFIG: BootloaderConfig = {
-    let mut config = BootloaderConfig::new_default();
-    config.mappings = {
-        let mut mappings = Mappings::new_default();
-        mappings.physical_memory = Some(Mapping::Dynamic);
-        mappings
-    };
-    config.kernel_stack_size = 5_000 * 1024; // 5,000 KiB
-    config
-};
+use bootloader_api::BootInfo;
+use x86_64::instructions;
+use kernel::{block_indefinitely, gdb, serial_println, terminal};
 
-bootloader_api::entry_point!(kernel_main, config = &CONFIG);
+bootloader_api::entry_point!(kernel_main, config = &kernel::CONFIG);
 
 fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
-    // TODO: implement acpi
-
-    serial_println!("[tokyo] system booted");
-
-    // memory allocation
-    let physical_offset = boot_info.physical_memory_offset.into_option().unwrap();
-    let mut offset_table = unsafe { mem::mapper(VirtAddr::new(physical_offset)) };
-    let mut frame_allocator = unsafe { KernelFrameAllocator::new(&boot_info.memory_regions).unwrap() };
-    mem::heap::init(&mut offset_table, &mut frame_allocator).expect("heap initialization should not fail");
-
-    // framebuffer
-    let frame_buffer = boot_info.framebuffer.as_mut().unwrap();
-    render::init_global_view(frame_buffer);
+    kernel::init(boot_info);
 
-    gdt::init(); // global descriptor table
-    idt::init(); // interrupt descriptor table
+    #[cfg(feature = "bench")]
+    kernel::render::bench::run();
 
-    unsafe { PICS.lock().initialize(); } // programmable interrupt controller
-
-    interrupts::enable(); // set interrupts
-
-    block_indefinitely();
+    serial_println!("[tokyo] idle"); // checked for by the boot tests
+    terminal::run();
 }
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     serial_println!("[tokyo] {:?}", info);
 
+    // hand the panicked state over to an attached debugger, rather than waiting for one
+    if gdb::is_attached() {
+        instructions::interrupts::int3();
+    }
+
     block_indefinitely();
 }
-
-fn block_indefinitely() -> ! {
-    loop { instructions::hlt(); }
-}
\ No newline at end of file
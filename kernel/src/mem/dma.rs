@@ -0,0 +1,129 @@
+//! Memory shared with devices, which access it by physical address.
+
+use alloc::vec::Vec;
+use core::slice;
+use x86_64::structures::paging::PhysFrame;
+use x86_64::{PhysAddr, VirtAddr};
+use crate::mem;
+
+/// Physically contiguous, zeroed memory for a device to read or write, freed when dropped.
+///
+/// It's accessed through the mapping of all physical memory, which DMA stays coherent with.
+#[derive(Debug)]
+pub struct DmaBuffer {
+    start: PhysFrame,
+    frames: usize
+}
+
+impl DmaBuffer {
+    /// Allocates at least `size` bytes, rounded up to whole frames, returning `None` if there
+    /// isn't enough contiguous memory.
+    pub fn new(size: usize) -> Option<Self> {
+        let frames = size.max(1).div_ceil(4096);
+        let start = mem::allocate_frames(frames)?;
+        let mut buffer = Self { start, frames };
+        buffer.as_mut_slice().fill(0);
+        Some(buffer)
+    }
+
+    /// Provides the physical address devices use.
+    pub fn phys(&self) -> PhysAddr {
+        self.start.start_address()
+    }
+
+    /// Provides the virtual address the kernel uses.
+    pub fn virt(&self) -> VirtAddr {
+        mem::phys_to_virt(self.phys())
+    }
+
+    /// Provides the size in bytes, a multiple of the frame size.
+    pub fn size(&self) -> usize {
+        self.frames * 4096
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.virt().as_ptr(), self.size()) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.virt().as_mut_ptr(), self.size()) }
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        unsafe { mem::deallocate_frames(self.start, self.frames) };
+    }
+}
+
+/// Zeroed frames for a device to read or write, allocated one at a time and freed when dropped.
+///
+/// For devices taking a list of pages, which don't need them to be contiguous.
+#[derive(Debug)]
+pub struct DmaPages {
+    frames: Vec<PhysFrame>
+}
+
+impl DmaPages {
+    /// Allocates `count` frames, returning `None` if there aren't enough.
+    pub fn new(count: usize) -> Option<Self> {
+        let mut pages = Self { frames: Vec::with_capacity(count) };
+        for _ in 0..count {
+            // frames allocated so far are freed by dropping the pages
+            pages.frames.push(mem::allocate_frames(1)?);
+        }
+        for frame in &pages.frames {
+            unsafe { slice::from_raw_parts_mut(mem::phys_to_virt(frame.start_address()).as_mut_ptr::<u8>(), 4096) }.fill(0);
+        }
+        Some(pages)
+    }
+
+    pub fn frames(&self) -> &[PhysFrame] {
+        &self.frames
+    }
+
+    /// Provides the size in bytes, a multiple of the frame size.
+    pub fn size(&self) -> usize {
+        self.frames.len() * 4096
+    }
+
+    /// Copies bytes out of the pages, starting at `offset`.
+    pub fn read(&self, offset: usize, buffer: &mut [u8]) {
+        for (i, address, size) in self.spans(offset, buffer.len()) {
+            buffer[i..i + size].copy_from_slice(unsafe { slice::from_raw_parts(address.as_ptr(), size) });
+        }
+    }
+
+    /// Copies bytes into the pages, starting at `offset`.
+    pub fn write(&mut self, offset: usize, buffer: &[u8]) {
+        for (i, address, size) in self.spans(offset, buffer.len()) {
+            unsafe { slice::from_raw_parts_mut(address.as_mut_ptr(), size) }.copy_from_slice(&buffer[i..i + size]);
+        }
+    }
+
+    /// Splits `length` bytes at `offset` along the frames, giving each part's offset within the
+    /// range, address and size.
+    fn spans(&self, offset: usize, length: usize) -> impl Iterator<Item = (usize, VirtAddr, usize)> + '_ {
+        assert!(offset + length <= self.size(), "range should lie within the pages");
+        let mut done = 0;
+        core::iter::from_fn(move || {
+            if done == length {
+                return None;
+            }
+            let position = offset + done;
+            let address = mem::phys_to_virt(self.frames[position / 4096].start_address()) + (position % 4096) as u64;
+            let size = (4096 - position % 4096).min(length - done);
+            let span = (done, address, size);
+            done += size;
+            Some(span)
+        })
+    }
+}
+
+impl Drop for DmaPages {
+    fn drop(&mut self) {
+        for &frame in &self.frames {
+            unsafe { mem::deallocate_frames(frame, 1) };
+        }
+    }
+}
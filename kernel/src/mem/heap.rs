@@ -1,9 +1,9 @@
 use bitvec::{BitArr, bitarr};
 use bitvec::order::Msb0;
-use bootloader_api::info::{MemoryRegion, MemoryRegionKind, MemoryRegions};
+use bootloader_api::info::{MemoryRegionKind, MemoryRegions};
 use linked_list_allocator::LockedHeap;
 use x86_64::PhysAddr;
-use x86_64::structures::paging::{FrameAllocator, Mapper, PhysFrame, Size4KiB};
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, Mapper, PhysFrame, Size4KiB};
 use x86_64::structures::paging::mapper::MapToError;
 use crate::{mem, serial_println};
 
@@ -32,34 +32,72 @@ pub fn init(
     Ok(())
 }
 
+/// Frames below 1 MiB are left alone, as firmware (UEFI in particular) reports parts of it as usable
+/// while still relying on them, and real mode code such as AP trampolines needs it later.
+const LOW_MEMORY_END: u64 = 0x10_0000;
+
 pub struct KernelFrameAllocator {
-    region: &'static MemoryRegion,
+    start: PhysAddr,
+    frames: usize,
     bitmap: BitArr!(for HEAP_SIZE, in u8, Msb0)
 }
 
 impl KernelFrameAllocator {
+    /// Create an allocator over the first usable region large enough for the heap.
+    ///
+    /// ## Safety
+    ///
+    /// The caller must ensure the regions are accurate, and that only one allocator hands out their frames.
     pub unsafe fn new(regions: &'static MemoryRegions) -> Option<Self> {
-        if let Some(region) = regions
+        // UEFI memory maps aren't sorted, merged or necessarily page-aligned,
+        // so only trust the whole frames within each region
+        if let Some((start, end)) = regions
             .iter()
             .filter(|r| r.kind == MemoryRegionKind::Usable)
-            .find(|r| (r.end - r.start) as usize >= HEAP_SIZE)
+            .map(|r| (PhysAddr::new(r.start.max(LOW_MEMORY_END)).align_up(4096u64), PhysAddr::new(r.end).align_down(4096u64)))
+            .find(|(start, end)| end > start && (*end - *start) as usize >= HEAP_SIZE)
         {
+            let frames = (((end - start) / 4096) as usize).min(HEAP_SIZE);
             return Some(Self {
-                region,
+                start,
+                frames,
                 bitmap: bitarr![u8, Msb0; 0; HEAP_SIZE]
             });
         }
         None
     }
+
+    /// Allocates `count` frames in a row, returning the first.
+    pub fn allocate_contiguous(&mut self, count: usize) -> Option<PhysFrame<Size4KiB>> {
+        let mut i = 0;
+        while i + count <= self.frames {
+            // skip past the last frame in use within the window, if any
+            match self.bitmap[i..i + count].last_one() {
+                Some(used) => i += used + 1,
+                None => {
+                    self.bitmap[i..i + count].fill(true);
+                    return Some(PhysFrame::containing_address(self.start + (i as u64 * 4096)));
+                }
+            }
+        }
+        None
+    }
 }
 
 unsafe impl FrameAllocator<Size4KiB> for KernelFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
-        if let Some(i) = self.bitmap.first_zero() {
-            let addr = PhysAddr::new(self.region.start + (i as u64 * 4096));
+        if let Some(i) = self.bitmap[..self.frames].first_zero() {
+            let addr = self.start + (i as u64 * 4096);
             self.bitmap.set(i, true);
             return Some(PhysFrame::containing_address(addr));
         }
         None
     }
-}
\ No newline at end of file
+}
+
+impl FrameDeallocator<Size4KiB> for KernelFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        let i = ((frame.start_address() - self.start) / 4096) as usize;
+        self.bitmap.set(i, false);
+    }
+}
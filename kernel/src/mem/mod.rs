@@ -1,11 +1,35 @@
+pub mod dma;
 pub mod heap;
 
+use spin::{Mutex, Once};
 use x86_64::registers::control::Cr3;
-use x86_64::structures::paging::{FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, Size4KiB};
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame, Size4KiB};
 use x86_64::structures::paging::mapper::MapToError;
 use x86_64::structures::paging::page::PageRangeInclusive;
-use x86_64::VirtAddr;
+use x86_64::{PhysAddr, VirtAddr};
+use crate::mem::heap::KernelFrameAllocator;
 
+/// Start of the virtual addresses that device memory is mapped to.
+pub const MMIO_START: u64 = 0x_5555_0000_0000;
+
+static PHYSICAL_OFFSET: Once<VirtAddr> = Once::new();
+
+/// The kernel's page table and frame allocator, once the heap is set up.
+static MAPPING: Once<Mutex<Mapping>> = Once::new();
+
+struct Mapping {
+    mapper: OffsetPageTable<'static>,
+    frame_allocator: KernelFrameAllocator,
+    /// The next free address after [`MMIO_START`](MMIO_START).
+    next_mmio: u64
+}
+
+/// Create a mapper for the active level 4 page table.
+///
+/// ## Safety
+///
+/// The caller must ensure all physical memory is mapped at `physical_offset`, and that
+/// mappers created here aren't used to create aliasing page table references.
 pub unsafe fn mapper(physical_offset: VirtAddr) -> OffsetPageTable<'static> {
     let (frame, _) = Cr3::read();
 
@@ -17,6 +41,66 @@ pub unsafe fn mapper(physical_offset: VirtAddr) -> OffsetPageTable<'static> {
     unsafe { OffsetPageTable::new(page_table, physical_offset) }
 }
 
+/// Records where the bootloader mapped all of physical memory, for use with [`phys_to_virt`](phys_to_virt).
+pub fn set_physical_offset(physical_offset: VirtAddr) {
+    PHYSICAL_OFFSET.call_once(|| physical_offset);
+}
+
+/// Provides the virtual address at which a physical address is mapped, including memory-mapped devices.
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    let offset = PHYSICAL_OFFSET.get().expect("physical offset should be set");
+    *offset + addr.as_u64()
+}
+
+/// Keeps the page table and frame allocator the heap was mapped with, for mapping memory later on.
+pub fn init_mapping(mapper: OffsetPageTable<'static>, frame_allocator: KernelFrameAllocator) {
+    MAPPING.call_once(|| Mutex::new(Mapping { mapper, frame_allocator, next_mmio: MMIO_START }));
+}
+
+/// Maps the memory of a device at `address`, uncached, returning where it was mapped.
+///
+/// Each call maps to a new range of virtual addresses, so mapping is meant to happen once per device.
+pub fn map_mmio(address: PhysAddr, size: u64) -> Result<VirtAddr, MapToError<Size4KiB>> {
+    let mut mapping = MAPPING.get().expect("mapping should be initialized").lock();
+    let Mapping { mapper, frame_allocator, next_mmio } = &mut *mapping;
+
+    let start = address.align_down(4096u64);
+    let pages = (address + size.max(1)).align_up(4096u64) - start;
+    let virt = VirtAddr::new(*next_mmio);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH;
+
+    for offset in (0..pages).step_by(4096) {
+        let page = Page::<Size4KiB>::containing_address(virt + offset);
+        let frame = PhysFrame::containing_address(start + offset);
+        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+    }
+
+    *next_mmio += pages + 4096; // an unmapped guard page between devices
+    Ok(virt + (address - start))
+}
+
+/// Allocates `count` physically contiguous frames, returning the first.
+///
+/// The frames stay mapped at [`phys_to_virt`](phys_to_virt) and aren't zeroed.
+pub fn allocate_frames(count: usize) -> Option<PhysFrame> {
+    let mut mapping = MAPPING.get().expect("mapping should be initialized").lock();
+    match count {
+        1 => mapping.frame_allocator.allocate_frame(),
+        _ => mapping.frame_allocator.allocate_contiguous(count)
+    }
+}
+
+/// Frees frames allocated by [`allocate_frames`](allocate_frames).
+///
+/// ## Safety
+/// The frames must have been allocated together, and nothing may use them afterwards.
+pub unsafe fn deallocate_frames(start: PhysFrame, count: usize) {
+    let mut mapping = MAPPING.get().expect("mapping should be initialized").lock();
+    for frame in PhysFrame::range(start, start + count as u64) {
+        unsafe { mapping.frame_allocator.deallocate_frame(frame) };
+    }
+}
+
 pub fn page_range(start: u64, size: u64) -> PageRangeInclusive {
     let start = VirtAddr::new(start);
     let end = start + (size - 1);
@@ -0,0 +1,602 @@
+//! NVMe controllers, such as one attached to QEMU with `-device nvme`.
+//!
+//! Each controller is driven through its admin queues and a single pair of I/O queues, with one
+//! command in flight on each. Data passes through bounce pages allocated frame by frame, which
+//! commands point at with a PRP list when they span more than two. Every active namespace becomes
+//! a block device named after the controller and namespace, e.g. `nvme0n1`.
+//!
+//! A command that times out, or a fatal error, leaves the controller disabled, after which it's
+//! reset and given empty queues again before the next command.
+
+pub mod queue;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::hint;
+use core::ptr;
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::VirtAddr;
+use crate::block::{self, BlockDevice, BlockError};
+use crate::driver::{Device, Driver, Match, ProbeError};
+use crate::irq::Vector;
+use crate::mem::dma::{DmaBuffer, DmaPages};
+use crate::pci::{self, msi};
+use crate::serial_println;
+use crate::time::{self, idle};
+use self::queue::{Command, QueuePair};
+
+pub static DRIVER: Driver = Driver {
+    name: "nvme",
+    matches: &[Match::PciClass { class: 0x01, subclass: 0x08, prog_if: Some(0x02) }],
+    probe,
+    remove
+};
+
+/// The BAR holding the controller's registers.
+const BAR: usize = 0;
+
+// registers of the controller
+const CAPABILITIES: u64 = 0x00;
+const VERSION: u64 = 0x08;
+const INTERRUPT_MASK_SET: u64 = 0x0C;
+const CONFIGURATION: u64 = 0x14;
+const STATUS: u64 = 0x1C;
+const ADMIN_QUEUE_ATTRIBUTES: u64 = 0x24;
+const ADMIN_SUBMISSION_QUEUE: u64 = 0x28;
+const ADMIN_COMPLETION_QUEUE: u64 = 0x30;
+const DOORBELLS: u64 = 0x1000;
+
+/// Set in the capabilities if the controller supports the NVM command set.
+const CAPABILITY_NVM: u64 = 1 << 37;
+
+const CONFIGURATION_ENABLE: u32 = 1 << 0;
+const CONFIGURATION_SHUTDOWN: u32 = 1 << 14;
+/// Sizes of submission and completion queue entries, as powers of two.
+const CONFIGURATION_ENTRY_SIZES: u32 = 6 << 16 | 4 << 20;
+
+const STATUS_READY: u32 = 1 << 0;
+const STATUS_FATAL: u32 = 1 << 1;
+const STATUS_SHUTDOWN: u32 = 3 << 2;
+const STATUS_SHUTDOWN_COMPLETE: u32 = 2 << 2;
+
+const ADMIN_CREATE_SUBMISSION_QUEUE: u8 = 0x01;
+const ADMIN_CREATE_COMPLETION_QUEUE: u8 = 0x05;
+const ADMIN_IDENTIFY: u8 = 0x06;
+
+const IO_FLUSH: u8 = 0x00;
+const IO_WRITE: u8 = 0x01;
+const IO_READ: u8 = 0x02;
+
+// data structures returned by identify
+const IDENTIFY_NAMESPACE: u32 = 0;
+const IDENTIFY_CONTROLLER: u32 = 1;
+const IDENTIFY_ACTIVE_NAMESPACES: u32 = 2;
+
+/// Set when creating a queue in physically contiguous memory.
+const QUEUE_CONTIGUOUS: u32 = 1 << 0;
+/// Set when creating a completion queue that raises interrupts.
+const QUEUE_INTERRUPTS: u32 = 1 << 1;
+
+const ADMIN_QUEUE_SIZE: u16 = 16;
+const MAX_IO_QUEUE_SIZE: u16 = 64;
+
+const PAGE_SIZE: usize = 4096;
+
+/// Bytes transferred by a single command, with longer requests split up.
+const MAX_TRANSFER: usize = 128 * 1024;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Controllers the driver took over, by the name of their device.
+static CONTROLLERS: Mutex<Vec<Attached>> = Mutex::new(Vec::new());
+
+struct Attached {
+    device: String,
+    controller: Arc<Controller>,
+    /// Names of the block devices of its namespaces.
+    namespaces: Vec<String>
+}
+
+/// Why a controller couldn't be set up or a command failed.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum NvmeError {
+    /// The controller lacks the NVM command set or 4 KiB pages.
+    Unsupported,
+    /// The controller didn't become ready or complete a command in time.
+    Timeout,
+    /// The controller reported a fatal error and stopped processing commands.
+    Fatal,
+    /// A command completed with an error, given as its status code type and status code.
+    Command(u16),
+    OutOfMemory
+}
+
+impl From<NvmeError> for BlockError {
+    fn from(error: NvmeError) -> Self {
+        match error {
+            NvmeError::Timeout => BlockError::Timeout,
+            _ => BlockError::Io
+        }
+    }
+}
+
+/// What a controller reported about itself in response to Identify.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct Identify {
+    pub model: String,
+    pub serial: String,
+    pub firmware: String,
+    /// The highest namespace identifier.
+    pub namespaces: u32,
+    /// Bytes a single command transfers at most, if limited.
+    pub max_transfer: Option<usize>,
+    /// Whether writes may be cached until flushed.
+    pub volatile_cache: bool
+}
+
+impl Identify {
+    /// Parses the data structure returned for the controller.
+    pub fn parse(data: &[u8; 4096]) -> Self {
+        Self {
+            model: string(&data[24..64]),
+            serial: string(&data[4..24]),
+            firmware: string(&data[64..72]),
+            namespaces: u32::from_le_bytes([data[516], data[517], data[518], data[519]]),
+            // a power of two in units of the minimum page size, which is 4 KiB for every controller
+            // driven, with limits too large to represent as good as none
+            max_transfer: match data[77] {
+                0 => None,
+                shift => 1usize.checked_shl(shift as u32).and_then(|pages| pages.checked_mul(PAGE_SIZE))
+            },
+            volatile_cache: data[525] & 1 != 0
+        }
+    }
+}
+
+/// Reads a string padded with spaces.
+fn string(bytes: &[u8]) -> String {
+    let string: String = bytes.iter().map(|&byte| byte as char).collect();
+    String::from(string.trim())
+}
+
+/// An NVMe controller, with its admin and I/O queues set up.
+pub struct Controller {
+    index: usize,
+    function: &'static pci::Device,
+    registers: VirtAddr,
+    /// Bytes between doorbells.
+    stride: u64,
+    /// How long the controller may take to become ready.
+    ready_timeout: Duration,
+    /// The vector raised on completions, if MSI or MSI-X could be set up.
+    vector: Option<Vector>,
+    identify: Identify,
+    /// Bytes transferred by a single command, with longer requests split up.
+    max_transfer: usize,
+    admin: Mutex<QueuePair>,
+    io: Mutex<Io>
+}
+
+/// The I/O queues, with the bounce pages and PRP list their commands use.
+struct Io {
+    queues: QueuePair,
+    pages: DmaPages,
+    /// Lists every page but the first, which is pointed at directly.
+    list: DmaBuffer,
+    /// Whether the controller was disabled after a command failed, and couldn't be set up again yet.
+    stopped: bool
+}
+
+impl Io {
+    fn new(size: u16) -> Option<Self> {
+        let pages = DmaPages::new(MAX_TRANSFER / PAGE_SIZE)?;
+        let mut list = DmaBuffer::new(PAGE_SIZE)?;
+        for (entry, frame) in list.as_mut_slice().as_chunks_mut::<8>().0.iter_mut().zip(&pages.frames()[1..]) {
+            *entry = frame.start_address().as_u64().to_le_bytes();
+        }
+        Some(Self { queues: QueuePair::new(1, size)?, pages, list, stopped: false })
+    }
+
+    /// Provides the PRP entries pointing at the first `length` bytes of the pages.
+    fn prp(&self, length: usize) -> [u64; 2] {
+        let frames = self.pages.frames();
+        let second = match length.div_ceil(PAGE_SIZE) {
+            0 | 1 => 0,
+            2 => frames[1].start_address().as_u64(),
+            _ => self.list.phys().as_u64()
+        };
+        [frames[0].start_address().as_u64(), second]
+    }
+}
+
+impl Controller {
+    /// Takes over a controller, resetting it and setting up its queues and interrupt.
+    pub fn new(function: &'static pci::Device, registers: VirtAddr, index: usize) -> Result<Self, NvmeError> {
+        function.enable(pci::COMMAND_MEMORY | pci::COMMAND_BUS_MASTER);
+        let capabilities = unsafe { ptr::read_volatile((registers + CAPABILITIES).as_ptr::<u64>()) };
+        // pages are always 4 KiB, the smallest size there is
+        if capabilities & CAPABILITY_NVM == 0 || capabilities >> 48 & 0xF != 0 {
+            return Err(NvmeError::Unsupported);
+        }
+        let max_entries = ((capabilities & 0xFFFF) as u16).saturating_add(1);
+
+        let admin = QueuePair::new(0, ADMIN_QUEUE_SIZE.min(max_entries)).ok_or(NvmeError::OutOfMemory)?;
+        let io = Io::new(MAX_IO_QUEUE_SIZE.min(max_entries)).ok_or(NvmeError::OutOfMemory)?;
+        let mut controller = Self {
+            index,
+            function,
+            registers,
+            stride: 4 << (capabilities >> 32 & 0xF),
+            ready_timeout: Duration::from_millis(500 * (capabilities >> 24 & 0xFF).max(1)),
+            vector: None,
+            identify: Identify::default(),
+            max_transfer: MAX_TRANSFER,
+            admin: Mutex::new(admin),
+            io: Mutex::new(io)
+        };
+        if let Err(error) = controller.start() {
+            controller.stop();
+            return Err(error);
+        }
+        Ok(controller)
+    }
+
+    /// Provides the number in the names of its namespaces.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn identify(&self) -> &Identify {
+        &self.identify
+    }
+
+    /// Provides the version of the specification the controller implements, as major and minor.
+    pub fn version(&self) -> (u16, u8) {
+        let version = self.read(VERSION);
+        ((version >> 16) as u16, (version >> 8) as u8)
+    }
+
+    /// Whether completions raise an interrupt, rather than being polled for.
+    pub fn uses_interrupts(&self) -> bool {
+        self.vector.is_some()
+    }
+
+    /// Lists the identifiers of the active namespaces.
+    pub fn active_namespaces(&self) -> Result<Vec<u32>, NvmeError> {
+        match self.identify_data(IDENTIFY_ACTIVE_NAMESPACES, 0) {
+            Ok(data) => Ok(data.as_slice()[..PAGE_SIZE].as_chunks::<4>().0.iter()
+                .map(|id| u32::from_le_bytes(*id))
+                .take_while(|&id| id != 0)
+                .collect()),
+            // controllers before version 1.1 can't list them, so every namespace is tried instead
+            Err(NvmeError::Command(_)) => Ok((1..=self.identify.namespaces.min(1024)).collect()),
+            Err(error) => Err(error)
+        }
+    }
+
+    /// Provides the capacity in blocks and block size of a namespace, or `None` if it's
+    /// inactive or formatted with metadata or blocks the driver doesn't handle.
+    pub fn namespace_format(&self, id: u32) -> Result<Option<(u64, usize)>, NvmeError> {
+        let data = self.identify_data(IDENTIFY_NAMESPACE, id)?;
+        let data = data.as_slice();
+        let blocks = u64::from_le_bytes(*data.first_chunk::<8>().unwrap());
+        let format = 128 + (data[26] & 0xF) as usize * 4;
+        let metadata = u16::from_le_bytes([data[format], data[format + 1]]);
+        let shift = data[format + 2];
+        if blocks == 0 || metadata != 0 || !(9..=12).contains(&shift) {
+            return Ok(None);
+        }
+        Ok(Some((blocks, 1 << shift)))
+    }
+
+    fn read(&self, register: u64) -> u32 {
+        unsafe { ptr::read_volatile((self.registers + register).as_ptr::<u32>()) }
+    }
+
+    fn write(&self, register: u64, value: u32) {
+        unsafe { ptr::write_volatile((self.registers + register).as_mut_ptr::<u32>(), value) }
+    }
+
+    fn write_u64(&self, register: u64, value: u64) {
+        self.write(register, value as u32);
+        self.write(register + 4, (value >> 32) as u32);
+    }
+
+    /// Writes the doorbell of a submission queue's tail or a completion queue's head.
+    fn ring(&self, queue: u16, completion: bool, value: u16) {
+        let doorbell = DOORBELLS + (2 * queue as u64 + completion as u64) * self.stride;
+        self.write(doorbell, value as u32);
+    }
+
+    /// Waits for the controller to report being ready or not.
+    fn wait_ready(&self, ready: bool) -> Result<(), NvmeError> {
+        let deadline = time::now() + self.ready_timeout;
+        loop {
+            let status = self.read(STATUS);
+            if (status & STATUS_READY != 0) == ready {
+                return Ok(());
+            }
+            if ready && status & STATUS_FATAL != 0 {
+                return Err(NvmeError::Fatal);
+            }
+            if time::now() > deadline {
+                return Err(NvmeError::Timeout);
+            }
+            hint::spin_loop();
+        }
+    }
+
+    /// Resets the controller and brings it up with its queues and interrupt.
+    fn start(&mut self) -> Result<(), NvmeError> {
+        // the interrupt just ends the halt in `run`, which then reads the completion queue itself
+        self.vector = msi::allocate(self.function, || {}).ok();
+        self.enable(&mut self.io.lock().queues)?;
+
+        let data = self.identify_data(IDENTIFY_CONTROLLER, 0)?;
+        self.identify = Identify::parse(data.as_slice().first_chunk().unwrap());
+        self.max_transfer = self.identify.max_transfer.map_or(MAX_TRANSFER, |max| max.min(MAX_TRANSFER));
+        Ok(())
+    }
+
+    /// Resets the controller and enables it again with empty queues, creating the I/O queues.
+    fn enable(&self, queues: &mut QueuePair) -> Result<(), NvmeError> {
+        self.write(CONFIGURATION, 0);
+        self.wait_ready(false)?;
+
+        {
+            let mut admin = self.admin.lock();
+            admin.reset();
+            let (size, submission, completion) = (admin.size() as u32 - 1, admin.submission_area(), admin.completion_area());
+            self.write(ADMIN_QUEUE_ATTRIBUTES, size | size << 16);
+            self.write_u64(ADMIN_SUBMISSION_QUEUE, submission.as_u64());
+            self.write_u64(ADMIN_COMPLETION_QUEUE, completion.as_u64());
+        }
+        if self.vector.is_none() {
+            self.write(INTERRUPT_MASK_SET, u32::MAX);
+        }
+
+        self.write(CONFIGURATION, CONFIGURATION_ENABLE | CONFIGURATION_ENTRY_SIZES);
+        self.wait_ready(true)?;
+
+        // the completion queue has to exist before the submission queue using it, with both
+        // raising the first message if there is one
+        queues.reset();
+        let (id, size, submission, completion) = (queues.id() as u32, queues.size() as u32 - 1, queues.submission_area(), queues.completion_area());
+        let interrupts = if self.vector.is_some() { QUEUE_INTERRUPTS } else { 0 };
+        self.admin(&Command {
+            opcode: ADMIN_CREATE_COMPLETION_QUEUE,
+            prp: [completion.as_u64(), 0],
+            dwords: [id | size << 16, QUEUE_CONTIGUOUS | interrupts, 0, 0, 0, 0],
+            ..Command::default()
+        })?;
+        self.admin(&Command {
+            opcode: ADMIN_CREATE_SUBMISSION_QUEUE,
+            prp: [submission.as_u64(), 0],
+            dwords: [id | size << 16, QUEUE_CONTIGUOUS | id << 16, 0, 0, 0, 0],
+            ..Command::default()
+        })?;
+        Ok(())
+    }
+
+    /// Has the controller write back its cache and stop, and releases its interrupt.
+    fn stop(&self) {
+        if self.read(CONFIGURATION) & CONFIGURATION_ENABLE != 0 && self.read(STATUS) & STATUS_READY != 0 {
+            self.write(CONFIGURATION, self.read(CONFIGURATION) | CONFIGURATION_SHUTDOWN);
+            let deadline = time::now() + self.ready_timeout;
+            while self.read(STATUS) & STATUS_SHUTDOWN != STATUS_SHUTDOWN_COMPLETE && time::now() < deadline {
+                hint::spin_loop();
+            }
+        }
+        self.write(CONFIGURATION, 0);
+        let _ = self.wait_ready(false);
+
+        if let Some(vector) = self.vector {
+            msi::release(self.function, vector);
+        }
+    }
+
+    /// Runs a command on a queue, returning its result.
+    fn run(&self, queues: &mut QueuePair, command: &Command) -> Result<u32, NvmeError> {
+        queues.push(command);
+        self.ring(queues.id(), false, queues.tail());
+
+        let deadline = time::now() + TIMEOUT;
+        loop {
+            if let Some(completion) = queues.pop() {
+                self.ring(queues.id(), true, queues.head());
+                // the rest of the status says whether to retry, which isn't done
+                return match completion.status & 0x7FF {
+                    0 => Ok(completion.result),
+                    status => Err(NvmeError::Command(status))
+                };
+            }
+            if self.read(STATUS) & STATUS_FATAL != 0 {
+                return Err(NvmeError::Fatal);
+            }
+            if time::now() > deadline {
+                // the controller may still complete the command, so it has to stop using the memory
+                self.write(CONFIGURATION, 0);
+                return Err(NvmeError::Timeout);
+            }
+            idle::pause(self.vector.is_some(), || queues.has_completion());
+        }
+    }
+
+    /// Runs a command on the I/O queues, setting the controller up again once a command timing
+    /// out or a fatal error left it disabled.
+    fn run_io(&self, io: &mut Io, command: &Command) -> Result<u32, NvmeError> {
+        if io.stopped {
+            self.enable(&mut io.queues)?;
+            io.stopped = false;
+        }
+
+        let result = self.run(&mut io.queues, command);
+        if let Err(NvmeError::Timeout | NvmeError::Fatal) = result {
+            io.stopped = true;
+            match self.enable(&mut io.queues) {
+                Ok(()) => io.stopped = false,
+                Err(error) => serial_println!("[tokyo] nvme{}: failed to restart: {:?}", self.index, error)
+            }
+        }
+        result
+    }
+
+    fn admin(&self, command: &Command) -> Result<u32, NvmeError> {
+        self.run(&mut self.admin.lock(), command)
+    }
+
+    /// Runs Identify, returning the page of data it fills in.
+    fn identify_data(&self, structure: u32, namespace: u32) -> Result<DmaBuffer, NvmeError> {
+        let data = DmaBuffer::new(PAGE_SIZE).ok_or(NvmeError::OutOfMemory)?;
+        self.admin(&Command {
+            opcode: ADMIN_IDENTIFY,
+            namespace,
+            prp: [data.phys().as_u64(), 0],
+            dwords: [structure, 0, 0, 0, 0, 0]
+        })?;
+        Ok(data)
+    }
+
+    /// Reads or writes `count` blocks of `length` bytes in total, through the bounce pages.
+    fn transfer(&self, io: &mut Io, opcode: u8, namespace: u32, block: u64, count: usize, length: usize) -> Result<(), NvmeError> {
+        let command = Command {
+            opcode,
+            namespace,
+            prp: io.prp(length),
+            dwords: [block as u32, (block >> 32) as u32, count as u32 - 1, 0, 0, 0]
+        };
+        self.run_io(io, &command).map(|_| ())
+    }
+}
+
+/// A namespace of a controller, accessed as a block device.
+pub struct Namespace {
+    name: String,
+    id: u32,
+    blocks: u64,
+    block_size: usize,
+    controller: Arc<Controller>
+}
+
+impl Namespace {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn controller(&self) -> &Controller {
+        &self.controller
+    }
+}
+
+impl BlockDevice for Namespace {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn blocks(&self) -> u64 {
+        self.blocks
+    }
+
+    fn read(&self, block: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+        block::check_request(self, block, buffer.len())?;
+        let max = self.controller.max_transfer;
+        let mut io = self.controller.io.lock();
+        for (i, chunk) in buffer.chunks_mut(max).enumerate() {
+            let block = block + (i * max / self.block_size) as u64;
+            self.controller.transfer(&mut io, IO_READ, self.id, block, chunk.len() / self.block_size, chunk.len())?;
+            io.pages.read(0, chunk);
+        }
+        Ok(())
+    }
+
+    fn write(&self, block: u64, buffer: &[u8]) -> Result<(), BlockError> {
+        block::check_request(self, block, buffer.len())?;
+        let max = self.controller.max_transfer;
+        let mut io = self.controller.io.lock();
+        for (i, chunk) in buffer.chunks(max).enumerate() {
+            let block = block + (i * max / self.block_size) as u64;
+            io.pages.write(0, chunk);
+            self.controller.transfer(&mut io, IO_WRITE, self.id, block, chunk.len() / self.block_size, chunk.len())?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        // without a volatile cache, writes are already on the media
+        if !self.controller.identify.volatile_cache {
+            return Ok(());
+        }
+        let mut io = self.controller.io.lock();
+        let command = Command { opcode: IO_FLUSH, namespace: self.id, ..Command::default() };
+        self.controller.run_io(&mut io, &command)?;
+        Ok(())
+    }
+}
+
+fn probe(device: &Arc<Device>) -> Result<(), ProbeError> {
+    let function = device.pci().ok_or(ProbeError::Unsupported)?;
+    let registers = function.mapped_bar(BAR).ok_or(ProbeError::MissingResource)?;
+
+    let mut controllers = CONTROLLERS.lock();
+    let index = (0..).find(|&index| controllers.iter().all(|attached| attached.controller.index != index)).unwrap();
+    let controller = Controller::new(function, registers, index).map_err(|error| match error {
+        NvmeError::Unsupported => ProbeError::Unsupported,
+        NvmeError::Timeout => ProbeError::Timeout,
+        NvmeError::Fatal | NvmeError::Command(_) | NvmeError::OutOfMemory => ProbeError::Failed
+    })?;
+    let controller = Arc::new(controller);
+    let (major, minor) = controller.version();
+    serial_println!("[tokyo] nvme{}: {}, version {}.{}, on {}", index, controller.identify.model, major, minor, device.name());
+
+    let ids = match controller.active_namespaces() {
+        Ok(ids) => ids,
+        Err(_) => {
+            controller.stop();
+            return Err(ProbeError::Failed);
+        }
+    };
+    let mut namespaces = Vec::new();
+    for id in ids {
+        let Ok(Some((blocks, block_size))) = controller.namespace_format(id) else {
+            continue;
+        };
+        let namespace = Arc::new(Namespace {
+            name: format!("nvme{}n{}", index, id),
+            id,
+            blocks,
+            block_size,
+            controller: controller.clone()
+        });
+        serial_println!("[tokyo] {}: {} blocks of {} bytes", namespace.name, blocks, block_size);
+        block::register(namespace.clone());
+        namespaces.push(namespace.name.clone());
+    }
+
+    controllers.push(Attached { device: String::from(device.name()), controller, namespaces });
+    Ok(())
+}
+
+fn remove(device: &Arc<Device>) {
+    let attached = {
+        let mut controllers = CONTROLLERS.lock();
+        let Some(index) = controllers.iter().position(|attached| attached.device == device.name()) else {
+            return;
+        };
+        controllers.remove(index)
+    };
+    for name in &attached.namespaces {
+        block::unregister(name);
+    }
+
+    // a command being run holds its queue's lock, so taking both waits for the last one to finish
+    let _io = attached.controller.io.lock();
+    let _admin = attached.controller.admin.lock();
+    attached.controller.stop();
+}
@@ -0,0 +1,152 @@
+//! Submission and completion queues, which hold commands for the controller and its replies.
+//!
+//! Each submission queue is paired with its own completion queue. New completions are told apart
+//! from old ones by their phase bit, which the controller inverts each time it wraps around.
+
+use core::ptr;
+use x86_64::PhysAddr;
+use crate::mem::dma::DmaBuffer;
+
+const SUBMISSION_ENTRY: usize = 64;
+const COMPLETION_ENTRY: usize = 16;
+
+/// A command as placed in a submission queue, leaving out the identifier the queue assigns.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
+pub struct Command {
+    pub opcode: u8,
+    pub namespace: u32,
+    /// The physical region page entries, pointing at the data.
+    pub prp: [u64; 2],
+    /// Command specific dwords 10 to 15.
+    pub dwords: [u32; 6]
+}
+
+/// A completion read from a completion queue.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Completion {
+    /// The identifier of the command it completes.
+    pub command: u16,
+    /// Command specific dword 0.
+    pub result: u32,
+    /// Status code type and status code, zero if the command succeeded.
+    pub status: u16
+}
+
+/// A submission queue and its completion queue, sharing an identifier.
+#[derive(Debug)]
+pub struct QueuePair {
+    id: u16,
+    size: u16,
+    submission: DmaBuffer,
+    completion: DmaBuffer,
+    tail: u16,
+    head: u16,
+    /// The phase of completions not yet read.
+    phase: bool,
+    next_command: u16
+}
+
+impl QueuePair {
+    /// Allocates a pair with `size` entries each, returning `None` if there isn't enough memory.
+    pub fn new(id: u16, size: u16) -> Option<Self> {
+        Some(Self {
+            id,
+            size,
+            submission: DmaBuffer::new(size as usize * SUBMISSION_ENTRY)?,
+            completion: DmaBuffer::new(size as usize * COMPLETION_ENTRY)?,
+            tail: 0,
+            head: 0,
+            phase: true,
+            next_command: 0
+        })
+    }
+
+    /// Empties both queues, for a controller that was reset and no longer remembers them.
+    pub fn reset(&mut self) {
+        // a completion is only new if its phase bit is set
+        self.completion.as_mut_slice().fill(0);
+        self.tail = 0;
+        self.head = 0;
+        self.phase = true;
+    }
+
+    /// Provides the identifier, zero for the admin queues.
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    pub fn submission_area(&self) -> PhysAddr {
+        self.submission.phys()
+    }
+
+    pub fn completion_area(&self) -> PhysAddr {
+        self.completion.phys()
+    }
+
+    /// Provides the submission queue tail to write to its doorbell.
+    pub fn tail(&self) -> u16 {
+        self.tail
+    }
+
+    /// Provides the completion queue head to write to its doorbell.
+    pub fn head(&self) -> u16 {
+        self.head
+    }
+
+    /// Places a command in the submission queue, returning its identifier.
+    ///
+    /// The queue holds one fewer command than its size, and the caller has to make sure
+    /// commands are completed before it fills up.
+    pub fn push(&mut self, command: &Command) -> u16 {
+        let id = self.next_command;
+        self.next_command = self.next_command.wrapping_add(1);
+
+        let mut entry = [0u32; SUBMISSION_ENTRY / 4];
+        entry[0] = command.opcode as u32 | (id as u32) << 16;
+        entry[1] = command.namespace;
+        entry[6] = command.prp[0] as u32;
+        entry[7] = (command.prp[0] >> 32) as u32;
+        entry[8] = command.prp[1] as u32;
+        entry[9] = (command.prp[1] >> 32) as u32;
+        entry[10..].copy_from_slice(&command.dwords);
+
+        let slot = (self.submission.virt() + (self.tail as usize * SUBMISSION_ENTRY) as u64).as_mut_ptr::<u32>();
+        for (i, dword) in entry.into_iter().enumerate() {
+            unsafe { ptr::write_volatile(slot.add(i), dword) };
+        }
+        self.tail = (self.tail + 1) % self.size;
+        id
+    }
+
+    /// Whether the controller posted a completion that wasn't read yet.
+    pub fn has_completion(&self) -> bool {
+        (self.completion_dword(3) >> 16 & 1 != 0) == self.phase
+    }
+
+    /// Reads the next completion, if the controller posted one.
+    pub fn pop(&mut self) -> Option<Completion> {
+        if !self.has_completion() {
+            return None;
+        }
+        let status = self.completion_dword(3);
+        let completion = Completion {
+            command: status as u16,
+            result: self.completion_dword(0),
+            status: (status >> 17) as u16
+        };
+        self.head = (self.head + 1) % self.size;
+        if self.head == 0 {
+            self.phase = !self.phase;
+        }
+        Some(completion)
+    }
+
+    fn completion_dword(&self, index: usize) -> u32 {
+        let slot = self.completion.virt() + (self.head as usize * COMPLETION_ENTRY + index * 4) as u64;
+        unsafe { ptr::read_volatile(slot.as_ptr::<u32>()) }
+    }
+}
@@ -0,0 +1,149 @@
+//! Access to configuration space, through the legacy I/O ports or memory-mapped ECAM.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::ptr;
+use spin::{Mutex, Once};
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+use x86_64::{PhysAddr, VirtAddr};
+use crate::{acpi, mem};
+use crate::pci::Address;
+
+const ADDRESS_PORT: u16 = 0xCF8;
+const DATA_PORT: u16 = 0xCFC;
+
+/// Set in the address port to enable a configuration access.
+const ENABLE: u32 = 1 << 31;
+
+/// Offset of the first allocation in the MCFG, after the header and a reserved field.
+const MCFG_ENTRIES: usize = 44;
+const MCFG_ENTRY_SIZE: usize = 16;
+
+static ACCESS: Once<Access> = Once::new();
+
+/// Serializes the two-step accesses through the legacy ports.
+static LEGACY: Mutex<()> = Mutex::new(());
+
+/// How configuration space is reached.
+pub enum Access {
+    /// Ports 0xCF8 and 0xCFC, reaching the first 256 bytes of functions in segment 0.
+    Legacy,
+    /// The enhanced configuration access mechanism of PCI Express, reaching all 4 KiB of each function.
+    Ecam(Vec<Ecam>)
+}
+
+/// A range of buses with memory-mapped configuration space, as listed by the MCFG.
+pub struct Ecam {
+    base: PhysAddr,
+    segment: u16,
+    start_bus: u8,
+    end_bus: u8,
+    /// Buses mapped so far, since mapping all of them up front takes 256 MiB.
+    mapped: Mutex<BTreeMap<u8, VirtAddr>>
+}
+
+impl Ecam {
+    fn contains(&self, address: Address) -> bool {
+        address.segment == self.segment && (self.start_bus..=self.end_bus).contains(&address.bus)
+    }
+
+    /// Maps the configuration spaces of a whole bus at once, so scanning it for functions
+    /// doesn't take a mapping for each of the 256 it may have.
+    fn space(&self, address: Address) -> VirtAddr {
+        let bus = *self.mapped.lock().entry(address.bus).or_insert_with(|| {
+            let offset = ((address.bus - self.start_bus) as u64) << 20;
+            mem::map_mmio(self.base + offset, 1 << 20).expect("configuration space should be mappable")
+        });
+        bus + ((address.device as u64) << 15 | (address.function as u64) << 12)
+    }
+}
+
+/// Chooses ECAM if the MCFG lists any ranges, and the legacy ports otherwise.
+pub fn init() -> &'static Access {
+    ACCESS.call_once(|| {
+        let ranges: Vec<Ecam> = acpi::find(b"MCFG")
+            .map(|mcfg| {
+                (MCFG_ENTRIES..mcfg.bytes().len())
+                    .step_by(MCFG_ENTRY_SIZE)
+                    .filter_map(|entry| Some(Ecam {
+                        base: PhysAddr::new(mcfg.u64(entry)?),
+                        segment: mcfg.u16(entry + 8)?,
+                        start_bus: mcfg.u8(entry + 10)?,
+                        end_bus: mcfg.u8(entry + 11)?,
+                        mapped: Mutex::new(BTreeMap::new())
+                    }))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        if ranges.is_empty() { Access::Legacy } else { Access::Ecam(ranges) }
+    })
+}
+
+/// Provides the way configuration space is accessed, or `None` before [`init`](init).
+pub fn access() -> Option<&'static Access> {
+    ACCESS.get()
+}
+
+/// Provides the segments and buses that can be reached.
+pub fn segments() -> Vec<(u16, u8, u8)> {
+    match ACCESS.get() {
+        Some(Access::Ecam(ranges)) => ranges.iter().map(|range| (range.segment, range.start_bus, range.end_bus)).collect(),
+        Some(Access::Legacy) => Vec::from([(0, 0, 255)]),
+        None => Vec::new()
+    }
+}
+
+/// Reads a dword of configuration space, returning all ones if it can't be reached.
+///
+/// `offset` is rounded down to a multiple of 4.
+pub fn read(address: Address, offset: u16) -> u32 {
+    let offset = offset & !3;
+    match ACCESS.get() {
+        Some(Access::Ecam(ranges)) => match ranges.iter().find(|range| range.contains(address)) {
+            Some(range) if offset < 4096 => unsafe {
+                ptr::read_volatile((range.space(address) + offset as u64).as_ptr::<u32>())
+            },
+            _ => u32::MAX
+        },
+        Some(Access::Legacy) if address.segment == 0 && offset < 256 => interrupts::without_interrupts(|| {
+            let _guard = LEGACY.lock();
+            unsafe {
+                Port::<u32>::new(ADDRESS_PORT).write(legacy_address(address, offset));
+                Port::<u32>::new(DATA_PORT).read()
+            }
+        }),
+        _ => u32::MAX
+    }
+}
+
+/// Writes a dword of configuration space, ignoring writes that can't reach it.
+///
+/// `offset` is rounded down to a multiple of 4.
+pub fn write(address: Address, offset: u16, value: u32) {
+    let offset = offset & !3;
+    match ACCESS.get() {
+        Some(Access::Ecam(ranges)) => {
+            if let Some(range) = ranges.iter().find(|range| range.contains(address)).filter(|_| offset < 4096) {
+                unsafe { ptr::write_volatile((range.space(address) + offset as u64).as_mut_ptr::<u32>(), value) }
+            }
+        }
+        Some(Access::Legacy) if address.segment == 0 && offset < 256 => interrupts::without_interrupts(|| {
+            let _guard = LEGACY.lock();
+            unsafe {
+                Port::<u32>::new(ADDRESS_PORT).write(legacy_address(address, offset));
+                Port::<u32>::new(DATA_PORT).write(value);
+            }
+        }),
+        _ => {}
+    }
+}
+
+fn legacy_address(address: Address, offset: u16) -> u32 {
+    ENABLE
+        | (address.bus as u32) << 16
+        | (address.device as u32) << 11
+        | (address.function as u32) << 8
+        | offset as u32
+}
@@ -0,0 +1,398 @@
+//! PCI and PCI Express devices, found by scanning the buses at boot.
+//!
+//! Configuration space is reached through [ECAM](config::Access::Ecam) if the ACPI MCFG table
+//! lists it, and through the legacy I/O ports otherwise. Buses are scanned from the host bridges
+//! down through every PCI-to-PCI bridge, recording each function along with its BARs, which are
+//! sized and mapped, its capabilities and its interrupt pin.
+
+pub mod config;
+pub mod msi;
+
+use alloc::vec::Vec;
+use core::fmt;
+use spin::Once;
+use x86_64::{PhysAddr, VirtAddr};
+use crate::mem;
+
+pub const COMMAND: u16 = 0x04;
+pub const STATUS: u16 = 0x06;
+pub const CAPABILITIES_POINTER: u16 = 0x34;
+pub const INTERRUPT_LINE: u16 = 0x3C;
+
+/// Enables responses to I/O space accesses.
+pub const COMMAND_IO: u16 = 1 << 0;
+/// Enables responses to memory space accesses.
+pub const COMMAND_MEMORY: u16 = 1 << 1;
+/// Enables the device to initiate DMA.
+pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+/// Disables the legacy INTx interrupt, for when MSI or MSI-X is used.
+pub const COMMAND_INTX_DISABLE: u16 = 1 << 10;
+
+/// Set in the status register if the device has a capabilities list.
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+
+const HEADER_GENERAL: u8 = 0x00;
+const HEADER_BRIDGE: u8 = 0x01;
+/// Set in the header type of function 0 if the device has more functions.
+const MULTI_FUNCTION: u8 = 1 << 7;
+
+/// Capabilities followed before the list is assumed to loop.
+const MAX_CAPABILITIES: usize = 48;
+
+static DEVICES: Once<Vec<Device>> = Once::new();
+
+/// The location of a function on the buses.
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct Address {
+    pub segment: u16,
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8
+}
+
+impl Address {
+    pub const fn new(segment: u16, bus: u8, device: u8, function: u8) -> Self {
+        Self { segment, bus, device, function }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04x}:{:02x}:{:02x}.{}", self.segment, self.bus, self.device, self.function)
+    }
+}
+
+/// A base address register, describing a range of memory or I/O ports the device decodes.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Bar {
+    Memory {
+        address: PhysAddr,
+        size: u64,
+        prefetchable: bool,
+        /// Whether the BAR takes up the next register as well, for the upper half of the address.
+        wide: bool,
+        /// Where the range is mapped, uncached, or `None` if mapping it failed.
+        mapped: Option<VirtAddr>
+    },
+    Io {
+        port: u16,
+        size: u16
+    }
+}
+
+/// An entry in a function's capabilities list.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Capability {
+    pub id: u8,
+    /// Offset of the capability in configuration space.
+    pub offset: u16
+}
+
+impl Capability {
+    pub const POWER_MANAGEMENT: u8 = 0x01;
+    pub const MSI: u8 = 0x05;
+    pub const VENDOR_SPECIFIC: u8 = 0x09;
+    pub const PCI_EXPRESS: u8 = 0x10;
+    pub const MSI_X: u8 = 0x11;
+}
+
+/// A function found on the buses.
+#[derive(Debug, Clone)]
+pub struct Device {
+    pub address: Address,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+    /// The layout of the rest of the header, without the multi-function bit.
+    pub header_type: u8,
+    pub subsystem_vendor_id: u16,
+    pub subsystem_id: u16,
+    /// Every BAR, with the upper half of 64-bit ones left as `None`.
+    pub bars: [Option<Bar>; 6],
+    pub capabilities: Vec<Capability>,
+    /// The legacy interrupt pin used, from 1 for INTA# to 4 for INTD#, or 0 for none.
+    pub interrupt_pin: u8,
+    /// The PIC line the firmware routed the interrupt pin to, if any.
+    pub interrupt_line: u8
+}
+
+impl Device {
+    /// Reads a dword of the function's configuration space.
+    pub fn read_u32(&self, offset: u16) -> u32 {
+        config::read(self.address, offset)
+    }
+
+    /// Reads a word of the function's configuration space.
+    pub fn read_u16(&self, offset: u16) -> u16 {
+        (self.read_u32(offset) >> ((offset & 2) * 8)) as u16
+    }
+
+    /// Reads a byte of the function's configuration space.
+    pub fn read_u8(&self, offset: u16) -> u8 {
+        (self.read_u32(offset) >> ((offset & 3) * 8)) as u8
+    }
+
+    /// Writes a dword of the function's configuration space.
+    pub fn write_u32(&self, offset: u16, value: u32) {
+        config::write(self.address, offset, value);
+    }
+
+    /// Writes a word of the function's configuration space, keeping the rest of its dword.
+    pub fn write_u16(&self, offset: u16, value: u16) {
+        let shift = (offset & 2) * 8;
+        let dword = self.read_u32(offset) & !(0xFFFF << shift);
+        self.write_u32(offset, dword | (value as u32) << shift);
+    }
+
+    /// Writes a byte of the function's configuration space, keeping the rest of its dword.
+    pub fn write_u8(&self, offset: u16, value: u8) {
+        let shift = (offset & 3) * 8;
+        let dword = self.read_u32(offset) & !(0xFF << shift);
+        self.write_u32(offset, dword | (value as u32) << shift);
+    }
+
+    /// Sets bits of the command register, such as [`COMMAND_BUS_MASTER`](COMMAND_BUS_MASTER).
+    pub fn enable(&self, command: u16) {
+        // the status half of the dword is cleared by writing ones, so only write the command
+        let value = self.read_u16(COMMAND) | command;
+        self.write_u32(COMMAND, value as u32);
+    }
+
+    /// Clears bits of the command register.
+    pub fn disable(&self, command: u16) {
+        let value = self.read_u16(COMMAND) & !command;
+        self.write_u32(COMMAND, value as u32);
+    }
+
+    /// Provides the offset of the first capability with an ID, such as [`Capability::MSI`](Capability::MSI).
+    pub fn capability(&self, id: u8) -> Option<u16> {
+        self.capabilities.iter().find(|capability| capability.id == id).map(|capability| capability.offset)
+    }
+
+    /// Provides the mapped address of a memory BAR.
+    pub fn mapped_bar(&self, index: usize) -> Option<VirtAddr> {
+        match self.bars.get(index).copied().flatten()? {
+            Bar::Memory { mapped, .. } => mapped,
+            Bar::Io { .. } => None
+        }
+    }
+
+    /// Describes the class of the function, e.g. "SATA controller".
+    pub fn class_name(&self) -> &'static str {
+        class_name(self.class, self.subclass)
+    }
+}
+
+/// Scans every reachable bus, mapping memory BARs along the way.
+///
+/// Must be called after the ACPI tables are found and memory mapping is initialized.
+pub fn init() -> usize {
+    config::init();
+    let devices = DEVICES.call_once(|| {
+        let mut devices = Vec::new();
+        for (segment, start_bus, _) in config::segments() {
+            scan_root(segment, start_bus, &mut devices);
+        }
+        devices.sort_by_key(|device| device.address);
+        devices
+    });
+    devices.len()
+}
+
+/// Provides every function found, ordered by address.
+pub fn devices() -> &'static [Device] {
+    DEVICES.get().map_or(&[], |devices| devices.as_slice())
+}
+
+/// Finds the first function with a vendor and device ID.
+pub fn find(vendor_id: u16, device_id: u16) -> Option<&'static Device> {
+    devices().iter().find(|device| device.vendor_id == vendor_id && device.device_id == device_id)
+}
+
+/// Scans the buses below the host bridges of a segment.
+fn scan_root(segment: u16, bus: u8, devices: &mut Vec<Device>) {
+    // a multi-function host bridge has one function per root bus
+    let host = Address::new(segment, bus, 0, 0);
+    if vendor_id(host).is_none() {
+        return;
+    }
+    if header_type(host) & MULTI_FUNCTION == 0 {
+        return scan_bus(segment, bus, devices);
+    }
+    for function in 0..8 {
+        if vendor_id(Address::new(segment, bus, 0, function)).is_none() {
+            continue;
+        }
+        // the functions of a host bridge near the last bus would number root buses past it
+        if let Some(root) = bus.checked_add(function) {
+            scan_bus(segment, root, devices);
+        }
+    }
+}
+
+fn scan_bus(segment: u16, bus: u8, devices: &mut Vec<Device>) {
+    for device in 0..32 {
+        let address = Address::new(segment, bus, device, 0);
+        if vendor_id(address).is_none() {
+            continue;
+        }
+
+        let functions = if header_type(address) & MULTI_FUNCTION != 0 { 8 } else { 1 };
+        for function in 0..functions {
+            let address = Address::new(segment, bus, device, function);
+            if vendor_id(address).is_none() {
+                continue;
+            }
+
+            let found = probe(address);
+            let bridge = found.header_type == HEADER_BRIDGE;
+            let secondary = found.read_u8(0x19);
+            devices.push(found);
+
+            // buses numbered at or below this one would lead back up
+            if bridge && secondary > bus {
+                scan_bus(segment, secondary, devices);
+            }
+        }
+    }
+}
+
+fn vendor_id(address: Address) -> Option<u16> {
+    let vendor_id = config::read(address, 0x00) as u16;
+    (vendor_id != 0xFFFF).then_some(vendor_id)
+}
+
+fn header_type(address: Address) -> u8 {
+    (config::read(address, 0x0C) >> 16) as u8
+}
+
+/// Reads the header of a function that is known to exist.
+fn probe(address: Address) -> Device {
+    let id = config::read(address, 0x00);
+    let class = config::read(address, 0x08);
+    let subsystem = config::read(address, 0x2C);
+    let interrupt = config::read(address, INTERRUPT_LINE);
+    let header_type = header_type(address) & !MULTI_FUNCTION;
+
+    let mut device = Device {
+        address,
+        vendor_id: id as u16,
+        device_id: (id >> 16) as u16,
+        class: (class >> 24) as u8,
+        subclass: (class >> 16) as u8,
+        prog_if: (class >> 8) as u8,
+        revision: class as u8,
+        header_type,
+        subsystem_vendor_id: if header_type == HEADER_GENERAL { subsystem as u16 } else { 0 },
+        subsystem_id: if header_type == HEADER_GENERAL { (subsystem >> 16) as u16 } else { 0 },
+        bars: [None; 6],
+        capabilities: Vec::new(),
+        interrupt_pin: (interrupt >> 8) as u8,
+        interrupt_line: interrupt as u8
+    };
+    device.bars = read_bars(&device);
+    device.capabilities = read_capabilities(&device);
+    device
+}
+
+/// Sizes every BAR by writing all ones and reading back which bits stuck, then maps memory BARs.
+fn read_bars(device: &Device) -> [Option<Bar>; 6] {
+    let count = match device.header_type {
+        HEADER_GENERAL => 6,
+        HEADER_BRIDGE => 2,
+        _ => 0
+    };
+
+    // stop decoding while the BARs hold the sizing pattern
+    let command = device.read_u16(COMMAND);
+    device.disable(COMMAND_IO | COMMAND_MEMORY);
+
+    let mut bars = [None; 6];
+    let mut index = 0;
+    while index < count {
+        let offset = 0x10 + (index as u16 * 4);
+        let original = device.read_u32(offset);
+        device.write_u32(offset, u32::MAX);
+        let mask = device.read_u32(offset);
+        device.write_u32(offset, original);
+
+        if original & 1 == 1 {
+            let mask = (mask & !0x3) as u16;
+            if mask != 0 {
+                bars[index] = Some(Bar::Io { port: (original & !0x3) as u16, size: !mask + 1 });
+            }
+            index += 1;
+            continue;
+        }
+
+        let wide = (original >> 1) & 0b11 == 0b10 && index + 1 < count;
+        let (address, mask) = if wide {
+            let high_offset = offset + 4;
+            let high = device.read_u32(high_offset);
+            device.write_u32(high_offset, u32::MAX);
+            let high_mask = device.read_u32(high_offset);
+            device.write_u32(high_offset, high);
+            (((high as u64) << 32) | (original & !0xF) as u64, ((high_mask as u64) << 32) | (mask & !0xF) as u64)
+        } else {
+            ((original & !0xF) as u64, (mask & !0xF) as u64 | 0xFFFF_FFFF_0000_0000)
+        };
+
+        if mask != 0xFFFF_FFFF_0000_0000 && mask != 0 {
+            let size = !mask + 1;
+            let address = PhysAddr::new(address);
+            let mapped = (address.as_u64() != 0).then(|| mem::map_mmio(address, size).ok()).flatten();
+            bars[index] = Some(Bar::Memory { address, size, prefetchable: original & 0x8 != 0, wide, mapped });
+        }
+        index += if wide { 2 } else { 1 };
+    }
+
+    device.write_u32(COMMAND, command as u32);
+    bars
+}
+
+fn read_capabilities(device: &Device) -> Vec<Capability> {
+    let mut capabilities = Vec::new();
+    if device.read_u16(STATUS) & STATUS_CAPABILITIES == 0 {
+        return capabilities;
+    }
+
+    let mut offset = (device.read_u8(CAPABILITIES_POINTER) & !0x3) as u16;
+    while offset != 0 && capabilities.len() < MAX_CAPABILITIES {
+        let header = device.read_u16(offset);
+        capabilities.push(Capability { id: header as u8, offset });
+        offset = ((header >> 8) as u8 & !0x3) as u16;
+    }
+    capabilities
+}
+
+/// Describes a class and subclass, falling back to the class alone.
+pub fn class_name(class: u8, subclass: u8) -> &'static str {
+    match (class, subclass) {
+        (0x01, 0x01) => "IDE interface",
+        (0x01, 0x06) => "SATA controller",
+        (0x01, 0x08) => "Non-Volatile memory controller",
+        (0x01, _) => "Mass storage controller",
+        (0x02, 0x00) => "Ethernet controller",
+        (0x02, _) => "Network controller",
+        (0x03, 0x00) => "VGA compatible controller",
+        (0x03, _) => "Display controller",
+        (0x04, _) => "Multimedia controller",
+        (0x05, _) => "Memory controller",
+        (0x06, 0x00) => "Host bridge",
+        (0x06, 0x01) => "ISA bridge",
+        (0x06, 0x04) => "PCI bridge",
+        (0x06, _) => "Bridge",
+        (0x07, _) => "Communication controller",
+        (0x08, _) => "System peripheral",
+        (0x09, _) => "Input device controller",
+        (0x0C, 0x03) => "USB controller",
+        (0x0C, 0x05) => "SMBus",
+        (0x0C, _) => "Serial bus controller",
+        (0x0D, _) => "Wireless controller",
+        (0xFF, _) => "Unassigned class",
+        _ => "Unclassified device"
+    }
+}
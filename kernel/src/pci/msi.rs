@@ -0,0 +1,188 @@
+//! Message signalled interrupts, which functions raise by writing to the local APIC directly.
+//!
+//! MSI gives a function one message, pointed at one vector. MSI-X gives it a table of messages
+//! in one of its BARs, each with a vector of its own, so e.g. every queue of a controller can
+//! interrupt separately. Either replaces the legacy interrupt pin, which is disabled when enabled.
+
+use core::ptr;
+use x86_64::VirtAddr;
+use crate::apic;
+use crate::irq::{self, Vector};
+use crate::pci::{Capability, Device, COMMAND_BUS_MASTER, COMMAND_INTX_DISABLE};
+
+/// Where messages are written, with the ID of the destination local APIC in bits 12 to 19.
+const MESSAGE_ADDRESS: u32 = 0xFEE0_0000;
+
+const MSI_ENABLE: u16 = 1 << 0;
+/// Set in the MSI message control if the message address has an upper half.
+const MSI_64_BIT: u16 = 1 << 7;
+/// Holds the number of messages enabled, as a power of two.
+const MSI_MULTIPLE_ENABLE: u16 = 0b111 << 4;
+
+const MSIX_TABLE_SIZE: u16 = 0x7FF;
+const MSIX_FUNCTION_MASK: u16 = 1 << 14;
+const MSIX_ENABLE: u16 = 1 << 15;
+/// Selects the BAR holding a structure, in the lowest bits of its offset.
+const MSIX_BIR: u32 = 0b111;
+
+const ENTRY_SIZE: u64 = 16;
+const ENTRY_ADDRESS: u64 = 0;
+const ENTRY_UPPER_ADDRESS: u64 = 4;
+const ENTRY_DATA: u64 = 8;
+const ENTRY_CONTROL: u64 = 12;
+const ENTRY_MASKED: u32 = 1 << 0;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum MsiError {
+    /// The function has neither MSI nor MSI-X, whichever was asked for.
+    Unsupported,
+    /// The BAR holding the MSI-X table isn't a mapped memory BAR.
+    UnmappedTable,
+    /// The MSI-X entry is past the end of the table.
+    InvalidEntry,
+    /// Every interrupt vector is in use.
+    NoVectors
+}
+
+/// The MSI-X table of a function.
+#[derive(Debug)]
+pub struct MsiX {
+    device: &'static Device,
+    capability: u16,
+    table: VirtAddr,
+    size: u16
+}
+
+impl MsiX {
+    /// Finds the table of a function, masking every entry in it.
+    pub fn new(device: &'static Device) -> Result<Self, MsiError> {
+        let capability = device.capability(Capability::MSI_X).ok_or(MsiError::Unsupported)?;
+        let size = (device.read_u16(capability + 2) & MSIX_TABLE_SIZE) + 1;
+        let location = device.read_u32(capability + 4);
+        let bar = device.mapped_bar((location & MSIX_BIR) as usize).ok_or(MsiError::UnmappedTable)?;
+
+        let table = Self { device, capability, table: bar + (location & !MSIX_BIR) as u64, size };
+        for entry in 0..size {
+            table.write(entry, ENTRY_CONTROL, ENTRY_MASKED);
+        }
+        Ok(table)
+    }
+
+    /// Provides the number of entries in the table.
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// Points an entry at a vector on the current CPU and unmasks it.
+    pub fn route(&self, entry: u16, vector: Vector) -> Result<(), MsiError> {
+        if entry >= self.size {
+            return Err(MsiError::InvalidEntry);
+        }
+        self.write(entry, ENTRY_CONTROL, ENTRY_MASKED);
+        self.write(entry, ENTRY_ADDRESS, message_address());
+        self.write(entry, ENTRY_UPPER_ADDRESS, 0);
+        self.write(entry, ENTRY_DATA, vector.number() as u32);
+        self.write(entry, ENTRY_CONTROL, 0);
+        Ok(())
+    }
+
+    /// Stops an entry from raising its vector.
+    pub fn mask(&self, entry: u16) -> Result<(), MsiError> {
+        if entry >= self.size {
+            return Err(MsiError::InvalidEntry);
+        }
+        self.write(entry, ENTRY_CONTROL, ENTRY_MASKED);
+        Ok(())
+    }
+
+    /// Enables MSI-X, disabling MSI and the legacy interrupt.
+    pub fn enable(&self) {
+        set_msi_control(self.device, MSI_ENABLE, false);
+        let control = self.device.read_u16(self.capability + 2);
+        self.device.write_u16(self.capability + 2, (control | MSIX_ENABLE) & !MSIX_FUNCTION_MASK);
+        self.device.enable(COMMAND_BUS_MASTER | COMMAND_INTX_DISABLE);
+    }
+
+    /// Disables MSI-X, enabling the legacy interrupt again.
+    pub fn disable(&self) {
+        let control = self.device.read_u16(self.capability + 2);
+        self.device.write_u16(self.capability + 2, control & !MSIX_ENABLE);
+        self.device.disable(COMMAND_INTX_DISABLE);
+    }
+
+    fn write(&self, entry: u16, field: u64, value: u32) {
+        let address = self.table + entry as u64 * ENTRY_SIZE + field;
+        unsafe { ptr::write_volatile(address.as_mut_ptr::<u32>(), value) }
+    }
+}
+
+/// Points the MSI message of a function at a vector on the current CPU and enables it, disabling
+/// MSI-X and the legacy interrupt.
+///
+/// Only a single message is enabled, even if the function supports more.
+pub fn enable_msi(device: &Device, vector: Vector) -> Result<(), MsiError> {
+    let capability = device.capability(Capability::MSI).ok_or(MsiError::Unsupported)?;
+    if let Some(msix) = device.capability(Capability::MSI_X) {
+        let control = device.read_u16(msix + 2);
+        device.write_u16(msix + 2, control & !MSIX_ENABLE);
+    }
+
+    let control = device.read_u16(capability + 2);
+    device.write_u16(capability + 2, control & !(MSI_ENABLE | MSI_MULTIPLE_ENABLE));
+    device.write_u32(capability + 4, message_address());
+    if control & MSI_64_BIT != 0 {
+        device.write_u32(capability + 8, 0);
+        device.write_u16(capability + 12, vector.number() as u16);
+    } else {
+        device.write_u16(capability + 8, vector.number() as u16);
+    }
+
+    set_msi_control(device, MSI_ENABLE, true);
+    device.enable(COMMAND_BUS_MASTER | COMMAND_INTX_DISABLE);
+    Ok(())
+}
+
+/// Disables MSI, enabling the legacy interrupt again.
+pub fn disable_msi(device: &Device) {
+    set_msi_control(device, MSI_ENABLE, false);
+    device.disable(COMMAND_INTX_DISABLE);
+}
+
+/// Allocates a vector running `handler` and routes the first message of a function to it,
+/// preferring MSI-X over MSI.
+///
+/// The vector is freed again if the function supports neither.
+pub fn allocate<F: Fn() + Send + Sync + 'static>(device: &'static Device, handler: F) -> Result<Vector, MsiError> {
+    let vector = irq::allocate(handler).ok_or(MsiError::NoVectors)?;
+    let routed = match MsiX::new(device) {
+        Ok(table) => table.route(0, vector).map(|_| table.enable()),
+        Err(MsiError::Unsupported) => enable_msi(device, vector),
+        Err(error) => Err(error)
+    };
+    if let Err(error) = routed {
+        irq::free(vector);
+        return Err(error);
+    }
+    Ok(vector)
+}
+
+/// Disables the message [`allocate`](allocate) routed, enabling the legacy interrupt again, and
+/// frees its vector.
+pub fn release(device: &'static Device, vector: Vector) {
+    match MsiX::new(device) {
+        Ok(table) => table.disable(),
+        Err(_) => disable_msi(device)
+    }
+    irq::free(vector);
+}
+
+fn set_msi_control(device: &Device, bits: u16, set: bool) {
+    if let Some(capability) = device.capability(Capability::MSI) {
+        let control = device.read_u16(capability + 2);
+        device.write_u16(capability + 2, if set { control | bits } else { control & !bits });
+    }
+}
+
+fn message_address() -> u32 {
+    MESSAGE_ADDRESS | (apic::id() as u32) << 12
+}
@@ -0,0 +1,91 @@
+//! Compares per-pixel drawing against the row-based methods of [`FrameBufferView`](FrameBufferView).
+//!
+//! Built with the `bench` feature, which also asks the bootloader for a 1280x720 frame buffer.
+//! Results are printed over serial in microseconds, as measured by [`time::now`](time::now).
+//!
+//! The benchmarks draw with interrupts disabled, so they are skipped when the clock source is
+//! the PIT, which only advances on its interrupt.
+
+use alloc::vec::Vec;
+use crate::render::{use_global_view, Color, Rect};
+use crate::render::view::FrameBufferView;
+use crate::{serial_println, time};
+
+const ROUNDS: u64 = 10;
+
+/// Runs every benchmark on the global view.
+pub fn run() {
+    let source = time::clock_source();
+    serial_println!("[bench] clock source: {}", source.name);
+    if source.name == "pit" {
+        serial_println!("[bench] skipped, the PIT doesn't advance while interrupts are disabled");
+        return;
+    }
+
+    use_global_view(|view| {
+        let info = view.info();
+        serial_println!("[bench] {}x{}, {} bytes per pixel", info.width, info.height, info.bytes_per_pixel);
+
+        let color = Color::new(0x20, 0x40, 0x80);
+        compare(view, "clear", |view| clear_per_pixel(view, color), |view| view.clear(color));
+
+        let (width, height) = (info.width / 2, info.height / 2);
+        let image = gradient(width, height);
+        let mut packed = Vec::with_capacity(image.len() * info.bytes_per_pixel);
+        for &color in &image {
+            packed.extend_from_slice(view.pack(color).as_bytes());
+        }
+        compare(
+            view,
+            "image",
+            |view| image_per_pixel(view, &image, width),
+            |view| view.blit((0, 0), (width, height), &packed)
+        );
+
+        let micros = measure(|| view.copy_rect(Rect::new(0, 16, info.width, info.height - 16), (0, 0)));
+        serial_println!("[bench] scroll: {} µs with copy_rect", micros);
+    });
+}
+
+/// Measures and prints both approaches to the same operation.
+fn compare<V: FrameBufferView>(view: &mut V, name: &str, per_pixel: impl Fn(&mut V), rows: impl Fn(&mut V)) {
+    let slow = measure(|| per_pixel(view));
+    let fast = measure(|| rows(view));
+    serial_println!(
+        "[bench] {}: {} µs per pixel, {} µs by rows, {}x faster",
+        name, slow, fast, slow / fast.max(1)
+    );
+}
+
+/// Provides the average number of microseconds spent in `func`.
+fn measure(mut func: impl FnMut()) -> u64 {
+    let start = time::now();
+    for _ in 0..ROUNDS {
+        func();
+    }
+    (time::now() - start).as_micros() as u64 / ROUNDS
+}
+
+/// How [`clear`](FrameBufferView::clear) used to work, converting the color for every pixel.
+fn clear_per_pixel<V: FrameBufferView>(view: &mut V, color: Color) {
+    let info = view.info();
+    for y in 0..info.height {
+        for x in 0..info.width {
+            unsafe { view.set_pixel_unchecked(((y * info.stride) + x) * info.bytes_per_pixel, color); }
+        }
+    }
+}
+
+fn image_per_pixel<V: FrameBufferView>(view: &mut V, image: &[Color], width: usize) {
+    let info = view.info();
+    for (i, &color) in image.iter().enumerate() {
+        let (x, y) = (i % width, i / width);
+        unsafe { view.set_pixel_unchecked(((y * info.stride) + x) * info.bytes_per_pixel, color); }
+    }
+}
+
+fn gradient(width: usize, height: usize) -> Vec<Color> {
+    (0..width * height)
+        .map(|i| Color::new((i % width * 255 / width) as u8, (i / width * 255 / height) as u8, 0x80))
+        .collect()
+}
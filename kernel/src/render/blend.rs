@@ -0,0 +1,49 @@
+//! Blending translucent colors onto the existing contents of a view.
+
+use crate::render::{Color, Rgba};
+
+/// How a translucent color is combined with the pixel below it.
+///
+/// In every mode, the source's alpha decides how much of the result is applied,
+/// so a fully transparent color leaves the pixel unchanged.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum BlendMode {
+    /// Paints the color over the pixel, like a sheet of tinted glass.
+    #[default]
+    SourceOver,
+    /// Adds the color to the pixel, brightening it. Useful for highlights.
+    Additive,
+    /// Multiplies the pixel by the color, darkening it. Useful for shadows.
+    Multiply
+}
+
+impl BlendMode {
+    /// Blends `src` onto `dest`.
+    pub fn blend(self, dest: Color, src: Rgba) -> Color {
+        let alpha = src.alpha as u16;
+        let channel = |dest: u8, src: u8| -> u8 {
+            let (dest, src) = (dest as u16, src as u16);
+            match self {
+                BlendMode::SourceOver => lerp(dest, src, alpha),
+                BlendMode::Additive => (dest + div_255(src * alpha)).min(255) as u8,
+                BlendMode::Multiply => lerp(dest, div_255(dest * src), alpha)
+            }
+        };
+
+        Color::new(
+            channel(dest.red, src.red),
+            channel(dest.green, src.green),
+            channel(dest.blue, src.blue)
+        )
+    }
+}
+
+/// Interpolates between two channel values, where `amount` is the share of `to` out of 255.
+fn lerp(from: u16, to: u16, amount: u16) -> u8 {
+    div_255(from * (255 - amount) + to * amount) as u8
+}
+
+/// Divides by 255, rounding to the nearest value.
+fn div_255(n: u16) -> u16 {
+    ((n as u32 + 127) / 255) as u16
+}
@@ -0,0 +1,231 @@
+//! Stacking surfaces on top of each other and presenting them on a view.
+//!
+//! The compositor keeps track of which parts of the screen changed, whether through
+//! drawing on a surface or by moving, restacking or hiding it, and only composes those.
+
+use alloc::vec::Vec;
+use crate::render::{use_global_view, Color, Rect};
+use crate::render::damage::Damage;
+use crate::render::draw::Point;
+use crate::render::surface::Surface;
+use crate::render::view::{clip, FrameBufferView};
+
+/// Identifies a surface added to a compositor.
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct SurfaceId(usize);
+
+/// A surface along with where and how it is shown.
+struct Layer {
+    id: SurfaceId,
+    surface: Surface,
+    pos: Point,
+    z: i32,
+    visible: bool,
+    key: Option<Color>
+}
+
+impl Layer {
+    /// The visible part of the layer on a screen, along with its offset into the surface.
+    fn clip(&self, screen: Rect) -> Option<(Rect, (usize, usize))> {
+        clip(self.pos, (self.surface.width(), self.surface.height()), screen)
+    }
+}
+
+/// Composes surfaces into a single image, from the lowest z-order to the highest.
+///
+/// Layers with the same z-order are stacked in the order they were added.
+pub struct Compositor {
+    /// The composed image, presented on every frame.
+    screen: Surface,
+    background: Color,
+    layers: Vec<Layer>,
+    next_id: usize,
+    damage: Damage
+}
+
+impl Compositor {
+    /// Create a compositor for a view, which only shows `background` until surfaces are added.
+    pub fn new<V: FrameBufferView>(view: &V, background: Color) -> Self {
+        let screen = Surface::new(view, view.width(), view.height());
+        let mut damage = Damage::new();
+        damage.add(screen.bounds());
+        Self { screen, background, layers: Vec::new(), next_id: 0, damage }
+    }
+
+    /// Create a surface that can be added to this compositor.
+    pub fn create_surface(&self, width: usize, height: usize) -> Surface {
+        Surface::new(&self.screen, width, height)
+    }
+
+    /// Shows a surface with its top left corner at `pos`.
+    pub fn add(&mut self, surface: Surface, pos: Point, z: i32) -> SurfaceId {
+        let id = SurfaceId(self.next_id);
+        self.next_id += 1;
+
+        let index = self.layers.partition_point(|other| other.z <= z);
+        self.layers.insert(index, Layer { id, surface, pos, z, visible: true, key: None });
+        self.damage_layer(id);
+        id
+    }
+
+    /// Removes a surface, handing it back.
+    pub fn remove(&mut self, id: SurfaceId) -> Option<Surface> {
+        let index = self.layers.iter().position(|layer| layer.id == id)?;
+        self.damage_layer(id);
+        Some(self.layers.remove(index).surface)
+    }
+
+    pub fn surface(&self, id: SurfaceId) -> Option<&Surface> {
+        self.layer(id).map(|layer| &layer.surface)
+    }
+
+    /// Provides a surface to draw on, whose changes are picked up on the next frame.
+    pub fn surface_mut(&mut self, id: SurfaceId) -> Option<&mut Surface> {
+        self.layer_mut(id).map(|layer| &mut layer.surface)
+    }
+
+    pub fn position(&self, id: SurfaceId) -> Option<Point> {
+        self.layer(id).map(|layer| layer.pos)
+    }
+
+    /// Moves a surface, which may be partially or entirely off screen.
+    pub fn set_position(&mut self, id: SurfaceId, pos: Point) {
+        self.update(id, |layer| layer.pos = pos);
+    }
+
+    /// Restacks a surface, placing it above the others with the same z-order.
+    pub fn set_z(&mut self, id: SurfaceId, z: i32) {
+        let Some(index) = self.layers.iter().position(|layer| layer.id == id) else {
+            return;
+        };
+        let mut layer = self.layers.remove(index);
+        layer.z = z;
+        let index = self.layers.partition_point(|other| other.z <= z);
+        self.layers.insert(index, layer);
+        self.damage_layer(id);
+    }
+
+    pub fn set_visible(&mut self, id: SurfaceId, visible: bool) {
+        self.update(id, |layer| layer.visible = visible);
+    }
+
+    /// Sets a color that is left out when composing the surface, showing what is below it instead.
+    ///
+    /// This allows for non-rectangular surfaces such as a mouse cursor.
+    pub fn set_color_key(&mut self, id: SurfaceId, key: Option<Color>) {
+        self.update(id, |layer| layer.key = key);
+    }
+
+    pub fn set_background(&mut self, background: Color) {
+        self.background = background;
+        self.damage.add(self.screen.bounds());
+    }
+
+    /// Composes the changed regions and copies them to the global view.
+    ///
+    /// This is meant to be called once per frame.
+    pub fn compose(&mut self) {
+        use_global_view(|view| self.compose_into(view));
+    }
+
+    /// Composes the changed regions and copies them to `view`, which should be the one
+    /// the compositor was created for.
+    pub fn compose_into<V: FrameBufferView>(&mut self, view: &mut V) {
+        let bounds = self.screen.bounds();
+
+        // pick up what was drawn on the surfaces
+        for layer in self.layers.iter_mut() {
+            let damage = layer.surface.take_damage();
+            if !layer.visible {
+                continue;
+            }
+            for rect in damage.rects() {
+                let pos = (layer.pos.0 + rect.x as isize, layer.pos.1 + rect.y as isize);
+                if let Some((rect, _)) = clip(pos, (rect.width, rect.height), bounds) {
+                    self.damage.add(rect);
+                }
+            }
+        }
+
+        let info = self.screen.info();
+        let pitch = info.stride * info.bytes_per_pixel;
+        for &rect in self.damage.rects() {
+            self.screen.fill_rect(rect, self.background);
+            for layer in self.layers.iter().filter(|layer| layer.visible) {
+                draw_layer(&mut self.screen, layer, rect);
+            }
+
+            let pixels = self.screen.pixels();
+            for y in rect.y..rect.bottom() {
+                let start = (y * pitch) + (rect.x * info.bytes_per_pixel);
+                let row = &pixels[start..start + (rect.width * info.bytes_per_pixel)];
+                view.blit((rect.x as isize, y as isize), (rect.width, 1), row);
+            }
+        }
+
+        self.damage.clear();
+        self.screen.take_damage();
+    }
+
+    fn layer(&self, id: SurfaceId) -> Option<&Layer> {
+        self.layers.iter().find(|layer| layer.id == id)
+    }
+
+    fn layer_mut(&mut self, id: SurfaceId) -> Option<&mut Layer> {
+        self.layers.iter_mut().find(|layer| layer.id == id)
+    }
+
+    /// Changes a layer, marking both the area it covered before and after as changed.
+    fn update<F: FnOnce(&mut Layer)>(&mut self, id: SurfaceId, func: F) {
+        self.damage_layer(id);
+        if let Some(layer) = self.layer_mut(id) {
+            func(layer);
+        }
+        self.damage_layer(id);
+    }
+
+    fn damage_layer(&mut self, id: SurfaceId) {
+        let bounds = self.screen.bounds();
+        if let Some((rect, _)) = self.layer(id).and_then(|layer| layer.clip(bounds)) {
+            self.damage.add(rect);
+        }
+    }
+}
+
+/// Copies the part of a layer within `area` onto the screen.
+fn draw_layer(screen: &mut Surface, layer: &Layer, area: Rect) {
+    let Some((visible, offset)) = layer.clip(screen.bounds()) else {
+        return;
+    };
+    let area = visible.intersection(&area);
+    if area.is_empty() {
+        return;
+    }
+
+    let info = screen.info();
+    let bytes_per_pixel = info.bytes_per_pixel;
+    let source_pitch = layer.surface.width() * bytes_per_pixel;
+    let pitch = info.stride * bytes_per_pixel;
+    let len = area.width * bytes_per_pixel;
+    let key = layer.key.map(|key| screen.pack(key));
+
+    let source = layer.surface.pixels();
+    let buffer = unsafe { screen.buffer_untracked() };
+    for row in 0..area.height {
+        let source_x = offset.0 + (area.x - visible.x);
+        let source_y = offset.1 + (area.y - visible.y) + row;
+        let from = (source_y * source_pitch) + (source_x * bytes_per_pixel);
+        let to = ((area.y + row) * pitch) + (area.x * bytes_per_pixel);
+        let (from, to) = (&source[from..from + len], &mut buffer[to..to + len]);
+
+        match key {
+            None => to.copy_from_slice(from),
+            Some(key) => {
+                let pixels = from.chunks_exact(bytes_per_pixel).zip(to.chunks_exact_mut(bytes_per_pixel));
+                for (from, to) in pixels.filter(|(from, _)| *from != key.as_bytes()) {
+                    to.copy_from_slice(from);
+                }
+            }
+        }
+    }
+}
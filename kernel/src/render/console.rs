@@ -0,0 +1,275 @@
+//! A text console drawn with a bitmap font, scrolling once it is full.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use crate::render::{Color, Rect};
+use crate::render::font::Font;
+use crate::render::view::FrameBufferView;
+
+/// Columns a tab advances to a multiple of.
+const TAB_WIDTH: usize = 8;
+
+/// A character along with its colors.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Cell {
+    pub c: char,
+    pub foreground: Color,
+    pub background: Color
+}
+
+/// A grid of character cells within an area of a view.
+///
+/// Text is kept in the grid and only drawn when the console is [flushed](Console::flush),
+/// so a console can be written to while something else is shown and [redrawn](Console::redraw)
+/// later. The console doesn't hold on to the view it draws to, so the same console can be
+/// drawn to a surface that is composed later or straight to the screen.
+pub struct Console {
+    font: &'static (dyn Font + Sync),
+    scale: usize,
+    area: Rect,
+    columns: usize,
+    rows: usize,
+    cursor: (usize, usize),
+    foreground: Color,
+    background: Color,
+    cells: Vec<Cell>,
+    /// Rows changed since the last flush.
+    dirty: Vec<bool>,
+    /// Rows scrolled up since the last flush.
+    scrolled: usize
+}
+
+impl Console {
+    /// Create a console covering `area`, with every font pixel enlarged to `scale` by `scale` pixels.
+    pub fn new(font: &'static (dyn Font + Sync), scale: usize, area: Rect) -> Self {
+        let mut console = Self {
+            font,
+            scale: 1,
+            area,
+            columns: 0,
+            rows: 0,
+            cursor: (0, 0),
+            foreground: Color::new(0xFF, 0xFF, 0xFF),
+            background: Color::new(0, 0, 0),
+            cells: Vec::new(),
+            dirty: Vec::new(),
+            scrolled: 0
+        };
+        console.set_font(font, scale);
+        console
+    }
+
+    /// Changes the font, resizing the grid to fit the area. The text is cleared.
+    pub fn set_font(&mut self, font: &'static (dyn Font + Sync), scale: usize) {
+        assert!(scale > 0, "scale should be at least 1");
+        let (width, height) = font.size();
+        self.font = font;
+        self.scale = scale;
+        self.columns = self.area.width / (width * scale);
+        self.rows = self.area.height / (height * scale);
+        self.cells = vec![self.blank(); self.columns * self.rows];
+        self.dirty = vec![true; self.rows];
+        self.cursor = (0, 0);
+    }
+
+    /// Sets the colors of text written from now on.
+    pub fn set_colors(&mut self, foreground: Color, background: Color) {
+        self.foreground = foreground;
+        self.background = background;
+    }
+
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Provides the cell the next character is written to, as `(column, row)`.
+    pub fn cursor(&self) -> (usize, usize) {
+        self.cursor
+    }
+
+    pub fn set_cursor(&mut self, cursor: (usize, usize)) {
+        self.cursor = (cursor.0.min(self.columns.saturating_sub(1)), cursor.1.min(self.rows.saturating_sub(1)));
+    }
+
+    /// Provides the character and colors at `(column, row)`.
+    pub fn get(&self, column: usize, row: usize) -> Cell {
+        self.cells[(row * self.columns) + column]
+    }
+
+    /// Provides the area of a cell in pixels.
+    pub fn cell(&self, column: usize, row: usize) -> Rect {
+        let (width, height) = self.cell_size();
+        Rect::new(self.area.x + (column * width), self.area.y + (row * height), width, height)
+    }
+
+    /// Empties the grid and moves the cursor to the top left, without drawing.
+    pub fn reset(&mut self) {
+        let blank = self.blank();
+        self.cells.fill(blank);
+        self.dirty.fill(true);
+        self.cursor = (0, 0);
+    }
+
+    /// Empties the grid and draws it, filling the area with the background color.
+    pub fn clear<V: FrameBufferView>(&mut self, view: &mut V) {
+        self.reset();
+        self.redraw(view);
+    }
+
+    /// Adds a character at the cursor without drawing it, handling `\n`, `\r`, `\t` and backspace.
+    pub fn push_char(&mut self, c: char) {
+        if self.columns == 0 || self.rows == 0 {
+            return;
+        }
+
+        match c {
+            '\n' => self.newline(),
+            '\r' => self.cursor.0 = 0,
+            '\t' => {
+                let next = (self.cursor.0 / TAB_WIDTH + 1) * TAB_WIDTH;
+                while self.cursor.0 < next.min(self.columns) {
+                    self.push_char(' ');
+                    if self.cursor.0 == 0 {
+                        break; // wrapped onto the next line
+                    }
+                }
+            }
+            '\x08' => {
+                if self.cursor.0 > 0 {
+                    self.cursor.0 -= 1;
+                    self.set(self.blank());
+                }
+            }
+            c => {
+                self.set(Cell { c, foreground: self.foreground, background: self.background });
+                self.cursor.0 += 1;
+                if self.cursor.0 == self.columns {
+                    self.newline();
+                }
+            }
+        }
+    }
+
+    /// Adds text at the cursor without drawing it.
+    pub fn push_str(&mut self, s: &str) {
+        for c in s.chars() {
+            self.push_char(c);
+        }
+    }
+
+    /// Writes a character at the cursor and draws the changes.
+    pub fn write_char<V: FrameBufferView>(&mut self, view: &mut V, c: char) {
+        self.push_char(c);
+        self.flush(view);
+    }
+
+    /// Writes text at the cursor and draws the changes.
+    pub fn write_str<V: FrameBufferView>(&mut self, view: &mut V, s: &str) {
+        self.push_str(s);
+        self.flush(view);
+    }
+
+    /// Borrows the console along with a view, for use with `write!`.
+    pub fn writer<'a, V: FrameBufferView>(&'a mut self, view: &'a mut V) -> Writer<'a, V> {
+        Writer { console: self, view }
+    }
+
+    /// Draws what changed since the last flush, scrolling the view along with the text.
+    pub fn flush<V: FrameBufferView>(&mut self, view: &mut V) {
+        if self.scrolled >= self.rows {
+            return self.redraw(view);
+        }
+
+        if self.scrolled > 0 {
+            let (width, height) = self.cell_size();
+            let shift = self.scrolled * height;
+            let text = Rect::new(self.area.x, self.area.y + shift, self.columns * width, (self.rows * height) - shift);
+            view.copy_rect(text, (self.area.x as isize, self.area.y as isize));
+            self.scrolled = 0;
+        }
+
+        for row in 0..self.rows {
+            if self.dirty[row] {
+                self.draw_row(view, row);
+                self.dirty[row] = false;
+            }
+        }
+    }
+
+    /// Draws the whole grid, e.g. after something else was shown on the view.
+    pub fn redraw<V: FrameBufferView>(&mut self, view: &mut V) {
+        view.fill_rect(self.area, self.background);
+        self.dirty.fill(true);
+        self.scrolled = 0;
+        self.flush(view);
+    }
+
+    fn draw_row<V: FrameBufferView>(&self, view: &mut V, row: usize) {
+        for column in 0..self.columns {
+            let cell = self.get(column, row);
+            let rect = self.cell(column, row);
+            match self.font.glyph_or_replacement(cell.c) {
+                Some(glyph) => glyph.draw(view, (rect.x, rect.y), self.scale, cell.foreground, Some(cell.background)),
+                None => view.fill_rect(rect, cell.background)
+            }
+        }
+    }
+
+    fn set(&mut self, cell: Cell) {
+        let (column, row) = self.cursor;
+        self.cells[(row * self.columns) + column] = cell;
+        self.dirty[row] = true;
+    }
+
+    fn newline(&mut self) {
+        self.cursor.0 = 0;
+        if self.cursor.1 + 1 < self.rows {
+            self.cursor.1 += 1;
+            return;
+        }
+
+        // scroll everything up by a row and clear the last one
+        self.cells.copy_within(self.columns.., 0);
+        let blank = self.blank();
+        let last = (self.rows - 1) * self.columns;
+        self.cells[last..].fill(blank);
+        self.dirty.copy_within(1.., 0);
+        self.dirty[self.rows - 1] = true;
+        self.scrolled += 1;
+    }
+
+    fn blank(&self) -> Cell {
+        Cell { c: ' ', foreground: self.foreground, background: self.background }
+    }
+
+    fn cell_size(&self) -> (usize, usize) {
+        let (width, height) = self.font.size();
+        (width * self.scale, height * self.scale)
+    }
+}
+
+impl Write for Console {
+    /// Adds text without drawing it, see [`flush`](Console::flush).
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push_str(s);
+        Ok(())
+    }
+}
+
+/// A console paired with the view it draws to.
+pub struct Writer<'a, V: FrameBufferView> {
+    console: &'a mut Console,
+    view: &'a mut V
+}
+
+impl<V: FrameBufferView> Write for Writer<'_, V> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.console.write_str(self.view, s);
+        Ok(())
+    }
+}
@@ -0,0 +1,57 @@
+//! Tracking of changed screen regions, so buffered views only copy what was drawn.
+
+use crate::render::Rect;
+
+/// Maximum number of separate regions tracked before they're collapsed into one.
+const MAX_RECTS: usize = 16;
+
+/// Regions of a view that changed since it was last presented.
+///
+/// Regions that touch are merged as they're added, and once too many separate ones are
+/// tracked, all of them collapse into their bounding box.
+#[derive(Debug, Default, Clone)]
+pub struct Damage {
+    rects: [Rect; MAX_RECTS],
+    len: usize
+}
+
+impl Damage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Marks a region as changed.
+    pub fn add(&mut self, rect: Rect) {
+        if rect.is_empty() {
+            return;
+        }
+
+        // grow an existing region if the new one touches it
+        if let Some(existing) = self.rects[..self.len].iter_mut().find(|r| r.touches(&rect)) {
+            *existing = existing.union(&rect);
+            return;
+        }
+
+        if self.len < MAX_RECTS {
+            self.rects[self.len] = rect;
+            self.len += 1;
+        } else {
+            let bounds = self.rects.iter().fold(rect, |acc, r| acc.union(r));
+            self.rects[0] = bounds;
+            self.len = 1;
+        }
+    }
+
+    /// Provides the changed regions, which may overlap.
+    pub fn rects(&self) -> &[Rect] {
+        &self.rects[..self.len]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+}
@@ -0,0 +1,173 @@
+//! Helpers for the shape drawing methods of [`FrameBufferView`](FrameBufferView), which clip
+//! shapes to the view's bounds.
+//!
+//! Coordinates are signed so shapes may extend past the edges of the screen,
+//! in which case only the visible part is drawn, without stepping through the rest.
+
+use core::ops::RangeInclusive;
+use crate::render::view::FrameBufferView;
+
+/// A position in pixels, which may lie outside of the view.
+pub type Point = (isize, isize);
+
+/// A line as drawn by Bresenham's algorithm, taking one step along its major axis per pixel.
+pub(crate) struct Line {
+    /// Whether the major axis is the vertical one.
+    steep: bool,
+    /// The start along the major and minor axes.
+    start: (i128, i128),
+    /// The direction of each axis, -1, 0 or 1.
+    sign: (i128, i128),
+    /// Distance covered along the minor axis, and the number of steps along the major one.
+    rise: u128,
+    run: u128
+}
+
+impl Line {
+    pub(crate) fn new(from: Point, to: Point) -> Self {
+        let (dx, dy) = (to.0 as i128 - from.0 as i128, to.1 as i128 - from.1 as i128);
+        let steep = dy.abs() > dx.abs();
+        let (start, delta) = if steep {
+            ((from.1 as i128, from.0 as i128), (dy, dx))
+        } else {
+            ((from.0 as i128, from.1 as i128), (dx, dy))
+        };
+        Self {
+            steep,
+            start,
+            sign: (delta.0.signum(), delta.1.signum()),
+            rise: delta.1.unsigned_abs(),
+            run: delta.0.unsigned_abs()
+        }
+    }
+
+    /// Provides the index of the last step, at the end of the line.
+    pub(crate) fn steps(&self) -> u128 {
+        self.run
+    }
+
+    /// Provides the distance along the minor axis at a step, rounded to the nearest pixel.
+    fn offset(&self, step: u128) -> u128 {
+        if self.run == 0 {
+            return 0;
+        }
+        // both are at most the run, so the product fits
+        let covered = step * self.rise;
+        let (offset, remainder) = (covered / self.run, covered % self.run);
+        offset + (remainder * 2 >= self.run) as u128
+    }
+
+    /// Provides the pixel at a step.
+    pub(crate) fn point(&self, step: u128) -> (i128, i128) {
+        let major = self.start.0 + self.sign.0 * step as i128;
+        let minor = self.start.1 + self.sign.1 * self.offset(step) as i128;
+        if self.steep { (minor, major) } else { (major, minor) }
+    }
+
+    /// Provides the point on the ideal line at a step, without rounding to a pixel.
+    pub(crate) fn exact(&self, step: u128) -> (f32, f32) {
+        let major = (self.start.0 + self.sign.0 * step as i128) as f32;
+        let fraction = if self.run == 0 { 0.0 } else { step as f32 * self.rise as f32 / self.run as f32 };
+        let minor = self.start.1 as f32 + self.sign.1 as f32 * fraction;
+        if self.steep { (minor, major) } else { (major, minor) }
+    }
+
+    /// Provides the steps whose pixels lie within `(0, 0)` to `size`, which are consecutive
+    /// since the line is straight.
+    pub(crate) fn visible(&self, size: (usize, usize)) -> Option<RangeInclusive<u128>> {
+        let (major_len, minor_len) = if self.steep { (size.1, size.0) } else { (size.0, size.1) };
+        if major_len == 0 || minor_len == 0 {
+            return None;
+        }
+
+        // the steps within the view along the major axis, which moves by one per step
+        let (first, last) = axis_range(self.start.0, self.sign.0, major_len as i128);
+        let first = first.max(0) as u128;
+        let last = last.min(self.run as i128);
+        if last < first as i128 {
+            return None;
+        }
+        let last = last as u128;
+
+        // the offsets along the minor axis within the view, which only ever grow with the steps
+        let (low, high) = axis_range(self.start.1, self.sign.1, minor_len as i128);
+        if high < 0 || self.offset(first) as i128 > high || (self.offset(last) as i128) < low {
+            return None;
+        }
+        let start = partition(first, last, |step| (self.offset(step) as i128) < low);
+        let end = partition(first, last, |step| self.offset(step) as i128 <= high) - 1;
+        (start <= end).then_some(start..=end)
+    }
+}
+
+/// Provides the distances from `start` in the direction of `sign` that lie within `0..len`,
+/// as an inclusive range which may be empty or negative.
+fn axis_range(start: i128, sign: i128, len: i128) -> (i128, i128) {
+    match sign {
+        -1 => (start - (len - 1), start),
+        0 if (0..len).contains(&start) => (0, i128::MAX),
+        0 => (1, 0),
+        _ => (-start, len - 1 - start)
+    }
+}
+
+/// Finds the first step in `first..=last` for which `predicate` no longer holds, given that it
+/// holds for all steps before that one, or `last + 1` if it holds throughout.
+fn partition(first: u128, last: u128, predicate: impl Fn(u128) -> bool) -> u128 {
+    let (mut low, mut high) = (first, last + 1);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        if predicate(middle) {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+    low
+}
+
+/// Limits a range of rows to the ones within the view.
+pub(crate) fn clip_rows<V: FrameBufferView + ?Sized>(view: &V, top: isize, bottom: isize) -> RangeInclusive<isize> {
+    top.max(0)..=bottom.min(view.height() as isize - 1)
+}
+
+/// Provides where the edge between two points crosses a row, which has to lie between them.
+pub(crate) fn crossing(from: Point, to: Point, y: isize) -> isize {
+    let (x0, y0, x1, y1) = (from.0 as i128, from.1 as i128, to.0 as i128, to.1 as i128);
+    let x = x0 + (y as i128 - y0) * (x1 - x0) / (y1 - y0);
+    x.clamp(isize::MIN as i128, isize::MAX as i128) as isize
+}
+
+/// Provides the distance from the center along one axis of an ellipse at a given
+/// distance along the other, i.e. `b * sqrt(1 - (d / a)^2)`.
+pub(crate) fn ellipse_extent(d: usize, a: usize, b: usize) -> usize {
+    if a == 0 {
+        return if d == 0 { b } else { 0 };
+    }
+    let (d, a, b) = (d as u128, a as u128, b as u128);
+    if d > a {
+        return 0;
+    }
+    let remaining = a * a - d * d;
+    match (b * b).checked_mul(remaining) {
+        Some(product) => isqrt(product / (a * a)) as usize,
+        // only for radii far past any screen, where the lost precision doesn't show
+        None => (b * isqrt(remaining) / a) as usize
+    }
+}
+
+/// Integer square root, rounded down.
+fn isqrt(n: u128) -> u128 {
+    if n < 2 {
+        return n;
+    }
+    // Newton's method, starting above the root
+    let mut x = n;
+    loop {
+        let y = (x + n / x) / 2;
+        if y >= x {
+            return x;
+        }
+        x = y;
+    }
+}
@@ -0,0 +1,144 @@
+//! Bitmap fonts for rendering text.
+//!
+//! The built-in [`BASIC`](BASIC) font is always available, while PC Screen Fonts (see [`psf`](psf))
+//! can be loaded from bytes, e.g. embedded with `include_bytes!` or read from a file.
+//!
+//! The terminals use [`TOKYO`](TOKYO), an 8x16 PC Screen Font drawn for the kernel and embedded
+//! in it, which is easier to read than `BASIC` once enlarged on large screens.
+
+pub mod psf;
+
+use alloc::vec::Vec;
+use font8x8::UnicodeFonts;
+use spin::Lazy;
+use crate::render::{Color, Rect};
+use crate::render::view::FrameBufferView;
+use self::psf::Psf;
+
+/// A monospaced bitmap font.
+pub trait Font {
+    /// Provides the size of every glyph in pixels.
+    fn size(&self) -> (usize, usize);
+
+    /// Provides the glyph of a character, or `None` if the font doesn't have one.
+    fn glyph(&self, c: char) -> Option<Glyph<'_>>;
+
+    /// Provides the glyph of a character, falling back to a replacement character.
+    fn glyph_or_replacement(&self, c: char) -> Option<Glyph<'_>> {
+        self.glyph(c)
+            .or_else(|| self.glyph(char::REPLACEMENT_CHARACTER))
+            .or_else(|| self.glyph('?'))
+    }
+}
+
+/// The pixels of a single character, where every set bit is drawn in the foreground color.
+///
+/// Rows are stored from the top, each padded to whole bytes, with the leftmost pixel
+/// in the most significant bit.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Glyph<'a> {
+    width: usize,
+    height: usize,
+    bits: Bits<'a>
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+enum Bits<'a> {
+    Borrowed(&'a [u8]),
+    Inline([u8; 8])
+}
+
+impl<'a> Glyph<'a> {
+    /// Create a glyph from `height` rows of `width` pixels, each padded to whole bytes.
+    pub fn new(width: usize, height: usize, bits: &'a [u8]) -> Self {
+        assert!(bits.len() >= width.div_ceil(8) * height, "glyph should hold every row");
+        Self { width, height, bits: Bits::Borrowed(bits) }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Checks whether the pixel at `(x, y)` is drawn in the foreground color.
+    pub fn is_set(&self, x: usize, y: usize) -> bool {
+        let bits = match &self.bits {
+            Bits::Borrowed(bits) => bits,
+            Bits::Inline(bits) => &bits[..]
+        };
+        let row = y * self.width.div_ceil(8);
+        bits[row + (x / 8)] & (0x80 >> (x % 8)) != 0
+    }
+
+    /// Draws the glyph with its top left corner at `pos`, with every pixel enlarged to
+    /// `scale` by `scale` pixels.
+    ///
+    /// Pixels not set are drawn in `background`, or left untouched if there is none.
+    pub fn draw<V: FrameBufferView>(
+        &self,
+        view: &mut V,
+        pos: (usize, usize),
+        scale: usize,
+        foreground: Color,
+        background: Option<Color>
+    ) {
+        let Some(background) = background else {
+            for y in 0..self.height {
+                for x in (0..self.width).filter(|&x| self.is_set(x, y)) {
+                    let rect = Rect::new(pos.0 + (x * scale), pos.1 + (y * scale), scale, scale);
+                    view.fill_rect(rect, foreground);
+                }
+            }
+            return;
+        };
+
+        // build each row once, then copy it for every scaled row
+        let (foreground, background) = (view.pack(foreground), view.pack(background));
+        let mut row = Vec::with_capacity(self.width * scale * foreground.as_bytes().len());
+        for y in 0..self.height {
+            row.clear();
+            for x in 0..self.width {
+                let pixel = if self.is_set(x, y) { foreground } else { background };
+                for _ in 0..scale {
+                    row.extend_from_slice(pixel.as_bytes());
+                }
+            }
+            for i in 0..scale {
+                let dest = (pos.0 as isize, (pos.1 + (y * scale) + i) as isize);
+                view.blit(dest, (self.width * scale, 1), &row);
+            }
+        }
+    }
+}
+
+/// The 8x16 font of the terminals, covering ASCII, the letters of Latin-1, box drawing and block elements.
+pub static TOKYO: Lazy<Psf<'static>> = Lazy::new(|| {
+    Psf::parse(include_bytes!("tokyo.psf")).expect("embedded font should be valid")
+});
+
+/// The 8x8 font built into the kernel, covering ASCII, Latin-1, Greek, box drawing and block elements.
+pub static BASIC: Basic = Basic;
+
+/// A font backed by the `font8x8` crate.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Basic;
+
+impl Font for Basic {
+    fn size(&self) -> (usize, usize) {
+        (8, 8)
+    }
+
+    fn glyph(&self, c: char) -> Option<Glyph<'_>> {
+        let bits = font8x8::BASIC_FONTS.get(c)
+            .or_else(|| font8x8::LATIN_FONTS.get(c))
+            .or_else(|| font8x8::BOX_FONTS.get(c))
+            .or_else(|| font8x8::BLOCK_FONTS.get(c))
+            .or_else(|| font8x8::GREEK_FONTS.get(c))?;
+
+        // font8x8 stores the leftmost pixel in the least significant bit
+        Some(Glyph { width: 8, height: 8, bits: Bits::Inline(bits.map(u8::reverse_bits)) })
+    }
+}
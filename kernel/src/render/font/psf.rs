@@ -0,0 +1,198 @@
+//! PC Screen Fonts, the bitmap console fonts used by Linux, in both versions 1 and 2.
+//!
+//! Fonts may carry a Unicode table mapping characters to glyphs. Fonts without one
+//! map each character to the glyph at the index of its code point.
+
+use alloc::collections::BTreeMap;
+use crate::render::font::{Font, Glyph};
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_HEADER_SIZE: usize = 4;
+const PSF1_MODE_512: u8 = 0x01;
+const PSF1_MODE_HAS_TABLE: u8 = 0x06; // either of the table flags
+const PSF1_SEPARATOR: u16 = 0xFFFF;
+const PSF1_SEQUENCE: u16 = 0xFFFE;
+
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xB5, 0x4A, 0x86];
+const PSF2_HEADER_SIZE: usize = 32;
+const PSF2_HAS_TABLE: u32 = 0x01;
+const PSF2_SEPARATOR: u8 = 0xFF;
+const PSF2_SEQUENCE: u8 = 0xFE;
+
+/// Reasons a font could not be parsed.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum ParseError {
+    /// The data doesn't start with either PSF signature.
+    UnknownFormat,
+    /// The data ends before the font does.
+    Truncated,
+    /// The header or Unicode table is malformed.
+    Invalid
+}
+
+/// A parsed PC Screen Font, borrowing its glyphs from the font data.
+#[derive(Debug, Clone)]
+pub struct Psf<'a> {
+    glyphs: &'a [u8],
+    width: usize,
+    height: usize,
+    /// Bytes per glyph.
+    glyph_size: usize,
+    length: usize,
+    unicode: Option<BTreeMap<char, usize>>
+}
+
+impl<'a> Psf<'a> {
+    /// Parses a font in either version.
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, ParseError> {
+        if bytes.starts_with(&PSF2_MAGIC) {
+            Self::parse_v2(bytes)
+        } else if bytes.starts_with(&PSF1_MAGIC) {
+            Self::parse_v1(bytes)
+        } else {
+            Err(ParseError::UnknownFormat)
+        }
+    }
+
+    fn parse_v1(bytes: &'a [u8]) -> Result<Self, ParseError> {
+        let header = bytes.get(..PSF1_HEADER_SIZE).ok_or(ParseError::Truncated)?;
+        let (mode, height) = (header[2], header[3] as usize);
+        if height == 0 {
+            return Err(ParseError::Invalid);
+        }
+        let length = if mode & PSF1_MODE_512 != 0 { 512 } else { 256 };
+
+        let end = PSF1_HEADER_SIZE + (length * height);
+        let glyphs = bytes.get(PSF1_HEADER_SIZE..end).ok_or(ParseError::Truncated)?;
+
+        let unicode = if mode & PSF1_MODE_HAS_TABLE != 0 {
+            let table = &bytes[end..];
+            if table.len() % 2 != 0 {
+                return Err(ParseError::Truncated);
+            }
+            let entries = table.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]]));
+            Some(unicode_v1(entries, length)?)
+        } else {
+            None
+        };
+
+        Ok(Self { glyphs, width: 8, height, glyph_size: height, length, unicode })
+    }
+
+    fn parse_v2(bytes: &'a [u8]) -> Result<Self, ParseError> {
+        let header = bytes.get(..PSF2_HEADER_SIZE).ok_or(ParseError::Truncated)?;
+        let field = |i: usize| u32::from_le_bytes([header[i], header[i + 1], header[i + 2], header[i + 3]]) as usize;
+        let (header_size, flags, length) = (field(8), field(12) as u32, field(16));
+        let (glyph_size, height, width) = (field(20), field(24), field(28));
+        if width == 0 || height == 0 || glyph_size < width.div_ceil(8) * height {
+            return Err(ParseError::Invalid);
+        }
+
+        let end = length.checked_mul(glyph_size)
+            .and_then(|size| size.checked_add(header_size))
+            .ok_or(ParseError::Invalid)?;
+        let glyphs = bytes.get(header_size..end).ok_or(ParseError::Truncated)?;
+
+        let unicode = if flags & PSF2_HAS_TABLE != 0 {
+            Some(unicode_v2(&bytes[end..], length)?)
+        } else {
+            None
+        };
+
+        Ok(Self { glyphs, width, height, glyph_size, length, unicode })
+    }
+
+    /// Provides the number of glyphs in the font.
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// Provides the glyph at an index, regardless of which character it represents.
+    pub fn glyph_at(&self, index: usize) -> Option<Glyph<'a>> {
+        let start = index.checked_mul(self.glyph_size)?;
+        let bits = self.glyphs.get(start..start + self.glyph_size)?;
+        Some(Glyph::new(self.width, self.height, bits))
+    }
+}
+
+impl Font for Psf<'_> {
+    fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    fn glyph(&self, c: char) -> Option<Glyph<'_>> {
+        let index = match &self.unicode {
+            Some(unicode) => *unicode.get(&c)?,
+            None => c as usize
+        };
+        self.glyph_at(index)
+    }
+}
+
+/// Reads a version 1 table of UCS-2 characters, with the entries of each glyph ending in a separator.
+fn unicode_v1(entries: impl Iterator<Item = u16>, length: usize) -> Result<BTreeMap<char, usize>, ParseError> {
+    let mut unicode = BTreeMap::new();
+    let mut glyph = 0;
+    let mut in_sequence = false;
+    for entry in entries {
+        match entry {
+            PSF1_SEPARATOR => {
+                glyph += 1;
+                in_sequence = false;
+            }
+            // sequences of combining characters aren't supported, so skip them
+            PSF1_SEQUENCE => in_sequence = true,
+            _ if in_sequence => {}
+            _ if glyph < length => {
+                let c = char::from_u32(entry as u32).ok_or(ParseError::Invalid)?;
+                unicode.entry(c).or_insert(glyph);
+            }
+            _ => return Err(ParseError::Invalid)
+        }
+    }
+    Ok(unicode)
+}
+
+/// Reads a version 2 table of UTF-8 characters, with the entries of each glyph ending in a separator.
+fn unicode_v2(mut table: &[u8], length: usize) -> Result<BTreeMap<char, usize>, ParseError> {
+    let mut unicode = BTreeMap::new();
+    let mut glyph = 0;
+    let mut in_sequence = false;
+    while let Some(&byte) = table.first() {
+        match byte {
+            PSF2_SEPARATOR => {
+                glyph += 1;
+                in_sequence = false;
+                table = &table[1..];
+            }
+            PSF2_SEQUENCE => {
+                in_sequence = true;
+                table = &table[1..];
+            }
+            _ => {
+                // the length of a UTF-8 character follows from its first byte
+                let len = match byte.leading_ones() {
+                    0 => 1,
+                    len @ 2..=4 => len as usize,
+                    _ => return Err(ParseError::Invalid)
+                };
+                let bytes = table.get(..len).ok_or(ParseError::Truncated)?;
+                let c = core::str::from_utf8(bytes).map_err(|_| ParseError::Invalid)?
+                    .chars().next().ok_or(ParseError::Invalid)?;
+                table = &table[len..];
+
+                if glyph >= length {
+                    return Err(ParseError::Invalid);
+                }
+                if !in_sequence {
+                    unicode.entry(c).or_insert(glyph);
+                }
+            }
+        }
+    }
+    Ok(unicode)
+}
@@ -0,0 +1,132 @@
+//! Uncompressed Windows bitmaps, with 1, 4 or 8 bit palettes or 16, 24 or 32 bit pixels.
+
+use alloc::vec::Vec;
+use crate::render::Rgba;
+use crate::render::image::{check_size, Bitmap, DecodeError};
+
+const FILE_HEADER_SIZE: usize = 14;
+const INFO_HEADER_SIZE: u32 = 40; // BITMAPINFOHEADER, later versions only append to it
+
+// compression methods
+const RGB: u32 = 0;
+const BITFIELDS: u32 = 3;
+const ALPHA_BITFIELDS: u32 = 6;
+
+/// Decodes a BMP file.
+pub fn decode(bytes: &[u8]) -> Result<Bitmap, DecodeError> {
+    if bytes.get(..2) != Some(b"BM") {
+        return Err(DecodeError::UnknownFormat);
+    }
+    let data_offset = u32_at(bytes, 10)? as usize;
+    let header_size = u32_at(bytes, 14)?;
+    if header_size < INFO_HEADER_SIZE {
+        return Err(DecodeError::Unsupported); // OS/2 headers
+    }
+
+    let width = i32_at(bytes, 18)?;
+    let height = i32_at(bytes, 22)?;
+    let bits = u16_at(bytes, 28)? as usize;
+    let compression = u32_at(bytes, 30)?;
+    let colors_used = u32_at(bytes, 46)? as usize;
+
+    // rows are stored from the bottom up, unless the height is negative
+    let top_down = height < 0;
+    if width < 0 {
+        return Err(DecodeError::Invalid);
+    }
+    let (width, height) = (width as usize, height.unsigned_abs() as usize);
+    let count = check_size(width, height)?;
+
+    let format = match (bits, compression) {
+        (1 | 4 | 8, RGB) => {
+            let entries = if colors_used == 0 { 1 << bits } else { colors_used.min(1 << bits) };
+            let start = FILE_HEADER_SIZE + header_size as usize;
+            let palette = bytes.get(start..start + (entries * 4)).ok_or(DecodeError::Truncated)?;
+            Format::Palette(palette.chunks_exact(4).map(|c| Rgba::new(c[2], c[1], c[0], u8::MAX)).collect())
+        }
+        (16, RGB) => Format::Masks([0x7C00, 0x03E0, 0x001F, 0]),
+        (24 | 32, RGB) => Format::Bgr,
+        (16 | 32, BITFIELDS | ALPHA_BITFIELDS) => {
+            // the masks follow a BITMAPINFOHEADER, or are part of the later headers
+            let alpha = if header_size >= 56 || compression == ALPHA_BITFIELDS { u32_at(bytes, 66)? } else { 0 };
+            Format::Masks([u32_at(bytes, 54)?, u32_at(bytes, 58)?, u32_at(bytes, 62)?, alpha])
+        }
+        _ => return Err(DecodeError::Unsupported)
+    };
+
+    // rows are padded to 4 bytes
+    let stride = (width * bits).div_ceil(32) * 4;
+    let data = bytes.get(data_offset..).ok_or(DecodeError::Truncated)?;
+    if data.len() < stride * height {
+        return Err(DecodeError::Truncated);
+    }
+
+    let mut pixels = Vec::with_capacity(count);
+    for y in 0..height {
+        let row = if top_down { y } else { height - 1 - y };
+        let row = &data[row * stride..(row + 1) * stride];
+        for x in 0..width {
+            pixels.push(format.pixel(row, x, bits)?);
+        }
+    }
+    Ok(Bitmap::new(width, height, pixels))
+}
+
+/// How the bits of a pixel map to a color.
+enum Format {
+    Palette(Vec<Rgba>),
+    Bgr,
+    /// Red, green, blue and alpha masks, where an empty alpha mask means the image is opaque.
+    Masks([u32; 4])
+}
+
+impl Format {
+    fn pixel(&self, row: &[u8], x: usize, bits: usize) -> Result<Rgba, DecodeError> {
+        match self {
+            Format::Palette(palette) => {
+                // pixels are packed from the most significant bit
+                let bit = x * bits;
+                let index = (row[bit / 8] >> (8 - bits - (bit % 8))) & ((1u16 << bits) - 1) as u8;
+                palette.get(index as usize).copied().ok_or(DecodeError::Invalid)
+            }
+            Format::Bgr => {
+                let i = x * (bits / 8);
+                Ok(Rgba::new(row[i + 2], row[i + 1], row[i], u8::MAX))
+            }
+            Format::Masks(masks) => {
+                let i = x * (bits / 8);
+                let value = if bits == 16 {
+                    u16::from_le_bytes([row[i], row[i + 1]]) as u32
+                } else {
+                    u32::from_le_bytes([row[i], row[i + 1], row[i + 2], row[i + 3]])
+                };
+                let alpha = if masks[3] == 0 { u8::MAX } else { channel(value, masks[3]) };
+                Ok(Rgba::new(channel(value, masks[0]), channel(value, masks[1]), channel(value, masks[2]), alpha))
+            }
+        }
+    }
+}
+
+/// Extracts the bits selected by a mask, scaled to the range of a byte.
+fn channel(value: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+    let max = mask >> mask.trailing_zeros();
+    let value = (value & mask) >> mask.trailing_zeros();
+    ((value as u64 * 255 + (max as u64 / 2)) / max as u64) as u8
+}
+
+fn u16_at(bytes: &[u8], pos: usize) -> Result<u16, DecodeError> {
+    let bytes = bytes.get(pos..pos + 2).ok_or(DecodeError::Truncated)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn u32_at(bytes: &[u8], pos: usize) -> Result<u32, DecodeError> {
+    let bytes = bytes.get(pos..pos + 4).ok_or(DecodeError::Truncated)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn i32_at(bytes: &[u8], pos: usize) -> Result<i32, DecodeError> {
+    u32_at(bytes, pos).map(|value| value as i32)
+}
@@ -0,0 +1,135 @@
+//! Decoding images into bitmaps that can be drawn onto any frame buffer view.
+//!
+//! Supported are uncompressed BMP, PPM (`P3` and `P6`) and QOI, which is enough for
+//! boot logos and screenshots without pulling in a general purpose image library.
+
+pub mod bmp;
+pub mod ppm;
+pub mod qoi;
+
+use alloc::vec::Vec;
+use crate::render::{Rect, Rgba};
+use crate::render::blend::BlendMode;
+use crate::render::draw::Point;
+use crate::render::view::FrameBufferView;
+
+/// The largest number of pixels an image may have, which keeps a bad header from exhausting the heap.
+pub const MAX_PIXELS: usize = 2048 * 2048;
+
+/// Reasons an image could not be decoded.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum DecodeError {
+    /// The data doesn't start with the signature of any supported format.
+    UnknownFormat,
+    /// The data ends before the image does.
+    Truncated,
+    /// The image uses a feature of its format that isn't supported, e.g. compression.
+    Unsupported,
+    /// The header or data is malformed.
+    Invalid
+}
+
+/// An image held in memory, with its pixels stored row by row from the top left.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Bitmap {
+    width: usize,
+    height: usize,
+    pixels: Vec<Rgba>,
+    opaque: bool
+}
+
+impl Bitmap {
+    /// Create a bitmap from `width * height` pixels.
+    pub fn new(width: usize, height: usize, pixels: Vec<Rgba>) -> Self {
+        assert_eq!(pixels.len(), width * height, "pixel count should match the size");
+        let opaque = pixels.iter().all(|pixel| pixel.alpha == u8::MAX);
+        Self { width, height, pixels, opaque }
+    }
+
+    /// Decodes an image, detecting its format from the first few bytes.
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        match bytes {
+            [b'B', b'M', ..] => bmp::decode(bytes),
+            [b'P', b'3' | b'6', ..] => ppm::decode(bytes),
+            [b'q', b'o', b'i', b'f', ..] => qoi::decode(bytes),
+            _ => Err(DecodeError::UnknownFormat)
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[Rgba] {
+        &self.pixels
+    }
+
+    /// Provides the pixel at `(x, y)`, panicking if it lies outside of the bitmap.
+    pub fn pixel(&self, pos: (usize, usize)) -> Rgba {
+        let (x, y) = pos;
+        assert!(x < self.width && y < self.height);
+        self.pixels[(y * self.width) + x]
+    }
+
+    /// Draws the bitmap at its own size with its top left corner at `pos`, clipped to the view.
+    pub fn draw<V: FrameBufferView>(&self, view: &mut V, pos: Point) {
+        self.draw_scaled(view, pos, (self.width, self.height));
+    }
+
+    /// Draws the bitmap stretched to `size` using nearest-neighbor sampling, clipped to the view.
+    ///
+    /// Opaque bitmaps are copied row by row, while translucent ones are blended onto the view.
+    pub fn draw_scaled<V: FrameBufferView>(&self, view: &mut V, pos: Point, size: (usize, usize)) {
+        if self.pixels.is_empty() {
+            return;
+        }
+
+        // the visible part of the destination, in view coordinates
+        let left = pos.0.max(0) as usize;
+        let top = pos.1.max(0) as usize;
+        let right = (pos.0 + size.0 as isize).max(0) as usize;
+        let bottom = (pos.1 + size.1 as isize).max(0) as usize;
+        let visible = Rect::new(left, top, right.saturating_sub(left), bottom.saturating_sub(top))
+            .intersection(&view.bounds());
+        if visible.is_empty() {
+            return;
+        }
+
+        // maps a view coordinate back to the bitmap
+        let source_x = |x: usize| (x as isize - pos.0) as usize * self.width / size.0;
+        let source_y = |y: usize| (y as isize - pos.1) as usize * self.height / size.1;
+
+        if !self.opaque {
+            for y in visible.y..visible.bottom() {
+                for x in visible.x..visible.right() {
+                    let pixel = self.pixel((source_x(x), source_y(y)));
+                    view.blend_pixel((x, y), pixel, BlendMode::SourceOver);
+                }
+            }
+            return;
+        }
+
+        let bytes_per_pixel = view.info().bytes_per_pixel;
+        let mut row = Vec::with_capacity(visible.width * bytes_per_pixel);
+        for y in visible.y..visible.bottom() {
+            row.clear();
+            let start = source_y(y) * self.width;
+            for x in visible.x..visible.right() {
+                row.extend_from_slice(view.pack(self.pixels[start + source_x(x)].rgb()).as_bytes());
+            }
+            view.blit((visible.x as isize, y as isize), (visible.width, 1), &row);
+        }
+    }
+}
+
+/// Checks image dimensions before anything is allocated for them, providing the pixel count.
+fn check_size(width: usize, height: usize) -> Result<usize, DecodeError> {
+    match width.checked_mul(height) {
+        Some(pixels) if pixels <= MAX_PIXELS => Ok(pixels),
+        _ => Err(DecodeError::Unsupported)
+    }
+}
@@ -0,0 +1,83 @@
+//! Netpbm pixmaps, both binary (`P6`) and plain (`P3`).
+
+use alloc::vec::Vec;
+use crate::render::Rgba;
+use crate::render::image::{check_size, Bitmap, DecodeError};
+
+/// Decodes a PPM file.
+pub fn decode(bytes: &[u8]) -> Result<Bitmap, DecodeError> {
+    let mut tokens = Tokens { bytes, pos: 0 };
+    let binary = match tokens.next() {
+        Some(b"P6") => true,
+        Some(b"P3") => false,
+        _ => return Err(DecodeError::UnknownFormat)
+    };
+    let width = tokens.number()?;
+    let height = tokens.number()?;
+    let max = tokens.number()?;
+    if max == 0 || max > u16::MAX as usize {
+        return Err(DecodeError::Invalid);
+    }
+    let count = check_size(width, height)?;
+
+    // samples are scaled from 0..=max to a byte
+    let scale = |sample: usize| -> Result<u8, DecodeError> {
+        if sample > max {
+            return Err(DecodeError::Invalid);
+        }
+        Ok(((sample * 255 + (max / 2)) / max) as u8)
+    };
+
+    let mut samples = Vec::with_capacity(count * 3);
+    if binary {
+        // a single whitespace character separates the header from the samples
+        let data = bytes.get(tokens.pos + 1..).ok_or(DecodeError::Truncated)?;
+        let size = if max > u8::MAX as usize { 2 } else { 1 };
+        let data = data.get(..count * 3 * size).ok_or(DecodeError::Truncated)?;
+        for sample in data.chunks_exact(size) {
+            let sample = sample.iter().fold(0, |value, &byte| (value << 8) | byte as usize);
+            samples.push(scale(sample)?);
+        }
+    } else {
+        for _ in 0..count * 3 {
+            samples.push(scale(tokens.number()?)?);
+        }
+    }
+
+    let pixels = samples.chunks_exact(3).map(|c| Rgba::new(c[0], c[1], c[2], u8::MAX)).collect();
+    Ok(Bitmap::new(width, height, pixels))
+}
+
+/// Splits the header and plain samples into whitespace separated tokens, skipping comments.
+struct Tokens<'a> {
+    bytes: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Tokens<'a> {
+    fn next(&mut self) -> Option<&'a [u8]> {
+        loop {
+            match self.bytes.get(self.pos)? {
+                byte if byte.is_ascii_whitespace() => self.pos += 1,
+                b'#' => {
+                    while self.bytes.get(self.pos).is_some_and(|&byte| byte != b'\n') {
+                        self.pos += 1;
+                    }
+                }
+                _ => break
+            }
+        }
+
+        let start = self.pos;
+        while self.bytes.get(self.pos).is_some_and(|byte| !byte.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+        Some(&self.bytes[start..self.pos])
+    }
+
+    fn number(&mut self) -> Result<usize, DecodeError> {
+        let token = self.next().ok_or(DecodeError::Truncated)?;
+        let digits = core::str::from_utf8(token).map_err(|_| DecodeError::Invalid)?;
+        digits.parse().map_err(|_| DecodeError::Invalid)
+    }
+}
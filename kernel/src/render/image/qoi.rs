@@ -0,0 +1,80 @@
+//! The Quite OK Image format, see <https://qoiformat.org/qoi-specification.pdf>.
+
+use alloc::vec::Vec;
+use crate::render::Rgba;
+use crate::render::image::{check_size, Bitmap, DecodeError};
+
+const HEADER_SIZE: usize = 14;
+
+// 8-bit tags, checked before the 2-bit ones
+const OP_RGB: u8 = 0xFE;
+const OP_RGBA: u8 = 0xFF;
+
+// 2-bit tags
+const OP_INDEX: u8 = 0x00;
+const OP_DIFF: u8 = 0x40;
+const OP_LUMA: u8 = 0x80;
+const OP_RUN: u8 = 0xC0;
+const TAG_MASK: u8 = 0xC0;
+
+/// Decodes a QOI file.
+pub fn decode(bytes: &[u8]) -> Result<Bitmap, DecodeError> {
+    let header = bytes.get(..HEADER_SIZE).ok_or(DecodeError::Truncated)?;
+    if &header[..4] != b"qoif" {
+        return Err(DecodeError::UnknownFormat);
+    }
+    let width = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    let height = u32::from_be_bytes([header[8], header[9], header[10], header[11]]) as usize;
+    if !matches!(header[12], 3 | 4) {
+        return Err(DecodeError::Invalid); // channels, the color space is only informative
+    }
+    let count = check_size(width, height)?;
+
+    let mut pixels = Vec::with_capacity(count);
+    let mut index = [Rgba::new(0, 0, 0, 0); 64];
+    let mut pixel = Rgba::new(0, 0, 0, u8::MAX);
+    let mut data = bytes[HEADER_SIZE..].iter().copied();
+    let mut next = || data.next().ok_or(DecodeError::Truncated);
+
+    while pixels.len() < count {
+        let op = next()?;
+        match op {
+            OP_RGB => pixel = Rgba::new(next()?, next()?, next()?, pixel.alpha),
+            OP_RGBA => pixel = Rgba::new(next()?, next()?, next()?, next()?),
+            _ => match op & TAG_MASK {
+                OP_INDEX => pixel = index[op as usize],
+                OP_DIFF => {
+                    // differences are stored with a bias of 2
+                    pixel.red = pixel.red.wrapping_add((op >> 4) & 0x03).wrapping_sub(2);
+                    pixel.green = pixel.green.wrapping_add((op >> 2) & 0x03).wrapping_sub(2);
+                    pixel.blue = pixel.blue.wrapping_add(op & 0x03).wrapping_sub(2);
+                }
+                OP_LUMA => {
+                    let green = (op & 0x3F).wrapping_sub(32);
+                    let byte = next()?;
+                    pixel.red = pixel.red.wrapping_add(green).wrapping_add(byte >> 4).wrapping_sub(8);
+                    pixel.green = pixel.green.wrapping_add(green);
+                    pixel.blue = pixel.blue.wrapping_add(green).wrapping_add(byte & 0x0F).wrapping_sub(8);
+                }
+                OP_RUN => {
+                    // the run includes the pixel below, which is pushed after
+                    let run = (op & 0x3F) as usize;
+                    for _ in 0..run.min(count - pixels.len() - 1) {
+                        pixels.push(pixel);
+                    }
+                }
+                _ => unreachable!()
+            }
+        }
+
+        index[hash(pixel)] = pixel;
+        pixels.push(pixel);
+    }
+
+    Ok(Bitmap::new(width, height, pixels))
+}
+
+fn hash(pixel: Rgba) -> usize {
+    let Rgba { red, green, blue, alpha } = pixel;
+    (red as usize * 3 + green as usize * 5 + blue as usize * 7 + alpha as usize * 11) % 64
+}
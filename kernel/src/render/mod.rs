@@ -1,4 +1,14 @@
+#[cfg(feature = "bench")]
+pub mod bench;
+pub mod blend;
+pub mod compositor;
+pub mod console;
+pub mod damage;
+pub mod draw;
+pub mod font;
+pub mod image;
 pub mod pixel;
+pub mod surface;
 pub mod view;
 
 use core::cell::OnceCell;
@@ -53,4 +63,99 @@ impl From<(u8, u8, u8)> for Color {
     fn from(value: (u8, u8, u8)) -> Self {
         Color::new(value.0, value.1, value.2)
     }
-}
\ No newline at end of file
+}
+
+/// An RGB color with an alpha channel, where an alpha of 0 is fully transparent.
+#[derive(Debug, Default, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct Rgba {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+    pub alpha: u8
+}
+
+impl Rgba {
+    pub fn new(red: u8, green: u8, blue: u8, alpha: u8) -> Self {
+        Self { red, green, blue, alpha }
+    }
+
+    /// Drops the alpha channel.
+    pub fn rgb(&self) -> Color {
+        Color::new(self.red, self.green, self.blue)
+    }
+}
+
+impl From<Color> for Rgba {
+    /// Colors without an alpha channel are opaque.
+    fn from(value: Color) -> Self {
+        Rgba::new(value.red, value.green, value.blue, u8::MAX)
+    }
+}
+
+impl From<(u8, u8, u8, u8)> for Rgba {
+    fn from(value: (u8, u8, u8, u8)) -> Self {
+        Rgba::new(value.0, value.1, value.2, value.3)
+    }
+}
+
+/// A rectangle measured in pixels, with its origin at the top left.
+#[derive(Debug, Default, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize
+}
+
+impl Rect {
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Self { x, y, width, height }
+    }
+
+    /// The first column to the right of the rectangle.
+    pub fn right(&self) -> usize {
+        self.x + self.width
+    }
+
+    /// The first row below the rectangle.
+    pub fn bottom(&self) -> usize {
+        self.y + self.height
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    pub fn contains(&self, other: &Rect) -> bool {
+        other.x >= self.x && other.y >= self.y
+            && other.right() <= self.right() && other.bottom() <= self.bottom()
+    }
+
+    /// Checks whether the rectangles overlap or share an edge.
+    pub fn touches(&self, other: &Rect) -> bool {
+        self.x <= other.right() && other.x <= self.right()
+            && self.y <= other.bottom() && other.y <= self.bottom()
+    }
+
+    /// The smallest rectangle containing both rectangles.
+    pub fn union(&self, other: &Rect) -> Rect {
+        if self.is_empty() {
+            return *other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Rect::new(x, y, self.right().max(other.right()) - x, self.bottom().max(other.bottom()) - y)
+    }
+
+    /// The overlapping area of both rectangles, which is empty if they don't overlap.
+    pub fn intersection(&self, other: &Rect) -> Rect {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+        Rect::new(x, y, right.saturating_sub(x), bottom.saturating_sub(y))
+    }
+}
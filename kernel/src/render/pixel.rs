@@ -1,7 +1,27 @@
-//! A collection of simple pixel drawing functions used by the frame buffer view.
+//! A collection of simple pixel drawing functions used by the frame buffer view,
+//! along with their reverse for reading pixels back.
 
 use crate::render::Color;
 
+/// A single pixel in a buffer's layout, ready to be copied into it.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Packed {
+    bytes: [u8; 4],
+    len: usize
+}
+
+impl Packed {
+    /// Create a packed pixel from the first `len` bytes.
+    pub fn new(bytes: [u8; 4], len: usize) -> Self {
+        assert!(len <= bytes.len(), "pixels are at most 4 bytes");
+        Self { bytes, len }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
 pub fn no_op(_pos: usize, _buffer: &mut [u8], _color: Color) {}
 
 pub fn rgb_24(pos: usize, buffer: &mut [u8], color: Color) {
@@ -11,10 +31,10 @@ pub fn rgb_24(pos: usize, buffer: &mut [u8], color: Color) {
 }
 
 pub fn rgb_32(pos: usize, buffer: &mut [u8], color: Color) {
-    buffer[pos]     = 0; // reserved byte on GOP buffers, leave this empty
-    buffer[pos + 1] = color.red;
-    buffer[pos + 2] = color.green;
-    buffer[pos + 3] = color.blue;
+    buffer[pos]     = color.red;
+    buffer[pos + 1] = color.green;
+    buffer[pos + 2] = color.blue;
+    buffer[pos + 3] = 0; // reserved byte on GOP buffers, leave this empty
 }
 
 pub fn bgr_24(pos: usize, buffer: &mut [u8], color: Color) {
@@ -24,12 +44,33 @@ pub fn bgr_24(pos: usize, buffer: &mut [u8], color: Color) {
 }
 
 pub fn bgr_32(pos: usize, buffer: &mut [u8], color: Color) {
-    buffer[pos]     = 0; // reserved byte on GOP buffers, leave this empty
-    buffer[pos + 1] = color.blue;
-    buffer[pos + 2] = color.green;
-    buffer[pos + 3] = color.red;
+    buffer[pos]     = color.blue;
+    buffer[pos + 1] = color.green;
+    buffer[pos + 2] = color.red;
+    buffer[pos + 3] = 0; // reserved byte on GOP buffers, leave this empty
 }
 
 pub fn u8(pos: usize, buffer: &mut [u8], color: Color) {
-    buffer[pos] = (color.red + color.blue + color.green) / 3;
-}
\ No newline at end of file
+    let sum = color.red as u16 + color.green as u16 + color.blue as u16;
+    buffer[pos] = (sum / 3) as u8;
+}
+
+/// Reads every pixel as black, for views that can't be read back.
+pub fn read_no_op(_pos: usize, _buffer: &[u8]) -> Color {
+    Color::default()
+}
+
+/// Reads a pixel written by [`rgb_24`](rgb_24) or [`rgb_32`](rgb_32).
+pub fn read_rgb(pos: usize, buffer: &[u8]) -> Color {
+    Color::new(buffer[pos], buffer[pos + 1], buffer[pos + 2])
+}
+
+/// Reads a pixel written by [`bgr_24`](bgr_24) or [`bgr_32`](bgr_32).
+pub fn read_bgr(pos: usize, buffer: &[u8]) -> Color {
+    Color::new(buffer[pos + 2], buffer[pos + 1], buffer[pos])
+}
+
+/// Reads a pixel written by [`u8`](u8), which only holds its brightness.
+pub fn read_u8(pos: usize, buffer: &[u8]) -> Color {
+    Color::new(buffer[pos], buffer[pos], buffer[pos])
+}
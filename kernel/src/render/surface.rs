@@ -0,0 +1,100 @@
+//! Off-screen surfaces, which are drawn to like any other view and presented by the
+//! [`Compositor`](crate::render::compositor::Compositor).
+
+use alloc::boxed::Box;
+use alloc::vec;
+use bootloader_api::info::FrameBufferInfo;
+use crate::render::{Color, Rect};
+use crate::render::damage::Damage;
+use crate::render::view::{FrameBufferView, PixelConverter, PixelReader};
+
+/// A buffer with its own dimensions, laid out like the view it was created for.
+///
+/// Since the layouts match, surfaces can be copied onto that view row by row.
+pub struct Surface {
+    info: FrameBufferInfo,
+    buffer: Box<[u8]>,
+    pixel_converter: PixelConverter,
+    pixel_reader: PixelReader,
+    damage: Damage
+}
+
+impl Surface {
+    /// Create a surface with the pixel layout of `view`, initially black.
+    pub fn new<V: FrameBufferView>(view: &V, width: usize, height: usize) -> Self {
+        let mut info = view.info();
+        info.width = width;
+        info.height = height;
+        info.stride = width; // rows aren't padded
+        info.byte_len = width * height * info.bytes_per_pixel;
+
+        Self {
+            info,
+            buffer: vec![0; info.byte_len].into_boxed_slice(),
+            pixel_converter: view.pixel_converter(),
+            pixel_reader: view.pixel_reader(),
+            damage: Damage::new()
+        }
+    }
+
+    /// Provides the pixels in the layout of the view the surface was created for.
+    pub fn pixels(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Provides the regions changed since the last call, in surface coordinates.
+    pub fn take_damage(&mut self) -> Damage {
+        core::mem::take(&mut self.damage)
+    }
+}
+
+impl FrameBufferView for Surface {
+    fn info(&self) -> FrameBufferInfo {
+        self.info
+    }
+
+    fn width(&self) -> usize {
+        self.info.width
+    }
+
+    fn height(&self) -> usize {
+        self.info.height
+    }
+
+    fn pixel_converter(&self) -> PixelConverter {
+        self.pixel_converter
+    }
+
+    fn pixel_reader(&self) -> PixelReader {
+        self.pixel_reader
+    }
+
+    fn set_pixel<C: Copy + Into<Color>>(&mut self, pos: (usize, usize), color: C) {
+        let (x, y) = pos;
+
+        assert!(x < self.info.width);
+        assert!(y < self.info.height);
+
+        let i = (y * self.info.stride) + x;
+        (self.pixel_converter)(i * self.info.bytes_per_pixel, &mut self.buffer, color.into());
+        self.damage.add(Rect::new(x, y, 1, 1));
+    }
+
+    unsafe fn set_pixel_unchecked(&mut self, index: usize, color: Color) {
+        (self.pixel_converter)(index, &mut self.buffer, color);
+    }
+
+    fn damage(&mut self, rect: Rect) {
+        self.damage.add(rect.intersection(&self.bounds()));
+    }
+
+    /// Returns a mutable reference to the pixels, marking all of them as changed.
+    unsafe fn buffer(&mut self) -> &mut [u8] {
+        self.damage.add(self.bounds());
+        &mut self.buffer
+    }
+
+    unsafe fn buffer_untracked(&mut self) -> &mut [u8] {
+        &mut self.buffer
+    }
+}
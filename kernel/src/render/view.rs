@@ -1,10 +1,19 @@
 use alloc::boxed::Box;
 use alloc::vec;
+use alloc::vec::Vec;
 use bootloader_api::info::{FrameBuffer, FrameBufferInfo, PixelFormat};
-use crate::render::{Color, pixel};
+use line_drawing::XiaolinWu;
+use crate::render::{Color, draw, pixel, Rect, Rgba};
+use crate::render::blend::BlendMode;
+use crate::render::draw::{Line, Point};
+use crate::render::pixel::Packed;
+use crate::render::damage::Damage;
 
 pub type PixelConverter = fn(usize, &mut [u8], Color);
 
+/// The reverse of a [`PixelConverter`](PixelConverter), reading a color back from the buffer.
+pub type PixelReader = fn(usize, &[u8]) -> Color;
+
 /// Provides a high-level abstraction for the frame buffer.
 pub trait FrameBufferView {
     /// Provides the frame buffer info.
@@ -25,21 +34,338 @@ pub trait FrameBufferView {
         pixel::no_op
     }
 
+    /// Provides the reverse of the pixel converter function, defaulting to a
+    /// no-op implementation which reads every pixel as black.
+    fn pixel_reader(&self) -> PixelReader {
+        pixel::read_no_op
+    }
+
+    /// Provides the area covered by the view, i.e. `(0, 0)` to `(width, height)`.
+    fn bounds(&self) -> Rect {
+        Rect::new(0, 0, self.width(), self.height())
+    }
+
+    /// Converts a color into the bytes of a single pixel in the buffer's layout.
+    ///
+    /// Converting once and copying the bytes avoids calling the converter for every pixel.
+    fn pack(&self, color: Color) -> Packed {
+        let mut bytes = [0; 4];
+        self.pixel_converter()(0, &mut bytes, color);
+        Packed::new(bytes, self.info().bytes_per_pixel)
+    }
+
     /// Clears the screen by setting every pixel to a single color.
     fn clear<C: Copy + Into<Color>>(&mut self, color: C) {
+        self.fill_rect(self.bounds(), color);
+    }
+
+    /// Fills a rectangle with a single color, clipped to the view.
+    fn fill_rect<C: Copy + Into<Color>>(&mut self, rect: Rect, color: C) {
+        let rect = rect.intersection(&self.bounds());
+        if rect.is_empty() {
+            return;
+        }
+
+        let info = self.info();
+        let pitch = info.stride * info.bytes_per_pixel; // bytes per row
+        let pixel = self.pack(color.into());
+        let buffer = unsafe { self.buffer_untracked() };
+
+        // fill the first row, then copy it to the others
+        let first = (rect.y * pitch) + (rect.x * info.bytes_per_pixel);
+        let row = first..first + (rect.width * info.bytes_per_pixel);
+        for bytes in buffer[row.clone()].chunks_exact_mut(info.bytes_per_pixel) {
+            bytes.copy_from_slice(pixel.as_bytes());
+        }
+        for y in 1..rect.height {
+            buffer.copy_within(row.clone(), first + (y * pitch));
+        }
+
+        self.damage(rect);
+    }
+
+    /// Copies pixels that are already in the buffer's layout onto the view, clipped to the view.
+    ///
+    /// `pixels` holds `size.1` rows of `size.0` pixels each, without padding in between,
+    /// usually produced with [`pack`](FrameBufferView::pack).
+    fn blit(&mut self, dest: (isize, isize), size: (usize, usize), pixels: &[u8]) {
+        let info = self.info();
+        assert!(pixels.len() >= size.0 * size.1 * info.bytes_per_pixel);
+
+        let Some((rect, (src_x, src_y))) = clip(dest, size, self.bounds()) else {
+            return;
+        };
+
+        let pitch = info.stride * info.bytes_per_pixel;
+        let src_pitch = size.0 * info.bytes_per_pixel;
+        let len = rect.width * info.bytes_per_pixel;
+        let buffer = unsafe { self.buffer_untracked() };
+        for row in 0..rect.height {
+            let src = ((src_y + row) * src_pitch) + (src_x * info.bytes_per_pixel);
+            let dest = ((rect.y + row) * pitch) + (rect.x * info.bytes_per_pixel);
+            buffer[dest..dest + len].copy_from_slice(&pixels[src..src + len]);
+        }
+
+        self.damage(rect);
+    }
+
+    /// Copies a region of the view to another position on it, e.g. to scroll.
+    ///
+    /// The regions may overlap, and parts outside of the view are left out.
+    fn copy_rect(&mut self, src: Rect, dest: (isize, isize)) {
+        let src = src.intersection(&self.bounds());
+        let Some((rect, (offset_x, offset_y))) = clip(dest, (src.width, src.height), self.bounds()) else {
+            return;
+        };
+
+        let info = self.info();
+        let pitch = info.stride * info.bytes_per_pixel;
+        let len = rect.width * info.bytes_per_pixel;
+        let buffer = unsafe { self.buffer_untracked() };
+        let mut copy_row = |row: usize| {
+            let from = ((src.y + offset_y + row) * pitch) + ((src.x + offset_x) * info.bytes_per_pixel);
+            let to = ((rect.y + row) * pitch) + (rect.x * info.bytes_per_pixel);
+            buffer.copy_within(from..from + len, to);
+        };
+
+        // copy rows in the direction that doesn't overwrite the ones still to be copied
+        if rect.y > src.y + offset_y {
+            (0..rect.height).rev().for_each(&mut copy_row);
+        } else {
+            (0..rect.height).for_each(&mut copy_row);
+        }
+
+        self.damage(rect);
+    }
+
+    /// Reads back the color of a pixel.
+    ///
+    /// This takes `&mut self`, since views only expose their buffer mutably.
+    fn get_pixel(&mut self, pos: (usize, usize)) -> Color {
+        let (x, y) = pos;
+        let info = self.info();
+        assert!(x < info.width);
+        assert!(y < info.height);
+
+        let reader = self.pixel_reader();
+        reader(((y * info.stride) + x) * info.bytes_per_pixel, unsafe { self.buffer_untracked() })
+    }
+
+    /// Blends a translucent color onto a pixel.
+    fn blend_pixel<C: Copy + Into<Rgba>>(&mut self, pos: (usize, usize), color: C, mode: BlendMode) {
+        let below = self.get_pixel(pos);
+        self.set_pixel(pos, mode.blend(below, color.into()));
+    }
+
+    /// Blends a translucent color onto every pixel of a rectangle, clipped to the view.
+    fn blend_rect<C: Copy + Into<Rgba>>(&mut self, rect: Rect, color: C, mode: BlendMode) {
+        let rect = rect.intersection(&self.bounds());
         let color = color.into();
+        if color.alpha == u8::MAX && mode == BlendMode::SourceOver {
+            return self.fill_rect(rect, color.rgb());
+        }
+
         let info = self.info();
+        let (convert, read) = (self.pixel_converter(), self.pixel_reader());
+        let buffer = unsafe { self.buffer_untracked() };
+        for y in rect.y..rect.bottom() {
+            for x in rect.x..rect.right() {
+                let i = ((y * info.stride) + x) * info.bytes_per_pixel;
+                convert(i, buffer, mode.blend(read(i, buffer), color));
+            }
+        }
+
+        self.damage(rect);
+    }
+
+    /// Sets a pixel if it lies within the view.
+    fn draw_pixel<C: Copy + Into<Color>>(&mut self, pos: Point, color: C) {
+        let (x, y) = pos;
+        if x >= 0 && y >= 0 && (x as usize) < self.width() && (y as usize) < self.height() {
+            self.set_pixel((x as usize, y as usize), color);
+        }
+    }
+
+    /// Blends a translucent color onto a pixel if it lies within the view.
+    fn draw_blended_pixel<C: Copy + Into<Rgba>>(&mut self, pos: Point, color: C, mode: BlendMode) {
+        let (x, y) = pos;
+        if x >= 0 && y >= 0 && (x as usize) < self.width() && (y as usize) < self.height() {
+            self.blend_pixel((x as usize, y as usize), color, mode);
+        }
+    }
+
+    /// Draws a horizontal line between two columns (inclusive) on a row.
+    ///
+    /// This is the building block of filled shapes, writing the whole span at once.
+    fn draw_hline<C: Copy + Into<Color>>(&mut self, x0: isize, x1: isize, y: isize, color: C) {
+        if y < 0 || y as usize >= self.height() {
+            return;
+        }
+        let start = x0.min(x1).max(0);
+        let end = x0.max(x1).min(self.width() as isize - 1);
+        if start > end {
+            return;
+        }
+
+        self.fill_rect(Rect::new(start as usize, y as usize, (end - start) as usize + 1, 1), color);
+    }
+
+    /// Draws a line using Bresenham's algorithm, only stepping through the part within the view.
+    fn draw_line<C: Copy + Into<Color>>(&mut self, from: Point, to: Point, color: C) {
+        if from.1 == to.1 {
+            return self.draw_hline(from.0, to.0, from.1, color);
+        }
+        let line = Line::new(from, to);
+        let Some(steps) = line.visible((self.width(), self.height())) else {
+            return;
+        };
+        for step in steps {
+            let (x, y) = line.point(step);
+            self.set_pixel((x as usize, y as usize), color);
+        }
+    }
+
+    /// Draws an anti-aliased line using Xiaolin Wu's algorithm.
+    ///
+    /// Partially covered pixels are blended with what is already on the view.
+    fn draw_line_aa<C: Copy + Into<Color>>(&mut self, from: Point, to: Point, color: C) {
+        let color = color.into();
+        let line = Line::new(from, to);
+        let Some(steps) = line.visible((self.width(), self.height())) else {
+            return;
+        };
+        // a step further on either side, for the partially covered pixels along the edges
+        let start = line.exact(steps.start().saturating_sub(1));
+        let end = line.exact((steps.end() + 1).min(line.steps()));
+        for (pos, coverage) in XiaolinWu::<f32, isize>::new(start, end) {
+            let alpha = (coverage.clamp(0.0, 1.0) * 255.0 + 0.5) as u8;
+            self.draw_blended_pixel(pos, Rgba::new(color.red, color.green, color.blue, alpha), BlendMode::SourceOver);
+        }
+    }
+
+    /// Draws the outline of a rectangle.
+    fn draw_rect<C: Copy + Into<Color>>(&mut self, pos: Point, size: (usize, usize), color: C) {
+        if size.0 == 0 || size.1 == 0 {
+            return;
+        }
+        let (x0, y0) = pos;
+        let (x1, y1) = (x0.saturating_add_unsigned(size.0 - 1), y0.saturating_add_unsigned(size.1 - 1));
+        self.draw_hline(x0, x1, y0, color);
+        self.draw_hline(x0, x1, y1, color);
+        for y in draw::clip_rows(self, y0.saturating_add(1), y1.saturating_sub(1)) {
+            self.draw_pixel((x0, y), color);
+            self.draw_pixel((x1, y), color);
+        }
+    }
 
-        for y in 0..self.height() {
-            for x in 0..self.width() {
-                let i = (y * info.stride) + x;
-                unsafe {
-                    self.set_pixel_unchecked(i * info.bytes_per_pixel, color);
+    /// Draws a filled rectangle.
+    fn draw_filled_rect<C: Copy + Into<Color>>(&mut self, pos: Point, size: (usize, usize), color: C) {
+        if size.0 == 0 || size.1 == 0 {
+            return;
+        }
+        let x1 = pos.0.saturating_add_unsigned(size.0 - 1);
+        for y in draw::clip_rows(self, pos.1, pos.1.saturating_add_unsigned(size.1 - 1)) {
+            self.draw_hline(pos.0, x1, y, color);
+        }
+    }
+
+    /// Draws the outline of a circle.
+    fn draw_circle<C: Copy + Into<Color>>(&mut self, center: Point, radius: usize, color: C) {
+        self.draw_ellipse(center, (radius, radius), color);
+    }
+
+    /// Draws a filled circle.
+    fn draw_filled_circle<C: Copy + Into<Color>>(&mut self, center: Point, radius: usize, color: C) {
+        self.draw_filled_ellipse(center, (radius, radius), color);
+    }
+
+    /// Draws the outline of an axis-aligned ellipse, only going through the rows within the view.
+    fn draw_ellipse<C: Copy + Into<Color>>(&mut self, center: Point, radii: (usize, usize), color: C) {
+        let (cx, cy) = center;
+        let (rx, ry) = radii;
+        for y in draw::clip_rows(self, cy.saturating_sub_unsigned(ry), cy.saturating_add_unsigned(ry)) {
+            // each row reaches in to where the next row out ends, so the steep parts have no gaps
+            let d = y.abs_diff(cy);
+            let outer = draw::ellipse_extent(d, ry, rx);
+            let inner = if d < ry {
+                draw::ellipse_extent(d + 1, ry, rx).saturating_add(1).min(outer)
+            } else {
+                0
+            };
+            self.draw_hline(cx.saturating_add_unsigned(inner), cx.saturating_add_unsigned(outer), y, color);
+            self.draw_hline(cx.saturating_sub_unsigned(outer), cx.saturating_sub_unsigned(inner), y, color);
+        }
+    }
+
+    /// Draws a filled axis-aligned ellipse.
+    fn draw_filled_ellipse<C: Copy + Into<Color>>(&mut self, center: Point, radii: (usize, usize), color: C) {
+        let (cx, cy) = center;
+        let (rx, ry) = radii;
+        for y in draw::clip_rows(self, cy.saturating_sub_unsigned(ry), cy.saturating_add_unsigned(ry)) {
+            let x = draw::ellipse_extent(y.abs_diff(cy), ry, rx);
+            self.draw_hline(cx.saturating_sub_unsigned(x), cx.saturating_add_unsigned(x), y, color);
+        }
+    }
+
+    /// Draws the outline of a triangle.
+    fn draw_triangle<C: Copy + Into<Color>>(&mut self, points: [Point; 3], color: C) {
+        self.draw_polygon(&points, color);
+    }
+
+    /// Draws a filled triangle.
+    fn draw_filled_triangle<C: Copy + Into<Color>>(&mut self, points: [Point; 3], color: C) {
+        self.draw_filled_polygon(&points, color);
+    }
+
+    /// Draws the outline of a polygon, connecting the last point back to the first.
+    fn draw_polygon<C: Copy + Into<Color>>(&mut self, points: &[Point], color: C) {
+        match points {
+            [] => {}
+            [point] => self.draw_pixel(*point, color),
+            _ => {
+                for (i, &from) in points.iter().enumerate() {
+                    let to = points[(i + 1) % points.len()];
+                    self.draw_line(from, to, color);
                 }
             }
         }
     }
 
+    /// Draws a filled polygon using the even-odd rule, which also covers its outline.
+    fn draw_filled_polygon<C: Copy + Into<Color>>(&mut self, points: &[Point], color: C) {
+        let Some(top) = points.iter().map(|p| p.1).min() else {
+            return;
+        };
+        let bottom = points.iter().map(|p| p.1).max().unwrap();
+
+        let mut crossings = Vec::with_capacity(points.len());
+        for y in draw::clip_rows(self, top, bottom) {
+            // edges include their upper end but not their lower one, so shared vertices count once
+            crossings.clear();
+            for (i, &from) in points.iter().enumerate() {
+                let to = points[(i + 1) % points.len()];
+                if (from.1 <= y && y < to.1) || (to.1 <= y && y < from.1) {
+                    crossings.push(draw::crossing(from, to, y));
+                }
+            }
+            crossings.sort_unstable();
+
+            for &[left, right] in crossings.as_chunks::<2>().0 {
+                self.draw_hline(left, right, y, color);
+            }
+        }
+
+        self.draw_polygon(points, color);
+    }
+
+    /// Marks a region as changed, for views that only present what changed.
+    ///
+    /// Writes through [`set_pixel`](FrameBufferView::set_pixel) and the other drawing methods
+    /// and [`buffer`](FrameBufferView::buffer) are tracked by the view itself, while writes through
+    /// [`set_pixel_unchecked`](FrameBufferView::set_pixel_unchecked) and
+    /// [`buffer_untracked`](FrameBufferView::buffer_untracked) must be reported here. Views that draw immediately ignore this.
+    fn damage(&mut self, _rect: Rect) {}
+
     /// Sets a pixel on the underlying buffer.
     ///
     /// `pos` represents an (x, y) coordinate pair, measured in pixels.
@@ -56,21 +382,50 @@ pub trait FrameBufferView {
     ///
     /// The caller must ensure that the passed `index` is within bounds, i.e. does not enter the
     /// padding area (`stride - width`) and doesn't exceed the byte length of the buffer.
+    /// The change is not tracked, see [`damage`](FrameBufferView::damage).
     unsafe fn set_pixel_unchecked(&mut self, index: usize, color: Color);
 
     /// Provides a mutable reference to the underlying buffer.
     ///
+    /// Views that track damage mark all of themselves as changed, since any part may be written.
+    ///
     /// ## Safety
     ///
     /// It is possible to invalidate the invariants of the view through this reference.
     /// Care should be taken to make sure all mutation accounts for pixel layout differences if necessary.
     unsafe fn buffer(&mut self) -> &mut [u8];
+
+    /// Provides a mutable reference to the underlying buffer, like [`buffer`](FrameBufferView::buffer),
+    /// without marking anything as changed.
+    ///
+    /// ## Safety
+    ///
+    /// As with [`buffer`](FrameBufferView::buffer), and the caller must report every change
+    /// through [`damage`](FrameBufferView::damage).
+    unsafe fn buffer_untracked(&mut self) -> &mut [u8] {
+        unsafe { self.buffer() }
+    }
+}
+
+/// Clips a rectangle at a signed position to `bounds`, returning the visible part along with
+/// its offset from the rectangle's origin.
+pub(crate) fn clip(pos: (isize, isize), size: (usize, usize), bounds: Rect) -> Option<(Rect, (usize, usize))> {
+    let (x, y) = pos;
+    let offset = (x.min(0).unsigned_abs(), y.min(0).unsigned_abs());
+    if offset.0 >= size.0 || offset.1 >= size.1 {
+        return None;
+    }
+
+    let rect = Rect::new(x.max(0) as usize, y.max(0) as usize, size.0 - offset.0, size.1 - offset.1);
+    let visible = rect.intersection(&bounds);
+    (!visible.is_empty()).then_some((visible, offset))
 }
 
 pub struct ImmediateView {
     info: FrameBufferInfo,
     frame_buffer: &'static mut [u8],
-    pixel_converter: PixelConverter
+    pixel_converter: PixelConverter,
+    pixel_reader: PixelReader
 }
 
 impl ImmediateView {
@@ -80,17 +435,20 @@ impl ImmediateView {
         let format = info.pixel_format;
         let depth = info.bytes_per_pixel;
 
-        // figure out the buffer-specific pixel converter function
-        let pixel_converter = match format {
-            PixelFormat::Rgb if depth == 3  => pixel::rgb_24,
-            PixelFormat::Rgb if depth == 4  => pixel::rgb_32,
-            PixelFormat::Bgr if depth == 3  => pixel::bgr_24,
-            PixelFormat::Bgr if depth == 4  => pixel::bgr_32,
-            PixelFormat::U8                 => pixel::u8,
+        // figure out the buffer-specific pixel converter and reader functions
+        let (pixel_converter, pixel_reader): (PixelConverter, PixelReader) = match format {
+            PixelFormat::Rgb if depth == 3  => (pixel::rgb_24, pixel::read_rgb),
+            PixelFormat::Rgb if depth == 4  => (pixel::rgb_32, pixel::read_rgb),
+            PixelFormat::Bgr if depth == 3  => (pixel::bgr_24, pixel::read_bgr),
+            PixelFormat::Bgr if depth == 4  => (pixel::bgr_32, pixel::read_bgr),
+            PixelFormat::U8                 => (pixel::u8, pixel::read_u8),
+            // GOP bit mask formats, which are usually one of the above in disguise
+            PixelFormat::Unknown { red_position: 0, green_position: 8, blue_position: 16 } if depth == 4 => (pixel::rgb_32, pixel::read_rgb),
+            PixelFormat::Unknown { red_position: 16, green_position: 8, blue_position: 0 } if depth == 4 => (pixel::bgr_32, pixel::read_bgr),
             _ => panic!("pixel format not supported: {:?}", format)
         };
 
-        Self { info, frame_buffer: frame_buffer.buffer_mut(), pixel_converter }
+        Self { info, frame_buffer: frame_buffer.buffer_mut(), pixel_converter, pixel_reader }
     }
 }
 
@@ -111,6 +469,10 @@ impl FrameBufferView for ImmediateView {
         self.pixel_converter
     }
 
+    fn pixel_reader(&self) -> PixelReader {
+        self.pixel_reader
+    }
+
     fn set_pixel<C: Copy + Into<Color>>(&mut self, pos: (usize, usize), color: C) {
         let (x, y) = pos; // position in pixels
 
@@ -140,7 +502,8 @@ pub type BufImmediateView = BufView<ImmediateView>;
 
 pub struct BufView<I: FrameBufferView> {
     view: I,
-    buffer: Box<[u8]>
+    buffer: Box<[u8]>,
+    damage: Damage
 }
 
 impl BufView<ImmediateView> {
@@ -154,12 +517,42 @@ impl<I: FrameBufferView> BufView<I> {
     /// dynamically allocating a back buffer of equivalent byte length.
     pub fn from(view: I) -> Self {
         let buffer = vec![0; view.info().byte_len].into_boxed_slice();
-        Self { view, buffer }
+        Self { view, buffer, damage: Damage::new() }
     }
 
-    /// Copies the contents of the back buffer to the frame buffer.
+    /// Provides the underlying view, bypassing the back buffer.
+    pub fn view_mut(&mut self) -> &mut I {
+        &mut self.view
+    }
+
+    /// Copies the regions of the back buffer changed since the last swap to the frame buffer.
     pub fn swap(&mut self) {
+        let info = self.info();
+        let pitch = info.stride * info.bytes_per_pixel; // bytes per row
+        let front = unsafe { self.view.buffer() };
+
+        for rect in self.damage.rects() {
+            if rect.x == 0 && rect.width == info.width {
+                // whole rows are contiguous, including their padding
+                let range = (rect.y * pitch)..(rect.bottom() * pitch).min(front.len());
+                front[range.clone()].copy_from_slice(&self.buffer[range]);
+                continue;
+            }
+
+            for y in rect.y..rect.bottom() {
+                let start = (y * pitch) + (rect.x * info.bytes_per_pixel);
+                let range = start..start + (rect.width * info.bytes_per_pixel);
+                front[range.clone()].copy_from_slice(&self.buffer[range]);
+            }
+        }
+
+        self.damage.clear();
+    }
+
+    /// Copies the entire back buffer to the frame buffer, for when the whole screen changed.
+    pub fn swap_full(&mut self) {
         unsafe { self.view.buffer() }.copy_from_slice(&self.buffer);
+        self.damage.clear();
     }
 }
 
@@ -180,6 +573,10 @@ impl<I: FrameBufferView> FrameBufferView for BufView<I> {
         self.view.pixel_converter()
     }
 
+    fn pixel_reader(&self) -> PixelReader {
+        self.view.pixel_reader()
+    }
+
     fn set_pixel<C: Copy + Into<Color>>(&mut self, pos: (usize, usize), color: C) {
         let (x, y) = pos;
 
@@ -193,14 +590,24 @@ impl<I: FrameBufferView> FrameBufferView for BufView<I> {
             &mut self.buffer,
             color.into()
         );
+        self.damage.add(Rect::new(x, y, 1, 1));
     }
 
     unsafe fn set_pixel_unchecked(&mut self, index: usize, color: Color) {
         self.pixel_converter()(index, &mut self.buffer, color);
     }
 
-    /// Returns a mutable reference to the back buffer.
+    fn damage(&mut self, rect: Rect) {
+        self.damage.add(rect.intersection(&self.bounds()));
+    }
+
+    /// Returns a mutable reference to the back buffer, marking all of it as changed.
     unsafe fn buffer(&mut self) -> &mut [u8] {
+        self.damage.add(Rect::new(0, 0, self.width(), self.height()));
+        &mut self.buffer
+    }
+
+    unsafe fn buffer_untracked(&mut self) -> &mut [u8] {
         &mut self.buffer
     }
 }
\ No newline at end of file
@@ -1,6 +1,7 @@
 use core::fmt::{Write, Arguments};
 use spin::{Lazy, Mutex};
 use uart_16550::SerialPort;
+use crate::log;
 
 pub static SERIAL1: Lazy<Mutex<SerialPort>> = Lazy::new(|| {
     let mut serial_port = unsafe { SerialPort::new(0x3F8) };
@@ -8,12 +9,20 @@ pub static SERIAL1: Lazy<Mutex<SerialPort>> = Lazy::new(|| {
     Mutex::new(serial_port)
 });
 
+/// The second serial port (COM2), reserved for the GDB remote stub.
+pub static SERIAL2: Lazy<Mutex<SerialPort>> = Lazy::new(|| {
+    let mut serial_port = unsafe { SerialPort::new(0x2F8) };
+    serial_port.init();
+    Mutex::new(serial_port)
+});
+
 #[doc(hidden)]
 pub fn print(args: Arguments) {
     SERIAL1.lock().write_fmt(args).expect("serial should be printable");
+    log::record(args);
 }
 
-/// Prints to the host through the serial interface.
+/// Prints to the host through the serial interface, recording it in the kernel log.
 #[macro_export]
 macro_rules! serial_print {
     ($($arg:tt)*) => {
@@ -0,0 +1,148 @@
+//! Commands built into the shell.
+
+use core::fmt::Write;
+use crate::block::{self, BlockDevice};
+use crate::driver::{self, Bus};
+use crate::pci::{self, Bar};
+use crate::render::console::Console;
+use crate::shell::{self, Command};
+use crate::time;
+
+pub(crate) const BUILTIN: &[Command] = &[
+    Command { name: "clear", help: "clear the terminal", run: clear },
+    Command { name: "date", help: "show the date and time in UTC", run: date },
+    Command { name: "echo", help: "print the arguments", run: echo },
+    Command { name: "help", help: "list the available commands", run: help },
+    Command { name: "idle", help: "show the time spent idle", run: idle },
+    Command { name: "lsblk", help: "list block devices, with statistics given -v", run: lsblk },
+    Command { name: "lsdev", help: "list devices and the drivers bound to them", run: lsdev },
+    Command { name: "lspci", help: "list PCI devices, with details given -v", run: lspci },
+    Command { name: "sync", help: "write cached blocks back to their disks", run: sync },
+    Command { name: "uptime", help: "show the time since boot", run: uptime }
+];
+
+fn clear(console: &mut Console, _args: &[&str]) {
+    console.reset();
+}
+
+fn date(console: &mut Console, _args: &[&str]) {
+    let _ = writeln!(console, "{}", time::wall_clock());
+}
+
+fn echo(console: &mut Console, args: &[&str]) {
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            console.push_char(' ');
+        }
+        console.push_str(arg);
+    }
+    console.push_char('\n');
+}
+
+fn help(console: &mut Console, _args: &[&str]) {
+    let commands = shell::commands();
+    let width = commands.iter().map(|command| command.name.len()).max().unwrap_or(0);
+    for command in commands {
+        let _ = writeln!(console, "{:width$}  {}", command.name, command.help, width = width);
+    }
+}
+
+fn idle(console: &mut Console, _args: &[&str]) {
+    let stats = time::idle::stats();
+    let residency = stats.residency();
+    let _ = writeln!(
+        console,
+        "idle {}.{:02}% of {}s, {} halts, {} tickless",
+        residency / 100, residency % 100, stats.uptime.as_secs(), stats.halts, stats.tickless_halts
+    );
+}
+
+fn lsblk(console: &mut Console, args: &[&str]) {
+    let verbose = args.contains(&"-v");
+    for disk in block::disks() {
+        let size = disk.blocks() * disk.block_size() as u64;
+        let _ = writeln!(
+            console,
+            "{:8}  {:>10} KiB  {} blocks of {} bytes{}",
+            disk.name(), size / 1024, disk.blocks(), disk.block_size(),
+            if disk.read_only() { ", read-only" } else { "" }
+        );
+        if !verbose {
+            continue;
+        }
+
+        let stats = disk.stats();
+        let (cached, dirty) = disk.cached();
+        let _ = writeln!(console, "    reads: {} requests, {} blocks", stats.reads, stats.blocks_read);
+        let _ = writeln!(console, "    writes: {} requests, {} blocks", stats.writes, stats.blocks_written);
+        let _ = writeln!(console, "    flushes: {}, merged: {}, errors: {}", stats.flushes, stats.merged, stats.errors);
+        let _ = writeln!(console, "    cache: {} hits, {} misses, {} blocks held, {} dirty", stats.hits, stats.misses, cached, dirty);
+    }
+}
+
+fn lsdev(console: &mut Console, _args: &[&str]) {
+    let devices = driver::devices();
+    let width = devices.iter().map(|(device, _)| device.name().len()).max().unwrap_or(0);
+    for (device, driver) in devices {
+        let description = match device.bus() {
+            Bus::Pci(device) => device.class_name(),
+            Bus::Acpi(device) => &device.name,
+            Bus::Isa(device) => device.name
+        };
+        let _ = writeln!(
+            console,
+            "{:width$}  {:16}  {}",
+            device.name(), driver.unwrap_or("-"), description, width = width
+        );
+    }
+}
+
+fn lspci(console: &mut Console, args: &[&str]) {
+    let verbose = args.contains(&"-v");
+    for device in pci::devices() {
+        let _ = writeln!(
+            console,
+            "{} {}: {:04x}:{:04x} (rev {:02x})",
+            device.address, device.class_name(), device.vendor_id, device.device_id, device.revision
+        );
+        if !verbose {
+            continue;
+        }
+
+        if device.interrupt_pin != 0 {
+            let pin = (b'A' + device.interrupt_pin - 1) as char;
+            let _ = writeln!(console, "    interrupt: pin {}, line {}", pin, device.interrupt_line);
+        }
+        for (index, bar) in device.bars.iter().enumerate() {
+            let _ = match bar {
+                Some(Bar::Memory { address, size, prefetchable, wide, .. }) => writeln!(
+                    console,
+                    "    BAR {}: memory at {:#x}, {} bytes, {}-bit{}",
+                    index, address.as_u64(), size, if *wide { 64 } else { 32 },
+                    if *prefetchable { ", prefetchable" } else { "" }
+                ),
+                Some(Bar::Io { port, size }) => writeln!(console, "    BAR {}: I/O ports at {:#x}, {} bytes", index, port, size),
+                None => Ok(())
+            };
+        }
+        for capability in &device.capabilities {
+            let _ = writeln!(console, "    capability {:#04x} at {:#04x}", capability.id, capability.offset);
+        }
+    }
+}
+
+fn sync(console: &mut Console, _args: &[&str]) {
+    if let Err(error) = block::sync() {
+        let _ = writeln!(console, "sync: {:?}", error);
+    }
+}
+
+fn uptime(console: &mut Console, _args: &[&str]) {
+    let uptime = time::uptime();
+    let (seconds, millis) = (uptime.as_secs(), uptime.subsec_millis());
+    let _ = writeln!(
+        console,
+        "up {}:{:02}:{:02}.{:03}",
+        seconds / 3600, (seconds / 60) % 60, seconds % 60, millis
+    );
+}
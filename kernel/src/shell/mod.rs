@@ -0,0 +1,115 @@
+//! A minimal line-based shell, running commands from a registry.
+//!
+//! Subsystems add their own commands with [`register`](register), the built-in ones
+//! live in [`commands`](commands).
+
+pub mod commands;
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use spin::{Lazy, Mutex};
+use crate::render::console::Console;
+
+const PROMPT: &str = "> ";
+
+static COMMANDS: Lazy<Mutex<BTreeMap<&'static str, Command>>> = Lazy::new(|| {
+    let mut commands = BTreeMap::new();
+    for command in commands::BUILTIN {
+        commands.insert(command.name, *command);
+    }
+    Mutex::new(commands)
+});
+
+/// A command that can be run from the shell.
+#[derive(Debug, Copy, Clone)]
+pub struct Command {
+    pub name: &'static str,
+    /// A one-line description shown by `help`.
+    pub help: &'static str,
+    /// Runs the command with its arguments, excluding the name, writing output to the console.
+    pub run: fn(&mut Console, &[&str])
+}
+
+/// Adds a command, replacing any with the same name.
+pub fn register(command: Command) {
+    COMMANDS.lock().insert(command.name, command);
+}
+
+/// Provides every registered command, sorted by name.
+pub fn commands() -> Vec<Command> {
+    COMMANDS.lock().values().copied().collect()
+}
+
+/// Runs a line of input, returning `false` if the command doesn't exist.
+pub fn execute(console: &mut Console, line: &str) -> bool {
+    let mut words = line.split_whitespace();
+    let Some(name) = words.next() else {
+        return true;
+    };
+    let arguments: Vec<&str> = words.collect();
+
+    // copied out, so commands can use the registry themselves
+    let command = COMMANDS.lock().get(name).copied();
+    match command {
+        Some(command) => (command.run)(console, &arguments),
+        None => {
+            let _ = writeln!(console, "{}: command not found", name);
+            return false;
+        }
+    }
+    true
+}
+
+/// The state of a shell attached to a console.
+#[derive(Debug, Default)]
+pub struct Shell {
+    line: String
+}
+
+impl Shell {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Shows the prompt, for when the shell starts.
+    pub fn start(&mut self, console: &mut Console) {
+        console.push_str(PROMPT);
+    }
+
+    /// Handles a typed character, echoing it and running the line on enter.
+    pub fn input(&mut self, console: &mut Console, c: char) {
+        if let Some(line) = self.edit(console, c) {
+            Self::run(console, &line);
+        }
+    }
+
+    /// Handles a typed character like [`input`](Self::input), but hands back the line on enter
+    /// rather than running it, for callers that have to release locks first.
+    pub fn edit(&mut self, console: &mut Console, c: char) -> Option<String> {
+        match c {
+            '\n' | '\r' => {
+                console.push_char('\n');
+                return Some(core::mem::take(&mut self.line));
+            }
+            '\x08' => {
+                if self.line.pop().is_some() {
+                    console.push_char('\x08');
+                }
+            }
+            c if !c.is_control() => {
+                self.line.push(c);
+                console.push_char(c);
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Runs a line handed back by [`edit`](Self::edit), then shows the prompt again.
+    pub fn run(console: &mut Console, line: &str) {
+        execute(console, line);
+        console.push_str(PROMPT);
+    }
+}
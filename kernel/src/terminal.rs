@@ -0,0 +1,200 @@
+//! Virtual terminals sharing the global view, switched between with Alt+F1 to Alt+F6.
+//!
+//! The first terminal always shows the kernel log, while the others each run a shell.
+//! Every terminal keeps its own text and cursor, so only the active one is drawn.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use pc_keyboard::{DecodedKey, KeyCode};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use crate::keyboard::{self, KeyPress};
+use crate::log;
+use crate::render::{use_global_view, Color, Rect};
+use crate::render::console::Console;
+use crate::render::font::Font;
+use crate::render::view::FrameBufferView;
+use crate::shell::Shell;
+use crate::time::{idle, timer};
+
+/// Number of virtual terminals, one for each of the hotkeys.
+pub const TERMINAL_COUNT: usize = 6;
+
+/// The terminal showing the kernel log.
+pub const LOG_TERMINAL: usize = 0;
+
+/// Columns and rows the terminals have at least, with the font enlarged as far as they still fit.
+pub const MIN_GRID: (usize, usize) = (80, 25);
+
+const LOG_COLOR: Color = Color { red: 0xAA, green: 0xAA, blue: 0xAA };
+const SHELL_COLOR: Color = Color { red: 0xFF, green: 0xFF, blue: 0xFF };
+const BACKGROUND: Color = Color { red: 0, green: 0, blue: 0 };
+
+static TERMINALS: Mutex<Option<Terminals>> = Mutex::new(None);
+
+struct Terminals {
+    terminals: Vec<Terminal>,
+    active: usize
+}
+
+struct Terminal {
+    /// Shared, so commands can write to it without the terminals being locked.
+    console: Arc<Mutex<Console>>,
+    kind: Kind
+}
+
+enum Kind {
+    /// Shows the kernel log, continuing from a position in it.
+    Log(u64),
+    Shell(Shell)
+}
+
+/// Sets up the terminals to cover the global view, starting on the kernel log.
+///
+/// The font is enlarged by the largest scale that keeps [`MIN_GRID`](MIN_GRID) on screen.
+/// Nothing is drawn until [`run`](run) is called.
+pub fn init(font: &'static (dyn Font + Sync)) {
+    let mut bounds = None;
+    use_global_view(|view| bounds = Some(view.bounds()));
+    let Some(bounds) = bounds else {
+        return;
+    };
+    let scale = scale_for(font, bounds);
+
+    let terminals = (0..TERMINAL_COUNT)
+        .map(|index| {
+            let mut console = Console::new(font, scale, bounds);
+            let color = if index == LOG_TERMINAL { LOG_COLOR } else { SHELL_COLOR };
+            console.set_colors(color, BACKGROUND);
+            console.reset(); // apply the colors to the empty grid
+
+            let kind = if index == LOG_TERMINAL {
+                Kind::Log(0)
+            } else {
+                let mut shell = Shell::new();
+                shell.start(&mut console);
+                Kind::Shell(shell)
+            };
+            Terminal { console: Arc::new(Mutex::new(console)), kind }
+        })
+        .collect();
+
+    *TERMINALS.lock() = Some(Terminals { terminals, active: LOG_TERMINAL });
+}
+
+/// Picks the largest integer scale at which a font fits [`MIN_GRID`](MIN_GRID) into an area,
+/// or 1 if it doesn't fit even unscaled.
+pub fn scale_for(font: &dyn Font, area: Rect) -> usize {
+    let (width, height) = font.size();
+    let columns = area.width / (width * MIN_GRID.0).max(1);
+    let rows = area.height / (height * MIN_GRID.1).max(1);
+    columns.min(rows).max(1)
+}
+
+/// Provides the index of the terminal currently shown.
+pub fn active() -> usize {
+    TERMINALS.lock().as_ref().map_or(LOG_TERMINAL, |terminals| terminals.active)
+}
+
+/// Shows another terminal, redrawing it in full.
+pub fn switch(index: usize) {
+    if let Some(terminals) = TERMINALS.lock().as_mut() {
+        terminals.switch(index);
+    }
+}
+
+/// Handles keyboard input and keeps the log terminal up to date, halting while there is nothing to do.
+///
+/// Expired [timers](timer) run here too, as this is the kernel's idle loop.
+pub fn run() -> ! {
+    switch(active());
+    loop {
+        timer::run_expired();
+        poll();
+
+        // check for work with interrupts disabled, so none is missed before halting
+        interrupts::disable();
+        if keyboard::has_input() || has_new_log() {
+            interrupts::enable();
+        } else {
+            idle::halt();
+        }
+    }
+}
+
+/// Processes pending input and log output, drawing the changes to the active terminal.
+pub fn poll() {
+    if let Some(terminals) = TERMINALS.lock().as_mut() {
+        for terminal in terminals.terminals.iter_mut() {
+            if let Kind::Log(position) = &mut terminal.kind {
+                let mut console = terminal.console.lock();
+                *position = log::read(*position, |text| console.push_str(text));
+            }
+        }
+    }
+
+    while let Some(press) = keyboard::next_key() {
+        // taken out first, as commands may take a while or use the terminals themselves
+        let command = TERMINALS.lock().as_mut().and_then(|terminals| terminals.handle(press));
+        if let Some((console, line)) = command {
+            Shell::run(&mut console.lock(), &line);
+        }
+    }
+
+    let console = TERMINALS.lock().as_ref().map(|terminals| terminals.terminals[terminals.active].console.clone());
+    if let Some(console) = console {
+        use_global_view(|view| console.lock().flush(view));
+    }
+}
+
+fn has_new_log() -> bool {
+    let terminals = TERMINALS.lock();
+    let position = terminals.as_ref().and_then(|terminals| match terminals.terminals[LOG_TERMINAL].kind {
+        Kind::Log(position) => Some(position),
+        Kind::Shell(_) => None
+    });
+    position.is_some_and(|position| position < log::position())
+}
+
+impl Terminals {
+    fn switch(&mut self, index: usize) {
+        if index >= self.terminals.len() {
+            return;
+        }
+        self.active = index;
+        let mut console = self.terminals[index].console.lock();
+        use_global_view(|view| console.redraw(view));
+    }
+
+    /// Handles a key press, returning the active terminal's console along with a line to run in
+    /// it once the terminals are unlocked.
+    fn handle(&mut self, press: KeyPress) -> Option<(Arc<Mutex<Console>>, String)> {
+        if press.alt {
+            if let Some(index) = hotkey(press.code) {
+                self.switch(index);
+                return None;
+            }
+        }
+
+        let terminal = &mut self.terminals[self.active];
+        if let (Kind::Shell(shell), Some(DecodedKey::Unicode(c))) = (&mut terminal.kind, press.key) {
+            let line = shell.edit(&mut terminal.console.lock(), c)?;
+            return Some((terminal.console.clone(), line));
+        }
+        None
+    }
+}
+
+/// Maps the function keys to the terminals they switch to.
+fn hotkey(code: KeyCode) -> Option<usize> {
+    match code {
+        KeyCode::F1 => Some(0),
+        KeyCode::F2 => Some(1),
+        KeyCode::F3 => Some(2),
+        KeyCode::F4 => Some(3),
+        KeyCode::F5 => Some(4),
+        KeyCode::F6 => Some(5),
+        _ => None
+    }
+}
@@ -0,0 +1,96 @@
+//! The kernel's test framework, used by the test binaries in `kernel/tests`.
+//!
+//! Each test binary boots on its own in QEMU, reports results over serial, and
+//! exits through the `isa-debug-exit` device, which the `qemu-test` runner translates
+//! back into a pass or fail for `cargo test`.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any;
+use core::panic::PanicInfo;
+use x86_64::instructions::port::Port;
+use crate::{block_indefinitely, serial_print, serial_println};
+use crate::block::BlockDevice;
+use crate::serial::SERIAL1;
+
+/// I/O port of the `isa-debug-exit` device, which must match the runner's QEMU arguments.
+pub const EXIT_PORT: u16 = 0xF4;
+
+/// Exit codes written to the `isa-debug-exit` device.
+///
+/// QEMU exits with `(code << 1) | 1`, so neither can be confused with QEMU's own failures.
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+#[repr(u32)]
+pub enum QemuExitCode {
+    Success = 0x10,
+    Failed = 0x11
+}
+
+/// Exits QEMU with the given code.
+pub fn exit_qemu(code: QemuExitCode) -> ! {
+    unsafe {
+        Port::new(EXIT_PORT).write(code as u32);
+    }
+
+    // only reachable when running outside of QEMU
+    block_indefinitely();
+}
+
+/// A test case that reports its own name.
+pub trait Testable {
+    fn run(&self);
+}
+
+impl<T: Fn()> Testable for T {
+    fn run(&self) {
+        serial_print!("{}...\t", any::type_name::<T>());
+        self();
+        serial_println!("[ok]");
+    }
+}
+
+/// Runs every `#[test_case]`, exiting QEMU once all of them pass.
+///
+/// A failing test panics, which is reported by [`panic`](panic) instead.
+pub fn test_runner(tests: &[&dyn Testable]) {
+    serial_println!("running {} tests", tests.len());
+    for test in tests {
+        test.run();
+    }
+    exit_qemu(QemuExitCode::Success);
+}
+
+/// Reports a failed test and exits QEMU. Test binaries call this from their panic handler.
+pub fn panic(info: &PanicInfo) -> ! {
+    serial_println!("[failed]\n");
+    serial_println!("Error: {}\n", info);
+    exit_qemu(QemuExitCode::Failed);
+}
+
+/// Asks the runner to capture a region of the screen and compare it against
+/// `kernel/tests/screenshots/<name>.png`, blocking until it has done so.
+///
+/// Mismatches don't panic, the runner fails the test binary once it exits instead.
+pub fn screenshot(name: &str, pos: (usize, usize), size: (usize, usize)) {
+    serial_println!("[screenshot] {} {} {} {} {}", name, pos.0, pos.1, size.0, size.1);
+    SERIAL1.lock().receive(); // acknowledgement from the runner
+}
+
+/// Writes `blocks` blocks of a pattern to a device from `block`, then checks that they read back
+/// the same, both at once and the last one on its own.
+///
+/// Requests go straight to the device, so the driver's splitting of long requests is what's tested.
+pub fn round_trip(device: &dyn BlockDevice, block: u64, blocks: usize) {
+    let size = device.block_size();
+    let written: Vec<u8> = (0..blocks * size).map(|i| (i * 7 + i / size) as u8).collect();
+    device.write(block, &written).unwrap();
+    device.flush().unwrap();
+
+    let mut read = vec![0; written.len()];
+    device.read(block, &mut read).unwrap();
+    assert!(read == written, "{} returned different data", device.name());
+
+    let mut last = vec![0; size];
+    device.read(block + blocks as u64 - 1, &mut last).unwrap();
+    assert!(last == written[(blocks - 1) * size..], "{} returned a different last block", device.name());
+}
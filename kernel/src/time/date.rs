@@ -0,0 +1,90 @@
+//! Calendar dates, converted to and from Unix time.
+
+use core::fmt;
+use core::time::Duration;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// A date and time of day in UTC, without leap seconds.
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct DateTime {
+    pub year: u16,
+    /// From 1 to 12.
+    pub month: u8,
+    /// From 1 to 31.
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8
+}
+
+impl DateTime {
+    /// Converts the time since 1970-01-01 00:00:00 UTC, dropping fractions of a second.
+    pub fn from_unix(time: Duration) -> Self {
+        let seconds = time.as_secs();
+        let (days, seconds) = (seconds / SECONDS_PER_DAY, seconds % SECONDS_PER_DAY);
+        let (year, month, day) = civil_from_days(days as i64);
+        Self {
+            year: year as u16,
+            month,
+            day,
+            hour: (seconds / 3600) as u8,
+            minute: (seconds / 60 % 60) as u8,
+            second: (seconds % 60) as u8
+        }
+    }
+
+    /// Provides the time since 1970-01-01 00:00:00 UTC, saturating at zero for earlier dates.
+    pub fn to_unix(&self) -> Duration {
+        let days = days_from_civil(self.year as i64, self.month, self.day);
+        let seconds = (days * SECONDS_PER_DAY as i64)
+            + (self.hour as i64 * 3600)
+            + (self.minute as i64 * 60)
+            + self.second as i64;
+        Duration::from_secs(seconds.max(0) as u64)
+    }
+
+    /// Provides the day of the week, where 0 is Sunday.
+    pub fn weekday(&self) -> u8 {
+        let days = days_from_civil(self.year as i64, self.month, self.day);
+        (days + 4).rem_euclid(7) as u8 // 1970-01-01 was a Thursday
+    }
+}
+
+impl fmt::Display for DateTime {
+    /// Formats as `YYYY-MM-DD HH:MM:SS UTC`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// Counts the days since 1970-01-01 in the proleptic Gregorian calendar.
+///
+/// Follows Howard Hinnant's algorithm, with years starting in March so leap days come last.
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = month as i64;
+    let day_of_year = ((153 * (if month > 2 { month - 3 } else { month + 9 })) + 2) / 5 + day as i64 - 1;
+    let day_of_era = (year_of_era * 365) + (year_of_era / 4) - (year_of_era / 100) + day_of_year;
+    (era * 146_097) + day_of_era - 719_468
+}
+
+/// Inverse of [`days_from_civil`](days_from_civil).
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - (day_of_era / 1460) + (day_of_era / 36_524) - (day_of_era / 146_096)) / 365;
+    let day_of_year = day_of_era - ((365 * year_of_era) + (year_of_era / 4) - (year_of_era / 100));
+    let month = ((5 * day_of_year) + 2) / 153;
+    let day = (day_of_year - (((153 * month) + 2) / 5) + 1) as u8;
+    let month = if month < 10 { month + 3 } else { month - 9 } as u8;
+    let year = (era * 400) + year_of_era + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
@@ -0,0 +1,69 @@
+//! The High Precision Event Timer, found through its ACPI table.
+//!
+//! Only the main counter is used, as a clock source. The timer comparators are left disabled.
+
+use core::ptr;
+use spin::Once;
+use x86_64::{PhysAddr, VirtAddr};
+use crate::{acpi, mem};
+use crate::time::ClockSource;
+
+const CAPABILITIES: usize = 0x000;
+const CONFIGURATION: usize = 0x010;
+const MAIN_COUNTER: usize = 0x0F0;
+
+/// Bytes of registers, with up to 32 timers after the general ones.
+const REGISTERS_SIZE: u64 = 0x400;
+
+/// Set in the capabilities if the main counter is 64 bits wide.
+const COUNTER_64BIT: u64 = 1 << 13;
+/// Starts the main counter.
+const ENABLE: u64 = 1 << 0;
+
+const FEMTOS_PER_SECOND: u64 = 1_000_000_000_000_000;
+
+/// Offset of the base address in the ACPI table, within a generic address structure.
+const TABLE_ADDRESS_SPACE: usize = 40;
+const TABLE_ADDRESS: usize = 44;
+
+static BASE: Once<VirtAddr> = Once::new();
+
+/// Enables the main counter of the HPET described by ACPI.
+///
+/// Returns `None` if there is no HPET, or if its counter is only 32 bits wide and would wrap
+/// around within minutes.
+pub fn init() -> Option<ClockSource> {
+    let table = acpi::find(b"HPET")?;
+    if table.u8(TABLE_ADDRESS_SPACE)? != 0 {
+        return None; // not memory mapped
+    }
+    let address = PhysAddr::new(table.u64(TABLE_ADDRESS)?);
+    let base = *BASE.try_call_once(|| mem::map_mmio(address, REGISTERS_SIZE)).ok()?;
+
+    let capabilities = unsafe { read(base, CAPABILITIES) };
+    let period = capabilities >> 32; // femtoseconds per count
+    if period == 0 || capabilities & COUNTER_64BIT == 0 {
+        return None;
+    }
+
+    unsafe {
+        let configuration = read(base, CONFIGURATION);
+        write(base, CONFIGURATION, configuration | ENABLE);
+    }
+
+    Some(ClockSource { name: "hpet", rating: 250, frequency: FEMTOS_PER_SECOND / period, read: counter })
+}
+
+/// Reads the main counter.
+fn counter() -> u64 {
+    let base = *BASE.get().expect("HPET should be initialized");
+    unsafe { read(base, MAIN_COUNTER) }
+}
+
+unsafe fn read(base: VirtAddr, register: usize) -> u64 {
+    unsafe { ptr::read_volatile((base + register as u64).as_ptr::<u64>()) }
+}
+
+unsafe fn write(base: VirtAddr, register: usize, value: u64) {
+    unsafe { ptr::write_volatile((base + register as u64).as_mut_ptr::<u64>(), value) }
+}
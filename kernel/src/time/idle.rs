@@ -0,0 +1,137 @@
+//! Tickless idle, halting the CPU without the timer interrupt until the next timer is due.
+//!
+//! While idle, the timer interrupt is masked and the local APIC timer is armed for the earliest
+//! [timer](super::timer) instead, so an idle machine only wakes up when there is work to do.
+//! The ticks missed in the meantime are counted once the CPU wakes up again.
+
+use core::hint;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
+use x86_64::instructions::interrupts;
+use crate::{apic, serial_println, time};
+use crate::time::timer;
+
+/// The longest the CPU stays halted without a timer due, so the tick is caught up regularly.
+const MAX_IDLE: Duration = Duration::from_secs(1);
+
+static TICKLESS: AtomicBool = AtomicBool::new(false);
+
+static IDLE_NANOS: AtomicU64 = AtomicU64::new(0);
+static HALTS: AtomicU64 = AtomicU64::new(0);
+static TICKLESS_HALTS: AtomicU64 = AtomicU64::new(0);
+
+/// Time spent idle since boot.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct IdleStats {
+    /// Time spent halted.
+    pub idle: Duration,
+    /// Time since boot, as of these statistics.
+    pub uptime: Duration,
+    /// Times the CPU was halted.
+    pub halts: u64,
+    /// Times the CPU was halted with the tick stopped.
+    pub tickless_halts: u64
+}
+
+impl IdleStats {
+    /// Provides the share of time spent idle, in hundredths of a percent.
+    pub fn residency(&self) -> u64 {
+        let uptime = self.uptime.as_nanos().max(1);
+        (self.idle.as_nanos() * 10_000 / uptime) as u64
+    }
+}
+
+/// Sets up the local APIC timer, enabling tickless idle if the clock source doesn't rely on the tick.
+///
+/// Must be called after [`init_clocks`](time::init_clocks), with interrupts enabled.
+pub fn init() {
+    apic::init();
+    let source = time::clock_source();
+    if source.name == time::PIT.name {
+        serial_println!("[tokyo] tickless idle unavailable, the clock source relies on the tick");
+        return;
+    }
+
+    TICKLESS.store(true, Ordering::Relaxed);
+    serial_println!("[tokyo] tickless idle with the local APIC timer in {:?}", apic::timer_mode().unwrap());
+}
+
+/// Returns whether idling stops the tick.
+pub fn is_tickless() -> bool {
+    TICKLESS.load(Ordering::Relaxed)
+}
+
+/// Enables or disables stopping the tick while idle, as long as it is available.
+pub fn set_tickless(enabled: bool) {
+    let available = apic::timer_mode().is_some() && time::clock_source().name != time::PIT.name;
+    TICKLESS.store(enabled && available, Ordering::Relaxed);
+}
+
+/// Halts until an interrupt arrives, stopping the tick if possible.
+///
+/// Must be called with interrupts disabled, after checking there is nothing left to do, so
+/// no interrupt can slip in before halting. Returns with interrupts enabled.
+pub fn halt() {
+    let start = time::now();
+
+    if is_tickless() {
+        halt_tickless();
+    } else {
+        interrupts::enable_and_hlt();
+    }
+
+    IDLE_NANOS.fetch_add((time::now() - start).as_nanos() as u64, Ordering::Relaxed);
+    HALTS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Waits a moment for a device polled through `done`, halting until the next interrupt if the
+/// device raises one when done and spinning otherwise.
+///
+/// `done` is checked with interrupts disabled, so the interrupt can't arrive just before halting.
+pub fn pause(interrupt: bool, done: impl FnOnce() -> bool) {
+    if interrupt && interrupts::are_enabled() {
+        interrupts::disable();
+        if done() {
+            interrupts::enable();
+        } else {
+            halt();
+        }
+    } else {
+        hint::spin_loop();
+    }
+}
+
+fn halt_tickless() {
+    let delay = match timer::next_expiry() {
+        Some(expires) => {
+            let ticks = expires.saturating_sub(time::ticks());
+            let frequency = time::frequency().max(1) as u64;
+            Duration::from_nanos(ticks.saturating_mul(1_000_000_000) / frequency).min(MAX_IDLE)
+        }
+        None => MAX_IDLE
+    };
+    if delay.is_zero() {
+        interrupts::enable(); // a timer is due already
+        return;
+    }
+
+    let tick = time::stop_tick();
+    apic::arm_timer(delay);
+    interrupts::enable_and_hlt();
+
+    interrupts::disable();
+    apic::disarm_timer();
+    time::resume_tick(tick);
+    interrupts::enable();
+    TICKLESS_HALTS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Provides the time spent idle since boot.
+pub fn stats() -> IdleStats {
+    IdleStats {
+        idle: Duration::from_nanos(IDLE_NANOS.load(Ordering::Relaxed)),
+        uptime: time::now(),
+        halts: HALTS.load(Ordering::Relaxed),
+        tickless_halts: TICKLESS_HALTS.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,270 @@
+//! Timekeeping, counting ticks of the timer interrupt since boot.
+//!
+//! The PIT raises the timer interrupt at the rate set by [`init`](init), and every tick
+//! advances a monotonic counter that [`uptime`](uptime) and [`sleep`](sleep) are based on.
+//! For finer measurements, [`now`](now) reads the best [clock source](ClockSource) available,
+//! such as an invariant TSC or the HPET. The date is read from the [RTC](rtc) once at boot,
+//! and [`wall_clock`](wall_clock) follows it from there. Callbacks can be deferred with the
+//! [timer wheel](timer).
+
+pub mod date;
+pub mod hpet;
+pub mod idle;
+pub mod pit;
+pub mod rtc;
+pub mod timer;
+pub mod tsc;
+
+pub use date::DateTime;
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::instructions;
+use x86_64::instructions::interrupts;
+use crate::idt::{self, InterruptIndex};
+use crate::serial_println;
+
+/// The rate of the timer interrupt set at boot, in hertz.
+pub const DEFAULT_FREQUENCY: u32 = 1000;
+
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+static CLOCK: Mutex<Clock> = Mutex::new(Clock { divisor: 0, ticks: 0, nanos: 0 });
+
+static SOURCE: Mutex<Option<Selected>> = Mutex::new(None);
+
+/// Unix time in nanoseconds when [`now`](now) was zero, set by [`init_wall_clock`](init_wall_clock).
+static BOOT_TIME: AtomicU64 = AtomicU64::new(0);
+
+/// The tick counter as a clock source, counting nanoseconds of [`uptime`](uptime).
+pub const PIT: ClockSource = ClockSource {
+    name: "pit",
+    rating: 100,
+    frequency: NANOS_PER_SECOND as u64,
+    read: || uptime().as_nanos() as u64
+};
+
+/// A free-running counter that [`now`](now) can be based on.
+#[derive(Debug, Copy, Clone)]
+pub struct ClockSource {
+    pub name: &'static str,
+    /// How precise and cheap to read the source is, the highest rated one available is used.
+    pub rating: u32,
+    /// Counts per second.
+    pub frequency: u64,
+    pub read: fn() -> u64
+}
+
+impl ClockSource {
+    /// Reads the counter.
+    pub fn count(&self) -> u64 {
+        (self.read)()
+    }
+}
+
+/// The clock source in use, along with the time at which it was selected.
+struct Selected {
+    source: ClockSource,
+    /// Count of the source when it was selected.
+    count: u64,
+    /// Nanoseconds since boot when it was selected.
+    nanos: u64
+}
+
+impl Selected {
+    fn nanos(&self) -> u64 {
+        let elapsed = self.source.count().wrapping_sub(self.count) as u128 * NANOS_PER_SECOND;
+        self.nanos + (elapsed / self.source.frequency as u128) as u64
+    }
+}
+
+/// The rate of the ticks, along with the time at which it was last changed.
+struct Clock {
+    /// Input clock cycles of the PIT between ticks, zero before [`init`](init).
+    divisor: u32,
+    /// Ticks counted before the rate was last changed.
+    ticks: u64,
+    /// Nanoseconds of uptime before the rate was last changed.
+    nanos: u64
+}
+
+impl Clock {
+    fn nanos(&self, ticks: u64) -> u64 {
+        let elapsed = (ticks - self.ticks) as u128 * self.divisor as u128 * NANOS_PER_SECOND;
+        self.nanos + (elapsed / pit::BASE_FREQUENCY as u128) as u64
+    }
+}
+
+/// Programs the timer interrupt to tick at about `frequency` hertz and enables it.
+///
+/// Can be called again to change the rate, without affecting the uptime counted so far.
+/// Must be called after the PICs have been initialized.
+pub fn init(frequency: u32) {
+    let divisor = pit::divisor(frequency);
+    interrupts::without_interrupts(|| {
+        let mut clock = CLOCK.lock();
+        let ticks = TICKS.load(Ordering::Relaxed);
+        if clock.divisor != 0 {
+            clock.nanos = clock.nanos(ticks);
+        }
+        clock.ticks = ticks;
+        clock.divisor = divisor;
+        unsafe { pit::set_divisor(divisor); }
+    });
+    idt::unmask(InterruptIndex::Timer);
+}
+
+/// Counts a tick, called by the timer interrupt handler.
+pub(crate) fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// The state of the tick while it is stopped for tickless idle.
+pub(crate) struct StoppedTick {
+    /// Input clock cycles of the PIT until its next interrupt.
+    count: u64,
+    divisor: u64,
+    stopped: Duration
+}
+
+/// Stops counting ticks by masking the timer interrupt, which the PIT keeps raising regardless.
+///
+/// Must be called with interrupts disabled, and with a [clock source](ClockSource) other than the
+/// PIT, which [`resume_tick`](resume_tick) catches up with.
+pub(crate) fn stop_tick() -> StoppedTick {
+    idt::mask(InterruptIndex::Timer);
+    let divisor = CLOCK.lock().divisor as u64;
+    let count = match pit::count() {
+        0 => 0x10000,
+        count => count as u64
+    };
+    StoppedTick { count, divisor, stopped: now() }
+}
+
+/// Counts the ticks missed since [`stop_tick`](stop_tick) and unmasks the timer interrupt.
+///
+/// Must be called with interrupts disabled.
+pub(crate) fn resume_tick(tick: StoppedTick) {
+    let elapsed = now() - tick.stopped;
+    let cycles = (elapsed.as_nanos() * pit::BASE_FREQUENCY as u128 / NANOS_PER_SECOND) as u64;
+
+    // the first missed interrupt is still pending, and is raised once unmasked
+    if cycles >= tick.count {
+        let missed = 1 + ((cycles - tick.count) / tick.divisor.max(1));
+        TICKS.fetch_add(missed - 1, Ordering::Relaxed);
+    }
+    idt::unmask(InterruptIndex::Timer);
+}
+
+/// Provides the number of timer interrupts since boot.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Provides the rate of the timer interrupt in hertz, or zero if it hasn't been set up.
+pub fn frequency() -> u32 {
+    let divisor = interrupts::without_interrupts(|| CLOCK.lock().divisor);
+    pit::BASE_FREQUENCY.checked_div(divisor).unwrap_or(0)
+}
+
+/// Provides the time since the timer was set up, at the resolution of a tick.
+pub fn uptime() -> Duration {
+    interrupts::without_interrupts(|| {
+        let clock = CLOCK.lock();
+        if clock.divisor == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_nanos(clock.nanos(TICKS.load(Ordering::Relaxed)))
+    })
+}
+
+/// Halts until at least `duration` has passed.
+///
+/// Relies on the timer interrupt, so interrupts have to be enabled.
+pub fn sleep(duration: Duration) {
+    assert!(frequency() != 0, "timer should be initialized before sleeping");
+    assert!(interrupts::are_enabled(), "interrupts should be enabled while sleeping");
+
+    let deadline = uptime() + duration;
+    while uptime() < deadline {
+        instructions::hlt(); // woken by the next tick at the latest
+    }
+}
+
+/// Halts until at least `millis` milliseconds have passed.
+pub fn sleep_ms(millis: u64) {
+    sleep(Duration::from_millis(millis));
+}
+
+/// Looks for the HPET and an invariant TSC, switching to the best clock source found.
+///
+/// The TSC is calibrated against the HPET if there is one, and the timer interrupt otherwise.
+/// Must be called after [`init`](init) and the ACPI tables, with interrupts enabled.
+pub fn init_clocks() {
+    offer(PIT);
+    if let Some(hpet) = hpet::init() {
+        offer(hpet);
+    }
+    if let Some(tsc) = tsc::init(&clock_source()) {
+        offer(tsc);
+    }
+
+    let source = clock_source();
+    serial_println!("[tokyo] clock source: {} at {} Hz", source.name, source.frequency);
+}
+
+/// Switches to a clock source if it is rated higher than the current one.
+pub fn offer(source: ClockSource) {
+    let better = interrupts::without_interrupts(|| {
+        SOURCE.lock().as_ref().is_none_or(|selected| source.rating > selected.source.rating)
+    });
+    if better {
+        select(source);
+    }
+}
+
+/// Switches to a clock source, continuing from the time read from the current one.
+pub fn select(source: ClockSource) {
+    assert!(source.frequency != 0, "clock source should have a frequency");
+    interrupts::without_interrupts(|| {
+        let mut selected = SOURCE.lock();
+        let nanos = selected.as_ref().map_or_else(|| uptime().as_nanos() as u64, Selected::nanos);
+        *selected = Some(Selected { source, count: source.count(), nanos });
+    });
+}
+
+/// Provides the clock source [`now`](now) reads from.
+pub fn clock_source() -> ClockSource {
+    interrupts::without_interrupts(|| SOURCE.lock().as_ref().map_or(PIT, |selected| selected.source))
+}
+
+/// Provides the time since boot with up to nanosecond resolution, depending on the clock source.
+///
+/// Before [`init_clocks`](init_clocks) is called, this is the same as [`uptime`](uptime).
+pub fn now() -> Duration {
+    let nanos = interrupts::without_interrupts(|| {
+        SOURCE.lock().as_ref().map_or_else(|| uptime().as_nanos() as u64, Selected::nanos)
+    });
+    Duration::from_nanos(nanos)
+}
+
+/// Reads the date from the RTC, which [`wall_clock`](wall_clock) advances from.
+pub fn init_wall_clock() {
+    let date = rtc::read();
+    let boot_time = date.to_unix().saturating_sub(now());
+    BOOT_TIME.store(boot_time.as_nanos() as u64, Ordering::Relaxed);
+    serial_println!("[tokyo] wall clock: {}", date);
+}
+
+/// Provides the time since 1970-01-01 00:00:00 UTC, or since boot if the date wasn't read yet.
+pub fn unix_time() -> Duration {
+    Duration::from_nanos(BOOT_TIME.load(Ordering::Relaxed)) + now()
+}
+
+/// Provides the current date and time in UTC.
+pub fn wall_clock() -> DateTime {
+    DateTime::from_unix(unix_time())
+}
@@ -0,0 +1,52 @@
+//! The Programmable Interval Timer (8253/8254), which raises the timer interrupt on IRQ 0.
+
+use x86_64::instructions::port::Port;
+
+/// The frequency of the PIT's input clock, in hertz.
+pub const BASE_FREQUENCY: u32 = 1_193_182;
+
+const CHANNEL0_PORT: u16 = 0x40;
+const COMMAND_PORT: u16 = 0x43;
+
+/// Channel 0, low then high byte of the reload value, mode 2 (rate generator), binary.
+const RATE_GENERATOR: u8 = 0b0011_0100;
+/// Channel 0, latching the current count so it can be read.
+const LATCH: u8 = 0b0000_0000;
+
+/// Provides the reload value closest to raising `frequency` interrupts per second.
+///
+/// The PIT can't go slower than about 18.2 Hz or faster than its input clock,
+/// so the frequency is clamped to that range.
+pub fn divisor(frequency: u32) -> u32 {
+    let divisor = (BASE_FREQUENCY + (frequency.max(1) / 2)) / frequency.max(1);
+    divisor.clamp(1, 0x10000)
+}
+
+/// Programs channel 0 to raise an interrupt every `divisor` cycles of the input clock.
+///
+/// ## Safety
+/// Changes the rate of the timer interrupt, which the caller has to account for.
+pub unsafe fn set_divisor(divisor: u32) {
+    assert!((1..=0x10000).contains(&divisor), "divisor should fit the reload register");
+    let reload = divisor as u16; // 0x10000 is written as 0
+
+    unsafe {
+        Port::<u8>::new(COMMAND_PORT).write(RATE_GENERATOR);
+        let mut channel = Port::<u8>::new(CHANNEL0_PORT);
+        channel.write(reload as u8);
+        channel.write((reload >> 8) as u8);
+    }
+}
+
+/// Reads the current count of channel 0, which counts down to the next interrupt.
+///
+/// A count of 0x10000 is read as 0.
+pub fn count() -> u16 {
+    unsafe {
+        Port::<u8>::new(COMMAND_PORT).write(LATCH);
+        let mut channel = Port::<u8>::new(CHANNEL0_PORT);
+        let low = channel.read();
+        let high = channel.read();
+        u16::from_le_bytes([low, high])
+    }
+}
@@ -0,0 +1,197 @@
+//! The CMOS real-time clock, which keeps the date while the machine is off.
+//!
+//! Besides the date, the RTC can raise a periodic interrupt on IRQ 8, which is offered as a
+//! low-rated [clock source](ClockSource) once enabled.
+
+use core::hint;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+use crate::acpi;
+use crate::idt::{self, InterruptIndex};
+use crate::time::{ClockSource, DateTime};
+
+const INDEX_PORT: u16 = 0x70;
+const DATA_PORT: u16 = 0x71;
+
+/// Keeps NMIs disabled while a register is selected.
+const DISABLE_NMI: u8 = 1 << 7;
+
+const SECONDS: u8 = 0x00;
+const MINUTES: u8 = 0x02;
+const HOURS: u8 = 0x04;
+const DAY: u8 = 0x07;
+const MONTH: u8 = 0x08;
+const YEAR: u8 = 0x09;
+const STATUS_A: u8 = 0x0A;
+const STATUS_B: u8 = 0x0B;
+const STATUS_C: u8 = 0x0C;
+const STATUS_D: u8 = 0x0D;
+
+/// Set in status A while the clock is being updated, when the date registers can't be read.
+const UPDATE_IN_PROGRESS: u8 = 1 << 7;
+/// Set in status B if the hours count from 0 to 23, rather than 1 to 12.
+const HOURS_24: u8 = 1 << 1;
+/// Set in status B if the registers are binary, rather than BCD.
+const BINARY: u8 = 1 << 2;
+/// Set in status B to enable the periodic interrupt.
+const PERIODIC: u8 = 1 << 6;
+/// Set in status C when the periodic interrupt was raised.
+const PERIODIC_FLAG: u8 = 1 << 6;
+/// Set in the hours register for the afternoon in 12-hour mode.
+const PM: u8 = 1 << 7;
+
+/// Offset of the century register's index in the FADT.
+const FADT_CENTURY: usize = 108;
+
+/// The frequency the RTC divides its periodic interrupt rate from.
+const BASE_FREQUENCY: u32 = 32_768;
+
+/// Serializes access to the index and data ports.
+static CMOS: Mutex<()> = Mutex::new(());
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Reads the current date and time, assuming the RTC is set to UTC.
+///
+/// The century is taken from the register named by the FADT, or assumed to be the 21st.
+pub fn read() -> DateTime {
+    let century = acpi::find(b"FACP").and_then(|fadt| fadt.u8(FADT_CENTURY)).filter(|&index| index != 0);
+
+    // read until two reads agree, in case an update started in between
+    let mut previous = read_raw(century);
+    loop {
+        let current = read_raw(century);
+        if current == previous {
+            break;
+        }
+        previous = current;
+    }
+
+    let status = read_register(STATUS_B);
+    decode(previous, status)
+}
+
+/// The date registers as read, before decoding BCD and the hour format.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Raw {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: Option<u8>
+}
+
+fn read_raw(century: Option<u8>) -> Raw {
+    while read_register(STATUS_A) & UPDATE_IN_PROGRESS != 0 {
+        hint::spin_loop();
+    }
+    Raw {
+        second: read_register(SECONDS),
+        minute: read_register(MINUTES),
+        hour: read_register(HOURS),
+        day: read_register(DAY),
+        month: read_register(MONTH),
+        year: read_register(YEAR),
+        century: century.map(read_register)
+    }
+}
+
+fn decode(raw: Raw, status: u8) -> DateTime {
+    let value = |byte: u8| if status & BINARY != 0 { byte } else { (byte >> 4) * 10 + (byte & 0x0F) };
+
+    let mut hour = value(raw.hour & !PM);
+    if status & HOURS_24 == 0 {
+        hour %= 12; // 12 AM is midnight
+        if raw.hour & PM != 0 {
+            hour += 12;
+        }
+    }
+
+    let century = raw.century.map_or(20, value) as u16;
+    DateTime {
+        year: (century * 100) + value(raw.year) as u16,
+        month: value(raw.month),
+        day: value(raw.day),
+        hour,
+        minute: value(raw.minute),
+        second: value(raw.second)
+    }
+}
+
+/// Enables the periodic interrupt at the fastest rate up to `frequency`, between 2 Hz and 8192 Hz.
+///
+/// Returns the clock source counting the interrupts. Must be called after the PICs have been initialized.
+pub fn enable_periodic(frequency: u32) -> ClockSource {
+    // the rate is a divider, shifting the base frequency right by one less than it
+    let rate = (3..=15).find(|&rate| BASE_FREQUENCY >> (rate - 1) <= frequency).unwrap_or(15);
+    interrupts::without_interrupts(|| {
+        let status = read_register(STATUS_A);
+        write_register(STATUS_A, (status & 0xF0) | rate as u8);
+        let status = read_register(STATUS_B);
+        write_register(STATUS_B, status | PERIODIC);
+        read_register(STATUS_C); // discard a pending interrupt
+    });
+    idt::unmask(InterruptIndex::Rtc);
+
+    ClockSource { name: "rtc", rating: 50, frequency: (BASE_FREQUENCY >> (rate - 1)) as u64, read: ticks }
+}
+
+/// Disables the periodic interrupt.
+pub fn disable_periodic() {
+    idt::mask(InterruptIndex::Rtc);
+    interrupts::without_interrupts(|| {
+        let status = read_register(STATUS_B);
+        write_register(STATUS_B, status & !PERIODIC);
+        read_register(STATUS_C); // discard a pending interrupt
+    });
+}
+
+/// Provides the number of periodic interrupts raised so far.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Counts a periodic interrupt, called by the RTC interrupt handler.
+pub(crate) fn interrupt() {
+    // the RTC raises no further interrupts until status C is read
+    if read_register(STATUS_C) & PERIODIC_FLAG != 0 {
+        TICKS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn read_register(register: u8) -> u8 {
+    interrupts::without_interrupts(|| {
+        let _guard = CMOS.lock();
+        unsafe {
+            Port::<u8>::new(INDEX_PORT).write(DISABLE_NMI | register);
+            let value = Port::<u8>::new(DATA_PORT).read();
+            deselect();
+            value
+        }
+    })
+}
+
+fn write_register(register: u8, value: u8) {
+    interrupts::without_interrupts(|| {
+        let _guard = CMOS.lock();
+        unsafe {
+            Port::<u8>::new(INDEX_PORT).write(DISABLE_NMI | register);
+            Port::<u8>::new(DATA_PORT).write(value);
+            deselect();
+        }
+    });
+}
+
+/// Enables NMIs again by selecting status D, which is read-only, without [`DISABLE_NMI`](DISABLE_NMI).
+///
+/// ## Safety
+/// The caller must hold the `CMOS` lock.
+unsafe fn deselect() {
+    unsafe {
+        Port::<u8>::new(INDEX_PORT).write(STATUS_D);
+    }
+}
@@ -0,0 +1,227 @@
+//! Deferred callbacks, run after a delay by a hierarchical timer wheel.
+//!
+//! The wheel advances with the ticks of the timer interrupt, but callbacks never run in
+//! interrupt context. Instead, [`run_expired`](run_expired) runs the callbacks that are due,
+//! which the idle loop of the [terminals](crate::terminal) does after every wakeup.
+//!
+//! Each level of the wheel has 64 slots, with a slot in the first level covering one tick and
+//! one in each following level covering a whole rotation of the level before it. Timers far
+//! off start in a higher level and cascade down as their expiry approaches, so adding, cancelling
+//! and advancing all take constant time. Each level also tracks which of its slots hold timers,
+//! which bounds when the next one is due in constant time too.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::array;
+use core::mem;
+use core::time::Duration;
+use spin::{Lazy, Mutex};
+use x86_64::instructions::interrupts;
+use crate::time;
+
+const LEVEL_BITS: u32 = 6;
+const SLOTS: usize = 1 << LEVEL_BITS;
+const LEVELS: usize = 4;
+
+/// The furthest ahead a timer can be placed, later ones are placed here and moved on as they cascade.
+const MAX_DELTA: u64 = (1 << (LEVEL_BITS * LEVELS as u32)) - 1;
+
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+
+static WHEEL: Lazy<Mutex<Wheel>> = Lazy::new(|| Mutex::new(Wheel::new(time::ticks())));
+
+/// Identifies a timer, so it can be cancelled.
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct TimerId(u64);
+
+type Callback = Box<dyn FnMut() + Send>;
+
+struct Timer {
+    /// The tick the timer is due at.
+    expires: u64,
+    /// Ticks between runs of a periodic timer.
+    period: Option<u64>,
+    /// Taken out while the callback runs.
+    callback: Option<Callback>
+}
+
+struct Wheel {
+    timers: BTreeMap<TimerId, Timer>,
+    /// Timers by level and slot. Cancelled timers are only removed from `timers`, and skipped here.
+    slots: [[Vec<TimerId>; SLOTS]; LEVELS],
+    /// A bit for every slot by level, set while the slot isn't empty.
+    occupied: [u64; LEVELS],
+    /// The next tick to process.
+    current: u64,
+    next_id: u64
+}
+
+impl Wheel {
+    fn new(current: u64) -> Self {
+        Self {
+            timers: BTreeMap::new(),
+            slots: array::from_fn(|_| array::from_fn(|_| Vec::new())),
+            occupied: [0; LEVELS],
+            current,
+            next_id: 0
+        }
+    }
+
+    fn add(&mut self, expires: u64, period: Option<u64>, callback: Callback) -> TimerId {
+        let id = TimerId(self.next_id);
+        self.next_id += 1;
+        self.timers.insert(id, Timer { expires, period, callback: Some(callback) });
+        self.place(id, expires);
+        id
+    }
+
+    /// Puts a timer into the slot of the lowest level that reaches its expiry.
+    fn place(&mut self, id: TimerId, expires: u64) {
+        let expires = expires.clamp(self.current, self.current + MAX_DELTA);
+        let delta = expires - self.current;
+        let level = (0..LEVELS)
+            .find(|&level| delta < 1 << (LEVEL_BITS * (level as u32 + 1)))
+            .unwrap_or(LEVELS - 1);
+        let slot = (expires >> (LEVEL_BITS * level as u32)) as usize % SLOTS;
+        self.slots[level][slot].push(id);
+        self.occupied[level] |= 1 << slot;
+    }
+
+    /// Empties a slot, returning the timers it held.
+    fn take(&mut self, level: usize, slot: usize) -> Vec<TimerId> {
+        self.occupied[level] &= !(1 << slot);
+        mem::take(&mut self.slots[level][slot])
+    }
+
+    /// Provides a tick no later than the earliest timer is due at, from the start of the first
+    /// occupied slot of each level.
+    fn next_expiry(&self) -> Option<u64> {
+        (0..LEVELS)
+            .filter_map(|level| {
+                let shift = LEVEL_BITS * level as u32;
+                // a slot is cascaded once the tick at its start is processed, and then holds the next rotation's
+                let first = (self.current >> shift) + !self.current.is_multiple_of(1 << shift) as u64;
+                let occupied = self.occupied[level].rotate_right((first % SLOTS as u64) as u32);
+                (occupied != 0).then(|| (first + occupied.trailing_zeros() as u64) << shift)
+            })
+            .min()
+    }
+
+    /// Processes the current tick, returning the timers due at it.
+    fn advance(&mut self) -> Vec<TimerId> {
+        // whenever a level completes a rotation, the next slot of the level above is spread out below
+        for level in 1..LEVELS {
+            let lower = self.current >> (LEVEL_BITS * (level as u32 - 1));
+            if !lower.is_multiple_of(SLOTS as u64) {
+                break;
+            }
+            let slot = (self.current >> (LEVEL_BITS * level as u32)) as usize % SLOTS;
+            for id in self.take(level, slot) {
+                if let Some(timer) = self.timers.get(&id) {
+                    let expires = timer.expires;
+                    self.place(id, expires);
+                }
+            }
+        }
+
+        let slot = self.current as usize % SLOTS;
+        let due = self.take(0, slot)
+            .into_iter()
+            .filter(|id| self.timers.contains_key(id))
+            .collect();
+        self.current += 1;
+        due
+    }
+}
+
+/// Runs `callback` once, after at least `delay` has passed.
+pub fn add_timer<F: FnMut() + Send + 'static>(delay: Duration, callback: F) -> TimerId {
+    let delay = to_ticks(delay);
+    interrupts::without_interrupts(|| {
+        let mut wheel = WHEEL.lock();
+        let expires = time::ticks() + delay;
+        wheel.add(expires, None, Box::new(callback))
+    })
+}
+
+/// Runs `callback` every `period`, starting after the first one has passed.
+///
+/// The period is rounded up to at least a tick of the timer interrupt.
+pub fn add_periodic_timer<F: FnMut() + Send + 'static>(period: Duration, callback: F) -> TimerId {
+    let period = to_ticks(period).max(1);
+    interrupts::without_interrupts(|| {
+        let mut wheel = WHEEL.lock();
+        let expires = time::ticks() + period;
+        wheel.add(expires, Some(period), Box::new(callback))
+    })
+}
+
+/// Cancels a timer, returning `false` if it already ran or was cancelled.
+///
+/// Can be called from a timer's own callback, which stops a periodic timer from running again.
+pub fn cancel_timer(id: TimerId) -> bool {
+    interrupts::without_interrupts(|| WHEEL.lock().timers.remove(&id).is_some())
+}
+
+/// Runs the callbacks of every timer that expired, returning how many ran.
+///
+/// Callbacks run one after another with interrupts enabled, and may add or cancel timers.
+pub fn run_expired() -> usize {
+    let mut ran = 0;
+    loop {
+        // take out one tick's worth of callbacks at a time, so they can use the wheel themselves
+        let due: Option<Vec<(TimerId, Callback)>> = interrupts::without_interrupts(|| {
+            let mut wheel = WHEEL.lock();
+            if wheel.current > time::ticks() {
+                return None; // caught up
+            }
+            let due = wheel.advance();
+            Some(due.into_iter()
+                .filter_map(|id| Some((id, wheel.timers.get_mut(&id)?.callback.take()?)))
+                .collect())
+        });
+        let Some(due) = due else {
+            return ran;
+        };
+
+        for (id, mut callback) in due {
+            callback();
+            ran += 1;
+
+            interrupts::without_interrupts(|| {
+                let mut wheel = WHEEL.lock();
+                let Some(timer) = wheel.timers.get_mut(&id) else {
+                    return; // cancelled by the callback
+                };
+                match timer.period {
+                    Some(period) => {
+                        timer.expires += period;
+                        timer.callback = Some(callback);
+                        let expires = timer.expires;
+                        wheel.place(id, expires);
+                    }
+                    None => {
+                        wheel.timers.remove(&id);
+                    }
+                }
+            });
+        }
+    }
+}
+
+/// Provides a tick no later than the earliest timer is due at, or `None` if there are no timers.
+///
+/// The tick is exact for timers due within a rotation of the first level, and earlier for those
+/// further off or cancelled, so waking up at it may find nothing due yet.
+///
+/// Also returns `None` if the wheel is in use, which can only happen after a panic.
+pub fn next_expiry() -> Option<u64> {
+    interrupts::without_interrupts(|| WHEEL.try_lock()?.next_expiry())
+}
+
+/// Converts a delay to ticks of the timer interrupt, rounding up.
+fn to_ticks(delay: Duration) -> u64 {
+    let frequency = time::frequency().max(1) as u128;
+    delay.as_nanos().saturating_mul(frequency).div_ceil(NANOS_PER_SECOND) as u64
+}
@@ -0,0 +1,51 @@
+//! The time stamp counter, used as a clock source when it runs at a constant rate.
+
+use core::arch::x86_64::{__cpuid, _rdtsc};
+use core::hint;
+use crate::time::ClockSource;
+
+/// Time spent counting TSC cycles against the reference clock source.
+const CALIBRATION_MS: u64 = 50;
+
+/// Reads the time stamp counter.
+pub fn read() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// Checks whether the TSC is invariant, running at a constant rate regardless of power states.
+pub fn is_invariant() -> bool {
+    let max = __cpuid(0x8000_0000).eax;
+    max >= 0x8000_0007 && __cpuid(0x8000_0007).edx & (1 << 8) != 0
+}
+
+/// Calibrates an invariant TSC against `reference`, returning `None` if the TSC isn't invariant.
+///
+/// The reference may only advance with the timer interrupt, so interrupts have to be enabled.
+pub fn init(reference: &ClockSource) -> Option<ClockSource> {
+    if !is_invariant() {
+        return None;
+    }
+    Some(ClockSource { name: "tsc", rating: 300, frequency: calibrate(reference), read })
+}
+
+/// Measures the TSC frequency against a reference clock source.
+pub fn calibrate(reference: &ClockSource) -> u64 {
+    // start right at a change of the reference, in case it is coarse
+    let first = reference.count();
+    let mut start = first;
+    while start == first {
+        hint::spin_loop();
+        start = reference.count();
+    }
+    let cycles = read();
+
+    let window = reference.frequency * CALIBRATION_MS / 1000;
+    let mut end = start;
+    while end - start < window {
+        hint::spin_loop();
+        end = reference.count();
+    }
+    let cycles = read() - cycles;
+
+    (cycles as u128 * reference.frequency as u128 / (end - start) as u128) as u64
+}
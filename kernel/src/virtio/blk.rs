@@ -0,0 +1,295 @@
+//! The virtio block device, such as a disk attached to QEMU with `-drive if=virtio`.
+//!
+//! Requests go through a single queue one at a time, through a bounce buffer so callers can pass
+//! any buffer. The driver waits for each to complete, halting until the queue's MSI-X interrupt
+//! arrives if it has one, and polling otherwise. A request that times out resets the device,
+//! which is then set up again with a fresh queue.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ptr;
+use core::time::Duration;
+use spin::Mutex;
+use crate::block::{self, BlockDevice, BlockError};
+use crate::driver::{Device, Driver, Match, ProbeError};
+use crate::irq::{self, Vector};
+use crate::mem::dma::DmaBuffer;
+use crate::pci::msi::MsiX;
+use crate::serial_println;
+use crate::time::{self, idle};
+use crate::virtio::queue::{Buffer, Queue};
+use crate::virtio::transport::Transport;
+use crate::virtio::{VirtioError, VENDOR_ID};
+
+pub static DRIVER: Driver = Driver {
+    name: "virtio-blk",
+    matches: &[
+        Match::Pci { vendor_id: VENDOR_ID, device_id: 0x1001 }, // transitional
+        Match::Pci { vendor_id: VENDOR_ID, device_id: 0x1042 }
+    ],
+    probe,
+    remove
+};
+
+pub const SECTOR_SIZE: usize = 512;
+
+const FEATURE_READ_ONLY: u64 = 1 << 5;
+const FEATURE_FLUSH: u64 = 1 << 9;
+
+const CONFIG_CAPACITY: u16 = 0;
+
+const REQUEST_IN: u32 = 0;
+const REQUEST_OUT: u32 = 1;
+const REQUEST_FLUSH: u32 = 4;
+
+const STATUS_OK: u8 = 0;
+const STATUS_UNSUPPORTED: u8 = 2;
+
+/// Descriptors a queue is limited to, which is plenty with one request at a time.
+const MAX_QUEUE_SIZE: u16 = 256;
+
+/// Bytes transferred by a single request, with longer ones split up.
+const MAX_TRANSFER: usize = 64 * 1024;
+
+/// Offsets in the bounce buffer, with the request header and status sharing the first page.
+const HEADER: usize = 0;
+const STATUS: usize = 16;
+const DATA: usize = 4096;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Disks the driver took over, by the name of their device.
+static DISKS: Mutex<Vec<(String, Arc<VirtioBlk>)>> = Mutex::new(Vec::new());
+
+/// A virtio block device.
+pub struct VirtioBlk {
+    name: String,
+    transport: Transport,
+    /// Capacity in sectors.
+    capacity: u64,
+    features: u64,
+    /// The vector raised on completions, if MSI-X could be set up.
+    vector: Option<Vector>,
+    request: Mutex<Request>
+}
+
+struct Request {
+    queue: Queue,
+    buffer: DmaBuffer,
+    /// Whether the device was reset after a request timed out, and couldn't be set up again yet.
+    stopped: bool
+}
+
+impl VirtioBlk {
+    /// Sets up a device and its queue, naming it after the first free `vdX`.
+    pub fn new(transport: Transport) -> Result<Self, VirtioError> {
+        let buffer = DmaBuffer::new(DATA + MAX_TRANSFER).ok_or(VirtioError::OutOfMemory)?;
+
+        // completions only wake up the waiting request, so the handler has nothing to do
+        let vector = irq::allocate(|| {});
+        let routed = vector.and_then(|vector| {
+            let table = MsiX::new(transport.device()).ok()?;
+            table.route(0, vector).ok()?;
+            table.enable();
+            Some(())
+        });
+        let vector = match (vector, routed) {
+            (Some(vector), None) => {
+                irq::free(vector);
+                None
+            }
+            (vector, _) => vector
+        };
+
+        let (features, queue) = match start(&transport, None, vector) {
+            Ok(started) => started,
+            Err(error) => {
+                if let Some(vector) = vector {
+                    irq::free(vector);
+                }
+                return Err(error);
+            }
+        };
+
+        Ok(Self {
+            name: block::next_name("vd"),
+            capacity: transport.config_u64(CONFIG_CAPACITY),
+            transport,
+            features,
+            vector,
+            request: Mutex::new(Request { queue, buffer, stopped: false })
+        })
+    }
+
+    /// Whether completions raise an interrupt, rather than being polled for.
+    pub fn uses_interrupts(&self) -> bool {
+        self.vector.is_some()
+    }
+
+    /// Sets the device up again with a fresh queue after it was reset, as the old queue's state
+    /// no longer matches the device's.
+    fn restart(&self, request: &mut Request) -> Result<(), VirtioError> {
+        let (features, queue) = start(&self.transport, Some(request.queue.size()), self.vector)?;
+        if features != self.features {
+            self.transport.reset();
+            return Err(VirtioError::FeaturesRefused);
+        }
+        request.queue = queue;
+        request.stopped = false;
+        Ok(())
+    }
+
+    /// Runs one request, with `data` bytes of the bounce buffer transferred.
+    fn submit(&self, request: &mut Request, kind: u32, sector: u64, data: usize) -> Result<(), BlockError> {
+        if request.stopped {
+            self.restart(request).map_err(|_| BlockError::Io)?;
+        }
+
+        let Request { queue, buffer, .. } = request;
+        let base = buffer.virt();
+        unsafe {
+            ptr::write_volatile((base + HEADER as u64).as_mut_ptr::<u32>(), kind);
+            ptr::write_volatile((base + HEADER as u64 + 4u64).as_mut_ptr::<u32>(), 0);
+            ptr::write_volatile((base + HEADER as u64 + 8u64).as_mut_ptr::<u64>(), sector);
+            ptr::write_volatile((base + STATUS as u64).as_mut_ptr::<u8>(), u8::MAX);
+        }
+
+        let header = Buffer { address: buffer.phys() + HEADER as u64, length: 16, writable: false };
+        let status = Buffer { address: buffer.phys() + STATUS as u64, length: 1, writable: true };
+        let payload = Buffer { address: buffer.phys() + DATA as u64, length: data as u32, writable: kind == REQUEST_IN };
+        let chain: &[Buffer] = if data == 0 { &[header, status] } else { &[header, payload, status] };
+        queue.push(chain).map_err(|_| BlockError::Io)?;
+        self.transport.notify(queue);
+
+        let deadline = time::now() + TIMEOUT;
+        while queue.pop_used().is_none() {
+            if time::now() > deadline {
+                // the device may still complete the request, so it has to stop using the buffers,
+                // and is then set up again for the next request
+                self.transport.reset();
+                request.stopped = true;
+                if let Err(error) = self.restart(request) {
+                    serial_println!("[tokyo] {}: failed to restart after a timeout: {:?}", self.name, error);
+                }
+                return Err(BlockError::Timeout);
+            }
+            idle::pause(self.vector.is_some(), || queue.has_used());
+        }
+
+        match unsafe { ptr::read_volatile((base + STATUS as u64).as_ptr::<u8>()) } {
+            STATUS_OK => Ok(()),
+            STATUS_UNSUPPORTED => Err(BlockError::Unsupported),
+            _ => Err(BlockError::Io)
+        }
+    }
+}
+
+impl BlockDevice for VirtioBlk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn blocks(&self) -> u64 {
+        self.capacity
+    }
+
+    fn read_only(&self) -> bool {
+        self.features & FEATURE_READ_ONLY != 0
+    }
+
+    fn read(&self, block: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+        block::check_request(self, block, buffer.len())?;
+        let mut request = self.request.lock();
+        for (i, chunk) in buffer.chunks_mut(MAX_TRANSFER).enumerate() {
+            let sector = block + (i * MAX_TRANSFER / SECTOR_SIZE) as u64;
+            self.submit(&mut request, REQUEST_IN, sector, chunk.len())?;
+            chunk.copy_from_slice(&request.buffer.as_slice()[DATA..DATA + chunk.len()]);
+        }
+        Ok(())
+    }
+
+    fn write(&self, block: u64, buffer: &[u8]) -> Result<(), BlockError> {
+        block::check_request(self, block, buffer.len())?;
+        if self.read_only() {
+            return Err(BlockError::ReadOnly);
+        }
+        let mut request = self.request.lock();
+        for (i, chunk) in buffer.chunks(MAX_TRANSFER).enumerate() {
+            let sector = block + (i * MAX_TRANSFER / SECTOR_SIZE) as u64;
+            request.buffer.as_mut_slice()[DATA..DATA + chunk.len()].copy_from_slice(chunk);
+            self.submit(&mut request, REQUEST_OUT, sector, chunk.len())?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        // without the feature, the device doesn't cache writes
+        if self.features & FEATURE_FLUSH == 0 {
+            return Ok(());
+        }
+        let mut request = self.request.lock();
+        self.submit(&mut request, REQUEST_FLUSH, 0, 0)
+    }
+}
+
+/// Resets a device, agrees on features and hands it a new queue, of `size` descriptors or as many
+/// as it allows, returning the features along with the queue.
+fn start(transport: &Transport, size: Option<u16>, vector: Option<Vector>) -> Result<(u64, Queue), VirtioError> {
+    let features = transport.negotiate(FEATURE_READ_ONLY | FEATURE_FLUSH)?;
+    let size = match (size, transport.max_queue_size(0)) {
+        (_, 0) => return Err(VirtioError::MissingQueue),
+        (Some(size), _) => size,
+        (None, max) if transport.is_modern() => max.min(MAX_QUEUE_SIZE),
+        (None, max) => max
+    };
+
+    let queue = Queue::new(0, size)?;
+    if let Err(error) = transport.enable_queue(&queue, vector.map(|_| 0)) {
+        transport.reset();
+        return Err(error);
+    }
+    transport.finish();
+    Ok((features, queue))
+}
+
+fn probe(device: &Arc<Device>) -> Result<(), ProbeError> {
+    let function = device.pci().ok_or(ProbeError::Unsupported)?;
+    let transport = Transport::new(function).map_err(|_| ProbeError::MissingResource)?;
+    let disk = VirtioBlk::new(transport).map_err(|error| match error {
+        VirtioError::FeaturesRefused => ProbeError::Unsupported,
+        VirtioError::MissingTransport | VirtioError::MissingQueue => ProbeError::MissingResource,
+        VirtioError::QueueFull | VirtioError::OutOfMemory => ProbeError::Failed
+    })?;
+
+    let disk = Arc::new(disk);
+    serial_println!("[tokyo] {}: {} sectors on {}", disk.name, disk.capacity, device.name());
+    block::register(disk.clone());
+    DISKS.lock().push((String::from(device.name()), disk));
+    Ok(())
+}
+
+fn remove(device: &Arc<Device>) {
+    let disk = {
+        let mut disks = DISKS.lock();
+        let Some(index) = disks.iter().position(|(name, _)| name == device.name()) else {
+            return;
+        };
+        disks.remove(index).1
+    };
+    block::unregister(&disk.name);
+
+    // in-flight requests hold the lock, so the device is only reset once they're done
+    let _request = disk.request.lock();
+    disk.transport.reset();
+    if let Some(vector) = disk.vector {
+        if let Ok(table) = MsiX::new(disk.transport.device()) {
+            table.disable();
+        }
+        irq::free(vector);
+    }
+}
@@ -0,0 +1,34 @@
+//! Virtio devices, the paravirtualized devices of QEMU and other hypervisors.
+//!
+//! Devices are reached through a [PCI transport](transport), either the modern one of virtio 1.0
+//! or the legacy one that came before, and exchange requests with the driver through
+//! [split virtqueues](queue) in shared memory.
+
+pub mod blk;
+pub mod queue;
+pub mod transport;
+
+pub const VENDOR_ID: u16 = 0x1AF4;
+
+pub const STATUS_ACKNOWLEDGE: u8 = 1 << 0;
+pub const STATUS_DRIVER: u8 = 1 << 1;
+pub const STATUS_DRIVER_OK: u8 = 1 << 2;
+pub const STATUS_FEATURES_OK: u8 = 1 << 3;
+pub const STATUS_FAILED: u8 = 1 << 7;
+
+/// Offered by devices implementing virtio 1.0, and required by the modern transport.
+pub const FEATURE_VERSION_1: u64 = 1 << 32;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum VirtioError {
+    /// The transport's capabilities or BARs are missing.
+    MissingTransport,
+    /// The device refused the features the driver accepted.
+    FeaturesRefused,
+    /// The queue doesn't exist on the device.
+    MissingQueue,
+    /// The queue has no free descriptors left.
+    QueueFull,
+    /// There isn't enough contiguous memory for a queue or buffer.
+    OutOfMemory
+}
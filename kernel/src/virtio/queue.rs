@@ -0,0 +1,170 @@
+//! Split virtqueues, made of a descriptor table and two rings in memory shared with the device.
+//!
+//! The driver chains descriptors of buffers together and offers the head of each chain in the
+//! available ring, which the device hands back through the used ring once it's done with them.
+//! All three parts are laid out in one allocation as the legacy transport requires, with the used
+//! ring starting on its own page.
+
+use alloc::vec::Vec;
+use core::ptr;
+use core::sync::atomic::{self, Ordering};
+use x86_64::{PhysAddr, VirtAddr};
+use crate::mem::dma::DmaBuffer;
+use crate::virtio::VirtioError;
+
+const DESCRIPTOR_SIZE: usize = 16;
+const USED_ELEMENT_SIZE: usize = 8;
+
+/// Set in a descriptor's flags if it continues in the `next` one.
+const DESCRIPTOR_NEXT: u16 = 1 << 0;
+/// Set in a descriptor's flags if the device writes the buffer, rather than reading it.
+const DESCRIPTOR_WRITE: u16 = 1 << 1;
+
+/// A buffer in physical memory, as part of a chain.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Buffer {
+    pub address: PhysAddr,
+    pub length: u32,
+    /// Whether the device writes the buffer.
+    pub writable: bool
+}
+
+/// A queue of requests to a device.
+#[derive(Debug)]
+pub struct Queue {
+    index: u16,
+    size: u16,
+    memory: DmaBuffer,
+    /// Offset of the used ring in `memory`.
+    used: usize,
+    /// Descriptors not part of any chain.
+    free: Vec<u16>,
+    /// The index the next chain is offered at in the available ring.
+    next_available: u16,
+    /// The index of the next chain the device will hand back in the used ring.
+    next_used: u16
+}
+
+impl Queue {
+    /// Allocates a queue with `size` descriptors, which has to be a power of two.
+    pub fn new(index: u16, size: u16) -> Result<Self, VirtioError> {
+        let count = size as usize;
+        let used = (DESCRIPTOR_SIZE * count + 6 + 2 * count).next_multiple_of(4096);
+        let memory = DmaBuffer::new(used + 6 + USED_ELEMENT_SIZE * count).ok_or(VirtioError::OutOfMemory)?;
+        Ok(Self {
+            index,
+            size,
+            memory,
+            used,
+            free: (0..size).rev().collect(),
+            next_available: 0,
+            next_used: 0
+        })
+    }
+
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// Provides the address of the descriptor table.
+    pub fn descriptor_area(&self) -> PhysAddr {
+        self.memory.phys()
+    }
+
+    /// Provides the address of the available ring, which only the driver writes.
+    pub fn driver_area(&self) -> PhysAddr {
+        self.memory.phys() + (DESCRIPTOR_SIZE * self.size as usize) as u64
+    }
+
+    /// Provides the address of the used ring, which only the device writes.
+    pub fn device_area(&self) -> PhysAddr {
+        self.memory.phys() + self.used as u64
+    }
+
+    /// Offers a chain of buffers to the device, returning the descriptor at its head.
+    ///
+    /// The device only sees the chain once it's notified.
+    pub fn push(&mut self, buffers: &[Buffer]) -> Result<u16, VirtioError> {
+        if buffers.is_empty() || buffers.len() > self.free.len() {
+            return Err(VirtioError::QueueFull);
+        }
+
+        let descriptors: Vec<u16> = (0..buffers.len()).map(|_| self.free.pop().unwrap()).collect();
+        for (i, buffer) in buffers.iter().enumerate() {
+            let next = descriptors.get(i + 1).copied();
+            let mut flags = if buffer.writable { DESCRIPTOR_WRITE } else { 0 };
+            if next.is_some() {
+                flags |= DESCRIPTOR_NEXT;
+            }
+
+            let descriptor = self.descriptor(descriptors[i]);
+            unsafe {
+                ptr::write_volatile(descriptor.as_mut_ptr::<u64>(), buffer.address.as_u64());
+                ptr::write_volatile((descriptor + 8u64).as_mut_ptr::<u32>(), buffer.length);
+                ptr::write_volatile((descriptor + 12u64).as_mut_ptr::<u16>(), flags);
+                ptr::write_volatile((descriptor + 14u64).as_mut_ptr::<u16>(), next.unwrap_or(0));
+            }
+        }
+
+        // the descriptors have to be visible before the ring entry, and the entry before the index
+        let head = descriptors[0];
+        let available = self.available();
+        let slot = (self.next_available % self.size) as u64;
+        unsafe { ptr::write_volatile((available + 4u64 + slot * 2).as_mut_ptr::<u16>(), head) };
+        atomic::fence(Ordering::SeqCst);
+        self.next_available = self.next_available.wrapping_add(1);
+        unsafe { ptr::write_volatile((available + 2u64).as_mut_ptr::<u16>(), self.next_available) };
+        atomic::fence(Ordering::SeqCst);
+        Ok(head)
+    }
+
+    /// Whether the device handed back a chain that wasn't popped yet.
+    pub fn has_used(&self) -> bool {
+        let index = unsafe { ptr::read_volatile((self.used_ring() + 2u64).as_ptr::<u16>()) };
+        index != self.next_used
+    }
+
+    /// Takes the next chain the device handed back, returning its head and the number of bytes
+    /// the device wrote, and frees its descriptors.
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        if !self.has_used() {
+            return None;
+        }
+        atomic::fence(Ordering::SeqCst);
+
+        let slot = (self.next_used % self.size) as u64;
+        let element = self.used_ring() + 4u64 + slot * USED_ELEMENT_SIZE as u64;
+        let (head, length) = unsafe {
+            (ptr::read_volatile(element.as_ptr::<u32>()) as u16, ptr::read_volatile((element + 4u64).as_ptr::<u32>()))
+        };
+        self.next_used = self.next_used.wrapping_add(1);
+
+        let mut descriptor = head;
+        loop {
+            self.free.push(descriptor);
+            let address = self.descriptor(descriptor);
+            let flags = unsafe { ptr::read_volatile((address + 12u64).as_ptr::<u16>()) };
+            if flags & DESCRIPTOR_NEXT == 0 {
+                break;
+            }
+            descriptor = unsafe { ptr::read_volatile((address + 14u64).as_ptr::<u16>()) };
+        }
+        Some((head, length))
+    }
+
+    fn descriptor(&self, index: u16) -> VirtAddr {
+        self.memory.virt() + (index as usize * DESCRIPTOR_SIZE) as u64
+    }
+
+    fn available(&self) -> VirtAddr {
+        self.memory.virt() + (DESCRIPTOR_SIZE * self.size as usize) as u64
+    }
+
+    fn used_ring(&self) -> VirtAddr {
+        self.memory.virt() + self.used as u64
+    }
+}
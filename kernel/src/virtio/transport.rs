@@ -0,0 +1,315 @@
+//! The PCI transport, through which a device's status, features and queues are configured.
+//!
+//! The modern transport of virtio 1.0 is described by vendor-specific capabilities pointing into
+//! memory BARs. Transitional devices also have the legacy transport in their first I/O BAR,
+//! which is used for devices that only have that one.
+
+use core::ptr;
+use x86_64::instructions::port::Port;
+use x86_64::VirtAddr;
+use crate::pci::{self, Bar, Capability};
+use crate::virtio::queue::Queue;
+use crate::virtio::{VirtioError, FEATURE_VERSION_1, STATUS_ACKNOWLEDGE, STATUS_DRIVER, STATUS_DRIVER_OK, STATUS_FAILED, STATUS_FEATURES_OK};
+
+/// Written as an MSI-X entry to have no interrupt raised.
+pub const NO_VECTOR: u16 = 0xFFFF;
+
+/// Types of the structures the modern transport's capabilities point to.
+const COMMON_CONFIG: u8 = 1;
+const NOTIFY_CONFIG: u8 = 2;
+const ISR_CONFIG: u8 = 3;
+const DEVICE_CONFIG: u8 = 4;
+
+// registers of the modern common configuration
+const DEVICE_FEATURE_SELECT: u64 = 0x00;
+const DEVICE_FEATURE: u64 = 0x04;
+const DRIVER_FEATURE_SELECT: u64 = 0x08;
+const DRIVER_FEATURE: u64 = 0x0C;
+const MSIX_CONFIG: u64 = 0x10;
+const DEVICE_STATUS: u64 = 0x14;
+const CONFIG_GENERATION: u64 = 0x15;
+const QUEUE_SELECT: u64 = 0x16;
+const QUEUE_SIZE: u64 = 0x18;
+const QUEUE_MSIX_VECTOR: u64 = 0x1A;
+const QUEUE_ENABLE: u64 = 0x1C;
+const QUEUE_NOTIFY_OFF: u64 = 0x1E;
+const QUEUE_DESCRIPTOR: u64 = 0x20;
+const QUEUE_DRIVER: u64 = 0x28;
+const QUEUE_DEVICE: u64 = 0x30;
+
+// registers of the legacy I/O BAR
+const LEGACY_DEVICE_FEATURES: u16 = 0x00;
+const LEGACY_DRIVER_FEATURES: u16 = 0x04;
+const LEGACY_QUEUE_ADDRESS: u16 = 0x08;
+const LEGACY_QUEUE_SIZE: u16 = 0x0C;
+const LEGACY_QUEUE_SELECT: u16 = 0x0E;
+const LEGACY_QUEUE_NOTIFY: u16 = 0x10;
+const LEGACY_DEVICE_STATUS: u16 = 0x12;
+const LEGACY_ISR: u16 = 0x13;
+const LEGACY_CONFIG_VECTOR: u16 = 0x14;
+const LEGACY_QUEUE_VECTOR: u16 = 0x16;
+/// Where the device configuration starts, moved back by the vector registers while MSI-X is enabled.
+const LEGACY_CONFIG: u16 = 0x14;
+const LEGACY_CONFIG_MSIX: u16 = 0x18;
+
+/// Set in the MSI-X message control while MSI-X is enabled.
+const MSIX_ENABLE: u16 = 1 << 15;
+
+/// How a device is configured.
+#[derive(Debug)]
+pub enum Transport {
+    Modern {
+        device: &'static pci::Device,
+        common: VirtAddr,
+        notify: VirtAddr,
+        /// Bytes between the notification registers of consecutive queues.
+        notify_multiplier: u32,
+        isr: VirtAddr,
+        config: VirtAddr
+    },
+    Legacy {
+        device: &'static pci::Device,
+        port: u16
+    }
+}
+
+impl Transport {
+    /// Finds the transport of a function, preferring the modern one, and enables the function
+    /// to respond through its BARs and access the queues.
+    pub fn new(device: &'static pci::Device) -> Result<Self, VirtioError> {
+        device.enable(pci::COMMAND_IO | pci::COMMAND_MEMORY | pci::COMMAND_BUS_MASTER);
+        if let Some(transport) = Self::modern(device) {
+            return Ok(transport);
+        }
+        match device.bars[0] {
+            Some(Bar::Io { port, .. }) => Ok(Transport::Legacy { device, port }),
+            _ => Err(VirtioError::MissingTransport)
+        }
+    }
+
+    fn modern(device: &'static pci::Device) -> Option<Self> {
+        let structure = |kind: u8| {
+            device.capabilities.iter()
+                .filter(|capability| capability.id == Capability::VENDOR_SPECIFIC)
+                .find(|capability| device.read_u8(capability.offset + 3) == kind)
+                .and_then(|capability| {
+                    let bar = device.mapped_bar(device.read_u8(capability.offset + 4) as usize)?;
+                    Some((bar + device.read_u32(capability.offset + 8) as u64, capability.offset))
+                })
+        };
+
+        let (notify, notify_capability) = structure(NOTIFY_CONFIG)?;
+        Some(Transport::Modern {
+            device,
+            common: structure(COMMON_CONFIG)?.0,
+            notify,
+            notify_multiplier: device.read_u32(notify_capability + 16),
+            isr: structure(ISR_CONFIG)?.0,
+            config: structure(DEVICE_CONFIG)?.0
+        })
+    }
+
+    /// Provides the PCI function of the device.
+    pub fn device(&self) -> &'static pci::Device {
+        match self {
+            Transport::Modern { device, .. } | Transport::Legacy { device, .. } => device
+        }
+    }
+
+    pub fn is_modern(&self) -> bool {
+        matches!(self, Transport::Modern { .. })
+    }
+
+    pub fn status(&self) -> u8 {
+        match self {
+            Transport::Modern { common, .. } => unsafe { read(*common + DEVICE_STATUS) },
+            Transport::Legacy { port, .. } => unsafe { Port::new(port + LEGACY_DEVICE_STATUS).read() }
+        }
+    }
+
+    pub fn set_status(&self, status: u8) {
+        match self {
+            Transport::Modern { common, .. } => unsafe { write(*common + DEVICE_STATUS, status) },
+            Transport::Legacy { port, .. } => unsafe { Port::new(port + LEGACY_DEVICE_STATUS).write(status) }
+        }
+    }
+
+    /// Resets the device, which stops it from using its queues.
+    pub fn reset(&self) {
+        self.set_status(0);
+        // the modern transport only finishes resetting once the status reads back as zero
+        while self.is_modern() && self.status() != 0 {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Resets the device and agrees on the features both it and `supported` have, returning them.
+    ///
+    /// [`FEATURE_VERSION_1`](FEATURE_VERSION_1) is added to the modern transport's features,
+    /// as it's required there and unavailable otherwise.
+    pub fn negotiate(&self, supported: u64) -> Result<u64, VirtioError> {
+        self.reset();
+        self.set_status(STATUS_ACKNOWLEDGE);
+        self.set_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+        let features = match self {
+            Transport::Modern { common, .. } => {
+                let offered = unsafe {
+                    write(*common + DEVICE_FEATURE_SELECT, 0u32);
+                    let low = read::<u32>(*common + DEVICE_FEATURE);
+                    write(*common + DEVICE_FEATURE_SELECT, 1u32);
+                    (read::<u32>(*common + DEVICE_FEATURE) as u64) << 32 | low as u64
+                };
+                let features = offered & (supported | FEATURE_VERSION_1);
+                if features & FEATURE_VERSION_1 == 0 {
+                    self.set_status(STATUS_FAILED);
+                    return Err(VirtioError::FeaturesRefused);
+                }
+                unsafe {
+                    write(*common + DRIVER_FEATURE_SELECT, 0u32);
+                    write(*common + DRIVER_FEATURE, features as u32);
+                    write(*common + DRIVER_FEATURE_SELECT, 1u32);
+                    write(*common + DRIVER_FEATURE, (features >> 32) as u32);
+                }
+
+                // the device only refuses features by leaving FEATURES_OK unset
+                self.set_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK);
+                if self.status() & STATUS_FEATURES_OK == 0 {
+                    self.set_status(STATUS_FAILED);
+                    return Err(VirtioError::FeaturesRefused);
+                }
+                features
+            }
+            Transport::Legacy { port, .. } => {
+                let offered = unsafe { Port::<u32>::new(port + LEGACY_DEVICE_FEATURES).read() } as u64;
+                let features = offered & supported;
+                unsafe { Port::<u32>::new(port + LEGACY_DRIVER_FEATURES).write(features as u32) };
+                features
+            }
+        };
+        Ok(features)
+    }
+
+    /// Marks the device as ready, once its queues are set up.
+    pub fn finish(&self) {
+        self.set_status(self.status() | STATUS_DRIVER_OK);
+    }
+
+    /// Provides how many descriptors a queue can have at most, or 0 if it doesn't exist.
+    pub fn max_queue_size(&self, index: u16) -> u16 {
+        match self {
+            Transport::Modern { common, .. } => unsafe {
+                write(*common + QUEUE_SELECT, index);
+                read(*common + QUEUE_SIZE)
+            },
+            Transport::Legacy { port, .. } => unsafe {
+                Port::new(port + LEGACY_QUEUE_SELECT).write(index);
+                Port::new(port + LEGACY_QUEUE_SIZE).read()
+            }
+        }
+    }
+
+    /// Hands a queue to the device, with interrupts going to an MSI-X entry unless it's `None`.
+    ///
+    /// The legacy transport requires the queue to have exactly [`max_queue_size`](Self::max_queue_size) descriptors.
+    pub fn enable_queue(&self, queue: &Queue, msix_entry: Option<u16>) -> Result<(), VirtioError> {
+        let max = self.max_queue_size(queue.index());
+        if max == 0 || queue.size() > max || (!self.is_modern() && queue.size() != max) {
+            return Err(VirtioError::MissingQueue);
+        }
+        let entry = msix_entry.unwrap_or(NO_VECTOR);
+
+        match self {
+            Transport::Modern { common, .. } => unsafe {
+                // selected by max_queue_size
+                write(*common + QUEUE_SIZE, queue.size());
+                write(*common + MSIX_CONFIG, NO_VECTOR);
+                write(*common + QUEUE_MSIX_VECTOR, entry);
+                for (register, address) in [
+                    (QUEUE_DESCRIPTOR, queue.descriptor_area()),
+                    (QUEUE_DRIVER, queue.driver_area()),
+                    (QUEUE_DEVICE, queue.device_area())
+                ] {
+                    write(*common + register, address.as_u64() as u32);
+                    write(*common + register + 4u64, (address.as_u64() >> 32) as u32);
+                }
+                write(*common + QUEUE_ENABLE, 1u16);
+            },
+            Transport::Legacy { port, .. } => unsafe {
+                if self.msix_enabled() {
+                    Port::<u16>::new(port + LEGACY_CONFIG_VECTOR).write(NO_VECTOR);
+                    Port::<u16>::new(port + LEGACY_QUEUE_VECTOR).write(entry);
+                }
+                let frame = (queue.descriptor_area().as_u64() >> 12) as u32;
+                Port::<u32>::new(port + LEGACY_QUEUE_ADDRESS).write(frame);
+            }
+        }
+        Ok(())
+    }
+
+    /// Tells the device a queue has new buffers.
+    pub fn notify(&self, queue: &Queue) {
+        match self {
+            Transport::Modern { common, notify, notify_multiplier, .. } => unsafe {
+                write(*common + QUEUE_SELECT, queue.index());
+                let offset = read::<u16>(*common + QUEUE_NOTIFY_OFF) as u64 * *notify_multiplier as u64;
+                write(*notify + offset, queue.index());
+            },
+            Transport::Legacy { port, .. } => unsafe {
+                Port::<u16>::new(port + LEGACY_QUEUE_NOTIFY).write(queue.index());
+            }
+        }
+    }
+
+    /// Reads and clears the interrupt status, for when the legacy interrupt is used.
+    pub fn interrupt_status(&self) -> u8 {
+        match self {
+            Transport::Modern { isr, .. } => unsafe { read(*isr) },
+            Transport::Legacy { port, .. } => unsafe { Port::new(port + LEGACY_ISR).read() }
+        }
+    }
+
+    /// Reads a dword of the device-specific configuration.
+    pub fn config_u32(&self, offset: u16) -> u32 {
+        match self {
+            Transport::Modern { config, .. } => unsafe { read(*config + offset as u64) },
+            Transport::Legacy { port, .. } => {
+                let start = if self.msix_enabled() { LEGACY_CONFIG_MSIX } else { LEGACY_CONFIG };
+                unsafe { Port::new(port + start + offset).read() }
+            }
+        }
+    }
+
+    /// Reads a quad word of the device-specific configuration, as a consistent whole.
+    pub fn config_u64(&self, offset: u16) -> u64 {
+        loop {
+            let generation = self.config_generation();
+            let value = (self.config_u32(offset + 4) as u64) << 32 | self.config_u32(offset) as u64;
+            if self.config_generation() == generation {
+                return value;
+            }
+        }
+    }
+
+    /// Changes whenever the device changes its configuration, and never for the legacy transport.
+    fn config_generation(&self) -> u8 {
+        match self {
+            Transport::Modern { common, .. } => unsafe { read(*common + CONFIG_GENERATION) },
+            Transport::Legacy { .. } => 0
+        }
+    }
+
+    fn msix_enabled(&self) -> bool {
+        let device = self.device();
+        device.capability(Capability::MSI_X)
+            .is_some_and(|capability| device.read_u16(capability + 2) & MSIX_ENABLE != 0)
+    }
+}
+
+unsafe fn read<T: Copy>(address: VirtAddr) -> T {
+    unsafe { ptr::read_volatile(address.as_ptr::<T>()) }
+}
+
+unsafe fn write<T: Copy>(address: VirtAddr, value: T) {
+    unsafe { ptr::write_volatile(address.as_mut_ptr::<T>(), value) }
+}
@@ -0,0 +1,109 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::panic::PanicInfo;
+use bootloader_api::BootInfo;
+use x86_64::PhysAddr;
+use kernel::ata::{self, ahci, Identify};
+use kernel::ata::pio::TaskFile;
+use kernel::block;
+use kernel::{driver, testing};
+
+bootloader_api::entry_point!(main, config = &kernel::CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+    test_main();
+    kernel::block_indefinitely();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::testing::panic(info)
+}
+
+/// Sectors of the scratch disks the test runner attaches.
+const SCRATCH_SECTORS: u64 = 2048;
+
+#[test_case]
+fn identify_data_is_parsed() {
+    let mut words = [0; 256];
+    for (i, pair) in b"QEMU HARDDISK   ".as_chunks::<2>().0.iter().enumerate() {
+        words[27 + i] = u16::from_be_bytes(*pair);
+    }
+    words[60] = 0x5678;
+    words[61] = 0x1234;
+    assert_eq!(Identify::parse(&words).sectors, 0x1234_5678);
+    assert_eq!(Identify::parse(&words).model, "QEMU HARDDISK");
+    assert!(!Identify::parse(&words).lba48);
+
+    words[83] = 1 << 10;
+    words[100] = 0x0001;
+    words[102] = 0x0002;
+    let identify = Identify::parse(&words);
+    assert!(identify.lba48);
+    assert_eq!(identify.sectors, 0x0002_0000_0001);
+}
+
+#[test_case]
+fn controllers_are_bound() {
+    let drivers: Vec<&str> = driver::devices().into_iter().filter_map(|(_, driver)| driver).collect();
+    assert!(drivers.contains(&"ata-pio"));
+    assert!(drivers.contains(&"ahci"));
+}
+
+#[test_case]
+fn boot_disk_is_readable() {
+    let disk = block::find("hda").unwrap();
+    assert!(disk.blocks() > SCRATCH_SECTORS);
+
+    // the BIOS image starts with a master boot record
+    let mut sector = [0; 512];
+    disk.read(0, &mut sector).unwrap();
+    assert_eq!(&sector[510..], &[0x55, 0xAA]);
+}
+
+#[test_case]
+fn scratch_disks_are_identified() {
+    for name in ["hdb", "sda"] {
+        let disk = block::find(name).unwrap();
+        assert_eq!(disk.block_size(), 512);
+        assert_eq!(disk.blocks(), SCRATCH_SECTORS, "{} has the wrong capacity", name);
+    }
+}
+
+#[test_case]
+fn long_requests_are_split() {
+    // longer than a single command on either controller
+    for name in ["hdb", "sda"] {
+        testing::round_trip(&**block::disk(name).unwrap().device(), 50, 300);
+    }
+}
+
+#[test_case]
+fn task_file_addresses_lba28_and_lba48() {
+    // the top bits of a 28-bit LBA go in the device register, and 256 sectors are written as 0
+    let task_file = TaskFile::new(false, 0x0ABC_DEF1, 256);
+    assert_eq!(task_file.device, ata::DEVICE_LBA | 0x0A);
+    assert_eq!(task_file.high, None);
+    assert_eq!(task_file.low, [0, 0xF1, 0xDE, 0xBC]);
+
+    let task_file = TaskFile::new(true, 0x1234_5678_9ABC, 256);
+    assert_eq!(task_file.device, ata::DEVICE_LBA);
+    assert_eq!(task_file.high, Some([1, 0x56, 0x34, 0x12]));
+    assert_eq!(task_file.low, [0, 0xBC, 0x9A, 0x78]);
+}
+
+#[test_case]
+fn ahci_needs_64_bit_addressing_above_4_gib() {
+    let limit = PhysAddr::new(1 << 32);
+    assert!(ahci::addressable(false, limit - 4096u64, 4096));
+    assert!(!ahci::addressable(false, limit - 4096u64, 8192));
+    assert!(ahci::addressable(true, limit, 4096));
+}
@@ -0,0 +1,241 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use bootloader_api::BootInfo;
+use spin::Mutex;
+use kernel::block::{self, cache, BlockDevice, BlockError};
+
+bootloader_api::entry_point!(main, config = &kernel::CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+    test_main();
+    kernel::block_indefinitely();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::testing::panic(info)
+}
+
+const BLOCK_SIZE: usize = 512;
+/// Blocks of most of the disks, small as they're held in the heap.
+const BLOCKS: u64 = 256;
+
+/// A disk in memory, counting the requests that reach it.
+struct RamDisk {
+    name: String,
+    data: Mutex<Vec<u8>>,
+    blocks: u64,
+    read_only: bool,
+    /// Whether writes fail, as if the device was broken.
+    failing: AtomicBool,
+    reads: AtomicUsize,
+    writes: AtomicUsize
+}
+
+impl RamDisk {
+    fn register(name: &str, blocks: u64, read_only: bool) -> Arc<RamDisk> {
+        let disk = Arc::new(RamDisk {
+            name: String::from(name),
+            data: Mutex::new(vec![0; blocks as usize * BLOCK_SIZE]),
+            blocks,
+            read_only,
+            failing: AtomicBool::new(false),
+            reads: AtomicUsize::new(0),
+            writes: AtomicUsize::new(0)
+        });
+        block::register(disk.clone());
+        disk
+    }
+
+    fn block(&self, block: u64) -> Vec<u8> {
+        let start = block as usize * BLOCK_SIZE;
+        self.data.lock()[start..start + BLOCK_SIZE].to_vec()
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    fn blocks(&self) -> u64 {
+        self.blocks
+    }
+
+    fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn read(&self, block: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+        block::check_request(self, block, buffer.len())?;
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        let start = block as usize * BLOCK_SIZE;
+        buffer.copy_from_slice(&self.data.lock()[start..start + buffer.len()]);
+        Ok(())
+    }
+
+    fn write(&self, block: u64, buffer: &[u8]) -> Result<(), BlockError> {
+        block::check_request(self, block, buffer.len())?;
+        if self.failing.load(Ordering::Relaxed) {
+            return Err(BlockError::Io);
+        }
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        let start = block as usize * BLOCK_SIZE;
+        self.data.lock()[start..start + buffer.len()].copy_from_slice(buffer);
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        Ok(())
+    }
+}
+
+#[test_case]
+fn writes_stay_cached_until_synced() {
+    let ram = RamDisk::register("ram0", BLOCKS, false);
+    let disk = block::disk("ram0").unwrap();
+
+    let written = vec![0xAB; 4 * BLOCK_SIZE];
+    disk.write(10, &written).unwrap();
+    assert_eq!(ram.writes.load(Ordering::Relaxed), 0);
+    assert_eq!(disk.cached(), (4, 4));
+
+    // the adjacent blocks are written back with one request
+    disk.sync().unwrap();
+    assert_eq!(ram.writes.load(Ordering::Relaxed), 1);
+    assert_eq!(ram.block(13), &written[..BLOCK_SIZE]);
+    assert_eq!(disk.cached(), (4, 0));
+
+    let stats = disk.stats();
+    assert_eq!((stats.writes, stats.blocks_written, stats.merged), (1, 4, 3));
+}
+
+#[test_case]
+fn separate_runs_are_written_back_separately() {
+    let ram = RamDisk::register("ram1", BLOCKS, false);
+    let disk = block::disk("ram1").unwrap();
+
+    disk.write(0, &[1; 2 * BLOCK_SIZE]).unwrap();
+    disk.write(5, &[2; BLOCK_SIZE]).unwrap();
+    disk.write(2, &[3; BLOCK_SIZE]).unwrap();
+    disk.flush().unwrap();
+
+    assert_eq!(ram.writes.load(Ordering::Relaxed), 2);
+    assert_eq!(ram.block(2), [3; BLOCK_SIZE]);
+    assert_eq!(ram.block(5), [2; BLOCK_SIZE]);
+    assert_eq!(disk.stats().flushes, 1);
+    assert_eq!(disk.stats().merged, 2); // the block written on its own joined the first run
+}
+
+#[test_case]
+fn reads_are_served_from_the_cache() {
+    let ram = RamDisk::register("ram2", BLOCKS, false);
+    let disk = block::disk("ram2").unwrap();
+    ram.data.lock()[..8 * BLOCK_SIZE].fill(7);
+
+    // the missing blocks are read with one request
+    let mut buffer = vec![0; 8 * BLOCK_SIZE];
+    disk.read(0, &mut buffer).unwrap();
+    assert_eq!(buffer, [7; 8 * BLOCK_SIZE]);
+    assert_eq!(ram.reads.load(Ordering::Relaxed), 1);
+
+    disk.read(2, &mut buffer[..4 * BLOCK_SIZE]).unwrap();
+    assert_eq!(ram.reads.load(Ordering::Relaxed), 1);
+
+    // only the blocks that aren't cached are read, the ones after them in a single request
+    disk.read(6, &mut buffer).unwrap();
+    assert_eq!(ram.reads.load(Ordering::Relaxed), 2);
+
+    let stats = disk.stats();
+    assert_eq!((stats.hits, stats.misses, stats.merged), (6, 14, 12));
+    assert_eq!((stats.reads, stats.blocks_read), (2, 14));
+}
+
+#[test_case]
+fn least_recently_used_blocks_are_evicted() {
+    // more than the cache holds, so the first blocks are written back to make room
+    let count = (cache::CAPACITY / BLOCK_SIZE + 256) as u64;
+    let ram = RamDisk::register("ram3", count, false);
+    let disk = block::disk("ram3").unwrap();
+
+    let pattern = |block: u64| [block as u8; BLOCK_SIZE];
+    for block in 0..count {
+        disk.write(block, &pattern(block)).unwrap();
+    }
+    assert!(cache::size() <= cache::CAPACITY);
+    assert_eq!(ram.block(0), pattern(0));
+    assert!(ram.writes.load(Ordering::Relaxed) < 16, "evicted blocks should be written back together");
+
+    let mut buffer = [0; BLOCK_SIZE];
+    for block in 0..count {
+        disk.read(block, &mut buffer).unwrap();
+        assert_eq!(buffer, pattern(block));
+    }
+    assert!(disk.stats().misses > 0);
+}
+
+#[test_case]
+fn failed_write_backs_are_retried() {
+    let broken = RamDisk::register("ram6", BLOCKS, false);
+    let disk = block::disk("ram6").unwrap();
+    broken.failing.store(true, Ordering::Relaxed);
+    disk.write(0, &[6; 4 * BLOCK_SIZE]).unwrap();
+
+    // filling the cache through another disk has the broken one's blocks evicted, which fails
+    // without failing the other disk's writes
+    let count = (cache::CAPACITY / BLOCK_SIZE) as u64;
+    RamDisk::register("ram7", count, false);
+    let other = block::disk("ram7").unwrap();
+    for block in 0..count {
+        other.write(block, &[7; BLOCK_SIZE]).unwrap();
+    }
+    assert_eq!(disk.cached(), (4, 4));
+    assert_eq!(disk.sync(), Err(BlockError::Io));
+
+    broken.failing.store(false, Ordering::Relaxed);
+    disk.sync().unwrap();
+    assert_eq!(broken.block(3), [6; BLOCK_SIZE]);
+    assert!(disk.stats().errors > 0);
+    block::unregister("ram7");
+}
+
+#[test_case]
+fn unregistering_writes_back_dirty_blocks() {
+    let ram = RamDisk::register("ram4", BLOCKS, false);
+    block::disk("ram4").unwrap().write(100, &[9; BLOCK_SIZE]).unwrap();
+    assert_eq!(ram.block(100), [0; BLOCK_SIZE]);
+
+    assert!(block::unregister("ram4"));
+    assert_eq!(ram.block(100), [9; BLOCK_SIZE]);
+    assert!(block::find("ram4").is_none());
+}
+
+#[test_case]
+fn invalid_requests_are_refused() {
+    RamDisk::register("ram5", BLOCKS, true);
+    let disk = block::find("ram5").unwrap();
+    let mut buffer = [0; BLOCK_SIZE];
+    assert_eq!(disk.write(0, &buffer), Err(BlockError::ReadOnly));
+    assert_eq!(disk.read(BLOCKS, &mut buffer), Err(BlockError::OutOfRange));
+    assert_eq!(disk.read(BLOCKS - 1, &mut [0; 2 * BLOCK_SIZE]), Err(BlockError::OutOfRange));
+    assert_eq!(disk.read(u64::MAX, &mut buffer), Err(BlockError::OutOfRange));
+    assert_eq!(disk.read(0, &mut buffer[..100]), Err(BlockError::UnalignedBuffer));
+    assert!(disk.read(BLOCKS - 1, &mut buffer).is_ok());
+}
@@ -0,0 +1,127 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+use bootloader_api::BootInfo;
+use spin::{Mutex, Once};
+use kernel::render::{Color, GLOBAL_VIEW, Rect};
+use kernel::render::damage::Damage;
+use kernel::render::view::{BufImmediateView, BufView, FrameBufferView};
+
+static VIEW: Once<Mutex<BufImmediateView>> = Once::new();
+
+bootloader_api::entry_point!(main, config = &kernel::CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+
+    // buffer the frame buffer itself, rather than the global view
+    let view = GLOBAL_VIEW.lock().take().unwrap();
+    VIEW.call_once(|| Mutex::new(BufView::from(view)));
+
+    test_main();
+    kernel::block_indefinitely();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::testing::panic(info)
+}
+
+const COLOR: Color = Color { red: 0x12, green: 0x34, blue: 0x56 };
+
+/// Reads a pixel's bytes from the frame buffer behind the back buffer.
+fn front_pixel(view: &mut BufImmediateView, pos: (usize, usize)) -> [u8; 4] {
+    let info = view.info();
+    let i = ((pos.1 * info.stride) + pos.0) * info.bytes_per_pixel;
+    let mut bytes = [0; 4];
+    let front = unsafe { view.view_mut().buffer() };
+    bytes[..info.bytes_per_pixel].copy_from_slice(&front[i..i + info.bytes_per_pixel]);
+    bytes
+}
+
+fn expected(view: &BufImmediateView, color: Color) -> [u8; 4] {
+    let mut bytes = [0; 4];
+    view.pixel_converter()(0, &mut bytes, color);
+    bytes[view.info().bytes_per_pixel..].fill(0);
+    bytes
+}
+
+#[test_case]
+fn damage_merges_touching_rects() {
+    let mut damage = Damage::new();
+    damage.add(Rect::new(0, 0, 10, 10));
+    damage.add(Rect::new(10, 0, 10, 10));
+    assert_eq!(damage.rects(), [Rect::new(0, 0, 20, 10)]);
+
+    damage.add(Rect::new(50, 50, 1, 1));
+    assert_eq!(damage.rects().len(), 2);
+
+    damage.clear();
+    assert!(damage.is_empty());
+}
+
+#[test_case]
+fn damage_collapses_when_full() {
+    let mut damage = Damage::new();
+    for i in 0..32 {
+        damage.add(Rect::new(i * 10, i * 10, 1, 1));
+    }
+    assert!(damage.rects().len() <= 16);
+
+    let bounds = damage.rects().iter().fold(Rect::default(), |acc, r| acc.union(r));
+    assert!(bounds.contains(&Rect::new(0, 0, 1, 1)));
+    assert!(bounds.contains(&Rect::new(310, 310, 1, 1)));
+}
+
+#[test_case]
+fn swap_copies_only_damage() {
+    let mut view = VIEW.get().unwrap().lock();
+    view.swap_full();
+
+    // drawn straight to the frame buffer, so a full copy would erase it
+    let untouched = (10, 10);
+    let info = view.info();
+    let i = ((untouched.1 * info.stride) + untouched.0) * info.bytes_per_pixel;
+    unsafe { view.view_mut().set_pixel_unchecked(i, COLOR); }
+
+    view.set_pixel((20, 20), COLOR);
+    view.swap();
+
+    assert_eq!(front_pixel(&mut view, (20, 20)), expected(&view, COLOR));
+    assert_eq!(front_pixel(&mut view, untouched), expected(&view, COLOR));
+
+    view.swap_full();
+    assert_eq!(front_pixel(&mut view, untouched), expected(&view, Color::default()));
+}
+
+#[test_case]
+fn clear_damages_everything() {
+    let mut view = VIEW.get().unwrap().lock();
+    view.clear(COLOR);
+    view.swap();
+
+    let (width, height) = (view.width(), view.height());
+    assert_eq!(front_pixel(&mut view, (0, 0)), expected(&view, COLOR));
+    assert_eq!(front_pixel(&mut view, (width - 1, height - 1)), expected(&view, COLOR));
+}
+
+#[test_case]
+fn buffer_writes_reach_the_screen() {
+    let mut view = VIEW.get().unwrap().lock();
+    view.clear(Color::default());
+    view.swap_full();
+
+    let pos = (30, 30);
+    let info = view.info();
+    let i = ((pos.1 * info.stride) + pos.0) * info.bytes_per_pixel;
+    let bytes = expected(&view, COLOR);
+    let buffer = unsafe { view.buffer() };
+    buffer[i..i + info.bytes_per_pixel].copy_from_slice(&bytes[..info.bytes_per_pixel]);
+    view.swap();
+
+    assert_eq!(front_pixel(&mut view, pos), bytes);
+}
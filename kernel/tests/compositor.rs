@@ -0,0 +1,129 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+use bootloader_api::BootInfo;
+use kernel::render::{use_global_view, Color, Rect};
+use kernel::render::compositor::Compositor;
+use kernel::render::view::{FrameBufferView, ImmediateView};
+
+bootloader_api::entry_point!(main, config = &kernel::CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+    test_main();
+    kernel::block_indefinitely();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::testing::panic(info)
+}
+
+const BACKGROUND: Color = Color { red: 0x10, green: 0x10, blue: 0x10 };
+const RED: Color = Color { red: 0xFF, green: 0, blue: 0 };
+const BLUE: Color = Color { red: 0, green: 0, blue: 0xFF };
+
+/// Runs a test against a compositor for the global view.
+fn with_compositor<F: FnOnce(&mut Compositor, &mut ImmediateView)>(func: F) {
+    let mut func = Some(func);
+    use_global_view(|view| {
+        let mut compositor = Compositor::new(view, BACKGROUND);
+        func.take().unwrap()(&mut compositor, view);
+    });
+}
+
+#[test_case]
+fn background_fills_screen() {
+    with_compositor(|compositor, view| {
+        view.clear(RED);
+        compositor.compose_into(view);
+        assert_eq!(view.get_pixel((0, 0)), BACKGROUND);
+        assert_eq!(view.get_pixel((view.width() - 1, view.height() - 1)), BACKGROUND);
+    });
+}
+
+#[test_case]
+fn higher_z_is_on_top() {
+    with_compositor(|compositor, view| {
+        let mut below = compositor.create_surface(20, 20);
+        below.clear(RED);
+        let mut above = compositor.create_surface(10, 10);
+        above.clear(BLUE);
+
+        // added in the opposite order of their z-order
+        compositor.add(above, (15, 15), 1);
+        compositor.add(below, (10, 10), 0);
+        compositor.compose_into(view);
+
+        assert_eq!(view.get_pixel((10, 10)), RED);
+        assert_eq!(view.get_pixel((15, 15)), BLUE);
+        assert_eq!(view.get_pixel((24, 24)), BLUE);
+        assert_eq!(view.get_pixel((25, 25)), BACKGROUND);
+    });
+}
+
+#[test_case]
+fn moving_uncovers_old_position() {
+    with_compositor(|compositor, view| {
+        let mut surface = compositor.create_surface(10, 10);
+        surface.clear(RED);
+        let id = compositor.add(surface, (0, 0), 0);
+        compositor.compose_into(view);
+
+        compositor.set_position(id, (-5, 20));
+        compositor.compose_into(view);
+        assert_eq!(view.get_pixel((0, 0)), BACKGROUND);
+        assert_eq!(view.get_pixel((0, 20)), RED);
+        assert_eq!(view.get_pixel((5, 20)), BACKGROUND); // clipped at the left edge
+    });
+}
+
+#[test_case]
+fn hidden_surfaces_are_skipped() {
+    with_compositor(|compositor, view| {
+        let mut surface = compositor.create_surface(10, 10);
+        surface.clear(RED);
+        let id = compositor.add(surface, (0, 0), 0);
+        compositor.set_visible(id, false);
+        compositor.compose_into(view);
+        assert_eq!(view.get_pixel((0, 0)), BACKGROUND);
+
+        compositor.set_visible(id, true);
+        compositor.compose_into(view);
+        assert_eq!(view.get_pixel((0, 0)), RED);
+    });
+}
+
+#[test_case]
+fn drawing_on_surface_is_composed() {
+    with_compositor(|compositor, view| {
+        let id = compositor.add(compositor.create_surface(10, 10), (30, 30), 0);
+        compositor.compose_into(view);
+        assert_eq!(view.get_pixel((32, 32)), Color::default());
+
+        let surface = compositor.surface_mut(id).unwrap();
+        surface.fill_rect(Rect::new(2, 2, 2, 2), BLUE);
+        compositor.compose_into(view);
+        assert_eq!(view.get_pixel((32, 32)), BLUE);
+        assert_eq!(view.get_pixel((34, 34)), Color::default());
+    });
+}
+
+#[test_case]
+fn color_key_shows_below() {
+    with_compositor(|compositor, view| {
+        let mut cursor = compositor.create_surface(4, 4);
+        cursor.clear(BLUE);
+        cursor.set_pixel((0, 0), RED);
+        let id = compositor.add(cursor, (50, 50), 10);
+        compositor.set_color_key(id, Some(BLUE));
+        compositor.compose_into(view);
+
+        assert_eq!(view.get_pixel((50, 50)), RED);
+        assert_eq!(view.get_pixel((51, 51)), BACKGROUND);
+    });
+}
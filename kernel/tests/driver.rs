@@ -0,0 +1,135 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use bootloader_api::BootInfo;
+use kernel::driver::{self, Device, Driver, Match, ProbeError};
+use kernel::{acpi, isa};
+
+bootloader_api::entry_point!(main, config = &kernel::CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+    test_main();
+    kernel::block_indefinitely();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::testing::panic(info)
+}
+
+static PROBED: AtomicUsize = AtomicUsize::new(0);
+static REMOVED: AtomicUsize = AtomicUsize::new(0);
+
+static HOST_BRIDGE: Driver = Driver {
+    name: "test-host-bridge",
+    matches: &[Match::PciClass { class: 0x06, subclass: 0x00, prog_if: None }],
+    probe: |_| {
+        PROBED.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    },
+    remove: |_| {
+        REMOVED.fetch_add(1, Ordering::Relaxed);
+    }
+};
+
+static DECLINING: Driver = Driver {
+    name: "test-declining",
+    matches: &[Match::Isa(0x3F8), Match::Acpi("PNP0501")],
+    probe: |_| Err(ProbeError::Unsupported),
+    remove: |_| panic!("a declined device should never be removed")
+};
+
+static SERIAL: Driver = Driver {
+    name: "test-serial",
+    matches: &[Match::Isa(0x3F8), Match::Acpi("PNP0501")],
+    probe: |device: &Arc<Device>| {
+        assert_eq!(device.name(), "acpi/PNP0501:00");
+        Ok(())
+    },
+    remove: |_| {}
+};
+
+#[test_case]
+fn devices_are_found_on_every_bus() {
+    let devices = driver::devices();
+    assert!(devices.iter().any(|(device, _)| device.name() == "pci/0000:00:00.0"));
+    assert!(devices.iter().any(|(device, _)| device.name() == "acpi/PNP0501:00"));
+    for device in isa::devices() {
+        assert!(driver::find(&alloc::format!("isa/{:x}", device.port)).is_some());
+    }
+    assert!(driver::find("pci/0000:00:00.0").unwrap().pci().is_some());
+}
+
+#[test_case]
+fn acpi_devices_are_named() {
+    let com1 = acpi::devices().iter().find(|device| device.hid == "PNP0501").unwrap();
+    assert_eq!(com1.instance, 0);
+    assert!(!com1.name.is_empty() && com1.name.len() <= 4);
+    assert!(acpi::devices().iter().any(|device| device.hid == "PNP0303"));
+}
+
+#[test_case]
+fn isa_devices_declared_by_acpi_are_skipped() {
+    let ports: alloc::vec::Vec<u16> = isa::devices().iter().map(|device| device.port).collect();
+    let declared = |hid: &str| acpi::devices().iter().any(|device| device.hid == hid);
+    // COM1 and the keyboard are declared by QEMU's tables, and show up as ACPI devices instead
+    assert!(declared("PNP0501") && !ports.contains(&0x3F8));
+    assert!(declared("PNP0303") && !ports.contains(&0x60));
+    assert_eq!(ports.contains(&0x70), !declared("PNP0B00"));
+    assert!(ports.is_sorted());
+}
+
+#[test_case]
+fn drivers_probe_and_remove() {
+    PROBED.store(0, Ordering::Relaxed);
+    REMOVED.store(0, Ordering::Relaxed);
+
+    let bound = driver::register(&HOST_BRIDGE);
+    assert_eq!(bound, 1);
+    assert_eq!(PROBED.load(Ordering::Relaxed), 1);
+    assert_eq!(driver::driver_of("pci/0000:00:00.0"), Some("test-host-bridge"));
+    assert_eq!(driver::register(&HOST_BRIDGE), 0);
+
+    assert_eq!(driver::unregister(&HOST_BRIDGE), 1);
+    assert_eq!(REMOVED.load(Ordering::Relaxed), 1);
+    assert_eq!(driver::driver_of("pci/0000:00:00.0"), None);
+}
+
+#[test_case]
+fn declined_devices_stay_available() {
+    assert_eq!(driver::register(&DECLINING), 0);
+    assert_eq!(driver::driver_of("acpi/PNP0501:00"), None);
+    assert_eq!(driver::register(&SERIAL), 1);
+    assert_eq!(driver::driver_of("acpi/PNP0501:00"), Some("test-serial"));
+
+    driver::unregister(&SERIAL);
+    driver::unregister(&DECLINING);
+}
+
+#[test_case]
+fn added_devices_are_probed_by_registered_drivers() {
+    driver::register(&HOST_BRIDGE);
+    PROBED.store(0, Ordering::Relaxed);
+    REMOVED.store(0, Ordering::Relaxed);
+
+    assert!(driver::remove_device("pci/0000:00:00.0"));
+    assert_eq!(REMOVED.load(Ordering::Relaxed), 1);
+    assert!(driver::find("pci/0000:00:00.0").is_none());
+
+    let host = kernel::pci::devices().first().unwrap();
+    let device = driver::add_device(driver::Bus::Pci(host));
+    assert_eq!(device.name(), "pci/0000:00:00.0");
+    assert_eq!(PROBED.load(Ordering::Relaxed), 1);
+    assert!(Arc::ptr_eq(&device, &driver::add_device(driver::Bus::Pci(host))));
+
+    driver::unregister(&HOST_BRIDGE);
+}
@@ -0,0 +1,183 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::panic::PanicInfo;
+use bootloader_api::BootInfo;
+use spin::Lazy;
+use kernel::render::{use_global_view, Color, Rect};
+use kernel::render::console::Console;
+use kernel::render::font::{Font, BASIC, TOKYO};
+use kernel::render::font::psf::{ParseError, Psf};
+use kernel::render::view::FrameBufferView;
+use kernel::terminal;
+
+bootloader_api::entry_point!(main, config = &kernel::CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+    test_main();
+    kernel::block_indefinitely();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::testing::panic(info)
+}
+
+/// A PSF2 font with 10x2 glyphs: an empty `?` and an `é` with its two top corners set.
+const PSF2: [u8; 49] = [
+    0x72, 0xB5, 0x4A, 0x86, // magic
+    0, 0, 0, 0, // version
+    32, 0, 0, 0, // header size
+    1, 0, 0, 0, // has a Unicode table
+    2, 0, 0, 0, // glyphs
+    4, 0, 0, 0, // bytes per glyph
+    2, 0, 0, 0, // height
+    10, 0, 0, 0, // width
+    0, 0, 0, 0, // ?
+    0x80, 0x40, 0, 0, // é
+    b'?', 0xFF,
+    0xC3, 0xA9, 0xFE, b'e', 0xCC, 0x81, 0xFF // é, then the sequence e + combining acute accent
+];
+
+static FONT: Lazy<Psf<'static>> = Lazy::new(|| Psf::parse(&PSF2).unwrap());
+
+const WHITE: Color = Color { red: 0xFF, green: 0xFF, blue: 0xFF };
+const BLACK: Color = Color { red: 0, green: 0, blue: 0 };
+
+#[test_case]
+fn psf1_unicode_table() {
+    let mut font = Vec::from([0x36, 0x04, 0x02, 2]); // 256 glyphs with a table, 2 rows each
+    font.resize(4 + (256 * 2), 0);
+    font[6] = 0x80; // glyph 1
+    font[7] = 0x01;
+    for entry in [0xFFFF, 0x41, 0x2500, 0xFFFF] {
+        font.extend_from_slice(&u16::to_le_bytes(entry));
+    }
+    for _ in 2..256 {
+        font.extend_from_slice(&[0xFF, 0xFF]);
+    }
+
+    let font = Psf::parse(&font).unwrap();
+    assert_eq!(font.size(), (8, 2));
+    assert_eq!(font.len(), 256);
+    for c in ['A', '─'] {
+        let glyph = font.glyph(c).unwrap();
+        assert!(glyph.is_set(0, 0));
+        assert!(glyph.is_set(7, 1));
+        assert!(!glyph.is_set(1, 0));
+    }
+    assert!(font.glyph('B').is_none());
+}
+
+#[test_case]
+fn psf2_unicode_table() {
+    assert_eq!(FONT.size(), (10, 2));
+    let glyph = FONT.glyph('é').unwrap();
+    assert!(glyph.is_set(0, 0));
+    assert!(glyph.is_set(9, 0));
+    assert!(!glyph.is_set(8, 0));
+    assert!(!glyph.is_set(0, 1));
+
+    // only part of a sequence, so not mapped on its own
+    assert!(FONT.glyph('e').is_none());
+    assert_eq!(FONT.glyph_or_replacement('e'), FONT.glyph('?'));
+}
+
+#[test_case]
+fn psf_errors() {
+    assert_eq!(Psf::parse(b"\x00\x01").err(), Some(ParseError::UnknownFormat));
+    assert_eq!(Psf::parse(&PSF2[..36]).err(), Some(ParseError::Truncated));
+    assert_eq!(Psf::parse(&[0x36, 0x04, 0, 16]).err(), Some(ParseError::Truncated));
+    assert_eq!(Psf::parse(&[0x36, 0x04, 0, 0]).err(), Some(ParseError::Invalid));
+}
+
+#[test_case]
+fn basic_font_covers_box_drawing() {
+    assert_eq!(BASIC.size(), (8, 8));
+    for c in ['A', 'é', '─', '█'] {
+        assert!(BASIC.glyph(c).is_some(), "missing {}", c);
+    }
+}
+
+#[test_case]
+fn embedded_font_covers_box_drawing() {
+    assert_eq!(TOKYO.size(), (8, 16));
+    for c in ['A', 'é', '─', '╬', '█', '\u{FFFD}'] {
+        assert!(TOKYO.glyph(c).is_some(), "missing {}", c);
+    }
+}
+
+#[test_case]
+fn terminal_scale_follows_screen_size() {
+    assert_eq!(terminal::scale_for(&*TOKYO, Rect::new(0, 0, 1920, 1080)), 2);
+    assert_eq!(terminal::scale_for(&*TOKYO, Rect::new(0, 0, 3840, 2160)), 5);
+    assert_eq!(terminal::scale_for(&*TOKYO, Rect::new(0, 0, 640, 480)), 1);
+    assert_eq!(terminal::scale_for(&*TOKYO, Rect::new(0, 0, 320, 200)), 1);
+}
+
+#[test_case]
+fn console_draws_box_drawing() {
+    let mut console = Console::new(&*TOKYO, 1, Rect::new(0, 200, 16, 16));
+    console.set_colors(WHITE, BLACK);
+
+    use_global_view(|view| {
+        console.clear(view);
+        console.write_str(view, "┼");
+        for y in 200..216 {
+            assert_eq!(view.get_pixel((3, y)), WHITE);
+        }
+        for x in 0..8 {
+            assert_eq!(view.get_pixel((x, 207)), WHITE);
+        }
+        assert_eq!(view.get_pixel((0, 200)), BLACK);
+        assert_eq!(view.get_pixel((7, 215)), BLACK);
+        assert_eq!(view.get_pixel((8, 207)), BLACK); // the next cell is empty
+    });
+}
+
+#[test_case]
+fn console_wraps_lines() {
+    // 4 columns and 2 rows of 16x16 cells
+    let mut console = Console::new(&BASIC, 2, Rect::new(0, 0, 64, 32));
+    assert_eq!((console.columns(), console.rows()), (4, 2));
+
+    use_global_view(|view| {
+        console.write_str(view, "ab");
+        assert_eq!(console.cursor(), (2, 0));
+        console.write_str(view, "cde");
+        assert_eq!(console.cursor(), (1, 1));
+        console.write_str(view, "\x08\tx");
+        assert_eq!(console.cursor(), (1, 1)); // the tab filled the row
+    });
+}
+
+#[test_case]
+fn console_draws_scaled_and_scrolls() {
+    // 2 columns and 2 rows of 20x4 cells
+    let mut console = Console::new(&*FONT, 2, Rect::new(0, 100, 40, 8));
+    console.set_colors(WHITE, BLACK);
+
+    use_global_view(|view| {
+        console.clear(view);
+        console.write_str(view, "é");
+        assert_eq!(view.get_pixel((0, 100)), WHITE);
+        assert_eq!(view.get_pixel((1, 101)), WHITE);
+        assert_eq!(view.get_pixel((2, 100)), BLACK);
+        assert_eq!(view.get_pixel((19, 101)), WHITE);
+        assert_eq!(view.get_pixel((0, 102)), BLACK);
+
+        // the second line scrolls into the first
+        console.write_str(view, "\n\nxé");
+        assert_eq!(console.cursor(), (0, 1));
+        assert_eq!(view.get_pixel((20, 100)), WHITE);
+        assert_eq!(view.get_pixel((0, 100)), BLACK);
+        assert_eq!(view.get_pixel((20, 104)), BLACK);
+    });
+}
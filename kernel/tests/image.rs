@@ -0,0 +1,155 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::panic::PanicInfo;
+use bootloader_api::BootInfo;
+use kernel::render::{use_global_view, Color, Rgba};
+use kernel::render::image::{Bitmap, DecodeError};
+use kernel::render::view::FrameBufferView;
+
+bootloader_api::entry_point!(main, config = &kernel::CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+    test_main();
+    kernel::block_indefinitely();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::testing::panic(info)
+}
+
+const RED: Rgba = Rgba { red: 0xFF, green: 0, blue: 0, alpha: 0xFF };
+const GREEN: Rgba = Rgba { red: 0, green: 0xFF, blue: 0, alpha: 0xFF };
+const BLUE: Rgba = Rgba { red: 0, green: 0, blue: 0xFF, alpha: 0xFF };
+const WHITE: Rgba = Rgba { red: 0xFF, green: 0xFF, blue: 0xFF, alpha: 0xFF };
+const BLACK: Rgba = Rgba { red: 0, green: 0, blue: 0, alpha: 0xFF };
+
+/// Builds a BMP file with a BITMAPINFOHEADER.
+fn bmp(width: i32, height: i32, bits: u16, palette: &[u8], data: &[u8]) -> Vec<u8> {
+    let offset = 14 + 40 + palette.len() as u32;
+    let mut file = Vec::new();
+    file.extend_from_slice(b"BM");
+    file.extend_from_slice(&(offset + data.len() as u32).to_le_bytes());
+    file.extend_from_slice(&[0; 4]);
+    file.extend_from_slice(&offset.to_le_bytes());
+
+    file.extend_from_slice(&40u32.to_le_bytes());
+    file.extend_from_slice(&width.to_le_bytes());
+    file.extend_from_slice(&height.to_le_bytes());
+    file.extend_from_slice(&1u16.to_le_bytes());
+    file.extend_from_slice(&bits.to_le_bytes());
+    file.extend_from_slice(&[0; 24]); // no compression, sizes and color counts
+
+    file.extend_from_slice(palette);
+    file.extend_from_slice(data);
+    file
+}
+
+/// A 2x2 image with red and green on top, blue and white below.
+fn bmp_24() -> Vec<u8> {
+    bmp(2, 2, 24, &[], &[
+        0xFF, 0, 0, 0xFF, 0xFF, 0xFF, 0, 0, // bottom row comes first
+        0, 0, 0xFF, 0, 0xFF, 0, 0, 0
+    ])
+}
+
+#[test_case]
+fn bmp_24_bit() {
+    let bitmap = Bitmap::decode(&bmp_24()).unwrap();
+    assert_eq!((bitmap.width(), bitmap.height()), (2, 2));
+    assert_eq!(bitmap.pixels(), &[RED, GREEN, BLUE, WHITE]);
+}
+
+#[test_case]
+fn bmp_palette_top_down() {
+    let palette = [0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0];
+    let bitmap = Bitmap::decode(&bmp(3, -1, 1, &palette, &[0b1010_0000, 0, 0, 0])).unwrap();
+    assert_eq!(bitmap.pixels(), &[WHITE, BLACK, WHITE]);
+}
+
+#[test_case]
+fn ppm_binary_and_plain() {
+    let mut binary = Vec::from(&b"P6\n# two pixels\n2 1\n255\n"[..]);
+    binary.extend_from_slice(&[0xFF, 0, 0, 0, 0, 0xFF]);
+    assert_eq!(Bitmap::decode(&binary).unwrap().pixels(), &[RED, BLUE]);
+
+    let plain = Bitmap::decode(b"P3 1 1 15\n15 0 0").unwrap();
+    assert_eq!(plain.pixels(), &[RED]);
+}
+
+#[test_case]
+fn qoi_operations() {
+    let mut file = Vec::from(&b"qoif"[..]);
+    file.extend_from_slice(&3u32.to_be_bytes());
+    file.extend_from_slice(&2u32.to_be_bytes());
+    file.extend_from_slice(&[4, 0]);
+    file.extend_from_slice(&[
+        0xFE, 0xFF, 0, 0, // rgb
+        0xC0, // run of one
+        0x9F, 0xA9, // luma
+        0xFF, 0, 0, 0xFF, 0x80, // rgba
+        0x32, // index of red
+        0x5E // diff
+    ]);
+    file.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+
+    let bitmap = Bitmap::decode(&file).unwrap();
+    assert_eq!(bitmap.pixels(), &[
+        RED, RED, GREEN,
+        Rgba::new(0, 0, 0xFF, 0x80), RED, Rgba::new(0xFE, 1, 0, 0xFF)
+    ]);
+}
+
+#[test_case]
+fn decode_errors() {
+    assert_eq!(Bitmap::decode(b"GIF89a"), Err(DecodeError::UnknownFormat));
+    assert_eq!(Bitmap::decode(&bmp_24()[..60]), Err(DecodeError::Truncated));
+    assert_eq!(Bitmap::decode(b"P6 2 2 255\n\x00"), Err(DecodeError::Truncated));
+    assert_eq!(Bitmap::decode(b"P3 1 1 15\n16 0 0"), Err(DecodeError::Invalid));
+    assert_eq!(Bitmap::decode(b"P6 100000 100000 255\n"), Err(DecodeError::Unsupported));
+}
+
+#[test_case]
+fn draw_is_clipped() {
+    let bitmap = Bitmap::decode(&bmp_24()).unwrap();
+    use_global_view(|view| {
+        view.clear(BLACK.rgb());
+        bitmap.draw(view, (-1, -1));
+        assert_eq!(view.get_pixel((0, 0)), WHITE.rgb());
+        assert_eq!(view.get_pixel((1, 0)), BLACK.rgb());
+        assert_eq!(view.get_pixel((0, 1)), BLACK.rgb());
+    });
+}
+
+#[test_case]
+fn draw_scaled_stretches() {
+    let bitmap = Bitmap::decode(&bmp_24()).unwrap();
+    use_global_view(|view| {
+        view.clear(BLACK.rgb());
+        bitmap.draw_scaled(view, (10, 10), (4, 4));
+        assert_eq!(view.get_pixel((11, 11)), RED.rgb());
+        assert_eq!(view.get_pixel((12, 11)), GREEN.rgb());
+        assert_eq!(view.get_pixel((11, 13)), BLUE.rgb());
+        assert_eq!(view.get_pixel((13, 13)), WHITE.rgb());
+        assert_eq!(view.get_pixel((14, 14)), BLACK.rgb());
+    });
+}
+
+#[test_case]
+fn draw_blends_translucent_pixels() {
+    let bitmap = Bitmap::new(1, 1, alloc::vec![Rgba::new(0xFF, 0xFF, 0xFF, 0x80)]);
+    use_global_view(|view| {
+        view.clear(BLACK.rgb());
+        bitmap.draw(view, (0, 0));
+        let Color { red, .. } = view.get_pixel((0, 0));
+        assert!((0x7F..=0x81).contains(&red), "{}", red);
+    });
+}
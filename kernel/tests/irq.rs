@@ -0,0 +1,76 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::hint;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use bootloader_api::BootInfo;
+use kernel::pci::msi::{self, MsiError};
+use kernel::pci::{self, Capability};
+use kernel::{apic, irq};
+
+bootloader_api::entry_point!(main, config = &kernel::CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+    test_main();
+    kernel::block_indefinitely();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::testing::panic(info)
+}
+
+static RAISED: AtomicUsize = AtomicUsize::new(0);
+
+#[test_case]
+fn vectors_are_dispatched_to_their_handler() {
+    let vector = irq::allocate(|| {
+        RAISED.fetch_add(1, Ordering::Relaxed);
+    }).unwrap();
+    assert!((irq::FIRST_VECTOR..=irq::LAST_VECTOR).contains(&vector.number()));
+
+    let raised = RAISED.load(Ordering::Relaxed);
+    apic::raise(vector.number());
+    while RAISED.load(Ordering::Relaxed) == raised {
+        hint::spin_loop();
+    }
+
+    // once freed, raising the vector does nothing but signal the end of the interrupt
+    assert!(irq::free(vector));
+    assert!(!irq::free(vector));
+    apic::raise(vector.number());
+    assert_eq!(RAISED.load(Ordering::Relaxed), raised + 1);
+}
+
+#[test_case]
+fn lowest_free_vector_is_allocated() {
+    let allocated = irq::allocated();
+    let first = irq::allocate(|| {}).unwrap();
+    let second = irq::allocate(|| {}).unwrap();
+    assert!(first < second);
+    assert_eq!(irq::allocated(), allocated + 2);
+
+    irq::free(first);
+    let third = irq::allocate(|| {}).unwrap();
+    assert_eq!(third, first);
+
+    irq::free(second);
+    irq::free(third);
+    assert_eq!(irq::allocated(), allocated);
+}
+
+#[test_case]
+fn msi_requires_a_capability() {
+    let allocated = irq::allocated();
+    for device in pci::devices() {
+        if device.capability(Capability::MSI).is_none() && device.capability(Capability::MSI_X).is_none() {
+            assert_eq!(msi::allocate(device, || {}), Err(MsiError::Unsupported));
+        }
+    }
+    assert_eq!(irq::allocated(), allocated);
+}
@@ -0,0 +1,92 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::panic::PanicInfo;
+use bootloader_api::BootInfo;
+use kernel::block;
+use kernel::{driver, testing};
+use kernel::mem::dma::DmaPages;
+use kernel::nvme::Identify;
+
+bootloader_api::entry_point!(main, config = &kernel::CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+    test_main();
+    kernel::block_indefinitely();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::testing::panic(info)
+}
+
+/// Blocks of the scratch namespace the test runner attaches.
+const SCRATCH_BLOCKS: u64 = 2048;
+
+#[test_case]
+fn identify_data_is_parsed() {
+    let mut data = [b' '; 4096];
+    data[4..12].copy_from_slice(b"deadbeef");
+    data[24..38].copy_from_slice(b"QEMU NVMe Ctrl");
+    data[77] = 0;
+    data[516..520].copy_from_slice(&256u32.to_le_bytes());
+    data[525] = 1;
+    let identify = Identify::parse(&data);
+    assert_eq!(identify.serial, "deadbeef");
+    assert_eq!(identify.model, "QEMU NVMe Ctrl");
+    assert_eq!(identify.namespaces, 256);
+    assert_eq!(identify.max_transfer, None);
+    assert!(identify.volatile_cache);
+
+    data[77] = 5;
+    assert_eq!(Identify::parse(&data).max_transfer, Some(128 * 1024));
+
+    // limits past what fits in a usize are as good as none
+    data[77] = 60;
+    assert_eq!(Identify::parse(&data).max_transfer, None);
+}
+
+#[test_case]
+fn pages_copy_across_frames() {
+    let mut pages = DmaPages::new(3).unwrap();
+    assert_eq!(pages.size(), 3 * 4096);
+    assert_eq!(pages.frames().len(), 3);
+
+    let written: Vec<u8> = (0..6000).map(|i| (i % 251) as u8).collect();
+    pages.write(3000, &written);
+    let mut read = vec![0; written.len()];
+    pages.read(3000, &mut read);
+    assert!(read == written);
+
+    let mut start = [0xFF; 8];
+    pages.read(0, &mut start);
+    assert_eq!(start, [0; 8]);
+}
+
+#[test_case]
+fn controller_is_bound() {
+    let drivers: Vec<&str> = driver::devices().into_iter().filter_map(|(_, driver)| driver).collect();
+    assert!(drivers.contains(&"nvme"));
+}
+
+#[test_case]
+fn namespace_is_discovered() {
+    let disk = block::find("nvme0n1").unwrap();
+    assert_eq!(disk.block_size(), 512);
+    assert_eq!(disk.blocks(), SCRATCH_BLOCKS);
+    assert!(!disk.read_only());
+}
+
+#[test_case]
+fn long_requests_use_prp_lists() {
+    // longer than a single command, with a PRP list for most of them and two entries for the last
+    testing::round_trip(&**block::disk("nvme0n1").unwrap().device(), 100, 2 * 256 + 12);
+}
@@ -0,0 +1,132 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::panic::PanicInfo;
+use bootloader_api::BootInfo;
+use bootloader_api::info::{MemoryRegion, MemoryRegionKind, MemoryRegions};
+use spin::Once;
+use x86_64::structures::paging::{FrameAllocator, Size4KiB, Translate};
+use x86_64::VirtAddr;
+use kernel::mem;
+use kernel::mem::heap::{HEAP_SIZE, HEAP_START, KernelFrameAllocator};
+
+static PHYSICAL_OFFSET: Once<VirtAddr> = Once::new();
+
+bootloader_api::entry_point!(main, config = &kernel::CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    let physical_offset = boot_info.physical_memory_offset.into_option().unwrap();
+    PHYSICAL_OFFSET.call_once(|| VirtAddr::new(physical_offset));
+
+    kernel::init(boot_info);
+    test_main();
+    kernel::block_indefinitely();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::testing::panic(info)
+}
+
+/// Builds memory regions on the heap, since the allocator only needs them to live forever.
+fn regions(regions: &[MemoryRegion]) -> &'static MemoryRegions {
+    let regions: &'static mut [MemoryRegion] = Box::leak(regions.to_vec().into_boxed_slice());
+    Box::leak(Box::new(MemoryRegions::from(regions)))
+}
+
+#[test_case]
+fn page_range_single_page() {
+    let range = mem::page_range(0x1000, 1);
+    assert_eq!(range.count(), 1);
+
+    let range = mem::page_range(0x1000, 4096);
+    assert_eq!(range.count(), 1);
+}
+
+#[test_case]
+fn page_range_crosses_boundary() {
+    let range = mem::page_range(0x1FFF, 2);
+    assert_eq!(range.count(), 2);
+
+    let range = mem::page_range(0x1000, 4097);
+    assert_eq!(range.count(), 2);
+}
+
+#[test_case]
+fn heap_is_mapped() {
+    let mapper = unsafe { mem::mapper(*PHYSICAL_OFFSET.get().unwrap()) };
+    for page in mem::page_range(HEAP_START as u64, HEAP_SIZE as u64) {
+        assert!(mapper.translate_addr(page.start_address()).is_some());
+    }
+}
+
+#[test_case]
+fn frame_allocator_hands_out_distinct_frames() {
+    let start = 0x10_0000;
+    let regions = regions(&[
+        MemoryRegion { start: 0, end: start, kind: MemoryRegionKind::Bootloader },
+        MemoryRegion { start, end: start + HEAP_SIZE as u64, kind: MemoryRegionKind::Usable }
+    ]);
+    let mut allocator = unsafe { KernelFrameAllocator::new(regions).unwrap() };
+
+    let frames: Vec<_> = (0..16)
+        .map(|_| FrameAllocator::<Size4KiB>::allocate_frame(&mut allocator).unwrap())
+        .collect();
+
+    for (i, frame) in frames.iter().enumerate() {
+        assert_eq!(frame.start_address().as_u64(), start + i as u64 * 4096);
+    }
+}
+
+#[test_case]
+fn frame_allocator_requires_large_region() {
+    let regions = regions(&[
+        MemoryRegion { start: 0x10_0000, end: 0x10_1000, kind: MemoryRegionKind::Usable }
+    ]);
+    assert!(unsafe { KernelFrameAllocator::new(regions) }.is_none());
+}
+
+#[test_case]
+fn frame_allocator_skips_low_memory() {
+    let regions = regions(&[
+        MemoryRegion { start: 0, end: 0x10_0000 + HEAP_SIZE as u64, kind: MemoryRegionKind::Usable }
+    ]);
+    let mut allocator = unsafe { KernelFrameAllocator::new(regions).unwrap() };
+    let frame = FrameAllocator::<Size4KiB>::allocate_frame(&mut allocator).unwrap();
+    assert_eq!(frame.start_address().as_u64(), 0x10_0000);
+}
+
+#[test_case]
+fn frame_allocator_aligns_region() {
+    let start = 0x20_0800;
+    let regions = regions(&[
+        MemoryRegion { start, end: start + 2 * HEAP_SIZE as u64, kind: MemoryRegionKind::Usable }
+    ]);
+    let mut allocator = unsafe { KernelFrameAllocator::new(regions).unwrap() };
+    let frame = FrameAllocator::<Size4KiB>::allocate_frame(&mut allocator).unwrap();
+    assert_eq!(frame.start_address().as_u64(), 0x20_1000);
+}
+
+#[test_case]
+fn frame_allocator_stays_within_region() {
+    let start = 0x10_0000;
+    let end = start + HEAP_SIZE as u64;
+    let regions = regions(&[
+        MemoryRegion { start, end, kind: MemoryRegionKind::Usable }
+    ]);
+    let mut allocator = unsafe { KernelFrameAllocator::new(regions).unwrap() };
+
+    let mut count = 0;
+    while let Some(frame) = FrameAllocator::<Size4KiB>::allocate_frame(&mut allocator) {
+        assert!(frame.start_address().as_u64() + 4096 <= end);
+        count += 1;
+    }
+    assert_eq!(count, HEAP_SIZE / 4096);
+}
@@ -0,0 +1,85 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::format;
+use core::panic::PanicInfo;
+use core::ptr;
+use bootloader_api::BootInfo;
+use kernel::pci::{self, Address, Bar};
+
+bootloader_api::entry_point!(main, config = &kernel::CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+    test_main();
+    kernel::block_indefinitely();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::testing::panic(info)
+}
+
+#[test_case]
+fn addresses_display_like_lspci() {
+    assert_eq!(format!("{}", Address::new(0, 0x1F, 3, 2)), "0000:1f:03.2");
+}
+
+#[test_case]
+fn host_bridge_is_found() {
+    let devices = pci::devices();
+    assert!(!devices.is_empty());
+    assert!(devices.windows(2).all(|pair| pair[0].address < pair[1].address));
+
+    let host = &devices[0];
+    assert_eq!(host.address, Address::new(0, 0, 0, 0));
+    assert_eq!((host.class, host.subclass), (0x06, 0x00));
+    assert_eq!(host.class_name(), "Host bridge");
+    assert_eq!(host.read_u16(0x00), host.vendor_id);
+    assert_eq!(host.read_u8(0x0B), host.class);
+}
+
+#[test_case]
+fn display_controller_bar_is_mapped() {
+    let display = pci::devices().iter().find(|device| device.class == 0x03).unwrap();
+    let (size, mapped) = display.bars.iter()
+        .find_map(|bar| match bar {
+            Some(Bar::Memory { size, mapped, .. }) => Some((*size, *mapped)),
+            _ => None
+        })
+        .unwrap();
+    assert!(size.is_power_of_two() && size >= 4096);
+
+    // the frame buffer is the first BAR of the standard VGA device, and readable once mapped
+    let mapped = mapped.unwrap();
+    assert_eq!(display.mapped_bar(0), Some(mapped));
+    unsafe { ptr::read_volatile(mapped.as_ptr::<u32>()) };
+}
+
+#[test_case]
+fn capability_lists_are_consistent() {
+    for device in pci::devices() {
+        for capability in &device.capabilities {
+            assert!(capability.offset >= 0x40, "{} has a capability in the header", device.address);
+            assert_eq!(device.read_u8(capability.offset), capability.id);
+            assert!(device.capability(capability.id).is_some());
+        }
+        assert!(device.interrupt_pin <= 4);
+    }
+}
+
+#[test_case]
+fn command_bits_toggle() {
+    let display = pci::devices().iter().find(|device| device.class == 0x03).unwrap();
+    let command = display.read_u16(pci::COMMAND);
+    display.enable(pci::COMMAND_BUS_MASTER);
+    assert!(display.read_u16(pci::COMMAND) & pci::COMMAND_BUS_MASTER != 0);
+    display.disable(pci::COMMAND_BUS_MASTER);
+    assert!(display.read_u16(pci::COMMAND) & pci::COMMAND_BUS_MASTER == 0);
+    display.write_u32(pci::COMMAND, command as u32);
+}
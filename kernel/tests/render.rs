@@ -0,0 +1,320 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::panic::PanicInfo;
+use bootloader_api::BootInfo;
+use kernel::render::{pixel, use_global_view, Color, Rect, Rgba};
+use kernel::render::blend::BlendMode;
+use kernel::render::view::{FrameBufferView, ImmediateView};
+
+bootloader_api::entry_point!(main, config = &kernel::CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+    test_main();
+    kernel::block_indefinitely();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::testing::panic(info)
+}
+
+const COLOR: Color = Color { red: 0x12, green: 0x34, blue: 0x56 };
+
+#[test_case]
+fn rgb_24_layout() {
+    let mut buffer = [0u8; 6];
+    pixel::rgb_24(3, &mut buffer, COLOR);
+    assert_eq!(buffer, [0, 0, 0, 0x12, 0x34, 0x56]);
+}
+
+#[test_case]
+fn bgr_24_layout() {
+    let mut buffer = [0u8; 6];
+    pixel::bgr_24(3, &mut buffer, COLOR);
+    assert_eq!(buffer, [0, 0, 0, 0x56, 0x34, 0x12]);
+}
+
+#[test_case]
+fn rgb_32_layout() {
+    let mut buffer = [0xFFu8; 8];
+    pixel::rgb_32(4, &mut buffer, COLOR);
+    assert_eq!(buffer, [0xFF, 0xFF, 0xFF, 0xFF, 0x12, 0x34, 0x56, 0]);
+}
+
+#[test_case]
+fn bgr_32_layout() {
+    let mut buffer = [0xFFu8; 8];
+    pixel::bgr_32(4, &mut buffer, COLOR);
+    assert_eq!(buffer, [0xFF, 0xFF, 0xFF, 0xFF, 0x56, 0x34, 0x12, 0]);
+}
+
+#[test_case]
+fn u8_does_not_overflow() {
+    let mut buffer = [0u8; 1];
+    pixel::u8(0, &mut buffer, Color::new(0xFF, 0xFF, 0xFF));
+    assert_eq!(buffer, [0xFF]);
+
+    pixel::u8(0, &mut buffer, Color::new(0x30, 0x60, 0x90));
+    assert_eq!(buffer, [0x60]);
+}
+
+#[test_case]
+fn set_pixel_writes_converted_bytes() {
+    use_global_view(|view| {
+        let info = view.info();
+        let converter = view.pixel_converter();
+        let (x, y) = (info.width - 1, info.height - 1);
+        let i = ((y * info.stride) + x) * info.bytes_per_pixel;
+
+        let mut expected = [0u8; 4];
+        converter(0, &mut expected, COLOR);
+
+        view.set_pixel((x, y), COLOR);
+        let buffer = unsafe { view.buffer() };
+        assert_eq!(buffer[i..i + info.bytes_per_pixel], expected[..info.bytes_per_pixel]);
+    });
+}
+
+#[test_case]
+fn clear_fills_every_pixel() {
+    use_global_view(|view| {
+        let info = view.info();
+        let converter = view.pixel_converter();
+
+        let mut expected = [0u8; 4];
+        converter(0, &mut expected, COLOR);
+
+        view.clear(COLOR);
+        let buffer = unsafe { view.buffer() };
+        for y in 0..info.height {
+            for x in 0..info.width {
+                let i = ((y * info.stride) + x) * info.bytes_per_pixel;
+                assert_eq!(buffer[i..i + info.bytes_per_pixel], expected[..info.bytes_per_pixel]);
+            }
+        }
+    });
+}
+
+/// Checks whether a pixel on the view holds the given color.
+fn has_color(view: &mut ImmediateView, pos: (usize, usize), color: Color) -> bool {
+    let info = view.info();
+    let mut expected = [0u8; 4];
+    view.pixel_converter()(0, &mut expected, color);
+
+    let i = ((pos.1 * info.stride) + pos.0) * info.bytes_per_pixel;
+    let buffer = unsafe { view.buffer() };
+    buffer[i..i + info.bytes_per_pixel] == expected[..info.bytes_per_pixel]
+}
+
+const BLACK: Color = Color { red: 0, green: 0, blue: 0 };
+
+#[test_case]
+fn line_covers_endpoints() {
+    use_global_view(|view| {
+        view.clear(BLACK);
+        view.draw_line((10, 10), (40, 25), COLOR);
+        assert!(has_color(view, (10, 10), COLOR));
+        assert!(has_color(view, (40, 25), COLOR));
+        assert!(has_color(view, (11, 9), BLACK));
+    });
+}
+
+#[test_case]
+fn filled_rect_covers_area() {
+    use_global_view(|view| {
+        view.clear(BLACK);
+        view.draw_filled_rect((5, 5), (10, 4), COLOR);
+        assert!(has_color(view, (5, 5), COLOR));
+        assert!(has_color(view, (14, 8), COLOR));
+        assert!(has_color(view, (15, 8), BLACK));
+        assert!(has_color(view, (14, 9), BLACK));
+    });
+}
+
+#[test_case]
+fn rect_outline_leaves_inside() {
+    use_global_view(|view| {
+        view.clear(BLACK);
+        view.draw_rect((5, 5), (10, 10), COLOR);
+        assert!(has_color(view, (5, 5), COLOR));
+        assert!(has_color(view, (14, 14), COLOR));
+        assert!(has_color(view, (10, 10), BLACK));
+    });
+}
+
+#[test_case]
+fn filled_circle_is_round() {
+    use_global_view(|view| {
+        view.clear(BLACK);
+        view.draw_filled_circle((50, 50), 10, COLOR);
+        assert!(has_color(view, (50, 50), COLOR));
+        assert!(has_color(view, (60, 50), COLOR));
+        assert!(has_color(view, (50, 40), COLOR));
+        assert!(has_color(view, (59, 59), BLACK)); // outside the corner of the circle
+    });
+}
+
+#[test_case]
+fn filled_triangle_covers_inside() {
+    use_global_view(|view| {
+        view.clear(BLACK);
+        view.draw_filled_triangle([(10, 10), (30, 10), (10, 30)], COLOR);
+        assert!(has_color(view, (12, 12), COLOR));
+        assert!(has_color(view, (10, 30), COLOR));
+        assert!(has_color(view, (28, 28), BLACK));
+    });
+}
+
+#[test_case]
+fn shapes_are_clipped() {
+    use_global_view(|view| {
+        let (width, height) = (view.width() as isize, view.height() as isize);
+        view.clear(BLACK);
+
+        // none of these may panic on out of bounds writes
+        view.draw_line((-100, -100), (width + 100, height + 100), COLOR);
+        view.draw_filled_rect((-10, -10), (20, 20), COLOR);
+        view.draw_filled_circle((width, height), 30, COLOR);
+        view.draw_ellipse((0, height / 2), (50, 2000), COLOR);
+        view.draw_filled_polygon(&[(-50, 0), (width + 50, 10), (width / 2, height + 50)], COLOR);
+
+        assert!(has_color(view, (0, 0), COLOR));
+        assert!(has_color(view, (view.width() - 1, view.height() - 1), COLOR));
+    });
+}
+
+#[test_case]
+fn huge_shapes_are_clipped() {
+    use_global_view(|view| {
+        let (width, height) = (view.width() as isize, view.height() as isize);
+        view.clear(BLACK);
+
+        // neither overflowing nor stepping through every point off the screen
+        view.draw_rect((isize::MIN, isize::MIN), (usize::MAX, usize::MAX), COLOR);
+        view.draw_line((isize::MIN, height / 2), (isize::MAX, height / 2 + 1), COLOR);
+        view.draw_line_aa((width / 2, isize::MIN / 2), (width / 2 + 1, isize::MAX / 2), COLOR);
+        view.draw_circle((width / 2, isize::MIN / 4), usize::MAX / 8, COLOR);
+        view.draw_ellipse((0, 0), (usize::MAX, usize::MAX), COLOR);
+        view.draw_filled_polygon(&[(isize::MIN, isize::MIN), (isize::MAX, 0), (0, isize::MAX)], COLOR);
+
+        assert!(has_color(view, (0, height as usize / 2), COLOR));
+        assert!(has_color(view, (width as usize / 2, 0), COLOR));
+    });
+}
+
+#[test_case]
+fn pack_matches_converter() {
+    use_global_view(|view| {
+        let mut expected = [0u8; 4];
+        view.pixel_converter()(0, &mut expected, COLOR);
+        assert_eq!(view.pack(COLOR).as_bytes(), &expected[..view.info().bytes_per_pixel]);
+    });
+}
+
+#[test_case]
+fn fill_rect_is_clipped() {
+    use_global_view(|view| {
+        let (width, height) = (view.width(), view.height());
+        view.clear(BLACK);
+        view.fill_rect(Rect::new(width - 5, height - 5, 20, 20), COLOR);
+        assert!(has_color(view, (width - 5, height - 5), COLOR));
+        assert!(has_color(view, (width - 1, height - 1), COLOR));
+        assert!(has_color(view, (width - 6, height - 5), BLACK));
+    });
+}
+
+#[test_case]
+fn blit_copies_rows() {
+    use_global_view(|view| {
+        let black = view.pack(BLACK);
+        let color = view.pack(COLOR);
+        // a 3x2 checkerboard
+        let mut pixels = Vec::new();
+        for i in 0..6 {
+            pixels.extend_from_slice(if i % 2 == 0 { color.as_bytes() } else { black.as_bytes() });
+        }
+
+        view.clear(BLACK);
+        view.blit((-1, 10), (3, 2), &pixels);
+        assert!(has_color(view, (0, 10), BLACK));
+        assert!(has_color(view, (1, 10), COLOR));
+        assert!(has_color(view, (0, 11), COLOR));
+        assert!(has_color(view, (2, 10), BLACK)); // right of the clipped image
+    });
+}
+
+#[test_case]
+fn copy_rect_handles_overlap() {
+    use_global_view(|view| {
+        view.clear(BLACK);
+        view.fill_rect(Rect::new(10, 10, 4, 1), COLOR);
+        view.fill_rect(Rect::new(10, 12, 4, 1), COLOR);
+
+        // move down by one row, so the source and destination overlap
+        view.copy_rect(Rect::new(10, 10, 4, 3), (10, 11));
+        assert!(has_color(view, (10, 11), COLOR));
+        assert!(has_color(view, (13, 13), COLOR));
+        assert!(has_color(view, (10, 12), BLACK));
+
+        // and back up again
+        view.copy_rect(Rect::new(10, 11, 4, 3), (10, 10));
+        assert!(has_color(view, (10, 10), COLOR));
+        assert!(has_color(view, (10, 12), COLOR));
+        assert!(has_color(view, (10, 11), BLACK));
+    });
+}
+
+#[test_case]
+fn readers_reverse_converters() {
+    let mut buffer = [0u8; 4];
+    for (convert, read) in [
+        (pixel::rgb_24 as fn(usize, &mut [u8], Color), pixel::read_rgb as fn(usize, &[u8]) -> Color),
+        (pixel::rgb_32, pixel::read_rgb),
+        (pixel::bgr_24, pixel::read_bgr),
+        (pixel::bgr_32, pixel::read_bgr)
+    ] {
+        convert(0, &mut buffer, COLOR);
+        assert_eq!(read(0, &buffer), COLOR);
+    }
+
+    pixel::u8(0, &mut buffer, Color::new(0x30, 0x60, 0x90));
+    assert_eq!(pixel::read_u8(0, &buffer), Color::new(0x60, 0x60, 0x60));
+}
+
+#[test_case]
+fn blend_modes() {
+    let dest = Color::new(100, 200, 50);
+    let transparent = Rgba::new(255, 255, 255, 0);
+    for mode in [BlendMode::SourceOver, BlendMode::Additive, BlendMode::Multiply] {
+        assert_eq!(mode.blend(dest, transparent), dest);
+    }
+
+    assert_eq!(BlendMode::SourceOver.blend(dest, COLOR.into()), COLOR);
+    assert_eq!(BlendMode::SourceOver.blend(BLACK, Rgba::new(200, 100, 0, 128)), Color::new(100, 50, 0));
+    assert_eq!(BlendMode::Additive.blend(dest, Rgba::new(100, 100, 100, 255)), Color::new(200, 255, 150));
+    assert_eq!(BlendMode::Multiply.blend(dest, Rgba::new(255, 0, 128, 255)), Color::new(100, 0, 25));
+}
+
+#[test_case]
+fn blend_pixel_reads_back_view() {
+    use_global_view(|view| {
+        view.clear(BLACK);
+        view.set_pixel((3, 3), Color::new(0xFF, 0xFF, 0xFF));
+        view.blend_pixel((3, 3), Rgba::new(0, 0, 0, 0x80), BlendMode::SourceOver);
+        view.blend_rect(Rect::new(0, 0, 2, 2), Rgba::new(0xFF, 0xFF, 0xFF, 0x80), BlendMode::Additive);
+
+        let gray = view.get_pixel((3, 3));
+        assert!((0x7E..=0x80).contains(&gray.red), "{:?}", gray);
+        assert_eq!(view.get_pixel((1, 1)), view.get_pixel((0, 0)));
+        assert_ne!(view.get_pixel((1, 1)), BLACK);
+        assert_eq!(view.get_pixel((2, 2)), BLACK);
+    });
+}
@@ -0,0 +1,60 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+use bootloader_api::BootInfo;
+use kernel::render::{use_global_view, Color};
+use kernel::render::view::FrameBufferView;
+use kernel::testing::screenshot;
+
+bootloader_api::entry_point!(main, config = &kernel::CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+    test_main();
+    kernel::block_indefinitely();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::testing::panic(info)
+}
+
+#[test_case]
+fn clear() {
+    use_global_view(|view| view.clear((0x20, 0x40, 0x80)));
+    screenshot("clear", (0, 0), (32, 32));
+}
+
+#[test_case]
+fn channels() {
+    // separate red, green and blue blocks catch swapped channels
+    use_global_view(|view| {
+        view.clear((0, 0, 0));
+        let colors = [(0xFF, 0, 0), (0, 0xFF, 0), (0, 0, 0xFF)];
+        for (i, color) in colors.into_iter().enumerate() {
+            for y in 0..16 {
+                for x in 0..16 {
+                    view.set_pixel((i * 16 + x, y), color);
+                }
+            }
+        }
+    });
+    screenshot("channels", (0, 0), (48, 16));
+}
+
+#[test_case]
+fn gradient() {
+    use_global_view(|view| {
+        view.clear((0, 0, 0));
+        for y in 0..64 {
+            for x in 0..64 {
+                view.set_pixel((x, y), Color::new(x as u8 * 4, y as u8 * 4, 0x80));
+            }
+        }
+    });
+    screenshot("gradient", (0, 0), (64, 64));
+}
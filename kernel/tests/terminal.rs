@@ -0,0 +1,152 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::string::String;
+use core::panic::PanicInfo;
+use bootloader_api::BootInfo;
+use kernel::{log, print, shell, terminal};
+use kernel::render::{use_global_view, Color, Rect};
+use kernel::render::console::Console;
+use kernel::render::font::BASIC;
+use kernel::render::view::FrameBufferView;
+use kernel::shell::{Command, Shell};
+
+bootloader_api::entry_point!(main, config = &kernel::CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+    test_main();
+    kernel::block_indefinitely();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::testing::panic(info)
+}
+
+const WHITE: Color = Color { red: 0xFF, green: 0xFF, blue: 0xFF };
+const BLACK: Color = Color { red: 0, green: 0, blue: 0 };
+
+/// Provides the text of a console row, without trailing blanks.
+fn row(console: &Console, row: usize) -> String {
+    let text: String = (0..console.columns()).map(|column| console.get(column, row).c).collect();
+    String::from(text.trim_end())
+}
+
+#[test_case]
+fn log_reads_from_position() {
+    let start = log::position();
+    print!("first ");
+    print!("second");
+
+    let mut text = String::new();
+    let end = log::read(start, |part| text.push_str(part));
+    assert_eq!(text, "first second");
+    assert_eq!(end, log::position());
+
+    // nothing new since
+    text.clear();
+    assert_eq!(log::read(end, |part| text.push_str(part)), end);
+    assert!(text.is_empty());
+}
+
+#[test_case]
+fn log_skips_overwritten_output() {
+    let start = log::position();
+    for _ in 0..(log::LOG_SIZE / 8) + 1 {
+        print!("ABCDEFGH");
+    }
+    print!("é");
+
+    let mut read = 0;
+    let mut text = String::new();
+    log::read(start, |part| {
+        read += part.len();
+        text.push_str(part);
+    });
+    assert_eq!(read, log::LOG_SIZE);
+    assert!(text.starts_with("CDEFGH"));
+    assert!(text.ends_with("ABCDEFGHé"));
+}
+
+#[test_case]
+fn console_keeps_text_until_flushed() {
+    let mut console = Console::new(&BASIC, 1, Rect::new(0, 0, 32, 16));
+    console.set_colors(WHITE, BLACK);
+    console.push_str("ab\ncd");
+    assert_eq!(row(&console, 0), "ab");
+    assert_eq!(row(&console, 1), "cd");
+    assert_eq!(console.get(0, 0).foreground, WHITE);
+
+    use_global_view(|view| {
+        view.fill_rect(Rect::new(0, 0, 32, 16), WHITE);
+        console.flush(view);
+        assert_eq!(view.get_pixel((31, 0)), BLACK);
+
+        // nothing changed, so nothing is drawn
+        view.fill_rect(Rect::new(0, 0, 32, 16), WHITE);
+        console.flush(view);
+        assert_eq!(view.get_pixel((31, 0)), WHITE);
+
+        console.redraw(view);
+        assert_eq!(view.get_pixel((31, 0)), BLACK);
+    });
+}
+
+#[test_case]
+fn console_scrolls_the_grid() {
+    let mut console = Console::new(&BASIC, 1, Rect::new(0, 0, 16, 16));
+    console.push_str("1\n2\n3");
+    assert_eq!(row(&console, 0), "2");
+    assert_eq!(row(&console, 1), "3");
+    assert_eq!(console.cursor(), (1, 1));
+}
+
+#[test_case]
+fn shell_runs_commands() {
+    let mut console = Console::new(&BASIC, 1, Rect::new(0, 0, 256, 64));
+    assert!(shell::execute(&mut console, "echo  hello   world"));
+    assert_eq!(row(&console, 0), "hello world");
+
+    assert!(!shell::execute(&mut console, "missing"));
+    assert_eq!(row(&console, 1), "missing: command not found");
+
+    assert!(shell::execute(&mut console, "   "));
+    assert_eq!(console.cursor(), (0, 2));
+}
+
+#[test_case]
+fn shell_registers_commands() {
+    fn greet(console: &mut Console, args: &[&str]) {
+        console.push_str("hi ");
+        console.push_str(args.first().unwrap_or(&"there"));
+    }
+
+    shell::register(Command { name: "greet", help: "say hi", run: greet });
+    assert!(shell::commands().iter().any(|command| command.name == "greet"));
+
+    let mut console = Console::new(&BASIC, 1, Rect::new(0, 0, 256, 64));
+    let mut shell = Shell::new();
+    shell.start(&mut console);
+    for c in "greez\x08t you\n".chars() {
+        shell.input(&mut console, c);
+    }
+    assert_eq!(row(&console, 0), "> greet you");
+    assert_eq!(row(&console, 1), "hi you");
+    assert_eq!(row(&console, 2), ">");
+}
+
+#[test_case]
+fn terminals_switch() {
+    assert_eq!(terminal::active(), terminal::LOG_TERMINAL);
+    terminal::switch(2);
+    assert_eq!(terminal::active(), 2);
+    terminal::switch(terminal::TERMINAL_COUNT);
+    assert_eq!(terminal::active(), 2);
+    terminal::switch(terminal::LOG_TERMINAL);
+}
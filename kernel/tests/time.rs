@@ -0,0 +1,238 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::time::Duration;
+use bootloader_api::BootInfo;
+use kernel::{acpi, log, println, time};
+use kernel::time::{idle, pit, rtc, timer, DateTime};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+bootloader_api::entry_point!(main, config = &kernel::CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+    test_main();
+    kernel::block_indefinitely();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::testing::panic(info)
+}
+
+#[test_case]
+fn pit_divisor_is_clamped() {
+    assert_eq!(pit::divisor(1000), 1193);
+    assert_eq!(pit::divisor(100), 11932);
+    assert_eq!(pit::divisor(1), 0x10000);
+    assert_eq!(pit::divisor(0), 0x10000);
+    assert_eq!(pit::divisor(u32::MAX), 1);
+}
+
+#[test_case]
+fn timer_ticks() {
+    assert_eq!(time::frequency(), pit::BASE_FREQUENCY / 1193);
+    let ticks = time::ticks();
+    let uptime = time::uptime();
+    time::sleep_ms(20);
+    assert!(time::ticks() >= ticks + 19);
+    assert!(time::uptime() >= uptime + Duration::from_millis(20));
+}
+
+#[test_case]
+fn frequency_change_keeps_uptime() {
+    time::init(100);
+    assert_eq!(time::frequency(), 99);
+    let uptime = time::uptime();
+    time::sleep_ms(50);
+    let elapsed = time::uptime() - uptime;
+    assert!(elapsed >= Duration::from_millis(50) && elapsed < Duration::from_millis(100));
+    time::init(time::DEFAULT_FREQUENCY);
+}
+
+#[test_case]
+fn log_lines_are_timestamped() {
+    println!();
+    let start = log::position();
+    println!("one");
+    println!("two");
+
+    let mut text = String::new();
+    log::read(start, |part| text.push_str(part));
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 2);
+    for (line, expected) in lines.iter().zip(["one", "two"]) {
+        let (stamp, rest) = line.split_once("] ").unwrap();
+        assert!(stamp.starts_with('[') && stamp.contains('.'));
+        assert_eq!(rest, expected);
+    }
+}
+
+#[test_case]
+fn acpi_tables_found() {
+    let fadt = acpi::find(b"FACP").unwrap();
+    assert_eq!(&fadt.signature(), b"FACP");
+    assert!(fadt.bytes().len() > acpi::HEADER_SIZE);
+    assert!(acpi::find(b"NONE").is_none());
+}
+
+#[test_case]
+fn best_clock_source_is_used() {
+    let source = time::clock_source();
+    assert!(source.rating > time::PIT.rating, "only {} is available", source.name);
+
+    let now = time::now();
+    assert!(time::now() > now);
+    time::sleep_ms(10);
+    let elapsed = time::now() - now;
+    assert!(elapsed >= Duration::from_millis(9) && elapsed < Duration::from_millis(50));
+}
+
+#[test_case]
+fn clock_source_switch_is_continuous() {
+    let source = time::clock_source();
+    let before = time::now();
+    time::select(time::PIT);
+    time::offer(source);
+    assert_eq!(time::clock_source().name, source.name);
+    let after = time::now();
+    assert!(after >= before && after - before < Duration::from_millis(10));
+}
+
+#[test_case]
+fn dates_convert_to_unix_time() {
+    let epoch = DateTime { year: 1970, month: 1, day: 1, hour: 0, minute: 0, second: 0 };
+    assert_eq!(epoch.to_unix(), Duration::ZERO);
+    assert_eq!(DateTime::from_unix(Duration::ZERO), epoch);
+    assert_eq!(epoch.weekday(), 4);
+
+    let leap_day = DateTime { year: 2024, month: 2, day: 29, hour: 23, minute: 59, second: 58 };
+    assert_eq!(leap_day.to_unix(), Duration::from_secs(1_709_251_198));
+    assert_eq!(DateTime::from_unix(Duration::from_millis(1_709_251_198_900)), leap_day);
+    assert_eq!(leap_day.weekday(), 4);
+    assert_eq!(format!("{}", leap_day), "2024-02-29 23:59:58 UTC");
+}
+
+#[test_case]
+fn wall_clock_follows_rtc() {
+    let date = time::wall_clock();
+    assert!(date.year >= 2024, "{}", date);
+    let rtc = rtc::read();
+    let difference = rtc.to_unix().abs_diff(date.to_unix());
+    assert!(difference <= Duration::from_secs(2), "{} and {}", rtc, date);
+}
+
+#[test_case]
+fn rtc_periodic_interrupt() {
+    let source = rtc::enable_periodic(1000);
+    assert_eq!(source.frequency, 1024);
+    let ticks = rtc::ticks();
+    time::sleep_ms(20);
+    rtc::disable_periodic();
+    assert!(rtc::ticks() - ticks >= 10);
+
+    let ticks = rtc::ticks();
+    time::sleep_ms(10);
+    assert_eq!(rtc::ticks(), ticks);
+}
+
+#[test_case]
+fn timers_run_in_order() {
+    let order = Arc::new(Mutex::new(Vec::new()));
+    for (delay, name) in [(150, "far"), (5, "near"), (70, "middle")] {
+        let order = order.clone();
+        timer::add_timer(Duration::from_millis(delay), move || order.lock().push(name));
+    }
+
+    timer::run_expired();
+    assert!(order.lock().is_empty());
+    time::sleep_ms(80);
+    assert_eq!(timer::run_expired(), 2);
+    time::sleep_ms(80);
+    assert_eq!(timer::run_expired(), 1);
+    assert_eq!(*order.lock(), ["near", "middle", "far"]);
+}
+
+#[test_case]
+fn timers_cancel() {
+    let runs = Arc::new(AtomicUsize::new(0));
+    let counter = runs.clone();
+    let id = timer::add_timer(Duration::from_millis(5), move || {
+        counter.fetch_add(1, Ordering::Relaxed);
+    });
+    assert!(timer::cancel_timer(id));
+    assert!(!timer::cancel_timer(id));
+
+    time::sleep_ms(10);
+    timer::run_expired();
+    assert_eq!(runs.load(Ordering::Relaxed), 0);
+}
+
+#[test_case]
+fn periodic_timers_repeat_until_cancelled() {
+    static ID: Mutex<Option<timer::TimerId>> = Mutex::new(None);
+    let runs = Arc::new(AtomicUsize::new(0));
+
+    let counter = runs.clone();
+    let id = timer::add_periodic_timer(Duration::from_millis(10), move || {
+        // cancels itself on the third run
+        if counter.fetch_add(1, Ordering::Relaxed) == 2 {
+            timer::cancel_timer(ID.lock().unwrap());
+        }
+    });
+    *ID.lock() = Some(id);
+
+    for _ in 0..6 {
+        time::sleep_ms(10);
+        timer::run_expired();
+    }
+    assert_eq!(runs.load(Ordering::Relaxed), 3);
+    assert!(!timer::cancel_timer(id));
+}
+
+#[test_case]
+fn next_expiry_is_never_late() {
+    let delay = Duration::from_millis(100);
+    let id = timer::add_timer(delay, || {});
+    let due = time::ticks() + time::frequency() as u64 * delay.as_millis() as u64 / 1000 + 1;
+    assert!(timer::next_expiry().is_some_and(|next| next <= due));
+    timer::cancel_timer(id);
+}
+
+#[test_case]
+fn tickless_idle_catches_up() {
+    assert!(idle::is_tickless());
+    let stats = idle::stats();
+    let (ticks, start) = (time::ticks(), time::now());
+
+    let done = Arc::new(AtomicBool::new(false));
+    let flag = done.clone();
+    timer::add_timer(Duration::from_millis(30), move || flag.store(true, Ordering::Relaxed));
+    while !done.load(Ordering::Relaxed) {
+        interrupts::disable();
+        idle::halt();
+        timer::run_expired();
+    }
+
+    let elapsed = time::now() - start;
+    assert!(elapsed >= Duration::from_millis(29) && elapsed < Duration::from_millis(60), "{:?}", elapsed);
+    let counted = Duration::from_millis(time::ticks() - ticks);
+    assert!(counted.abs_diff(elapsed) < Duration::from_millis(3), "{:?} ticks in {:?}", counted, elapsed);
+
+    let after = idle::stats();
+    assert!(after.tickless_halts > stats.tickless_halts);
+    assert!(after.idle > stats.idle);
+    assert!(after.residency() <= 10_000);
+}
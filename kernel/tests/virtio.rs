@@ -0,0 +1,94 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use core::panic::PanicInfo;
+use bootloader_api::BootInfo;
+use kernel::block;
+use kernel::{driver, testing};
+use kernel::mem::dma::DmaBuffer;
+use kernel::virtio::queue::{Buffer, Queue};
+
+bootloader_api::entry_point!(main, config = &kernel::CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init(boot_info);
+    test_main();
+    kernel::block_indefinitely();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::testing::panic(info)
+}
+
+/// Sectors of the scratch disks the test runner attaches, one modern and one legacy-only.
+const SCRATCH_SECTORS: u64 = 2048;
+
+#[test_case]
+fn dma_buffers_are_contiguous_and_zeroed() {
+    let buffer = DmaBuffer::new(3 * 4096 + 1).unwrap();
+    assert_eq!(buffer.size(), 4 * 4096);
+    assert!(buffer.phys().is_aligned(4096u64));
+    assert!(buffer.as_slice().iter().all(|&byte| byte == 0));
+
+    // freed frames are handed out again
+    let phys = buffer.phys();
+    drop(buffer);
+    assert_eq!(DmaBuffer::new(4 * 4096).unwrap().phys(), phys);
+}
+
+#[test_case]
+fn queue_descriptors_are_recycled() {
+    let mut queue = Queue::new(0, 4).unwrap();
+    let buffer = Buffer { address: queue.descriptor_area(), length: 16, writable: false };
+    queue.push(&[buffer, buffer, buffer]).unwrap();
+    assert!(queue.push(&[buffer, buffer]).is_err());
+    assert!(!queue.has_used());
+    assert!(queue.pop_used().is_none());
+}
+
+#[test_case]
+fn disks_are_bound_and_named() {
+    let disks: alloc::vec::Vec<_> = driver::devices()
+        .into_iter()
+        .filter(|(_, driver)| *driver == Some("virtio-blk"))
+        .collect();
+    assert_eq!(disks.len(), 2);
+
+    for name in ["vda", "vdb"] {
+        let disk = block::find(name).unwrap();
+        assert_eq!(disk.block_size(), 512);
+        assert_eq!(disk.blocks(), SCRATCH_SECTORS);
+        assert!(!disk.read_only());
+    }
+}
+
+#[test_case]
+fn long_requests_are_split() {
+    // longer than a single request, on both the modern and the legacy transport
+    for name in ["vda", "vdb"] {
+        testing::round_trip(&**block::disk(name).unwrap().device(), 100, 320);
+    }
+}
+
+#[test_case]
+fn removed_disks_are_unregistered() {
+    let (device, _) = driver::devices()
+        .into_iter()
+        .find(|(_, driver)| *driver == Some("virtio-blk"))
+        .unwrap();
+    let name = device.name();
+
+    assert!(driver::remove_device(name));
+    assert_eq!(block::devices().iter().filter(|disk| disk.name().starts_with("vd")).count(), 1);
+
+    // added again, the disk takes the first free name
+    let bus = device.bus().clone();
+    driver::add_device(bus);
+    assert_eq!(block::devices().iter().filter(|disk| disk.name().starts_with("vd")).count(), 2);
+}
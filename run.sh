@@ -1,8 +1,10 @@
+# Arguments before `--` go to cargo, arguments after it go to the runner, e.g.:
+#   ./run.sh bios --release -- --memory 1G --gdb
 if [[ $1 == "bios" || $1 == "uefi" ]]
 then
-  cargo run --bin qemu-$1 $2
+  cargo run --bin qemu-$1 "${@:2}"
 else
   printf "Invalid or missing argument at 1st position, expected:\n  uefi = Launch QEMU with the UEFI image\n  bios = Launch QEMU with the BIOS image\n"
   exit -1
 fi
-exit 0
\ No newline at end of file
+exit 0
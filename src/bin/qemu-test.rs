@@ -0,0 +1,97 @@
+use std::env;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::process::Stdio;
+use std::thread;
+use std::time::Duration;
+use bootloader::DiskImageBuilder;
+use tokyo::{qemu, screenshot};
+use tokyo::qemu::{Firmware, Options};
+
+/// Exit status of QEMU after the kernel writes `QemuExitCode::Success` to `isa-debug-exit`.
+const SUCCESS: i32 = (0x10 << 1) | 1;
+
+const TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Size of the zeroed scratch disks attached to every test, 1 MiB.
+const SCRATCH_SIZE: u64 = 1024 * 1024;
+
+/// Boots a kernel test binary in QEMU, used as the cargo runner for `x86_64-unknown-none`.
+///
+/// Options from `qemu.conf` apply to tests as well, with a default timeout and no display,
+/// except for the interactive ones that would keep a test from running on its own.
+fn main() {
+    let kernel_path = PathBuf::from(env::args().nth(1).expect("expected a kernel binary as the 1st argument"));
+    let image_path = kernel_path.with_extension("img");
+    DiskImageBuilder::new(kernel_path).create_bios_image(&image_path).unwrap();
+
+    // arguments after the binary belong to the test harness, not to QEMU
+    let mut options = Options::load([]).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2);
+    });
+    options.timeout = options.timeout.or(Some(TIMEOUT));
+    options.stub_port = None; // tests run alongside other QEMU instances
+    options.gdb = false; // nobody attaches a debugger to continue the boot
+    options.nographic = false; // stdio carries serial alone, to serve screenshot requests
+    options.devices.push(String::from("isa-debug-exit,iobase=0xf4,iosize=0x04"));
+
+    // storage drivers are tested against scratch disks, with a second virtio disk only offering the legacy transport,
+    // an IDE disk next to the boot disk, a SATA disk on an AHCI controller and an NVMe namespace
+    let virtio = scratch_disk(&image_path, "vda");
+    options.disks.push(format!("if=virtio,format=raw,file={}", virtio.display()));
+    let legacy = scratch_disk(&image_path, "vdb");
+    options.disks.push(format!("if=none,id=legacy,format=raw,file={}", legacy.display()));
+    options.devices.push(String::from("virtio-blk-pci,drive=legacy,disable-modern=on"));
+    let ide = scratch_disk(&image_path, "hdb");
+    options.disks.push(format!("if=ide,index=1,format=raw,file={}", ide.display()));
+    let sata = scratch_disk(&image_path, "sda");
+    options.disks.push(format!("if=none,id=sata,format=raw,file={}", sata.display()));
+    options.devices.push(String::from("ahci,id=ahci"));
+    options.devices.push(String::from("ide-hd,drive=sata,bus=ahci.0"));
+    let nvme = scratch_disk(&image_path, "nvme0n1");
+    options.disks.push(format!("if=none,id=nvme,format=raw,file={}", nvme.display()));
+    options.devices.push(String::from("nvme,serial=deadbeef,drive=nvme"));
+    options.extra.extend(["-display", "none"].map(String::from));
+
+    // the monitor takes screenshots, while serial is piped through to serve their requests
+    let monitor_path = image_path.with_extension("monitor");
+    options.extra.push(String::from("-monitor"));
+    options.extra.push(format!("unix:{},server,nowait", monitor_path.display()));
+
+    let mut command = qemu::command(&image_path, &Firmware::Bios, &options);
+    command.stdin(Stdio::piped()).stdout(Stdio::piped());
+    let mut child = command.spawn().expect("failed to launch qemu-system-x86_64");
+
+    let serial_in = child.stdin.take().unwrap();
+    let serial_out = child.stdout.take().unwrap();
+    let scratch = image_path.clone();
+    let screenshots = thread::spawn(move || screenshot::serve(serial_out, serial_in, &monitor_path, &scratch));
+
+    let exit_status = qemu::wait(child, options.timeout);
+    let failures = screenshots.join().unwrap();
+    if !failures.is_empty() {
+        eprintln!("{} screenshot(s) did not match", failures.len());
+        process::exit(1);
+    }
+
+    match exit_status.map(|s| s.code()) {
+        Some(Some(SUCCESS)) => process::exit(0),
+        Some(code) => {
+            eprintln!("test failed with QEMU exit code {:?}", code);
+            process::exit(1);
+        }
+        None => {
+            eprintln!("test timed out after {:?}", options.timeout.unwrap());
+            process::exit(1);
+        }
+    }
+}
+
+/// Creates a zeroed disk image next to the test's boot image, replacing any left by a previous run.
+fn scratch_disk(image_path: &Path, name: &str) -> PathBuf {
+    let path = image_path.with_extension(name);
+    File::create(&path).and_then(|file| file.set_len(SCRATCH_SIZE)).unwrap();
+    path
+}
@@ -1,11 +1,18 @@
 use std::process;
-use std::process::Command;
+use std::path::Path;
+use tokyo::qemu;
+use tokyo::qemu::Firmware;
 
 fn main() {
-    let mut command = Command::new("qemu-system-x86_64");
-    command.arg("-drive").arg(format!("format=raw,file={}", env!("UEFI_IMAGE")));
-    command.arg("-bios").arg(ovmf_prebuilt::ovmf_pure_efi());
-    command.arg("-serial").arg("stdio");
-    let exit_status = command.status().unwrap();
-    process::exit(exit_status.code().unwrap_or(-1));
-}
\ No newline at end of file
+    let options = qemu::options_or_exit();
+
+    let firmware = Firmware::Uefi(ovmf_prebuilt::ovmf_pure_efi());
+    let mut command = qemu::command(Path::new(env!("UEFI_IMAGE")), &firmware, &options);
+    match qemu::run(&mut command, options.timeout) {
+        Some(exit_status) => process::exit(exit_status.code().unwrap_or(-1)),
+        None => {
+            eprintln!("qemu timed out");
+            process::exit(-1);
+        }
+    }
+}
@@ -0,0 +1,4 @@
+//! Host-side tooling shared by the QEMU runner binaries.
+
+pub mod qemu;
+pub mod screenshot;
@@ -0,0 +1,272 @@
+//! Launching QEMU with options from `qemu.conf` and the command line.
+//!
+//! Every runner reads the config file in the repository root first, then its own arguments,
+//! so flags on the command line override the file. The file holds the same flags, one per line:
+//!
+//! ```text
+//! # lines starting with a hash are ignored
+//! --memory 1G
+//! --cpus 2
+//! ```
+
+use std::{env, fs, process};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Path of the config file shared by every developer's runners.
+pub const CONFIG_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/qemu.conf");
+
+/// Default TCP port the kernel's GDB stub is exposed on, through the second serial port.
+pub const GDB_STUB_PORT: u16 = 1235;
+
+pub const USAGE: &str = "\
+Options:
+  --memory <size>     Guest memory size, e.g. 512M or 2G
+  --cpus <n>          Number of virtual CPUs
+  --nographic         Disable the graphical display, multiplexing serial and monitor on stdio
+  --disk <spec>       Attach a disk, either a raw image path or a full -drive specification
+  --nic <spec>        Attach a network interface, e.g. user,model=e1000
+  --usb <device>      Attach a USB device to an xHCI controller, e.g. usb-kbd
+  --device <spec>     Attach any other device
+  --gdb               Wait for QEMU's own GDB server on port 1234 before booting (-s -S)
+  --stub-port <port>  Expose the kernel's GDB stub on another TCP port than 1235
+  --no-stub           Don't expose the kernel's GDB stub, e.g. when running several instances
+  --timeout <secs>    Kill QEMU after the given number of seconds
+  --log <file>        Copy serial output into a file
+  --config <file>     Read options from a file other than qemu.conf
+  -- <args>...        Pass the remaining arguments to QEMU unchanged";
+
+/// Reads options for a runner binary, exiting with the usage on `--help` or invalid options.
+pub fn options_or_exit() -> Options {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        println!("{}", USAGE);
+        process::exit(0);
+    }
+
+    Options::load(args).unwrap_or_else(|e| {
+        eprintln!("{}\n\n{}", e, USAGE);
+        process::exit(2);
+    })
+}
+
+/// Options for a QEMU invocation.
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub memory: Option<String>,
+    pub cpus: Option<u32>,
+    pub nographic: bool,
+    pub disks: Vec<String>,
+    pub nics: Vec<String>,
+    pub usb: Vec<String>,
+    pub devices: Vec<String>,
+    pub gdb: bool,
+    pub stub_port: Option<u16>,
+    pub timeout: Option<Duration>,
+    pub log: Option<PathBuf>,
+    pub extra: Vec<String>
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            memory: None,
+            cpus: None,
+            nographic: false,
+            disks: Vec::new(),
+            nics: Vec::new(),
+            usb: Vec::new(),
+            devices: Vec::new(),
+            gdb: false,
+            stub_port: Some(GDB_STUB_PORT),
+            timeout: None,
+            log: None,
+            extra: Vec::new()
+        }
+    }
+}
+
+impl Options {
+    /// Reads options from the config file, if there is one, then from `args`.
+    pub fn load(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let args: Vec<String> = args.into_iter().collect();
+
+        // the config file can be swapped on the command line, so look for that first
+        let mut config = None;
+        let mut rest = args.iter();
+        while let Some(arg) = rest.next() {
+            match arg.split_once('=') {
+                _ if arg == "--" => break,
+                Some(("--config", path)) => config = Some(path.to_string()),
+                _ if arg == "--config" => config = Some(rest.next().cloned().ok_or("missing value for --config")?),
+                _ => {}
+            }
+        }
+
+        let mut options = Self::default();
+        match &config {
+            Some(path) => options.parse_file(Path::new(path))?,
+            None if Path::new(CONFIG_PATH).exists() => options.parse_file(Path::new(CONFIG_PATH))?,
+            None => {}
+        }
+        options.parse(args)?;
+        Ok(options)
+    }
+
+    /// Reads options from a file containing one flag per line.
+    pub fn parse_file(&mut self, path: &Path) -> Result<(), String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+
+        let args = contents.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .flat_map(|line| line.split_whitespace())
+            .map(String::from);
+        self.parse(args)
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Reads options from command line arguments, overriding any already set.
+    pub fn parse(&mut self, args: impl IntoIterator<Item = String>) -> Result<(), String> {
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            // allow both `--flag value` and `--flag=value`
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+                _ => (arg, None)
+            };
+            let mut value = || inline.clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("missing value for {}", flag));
+
+            match flag.as_str() {
+                "--memory" => self.memory = Some(value()?),
+                "--cpus" => {
+                    let value = value()?;
+                    self.cpus = Some(value.parse().map_err(|_| format!("invalid CPU count: {}", value))?);
+                }
+                "--nographic" => self.nographic = true,
+                "--disk" => self.disks.push(value()?),
+                "--nic" => self.nics.push(value()?),
+                "--usb" => self.usb.push(value()?),
+                "--device" => self.devices.push(value()?),
+                "--gdb" => self.gdb = true,
+                "--stub-port" => {
+                    let value = value()?;
+                    self.stub_port = Some(value.parse().map_err(|_| format!("invalid port: {}", value))?);
+                }
+                "--no-stub" => self.stub_port = None,
+                "--timeout" => {
+                    let value = value()?;
+                    let secs = value.parse().map_err(|_| format!("invalid timeout: {}", value))?;
+                    self.timeout = Some(Duration::from_secs(secs));
+                }
+                "--log" => self.log = Some(PathBuf::from(value()?)),
+                "--config" => { value()?; } // already read by `load`
+                "--" => self.extra.extend(args.by_ref()),
+                _ => return Err(format!("unknown option: {}", flag))
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Firmware to boot the disk image with.
+#[derive(Debug, Clone)]
+pub enum Firmware {
+    Bios,
+    Uefi(PathBuf)
+}
+
+/// Builds a QEMU command booting `image` with the given options.
+pub fn command(image: &Path, firmware: &Firmware, options: &Options) -> Command {
+    let mut command = Command::new("qemu-system-x86_64");
+    command.arg("-drive").arg(format!("format=raw,file={}", image.display()));
+    if let Firmware::Uefi(ovmf) = firmware {
+        command.arg("-bios").arg(ovmf);
+    }
+
+    if let Some(memory) = &options.memory {
+        command.arg("-m").arg(memory);
+    }
+    if let Some(cpus) = options.cpus {
+        command.arg("-smp").arg(cpus.to_string());
+    }
+
+    // first serial port on stdio, sharing it with the monitor when there is no display
+    let mut serial = String::from("stdio,id=com1");
+    if options.nographic {
+        serial.push_str(",mux=on");
+    }
+    if let Some(log) = &options.log {
+        serial.push_str(&format!(",logfile={}", log.display()));
+    }
+    command.arg("-chardev").arg(serial);
+    command.arg("-serial").arg("chardev:com1");
+    if options.nographic {
+        command.arg("-nographic");
+        command.arg("-mon").arg("chardev=com1");
+    }
+
+    // second serial port for the kernel's GDB stub
+    if let Some(port) = options.stub_port {
+        command.arg("-serial").arg(format!("tcp::{},server,nowait", port));
+    }
+
+    for disk in &options.disks {
+        // a bare path is a raw image, anything else is passed to -drive as is
+        match disk.contains('=') {
+            true => command.arg("-drive").arg(disk),
+            false => command.arg("-drive").arg(format!("format=raw,file={}", disk))
+        };
+    }
+    for nic in &options.nics {
+        command.arg("-nic").arg(nic);
+    }
+    if !options.usb.is_empty() {
+        command.arg("-device").arg("qemu-xhci,id=xhci");
+        for device in &options.usb {
+            command.arg("-device").arg(format!("{},bus=xhci.0", device));
+        }
+    }
+    for device in &options.devices {
+        command.arg("-device").arg(device);
+    }
+
+    if options.gdb {
+        command.arg("-s").arg("-S");
+    }
+    command.args(&options.extra);
+    command
+}
+
+/// Runs QEMU to completion, killing it if it exceeds the timeout.
+///
+/// Returns `None` if QEMU was killed.
+pub fn run(command: &mut Command, timeout: Option<Duration>) -> Option<ExitStatus> {
+    let child = command.spawn().expect("failed to launch qemu-system-x86_64");
+    wait(child, timeout)
+}
+
+/// Waits for a QEMU process, killing it if it exceeds the timeout.
+pub fn wait(mut child: Child, timeout: Option<Duration>) -> Option<ExitStatus> {
+    let Some(timeout) = timeout else {
+        return Some(child.wait().unwrap());
+    };
+
+    let start = Instant::now();
+    loop {
+        if let Some(exit_status) = child.try_wait().unwrap() {
+            return Some(exit_status);
+        }
+        if start.elapsed() > timeout {
+            child.kill().unwrap();
+            child.wait().unwrap();
+            return None;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
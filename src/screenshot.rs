@@ -0,0 +1,247 @@
+//! Capturing the framebuffer through the QEMU monitor and comparing it against reference images.
+//!
+//! Kernel tests request a capture by printing a marker line over serial (see
+//! `kernel::testing::screenshot`), then wait for the runner to acknowledge it. The captured
+//! region is compared against `kernel/tests/screenshots/<name>.png`.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::{env, fs, thread};
+use std::time::Duration;
+
+/// Directory holding the committed reference images.
+pub const REFERENCE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/kernel/tests/screenshots");
+
+/// Prefix of the serial line a kernel test prints to request a capture.
+pub const MARKER: &str = "[screenshot] ";
+
+/// Set to write captured regions as the new reference images instead of comparing them.
+pub const BLESS_VAR: &str = "TOKYO_BLESS_SCREENSHOTS";
+
+/// A capture request, parsed from `[screenshot] <name> <x> <y> <width> <height>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub name: String,
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize
+}
+
+impl Request {
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut parts = line.trim().strip_prefix(MARKER)?.split_whitespace();
+        let name = parts.next()?.to_string();
+        let mut next = || parts.next()?.parse().ok();
+        Some(Self { name, x: next()?, y: next()?, width: next()?, height: next()? })
+    }
+}
+
+/// An 8-bit RGB image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>
+}
+
+impl Image {
+    /// Reads a binary PPM (P6) file, which is what `screendump` produces.
+    pub fn read_ppm(path: &Path) -> Result<Self, String> {
+        let bytes = fs::read(path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+
+        // header: magic, width, height and max value separated by whitespace, then one more byte
+        let mut fields = Vec::new();
+        let mut i = 0;
+        while fields.len() < 4 {
+            while bytes.get(i).is_some_and(u8::is_ascii_whitespace) {
+                i += 1;
+            }
+            if bytes.get(i) == Some(&b'#') {
+                while bytes.get(i).is_some_and(|&b| b != b'\n') {
+                    i += 1;
+                }
+                continue;
+            }
+            let start = i;
+            while bytes.get(i).is_some_and(|b| !b.is_ascii_whitespace()) {
+                i += 1;
+            }
+            if start == i {
+                return Err(format!("truncated PPM header in {}", path.display()));
+            }
+            fields.push(String::from_utf8_lossy(&bytes[start..i]).into_owned());
+        }
+        i += 1;
+
+        let invalid = || format!("unsupported PPM file {}", path.display());
+        if fields[0] != "P6" || fields[3] != "255" {
+            return Err(invalid());
+        }
+        let width: usize = fields[1].parse().map_err(|_| invalid())?;
+        let height: usize = fields[2].parse().map_err(|_| invalid())?;
+
+        let data = bytes.get(i..i + width * height * 3).ok_or_else(invalid)?.to_vec();
+        Ok(Self { width, height, data })
+    }
+
+    pub fn read_png(path: &Path) -> Result<Self, String> {
+        let error = |e: &dyn std::fmt::Display| format!("failed to read {}: {}", path.display(), e);
+
+        let file = File::open(path).map_err(|e| error(&e))?;
+        let mut decoder = png::Decoder::new(file);
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let mut reader = decoder.read_info().map_err(|e| error(&e))?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).map_err(|e| error(&e))?;
+        let buffer = &buffer[..info.buffer_size()];
+
+        if info.bit_depth != png::BitDepth::Eight {
+            return Err(error(&"only 8-bit images are supported"));
+        }
+        let data = match info.color_type {
+            png::ColorType::Rgb => buffer.to_vec(),
+            png::ColorType::Rgba => buffer.chunks_exact(4).flat_map(|p| [p[0], p[1], p[2]]).collect(),
+            png::ColorType::Grayscale => buffer.iter().flat_map(|&p| [p, p, p]).collect(),
+            png::ColorType::GrayscaleAlpha => buffer.chunks_exact(2).flat_map(|p| [p[0], p[0], p[0]]).collect(),
+            png::ColorType::Indexed => return Err(error(&"indexed images are not supported"))
+        };
+        Ok(Self { width: info.width as usize, height: info.height as usize, data })
+    }
+
+    pub fn write_png(&self, path: &Path) -> Result<(), String> {
+        let error = |e: &dyn std::fmt::Display| format!("failed to write {}: {}", path.display(), e);
+
+        let file = File::create(path).map_err(|e| error(&e))?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(|e| error(&e))?;
+        writer.write_image_data(&self.data).map_err(|e| error(&e))
+    }
+
+    /// Copies a region of the image, failing if it doesn't fit.
+    pub fn crop(&self, x: usize, y: usize, width: usize, height: usize) -> Option<Self> {
+        if x + width > self.width || y + height > self.height {
+            return None;
+        }
+        let mut data = Vec::with_capacity(width * height * 3);
+        for row in y..y + height {
+            let start = (row * self.width + x) * 3;
+            data.extend_from_slice(&self.data[start..start + width * 3]);
+        }
+        Some(Self { width, height, data })
+    }
+}
+
+/// A connection to QEMU's human monitor over a Unix socket.
+pub struct Monitor {
+    stream: BufReader<UnixStream>
+}
+
+impl Monitor {
+    /// Connects to the monitor socket, retrying while QEMU starts up.
+    pub fn connect(path: &Path) -> Result<Self, String> {
+        let mut attempts = 0;
+        let stream = loop {
+            match UnixStream::connect(path) {
+                Ok(stream) => break stream,
+                Err(_) if attempts < 50 => {
+                    attempts += 1;
+                    thread::sleep(Duration::from_millis(100));
+                }
+                Err(e) => return Err(format!("failed to connect to {}: {}", path.display(), e))
+            }
+        };
+
+        let mut monitor = Self { stream: BufReader::new(stream) };
+        monitor.read_prompt()?;
+        Ok(monitor)
+    }
+
+    /// Runs a command, waiting for it to complete.
+    pub fn command(&mut self, command: &str) -> Result<(), String> {
+        writeln!(self.stream.get_mut(), "{}", command).map_err(|e| e.to_string())?;
+        self.read_prompt()
+    }
+
+    /// Reads until the `(qemu) ` prompt, which follows the output of every command.
+    fn read_prompt(&mut self) -> Result<(), String> {
+        const PROMPT: &[u8] = b"(qemu) ";
+        let mut received = Vec::new();
+        while !received.ends_with(PROMPT) {
+            let mut byte = [0];
+            self.stream.read_exact(&mut byte).map_err(|e| format!("monitor closed: {}", e))?;
+            received.push(byte[0]);
+        }
+        Ok(())
+    }
+
+    /// Captures the whole screen.
+    pub fn screendump(&mut self, path: &Path) -> Result<Image, String> {
+        self.command(&format!("screendump {}", path.display()))?;
+        Image::read_ppm(path)
+    }
+}
+
+/// Handles a capture request, comparing the region against its reference image.
+///
+/// Mismatching captures are written next to `scratch` for inspection.
+pub fn check(monitor: &mut Monitor, request: &Request, scratch: &Path) -> Result<(), String> {
+    let screen = monitor.screendump(&scratch.with_extension(format!("{}.ppm", request.name)))?;
+    let actual = screen.crop(request.x, request.y, request.width, request.height)
+        .ok_or_else(|| format!("screenshot '{}' is outside of the {}x{} screen", request.name, screen.width, screen.height))?;
+
+    let reference_path = PathBuf::from(REFERENCE_DIR).join(format!("{}.png", request.name));
+    if env::var_os(BLESS_VAR).is_some() {
+        fs::create_dir_all(REFERENCE_DIR).map_err(|e| e.to_string())?;
+        return actual.write_png(&reference_path);
+    }
+
+    let reference = Image::read_png(&reference_path)?;
+    if reference == actual {
+        return Ok(());
+    }
+
+    let actual_path = scratch.with_extension(format!("{}.png", request.name));
+    actual.write_png(&actual_path)?;
+    Err(format!(
+        "screenshot '{}' differs from {}, captured region written to {}",
+        request.name, reference_path.display(), actual_path.display()
+    ))
+}
+
+/// Forwards serial output from `input` to stdout, serving capture requests along the way.
+///
+/// Each request is acknowledged on `ack` whether or not it matched, so the kernel can carry on.
+/// Returns the failed comparisons.
+pub fn serve(input: impl Read, mut ack: impl Write, monitor_path: &Path, scratch: &Path) -> Vec<String> {
+    let mut failures = Vec::new();
+    let mut monitor = None;
+
+    for line in BufReader::new(input).lines() {
+        let Ok(line) = line else { break };
+        println!("{}", line);
+
+        let Some(request) = Request::parse(&line) else { continue };
+        if monitor.is_none() {
+            match Monitor::connect(monitor_path) {
+                Ok(connected) => monitor = Some(connected),
+                Err(e) => failures.push(e)
+            }
+        }
+        if let Some(monitor) = monitor.as_mut() {
+            if let Err(e) = check(monitor, &request, scratch) {
+                println!("{}", e);
+                failures.push(e);
+            }
+        }
+
+        if ack.write_all(b"\n").and_then(|_| ack.flush()).is_err() {
+            break;
+        }
+    }
+    failures
+}
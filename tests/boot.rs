@@ -0,0 +1,48 @@
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use tokyo::qemu;
+use tokyo::qemu::{Firmware, Options};
+
+const TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Boots the kernel image and waits for it to report reaching its idle loop over serial.
+fn boots_to_idle(image: &Path, firmware: Firmware) {
+    let options = Options {
+        stub_port: None, // both firmwares boot at the same time
+        extra: ["-display", "none"].map(String::from).to_vec(),
+        ..Options::default()
+    };
+    let mut command = qemu::command(image, &firmware, &options);
+    command.stdin(Stdio::null()).stdout(Stdio::piped());
+    let mut child = command.spawn().expect("failed to launch qemu-system-x86_64");
+
+    let stdout = child.stdout.take().unwrap();
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            println!("{}", line);
+            if line.trim() == "[tokyo] idle" {
+                let _ = sender.send(());
+            }
+        }
+    });
+
+    let result = receiver.recv_timeout(TIMEOUT);
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert!(result.is_ok(), "kernel did not reach its idle loop within {:?}", TIMEOUT);
+}
+
+#[test]
+fn bios() {
+    boots_to_idle(Path::new(env!("BIOS_IMAGE")), Firmware::Bios);
+}
+
+#[test]
+fn uefi() {
+    boots_to_idle(Path::new(env!("UEFI_IMAGE")), Firmware::Uefi(ovmf_prebuilt::ovmf_pure_efi()));
+}
@@ -0,0 +1,16 @@
+use std::process::Command;
+
+/// Runs the kernel's test binaries, booting each in QEMU through the `qemu-test` runner.
+///
+/// A separate target directory is used so the nested build doesn't wait on this one.
+#[test]
+fn kernel() {
+    let status = Command::new(env!("CARGO"))
+        .args(["test", "--package", "kernel", "--target", "x86_64-unknown-none"])
+        .arg("--target-dir").arg(concat!(env!("CARGO_TARGET_TMPDIR"), "/kernel"))
+        .env("CARGO_TARGET_X86_64_UNKNOWN_NONE_RUNNER", env!("CARGO_BIN_EXE_qemu-test"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
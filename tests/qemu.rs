@@ -0,0 +1,79 @@
+use std::path::PathBuf;
+use std::time::Duration;
+use tokyo::qemu::{Options, GDB_STUB_PORT};
+
+fn parse(args: &[&str]) -> Result<Options, String> {
+    let mut options = Options::default();
+    options.parse(args.iter().map(|arg| arg.to_string()))?;
+    Ok(options)
+}
+
+#[test]
+fn values_follow_or_are_joined_to_flags() {
+    let options = parse(&["--memory", "1G", "--cpus=4", "--timeout", "30", "--log=serial.log"]).unwrap();
+    assert_eq!(options.memory.as_deref(), Some("1G"));
+    assert_eq!(options.cpus, Some(4));
+    assert_eq!(options.timeout, Some(Duration::from_secs(30)));
+    assert_eq!(options.log, Some(PathBuf::from("serial.log")));
+}
+
+#[test]
+fn later_options_override_earlier_ones() {
+    let mut options = parse(&["--memory", "512M", "--stub-port", "4000"]).unwrap();
+    assert_eq!(options.stub_port, Some(4000));
+
+    options.parse(["--memory", "2G", "--no-stub", "--gdb", "--nographic"].map(String::from)).unwrap();
+    assert_eq!(options.memory.as_deref(), Some("2G"));
+    assert_eq!(options.stub_port, None);
+    assert!(options.gdb && options.nographic);
+}
+
+#[test]
+fn repeated_devices_accumulate() {
+    let options = parse(&["--disk", "a.img", "--disk", "if=virtio,file=b.img", "--usb", "usb-kbd", "--nic=user"]).unwrap();
+    assert_eq!(options.disks, ["a.img", "if=virtio,file=b.img"]);
+    assert_eq!(options.usb, ["usb-kbd"]);
+    assert_eq!(options.nics, ["user"]);
+}
+
+#[test]
+fn arguments_after_a_separator_are_passed_through() {
+    let options = parse(&["--cpus", "2", "--", "--memory", "-no-reboot"]).unwrap();
+    assert_eq!(options.memory, None);
+    assert_eq!(options.extra, ["--memory", "-no-reboot"]);
+}
+
+#[test]
+fn defaults_are_kept_without_options() {
+    let options = parse(&[]).unwrap();
+    assert_eq!(options.stub_port, Some(GDB_STUB_PORT));
+    assert!(!options.gdb && !options.nographic);
+    assert!(options.disks.is_empty() && options.extra.is_empty());
+}
+
+#[test]
+fn invalid_options_are_refused() {
+    assert_eq!(parse(&["--floppy"]).unwrap_err(), "unknown option: --floppy");
+    assert_eq!(parse(&["--memory"]).unwrap_err(), "missing value for --memory");
+    assert_eq!(parse(&["--cpus", "many"]).unwrap_err(), "invalid CPU count: many");
+    assert_eq!(parse(&["--stub-port=70000"]).unwrap_err(), "invalid port: 70000");
+    assert_eq!(parse(&["--timeout", "-1"]).unwrap_err(), "invalid timeout: -1");
+}
+
+#[test]
+fn config_file_can_be_given_joined_to_the_flag() {
+    let path = std::env::temp_dir().join(format!("tokyo-qemu-{}.conf", std::process::id()));
+    std::fs::write(&path, "# test config\n--memory 256M\n--cpus 3\n").unwrap();
+
+    let config = format!("--config={}", path.display());
+    let options = Options::load([config.clone(), "--cpus".into(), "1".into()]).unwrap();
+    assert_eq!(options.memory.as_deref(), Some("256M"));
+    assert_eq!(options.cpus, Some(1));
+
+    // past the separator it is an argument for QEMU
+    let missing = "--config=/nonexistent/qemu.conf".to_string();
+    let options = Options::load([config, "--".into(), missing.clone()]).unwrap();
+    assert_eq!(options.extra, [missing]);
+
+    std::fs::remove_file(&path).unwrap();
+}